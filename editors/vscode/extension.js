@@ -0,0 +1,16 @@
+const vscode = require('vscode');
+
+function activate(context) {
+  context.subscriptions.push(
+    vscode.debug.registerDebugAdapterDescriptorFactory('teenyat', {
+      createDebugAdapterDescriptor() {
+        const exe = vscode.workspace.getConfiguration('teenyat').get('executable');
+        return new vscode.DebugAdapterExecutable(exe, ['dap']);
+      },
+    })
+  );
+}
+
+function deactivate() {}
+
+module.exports = { activate, deactivate };
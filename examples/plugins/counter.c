@@ -0,0 +1,46 @@
+/*
+ * A minimal teenyat device plugin: a counter at 0x9000.
+ *
+ * Reading port 0 returns the count and then increments it, and writing port
+ * 0 sets it.
+ *
+ *     cc -shared -fPIC -o libcounter.so counter.c
+ *     teenyat program.tat --plugin ./libcounter.so
+ */
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct {
+    uint32_t abi_version;
+    uint16_t base;
+    uint16_t ports;
+    void *state;
+    uint16_t (*read)(void *state, uint16_t port);
+    void (*write)(void *state, uint16_t port, uint16_t val);
+    void (*destroy)(void *state);
+} PluginDesc;
+
+static uint16_t counter_read(void *state, uint16_t port) {
+    uint16_t *count = state;
+    (void)port;
+    return (*count)++;
+}
+
+static void counter_write(void *state, uint16_t port, uint16_t val) {
+    uint16_t *count = state;
+    (void)port;
+    *count = val;
+}
+
+int teenyat_device_init(PluginDesc *desc) {
+    if (desc->abi_version != 1) {
+        return 1;
+    }
+    desc->base = 0x9000;
+    desc->ports = 1;
+    desc->state = calloc(1, sizeof(uint16_t));
+    desc->read = counter_read;
+    desc->write = counter_write;
+    desc->destroy = free;
+    return desc->state == NULL;
+}
@@ -0,0 +1,712 @@
+//! Static analysis of assembled programs.
+
+use crate::arch::*;
+use crate::debuginfo::DebugInfo;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Display};
+
+const END_ADDR: u16 = 0xFFFF;
+
+/// The addresses of the instructions that differ between two programs,
+/// including any that only one of them has.
+pub fn diff(a: &[u16], b: &[u16]) -> Vec<u16> {
+    (0..a.len().max(b.len()))
+        .step_by(2)
+        .filter(|&addr| a.get(addr..addr + 2) != b.get(addr..addr + 2))
+        .map(|addr| addr as u16)
+        .collect()
+}
+
+/// Where control can go after an instruction.
+#[derive(Debug, Default)]
+pub struct Flow {
+    /// Addresses execution may continue at within the same routine.
+    pub next: Vec<u16>,
+    /// The target of a `call`, which returns to the next instruction.
+    pub call: Option<u16>,
+}
+
+/// Decodes every instruction of a program, skipping words that do not decode
+/// and the data `debug` knows of, so that instructions after an odd number
+/// of data words are still found.
+pub fn decode(words: &[u16], debug: &DebugInfo) -> BTreeMap<u16, Instruction> {
+    let mut program = BTreeMap::new();
+    let mut addr = 0;
+    while addr + 1 < words.len() {
+        if let Some(len) = debug.data_len(addr as u16) {
+            addr += len as usize;
+            continue;
+        }
+        let ins = Instruction::new(words[addr], words[addr + 1]);
+        if ins.get_op_code().is_ok() {
+            program.insert(addr as u16, ins);
+        }
+        addr += 2;
+    }
+    program
+}
+
+/// The control flow out of the instruction at `addr`. Jumps through registers
+/// other than `set pc` and `pop pc` cannot be followed and fall through.
+pub fn flow(addr: u16, ins: &Instruction) -> Flow {
+    use OpCode::*;
+    let next = addr.wrapping_add(2);
+    let op = match ins.get_op_code() {
+        Ok(op) => op,
+        Err(_) => return Flow::default(),
+    };
+    let ra_is_pc = op.num_regs() > 0 && ins.get_ra() == Ok(RegMnem::Pc);
+    // Comparing a register with itself, as in `je pc, pc, !label`, settles
+    // the jump before it runs.
+    let same_regs = op.num_regs() == 2 && ins.get_ra() == ins.get_rb();
+    match op {
+        Jle | Je | Jge if same_regs => Flow {
+            next: vec![ins.word_imm],
+            call: None,
+        },
+        Jl | Jne | Jg if same_regs => Flow {
+            next: vec![next],
+            call: None,
+        },
+        Jl | Jle | Je | Jne | Jge | Jg => Flow {
+            next: vec![next, ins.word_imm],
+            call: None,
+        },
+        Set if ra_is_pc => Flow {
+            next: vec![ins.word_imm],
+            call: None,
+        },
+        Pop if ra_is_pc => Flow::default(),
+        Iret => Flow::default(),
+        Load | Stor if ins.word_imm == END_ADDR => Flow::default(),
+        Call => Flow {
+            next: vec![next],
+            call: Some(ins.word_imm),
+        },
+        _ => Flow {
+            next: vec![next],
+            call: None,
+        },
+    }
+}
+
+/// Where execution can start: address 0, and every label whose address the
+/// program uses as a value, in an immediate or a `dw` word, as a handler
+/// installed with `set ax, !tick | stor 0x7000, ax` is.
+pub fn entry_points(
+    words: &[u16],
+    program: &BTreeMap<u16, Instruction>,
+    debug: &DebugInfo,
+) -> Vec<u16> {
+    let labels: HashSet<u16> = debug.labels.values().copied().collect();
+    let data = (0..words.len())
+        .filter(|&addr| debug.is_data(addr as u16))
+        .map(|addr| words[addr]);
+    let mut entries: Vec<u16> = program
+        .values()
+        .map(|ins| ins.word_imm)
+        .chain(data)
+        .filter(|val| labels.contains(val))
+        .collect();
+    entries.push(0);
+    entries.sort_unstable();
+    entries.dedup();
+    entries
+}
+
+/// The addresses of every instruction reachable from `entries`, following
+/// jumps and calls. Code only reached through computed jumps is missed.
+pub fn reachable(program: &BTreeMap<u16, Instruction>, entries: &[u16]) -> HashSet<u16> {
+    let mut seen = HashSet::new();
+    let mut work = entries.to_vec();
+    while let Some(addr) = work.pop() {
+        if let Some(ins) = program.get(&addr) {
+            if seen.insert(addr) {
+                let flow = flow(addr, ins);
+                work.extend(flow.call);
+                work.extend(flow.next);
+            }
+        }
+    }
+    seen
+}
+
+/// A run of reachable instructions that is only entered at its first one
+/// and, apart from calls, only left after its last.
+#[derive(Debug, Default)]
+pub struct Block {
+    /// The addresses of its instructions, in order.
+    pub instructions: Vec<u16>,
+    /// The blocks execution may continue at afterwards.
+    pub next: Vec<u16>,
+    /// The routines it calls.
+    pub calls: Vec<u16>,
+}
+
+/// Splits the part of a program reachable from `entries` into basic blocks,
+/// keyed by the address of their first instruction.
+pub fn blocks(program: &BTreeMap<u16, Instruction>, entries: &[u16]) -> BTreeMap<u16, Block> {
+    let code = reachable(program, entries);
+    let mut leaders: HashSet<u16> = entries.iter().copied().collect();
+    for &addr in code.iter() {
+        let flow = flow(addr, &program[&addr]);
+        leaders.extend(flow.call);
+        if flow.next != [addr.wrapping_add(2)] {
+            leaders.extend(flow.next);
+            leaders.insert(addr.wrapping_add(2));
+        }
+    }
+    let mut blocks: BTreeMap<u16, Block> = BTreeMap::new();
+    let mut current: Option<u16> = None;
+    let mut addrs: Vec<u16> = code.into_iter().collect();
+    addrs.sort_unstable();
+    for addr in addrs {
+        let start = match current {
+            Some(start) if !leaders.contains(&addr) => start,
+            _ => addr,
+        };
+        let flow = flow(addr, &program[&addr]);
+        let block = blocks.entry(start).or_default();
+        block.instructions.push(addr);
+        block.calls.extend(flow.call);
+        let next = addr.wrapping_add(2);
+        if flow.next == [next] && !leaders.contains(&next) {
+            current = Some(start);
+        } else {
+            block.next = flow.next;
+            current = None;
+        }
+    }
+    blocks
+}
+
+/// The control flow graph of a program in Graphviz DOT format, one box per
+/// basic block reachable from `entries` listing its labels and instructions.
+/// Calls are dashed edges.
+pub fn dot(
+    program: &BTreeMap<u16, Instruction>,
+    entries: &[u16],
+    labels: &HashMap<String, u16>,
+) -> String {
+    let mut names: BTreeMap<u16, Vec<&str>> = BTreeMap::new();
+    for (name, addr) in labels.iter() {
+        names.entry(*addr).or_default().push(name);
+    }
+    let mut out = String::from("digraph cfg {\n    node [shape=box fontname=\"monospace\"];\n");
+    let blocks = blocks(program, entries);
+    for (start, block) in blocks.iter() {
+        let mut text = String::new();
+        for addr in block.instructions.iter() {
+            let mut here = names.get(addr).cloned().unwrap_or_default();
+            here.sort_unstable();
+            for name in here {
+                text.push_str(&format!("{}\\l", dot_escape(name)));
+            }
+            let ins = program[addr].disassemble().unwrap_or_default();
+            text.push_str(&format!("0x{:04x}: {}\\l", addr, dot_escape(&ins)));
+        }
+        out.push_str(&format!("    b{:04x} [label=\"{}\"];\n", start, text));
+        for next in block.next.iter().filter(|next| blocks.contains_key(next)) {
+            out.push_str(&format!("    b{:04x} -> b{:04x};\n", start, next));
+        }
+        for call in block.calls.iter() {
+            out.push_str(&format!(
+                "    b{:04x} -> b{:04x} [style=dashed];\n",
+                start, call
+            ));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Escapes text for a double quoted DOT string.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Runs of the instructions at `addrs` that control can never reach from
+/// `entries`, as (first, last) address pairs. Code that is only entered
+/// through a computed jump is reported too.
+pub fn unreachable(
+    program: &BTreeMap<u16, Instruction>,
+    entries: &[u16],
+    addrs: &[u16],
+) -> Vec<(u16, u16)> {
+    let reached: HashSet<u16> = blocks(program, entries)
+        .values()
+        .flat_map(|block| block.instructions.iter().copied())
+        .collect();
+    let mut addrs: Vec<u16> = addrs.to_vec();
+    addrs.sort_unstable();
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for addr in addrs.into_iter().filter(|addr| !reached.contains(addr)) {
+        match runs.last_mut() {
+            Some((_, last)) if last.wrapping_add(2) == addr => *last = addr,
+            _ => runs.push((addr, addr)),
+        }
+    }
+    runs
+}
+
+/// Every `call` target reachable from `start` without leaving the routine.
+fn calls_from(start: u16, program: &BTreeMap<u16, Instruction>) -> HashSet<u16> {
+    let mut calls = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut work = vec![start];
+    while let Some(addr) = work.pop() {
+        if !seen.insert(addr) {
+            continue;
+        }
+        if let Some(ins) = program.get(&addr) {
+            let flow = flow(addr, ins);
+            calls.extend(flow.call);
+            work.extend(flow.next);
+        }
+    }
+    calls
+}
+
+/// How deeply calls can nest below the routine at `addr`, or None if it can
+/// recurse.
+fn call_depth(
+    addr: u16,
+    program: &BTreeMap<u16, Instruction>,
+    memo: &mut HashMap<u16, Option<usize>>,
+    active: &mut HashSet<u16>,
+) -> Option<usize> {
+    if let Some(depth) = memo.get(&addr) {
+        return *depth;
+    }
+    if !active.insert(addr) {
+        return None;
+    }
+    let mut depth = Some(0);
+    for callee in calls_from(addr, program) {
+        let inner = call_depth(callee, program, memo, active).map(|d| d + 1);
+        depth = match (depth, inner) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+    }
+    active.remove(&addr);
+    memo.insert(addr, depth);
+    depth
+}
+
+/// A stack mistake found by `check_stack`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackWarning {
+    /// A `pop` with nothing pushed since the routine began, which takes its
+    /// return address, or outside any routine pops an empty stack.
+    Underflow(u16),
+    /// A return with words still pushed, which returns to the last of them.
+    Unbalanced { addr: u16, pushed: usize },
+    /// The routine starting here can only return by calling itself again.
+    EndlessRecursion(u16),
+}
+
+impl StackWarning {
+    /// The address of the instruction responsible.
+    pub fn addr(&self) -> u16 {
+        match self {
+            StackWarning::Underflow(addr) => *addr,
+            StackWarning::Unbalanced { addr, .. } => *addr,
+            StackWarning::EndlessRecursion(addr) => *addr,
+        }
+    }
+}
+
+impl Display for StackWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackWarning::Underflow(_) => write!(f, "pops more than was pushed"),
+            StackWarning::Unbalanced { pushed, .. } => write!(
+                f,
+                "returns with {} word{} still pushed",
+                pushed,
+                if *pushed == 1 { "" } else { "s" }
+            ),
+            StackWarning::EndlessRecursion(_) => {
+                write!(f, "recursion with no way to return without recursing again")
+            }
+        }
+    }
+}
+
+/// An immediate outside the range its instruction gives meaning to, found
+/// by `check_immediates`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImmWarning {
+    /// A `shl` or `shr` by more than 15, which always gives 0.
+    Shift { addr: u16, amount: u16 },
+    /// A `cmovz` or `cmovnz` whose condition names no register, which
+    /// stops the program when it runs.
+    Register { addr: u16, number: u16 },
+}
+
+impl ImmWarning {
+    /// The address of the instruction responsible.
+    pub fn addr(&self) -> u16 {
+        match self {
+            ImmWarning::Shift { addr, .. } => *addr,
+            ImmWarning::Register { addr, .. } => *addr,
+        }
+    }
+}
+
+impl Display for ImmWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImmWarning::Shift { amount, .. } => {
+                write!(
+                    f,
+                    "shift by {} always gives 0; shifts past 15 lose every bit",
+                    amount
+                )
+            }
+            ImmWarning::Register { number, .. } => {
+                write!(
+                    f,
+                    "register number {} does not exist; registers go up to 7",
+                    number
+                )
+            }
+        }
+    }
+}
+
+/// Checks the immediates of every instruction against the range its
+/// instruction can use.
+pub fn check_immediates(program: &BTreeMap<u16, Instruction>) -> Vec<ImmWarning> {
+    use OpCode::*;
+    let mut warnings = Vec::new();
+    for (&addr, ins) in program.iter() {
+        let imm = ins.word_imm;
+        match ins.get_op_code() {
+            Ok(Shl | Shr) if imm > 15 => warnings.push(ImmWarning::Shift { addr, amount: imm }),
+            Ok(Cmovz | Cmovnz) if imm > 7 => {
+                warnings.push(ImmWarning::Register { addr, number: imm })
+            }
+            _ => {}
+        }
+    }
+    warnings
+}
+
+/// A jump, call or `set pc` whose target can't be the start of an
+/// instruction, found by `check_targets`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetWarning {
+    /// The address of the instruction responsible.
+    pub addr: u16,
+    pub target: u16,
+    /// Whether the target is inside the program, rather than past its end
+    /// at an odd address.
+    pub inside: bool,
+}
+
+impl Display for TargetWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.inside {
+            write!(
+                f,
+                "jumps to 0x{:04x}, which is not the start of an instruction; is the label on a data word, or an offset wrong?",
+                self.target
+            )
+        } else {
+            write!(
+                f,
+                "jumps to the odd address 0x{:04x}; instructions are two words long",
+                self.target
+            )
+        }
+    }
+}
+
+/// Checks where every jump, call and `set pc` goes. A target inside the
+/// program, which is `len` words long, must be an instruction of `program`;
+/// one past the end is only known to be wrong when it is odd.
+pub fn check_targets(program: &BTreeMap<u16, Instruction>, len: usize) -> Vec<TargetWarning> {
+    use OpCode::*;
+    let mut warnings = Vec::new();
+    for (&addr, ins) in program.iter() {
+        let same_regs = ins.get_ra() == ins.get_rb();
+        let jumps = match ins.get_op_code() {
+            // Never taken, so its target doesn't matter.
+            Ok(Jl | Jne | Jg) if same_regs => false,
+            Ok(Jl | Jle | Je | Jne | Jge | Jg | Call) => true,
+            Ok(Set) => ins.get_ra() == Ok(RegMnem::Pc),
+            _ => false,
+        };
+        let target = ins.word_imm;
+        if !jumps {
+            continue;
+        }
+        let inside = (target as usize) < len;
+        if (inside && !program.contains_key(&target)) || (!inside && target % 2 == 1) {
+            warnings.push(TargetWarning {
+                addr,
+                target,
+                inside,
+            });
+        }
+    }
+    warnings
+}
+
+/// How a routine uses the stack, as seen by following its code.
+#[derive(Debug, Default)]
+struct RoutineStack {
+    /// The most words it has pushed at once, not counting its calls.
+    max: usize,
+    /// Each routine it calls with the words pushed at the time.
+    calls: Vec<(u16, usize)>,
+    warnings: Vec<StackWarning>,
+    /// Whether the amount pushed depends on the path taken, or `sp` is
+    /// changed directly, so that none of the above can be trusted.
+    unknown: bool,
+}
+
+/// Follows a routine, keeping count of the words pushed. The routine at
+/// address 0 is the program itself, which has no return address to pop.
+fn routine_stack(entry: u16, program: &BTreeMap<u16, Instruction>) -> RoutineStack {
+    use OpCode::*;
+    let mut stack = RoutineStack::default();
+    let mut seen: HashMap<u16, usize> = HashMap::new();
+    let mut work = vec![(entry, 0usize)];
+    while let Some((addr, depth)) = work.pop() {
+        if let Some(&before) = seen.get(&addr) {
+            stack.unknown |= before != depth;
+            continue;
+        }
+        seen.insert(addr, depth);
+        let ins = match program.get(&addr) {
+            Some(ins) => ins,
+            None => continue,
+        };
+        let op = match ins.get_op_code() {
+            Ok(op) => op,
+            Err(_) => continue,
+        };
+        let ra = if op.num_regs() > 0 {
+            ins.get_ra().ok()
+        } else {
+            None
+        };
+        let writes_sp = ra == Some(RegMnem::Sp)
+            && !matches!(op, Stor | PStor | Push | Jl | Jle | Je | Jne | Jge | Jg);
+        let returns = op == Iret || (op == Pop && ra == Some(RegMnem::Pc));
+        let flow = flow(addr, ins);
+        let depth = match op {
+            _ if returns => {
+                if depth > 0 {
+                    stack.warnings.push(StackWarning::Unbalanced {
+                        addr,
+                        pushed: depth,
+                    });
+                } else if entry == 0 {
+                    stack.warnings.push(StackWarning::Underflow(addr));
+                }
+                continue;
+            }
+            Push => depth + 1,
+            Dec if writes_sp => depth + 1,
+            Pop if !writes_sp => pop_one(&mut stack, addr, depth),
+            Inc if writes_sp => pop_one(&mut stack, addr, depth),
+            _ if writes_sp => {
+                stack.unknown = true;
+                continue;
+            }
+            Call => {
+                stack.calls.extend(flow.call.map(|callee| (callee, depth)));
+                depth
+            }
+            _ => depth,
+        };
+        stack.max = stack.max.max(depth);
+        work.extend(flow.next.into_iter().map(|next| (next, depth)));
+    }
+    if stack.unknown {
+        stack.warnings.clear();
+    }
+    stack
+}
+
+/// The words pushed after a pop, warning if there were none.
+fn pop_one(stack: &mut RoutineStack, addr: u16, depth: usize) -> usize {
+    if depth == 0 {
+        stack.warnings.push(StackWarning::Underflow(addr));
+    }
+    depth.saturating_sub(1)
+}
+
+/// Whether the routine at `entry` can return, or end the program, without
+/// calling any of the routines in `avoid`.
+fn returns_avoiding(
+    entry: u16,
+    program: &BTreeMap<u16, Instruction>,
+    avoid: &HashSet<u16>,
+) -> bool {
+    let mut seen = HashSet::new();
+    let mut work = vec![entry];
+    while let Some(addr) = work.pop() {
+        if !seen.insert(addr) {
+            continue;
+        }
+        let ins = match program.get(&addr) {
+            Some(ins) => ins,
+            None => continue,
+        };
+        let flow = flow(addr, ins);
+        if flow.next.is_empty() {
+            return true;
+        }
+        if flow.call.is_some_and(|callee| avoid.contains(&callee)) {
+            continue;
+        }
+        work.extend(flow.next);
+    }
+    false
+}
+
+/// Every routine reachable through calls from the routine at `start`,
+/// including `start` only if it can call itself.
+fn callees(start: u16, program: &BTreeMap<u16, Instruction>) -> HashSet<u16> {
+    let mut found = HashSet::new();
+    let mut work: Vec<u16> = calls_from(start, program).into_iter().collect();
+    while let Some(addr) = work.pop() {
+        if found.insert(addr) {
+            work.extend(calls_from(addr, program));
+        }
+    }
+    found
+}
+
+/// Checks that every routine reachable from the start of the program pops
+/// no more than it pushed and returns with nothing left pushed, and that
+/// recursive routines have some way to return. Routines whose stack use
+/// depends on the path taken, such as ones that push in a loop, are not
+/// checked.
+pub fn check_stack(program: &BTreeMap<u16, Instruction>) -> Vec<StackWarning> {
+    let mut warnings = Vec::new();
+    let mut routines: Vec<u16> = callees(0, program).into_iter().collect();
+    routines.push(0);
+    routines.sort_unstable();
+    routines.dedup();
+    for &routine in routines.iter() {
+        warnings.extend(routine_stack(routine, program).warnings);
+        let reach = callees(routine, program);
+        if reach.contains(&routine) {
+            let cycle: HashSet<u16> = reach
+                .into_iter()
+                .filter(|&other| callees(other, program).contains(&routine))
+                .collect();
+            if !returns_avoiding(routine, program, &cycle) {
+                warnings.push(StackWarning::EndlessRecursion(routine));
+            }
+        }
+    }
+    warnings.sort_by_key(StackWarning::addr);
+    warnings.dedup();
+    warnings
+}
+
+/// The most words the routine at `addr` and the routines it calls can have
+/// pushed at once, counting return addresses, or None if that can't be
+/// worked out.
+fn stack_depth(
+    addr: u16,
+    program: &BTreeMap<u16, Instruction>,
+    memo: &mut HashMap<u16, Option<usize>>,
+    active: &mut HashSet<u16>,
+) -> Option<usize> {
+    if let Some(depth) = memo.get(&addr) {
+        return *depth;
+    }
+    if !active.insert(addr) {
+        return None;
+    }
+    let stack = routine_stack(addr, program);
+    let mut depth = if stack.unknown { None } else { Some(stack.max) };
+    for (callee, pushed) in stack.calls {
+        let inner = stack_depth(callee, program, memo, active).map(|d| pushed + 1 + d);
+        depth = depth.zip(inner).map(|(a, b)| a.max(b));
+    }
+    active.remove(&addr);
+    memo.insert(addr, depth);
+    depth
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    pub words: usize,
+    /// Instructions reachable from the start of the program or a label it
+    /// uses as a value.
+    pub instructions: usize,
+    /// Words that aren't part of a reachable instruction.
+    pub data_words: usize,
+    /// The first and last nonzero words, or None if the program is empty.
+    pub used: Option<(u16, u16)>,
+    pub histogram: Vec<(OpCode, usize)>,
+    pub labels: usize,
+    /// The deepest nesting of calls, or None if the program can recurse.
+    pub call_depth: Option<usize>,
+    /// The most words the program can have on the stack at once, or None if
+    /// it recurses or its stack use depends on the path taken.
+    pub stack_depth: Option<usize>,
+}
+
+pub fn stats(words: &[u16], debug: &DebugInfo) -> Stats {
+    let program = decode(words, debug);
+    let code = reachable(&program, &entry_points(words, &program, debug));
+    let mut counts: HashMap<OpCode, usize> = HashMap::new();
+    for addr in code.iter() {
+        if let Ok(op) = program[addr].get_op_code() {
+            *counts.entry(op).or_insert(0) += 1;
+        }
+    }
+    let first = words.iter().position(|&word| word != 0);
+    let last = words.iter().rposition(|&word| word != 0);
+    let mut histogram: Vec<(OpCode, usize)> = counts.into_iter().collect();
+    histogram.sort_by_key(|(op, count)| (std::cmp::Reverse(*count), op.to_int()));
+    let call_depth = call_depth(0, &program, &mut HashMap::new(), &mut HashSet::new());
+    let stack_depth = stack_depth(0, &program, &mut HashMap::new(), &mut HashSet::new());
+    Stats {
+        words: words.len(),
+        instructions: code.len(),
+        data_words: words.len() - 2 * code.len(),
+        used: first.zip(last).map(|(a, b)| (a as u16, b as u16)),
+        histogram,
+        labels: debug.labels.len(),
+        call_depth,
+        stack_depth,
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Program size: {} words ({} instructions, {} words of data)",
+            self.words, self.instructions, self.data_words
+        )?;
+        if let Some((first, last)) = self.used {
+            writeln!(f, "Used addresses: 0x{:04x} to 0x{:04x}", first, last)?;
+        }
+        writeln!(f, "Labels: {}", self.labels)?;
+        match self.call_depth {
+            Some(depth) => writeln!(f, "Deepest call nesting: {}", depth)?,
+            None => writeln!(f, "Deepest call nesting: unbounded (recursive)")?,
+        }
+        match self.stack_depth {
+            Some(depth) => writeln!(f, "Deepest stack use: {} words", depth)?,
+            None => writeln!(f, "Deepest stack use: unknown")?,
+        }
+        writeln!(f, "Instruction counts:")?;
+        for (op, count) in self.histogram.iter() {
+            writeln!(f, "  {:<6} {}", op.mnemonic(), count)?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,11 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+#[cfg(feature = "std")]
 use std::fs::{self, File};
+#[cfg(feature = "std")]
 use std::io::{self, Read, Write};
 
 const OP_CODE_MASK: u16 = !(!0u16 << 5) << 11;
@@ -7,7 +14,24 @@ const RA_MASK: u16 = !(!0u16 << 3) << 8;
 const RA_SHIFT: u16 = 8;
 const RB_MASK: u16 = !(!0u16 << 3) << 5;
 const RB_SHIFT: u16 = 5;
-const MEM_SIZE: u16 = 32768;
+/// The opcode field of instructions numbered 32 and up, whose number less
+/// 32 is kept in the bits below the registers.
+const EXTENDED: u16 = 31;
+const EXT_MASK: u16 = !(!0u16 << 5);
+/// The words of memory a program is assembled and loaded into, and the most
+/// a machine configuration can give it.
+pub const MEM_SIZE: u16 = 32768;
+/// The bytes a `.rom` file with a header starts with.
+const ROM_MAGIC: &[u8; 8] = b"TeenyAT\x1a";
+/// The header line marking a ROM whose words are run-length encoded.
+const RLE: &str = "compression=rle";
+/// The header key giving the ROM's byte order.
+const ENDIAN: &str = "endian";
+/// Set in the count word of a compressed run that repeats one word.
+const RLE_REPEAT: u16 = 0x8000;
+
+/// The conventional names of the registers, indexed by register number.
+pub const REG_NAMES: [&str; 8] = ["pc", "ax", "bx", "cx", "dx", "ex", "fx", "sp"];
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Instruction {
@@ -24,8 +48,11 @@ impl Instruction {
     }
 
     pub fn with_vals(op: OpCode, ra: RegMnem, rb: RegMnem, imm: u16) -> Self {
-        let word1 =
-            op.to_int() << OP_CODE_SHIFT | ra.to_int() << RA_SHIFT | rb.to_int() << RB_SHIFT;
+        let (code, ext) = match op.to_int() {
+            code if code >= 32 => (EXTENDED, code - 32),
+            code => (code, 0),
+        };
+        let word1 = code << OP_CODE_SHIFT | ra.to_int() << RA_SHIFT | rb.to_int() << RB_SHIFT | ext;
         Self {
             word_op_regs: word1,
             word_imm: imm,
@@ -34,6 +61,9 @@ impl Instruction {
 
     pub fn get_op_code(&self) -> Result<OpCode, ArchError> {
         let code = (self.word_op_regs & OP_CODE_MASK) >> OP_CODE_SHIFT;
+        if code == EXTENDED {
+            return OpCode::from_int(32 + (self.word_op_regs & EXT_MASK));
+        }
         OpCode::from_int(code)
     }
 
@@ -46,6 +76,47 @@ impl Instruction {
         let code = (self.word_op_regs & RB_MASK) >> RB_SHIFT;
         RegMnem::from_int(code)
     }
+
+    /// The instruction in assembler syntax, or None if it doesn't decode.
+    pub fn disassemble(&self) -> Option<String> {
+        use OpCode::*;
+        let op = self.get_op_code().ok()?;
+        let mut regs = Vec::new();
+        if op.num_regs() > 0 {
+            regs.push(REG_NAMES[self.get_ra().ok()?.to_int() as usize].to_string());
+        }
+        if op.num_regs() > 1 {
+            regs.push(REG_NAMES[self.get_rb().ok()?.to_int() as usize].to_string());
+        }
+        let addr = format!("0x{:04x}", self.word_imm);
+        let operands = match op {
+            Set => {
+                // Small negative numbers read best signed and large values
+                // (usually addresses) in hex.
+                let imm = match self.word_imm {
+                    0..=0x7FFF => self.word_imm.to_string(),
+                    0xFC00.. => (self.word_imm as i16).to_string(),
+                    _ => addr,
+                };
+                vec![regs[0].clone(), imm]
+            }
+            Shl | Shr => vec![regs[0].clone(), self.word_imm.to_string()],
+            Load => vec![regs[0].clone(), addr],
+            Stor => vec![addr, regs[0].clone()],
+            Call => vec![addr],
+            Int => vec![self.word_imm.to_string()],
+            Jl | Jle | Je | Jne | Jge | Jg => vec![regs[0].clone(), regs[1].clone(), addr],
+            Cmovz | Cmovnz => {
+                let cond = REG_NAMES.get(self.word_imm as usize)?;
+                vec![regs[0].clone(), regs[1].clone(), cond.to_string()]
+            }
+            _ => regs,
+        };
+        if operands.is_empty() {
+            return Some(op.mnemonic());
+        }
+        Some(format!("{} {}", op.mnemonic(), operands.join(", ")))
+    }
 }
 
 impl Display for Instruction {
@@ -63,7 +134,7 @@ impl Display for Instruction {
             "".to_string()
         };
         let imm_type = match op {
-            OpCode::Set | OpCode::Shl | OpCode::Shr => " imm",
+            OpCode::Set | OpCode::Shl | OpCode::Shr | OpCode::Int => " imm",
             OpCode::Load
             | OpCode::Stor
             | OpCode::Call
@@ -79,8 +150,9 @@ impl Display for Instruction {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub enum OpCode {
+    #[default]
     Set,
     Copy,
     Load,
@@ -110,10 +182,28 @@ pub enum OpCode {
     Jne,
     Jge,
     Jg,
+    /// Enable interrupts.
+    Ei,
+    /// Disable interrupts.
+    Di,
+    /// Return from an interrupt handler, enabling interrupts again.
+    Iret,
+    /// Call the host service numbered by the immediate; see `syscall`.
+    Int,
+    /// Signed division, rounding toward zero.
+    Divs,
+    /// The remainder of `divs`, which has the sign of the dividend.
+    Mods,
+    /// Copy rb to ra if the register numbered by the immediate is zero.
+    Cmovz,
+    /// Copy rb to ra if the register numbered by the immediate is not zero.
+    Cmovnz,
+    /// Sign-extend the low byte of ra into the whole word.
+    Sext,
 }
 
 impl OpCode {
-    pub fn to_int(&self) -> u16 {
+    pub fn to_int(self) -> u16 {
         use OpCode::*;
         match self {
             Set => 0,
@@ -145,6 +235,15 @@ impl OpCode {
             Jne => 26,
             Jge => 27,
             Jg => 28,
+            Ei => 32,
+            Di => 33,
+            Iret => 34,
+            Int => 35,
+            Divs => 36,
+            Mods => 37,
+            Cmovz => 38,
+            Cmovnz => 39,
+            Sext => 40,
         }
     }
 
@@ -180,19 +279,46 @@ impl OpCode {
             26 => Ok(Jne),
             27 => Ok(Jge),
             28 => Ok(Jg),
+            32 => Ok(Ei),
+            33 => Ok(Di),
+            34 => Ok(Iret),
+            35 => Ok(Int),
+            36 => Ok(Divs),
+            37 => Ok(Mods),
+            38 => Ok(Cmovz),
+            39 => Ok(Cmovnz),
+            40 => Ok(Sext),
             _ => Err(ArchError::InvalidOpCode(code)),
         }
     }
 
+    pub fn mnemonic(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
+    /// An estimate of the clock cycles the instruction takes: one to fetch
+    /// and execute it, one more for each data memory access, and extra for
+    /// multiplication and division.
+    pub fn cycles(&self) -> u64 {
+        use OpCode::*;
+        match self {
+            Load | Stor | PLoad | PStor | Push | Pop | Call | Iret => 2,
+            Mult => 4,
+            Div | Mod | Divs | Mods => 8,
+            _ => 1,
+        }
+    }
+
     pub fn num_regs(&self) -> u16 {
         use OpCode::*;
         match self {
-            Call => 0,
-            Set | Load | Stor | Push | Pop | Neg | Inc | Dec | Inv | Shl | Shr => 1,
+            Call | Ei | Di | Iret | Int => 0,
+            Set | Load | Stor | Push | Pop | Neg | Inc | Dec | Inv | Shl | Shr | Sext => 1,
             _ => 2,
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(op: &str) -> Result<OpCode, ArchError> {
         use OpCode::*;
         let op = op.to_ascii_lowercase();
@@ -226,17 +352,20 @@ impl OpCode {
             "jne" => Ok(Jne),
             "jge" => Ok(Jge),
             "jg" => Ok(Jg),
+            "ei" => Ok(Ei),
+            "di" => Ok(Di),
+            "iret" => Ok(Iret),
+            "int" => Ok(Int),
+            "divs" => Ok(Divs),
+            "mods" => Ok(Mods),
+            "cmovz" => Ok(Cmovz),
+            "cmovnz" => Ok(Cmovnz),
+            "sext" => Ok(Sext),
             _ => Err(ArchError::InvalidOpMnem(op)),
         }
     }
 }
 
-impl Default for OpCode {
-    fn default() -> Self {
-        OpCode::Set
-    }
-}
-
 #[derive(Clone, Debug)]
 pub struct Register {
     pub mnem: RegMnem,
@@ -255,8 +384,12 @@ impl Display for Register {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// The lettered names are aliases for the numbered registers; they are only
+// ever matched against, never produced by decoding.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub enum RegMnem {
+    #[default]
     Pc,
     _R0,
     R1,
@@ -276,7 +409,7 @@ pub enum RegMnem {
 }
 
 impl RegMnem {
-    pub fn to_int(&self) -> u16 {
+    pub fn to_int(self) -> u16 {
         use RegMnem::*;
         match self {
             Pc => 0,
@@ -313,6 +446,7 @@ impl RegMnem {
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(mnem: &str) -> Result<RegMnem, ArchError> {
         use RegMnem::*;
         let mnem = mnem.to_ascii_lowercase();
@@ -330,40 +464,416 @@ impl RegMnem {
     }
 }
 
-impl Default for RegMnem {
-    fn default() -> Self {
-        RegMnem::Pc
+/// The order of the two bytes of each word in a `.rom` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    /// Low byte first, as teenyat has always written ROMs.
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endian {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "little" => Some(Endian::Little),
+            "big" => Some(Endian::Big),
+            _ => None,
+        }
     }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Endian::Little => "little",
+            Endian::Big => "big",
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn word(self, pair: [u8; 2]) -> u16 {
+        match self {
+            Endian::Little => u16::from_le_bytes(pair),
+            Endian::Big => u16::from_be_bytes(pair),
+        }
+    }
+
+    fn bytes(self, word: u16) -> [u8; 2] {
+        match self {
+            Endian::Little => word.to_le_bytes(),
+            Endian::Big => word.to_be_bytes(),
+        }
+    }
+}
+
+/// How to read a `.rom` file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RomOptions {
+    /// The byte order the ROM is expected in, or `None` to take it from its
+    /// header. A ROM without a header is read in this order, little-endian
+    /// if `None`; one whose header gives another order is an error.
+    pub endian: Option<Endian>,
+    /// Load as much of an oversized ROM as fits instead of failing.
+    pub allow_truncate: bool,
+}
+
+/// What a program says about itself with `.title`, `.author` and
+/// `.version`, kept in the header of its `.rom`.
+///
+/// A ROM with any of them set starts with `ROM_MAGIC`, then the length of the
+/// rest of the header as two bytes, low byte first whatever the ROM's byte
+/// order, then that many bytes of `key=value` lines, padded with a 0 byte to
+/// an even length so the program's words start on a word boundary. The
+/// lines always include the ROM's byte order, as `endian=little` or
+/// `endian=big`, so a reader never has to guess it. A little-endian ROM
+/// with none set and not compressed has no header, so ROMs look as they
+/// always have unless asked otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RomMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+}
+
+impl RomMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.fields().iter().all(|(_, val)| val.is_none())
+    }
+
+    /// Each field with its key in the header.
+    pub fn fields(&self) -> [(&'static str, &Option<String>); 3] {
+        [
+            ("title", &self.title),
+            ("author", &self.author),
+            ("version", &self.version),
+        ]
+    }
+
+    /// The header of a ROM holding this metadata with its words in `endian`
+    /// order, empty if there is no metadata and the ROM is little-endian and
+    /// not `compressed`.
+    pub(crate) fn header(&self, compressed: bool, endian: Endian) -> Vec<u8> {
+        if self.is_empty() && !compressed && endian == Endian::Little {
+            return Vec::new();
+        }
+        let mut text = Vec::new();
+        for (key, val) in self.fields() {
+            if let Some(val) = val {
+                text.extend(format!("{}={}\n", key, val).bytes());
+            }
+        }
+        text.extend(format!("{}={}\n", ENDIAN, endian.name()).bytes());
+        if compressed {
+            text.extend(format!("{}\n", RLE).bytes());
+        }
+        if text.len() % 2 == 1 {
+            text.push(0);
+        }
+        let mut header = ROM_MAGIC.to_vec();
+        header.extend((text.len() as u16).to_le_bytes());
+        header.extend(text);
+        header
+    }
+
+    /// Splits the header off a ROM, if it has one. Keys it doesn't know are
+    /// skipped, so newer ROMs still load.
+    #[cfg(feature = "std")]
+    fn split_header<'a>(path: &str, bytes: &'a [u8]) -> io::Result<RomHeader<'a>> {
+        let rest = match bytes.strip_prefix(ROM_MAGIC) {
+            Some(rest) => rest,
+            None => {
+                return Ok(RomHeader {
+                    metadata: Self::default(),
+                    compressed: false,
+                    endian: None,
+                    words: bytes,
+                })
+            }
+        };
+        let len = match rest {
+            [lo, hi, ..] => u16::from_le_bytes([*lo, *hi]) as usize,
+            _ => 0,
+        };
+        if rest.len() < 2 + len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has a header that is cut short", path),
+            ));
+        }
+        let (text, words) = rest[2..].split_at(len);
+        let mut metadata = Self::default();
+        let mut compressed = false;
+        let mut endian = None;
+        for line in String::from_utf8_lossy(text).trim_end_matches('\0').lines() {
+            let (key, val) = line.split_once('=').unwrap_or((line, ""));
+            let field = match key {
+                "title" => &mut metadata.title,
+                "author" => &mut metadata.author,
+                "version" => &mut metadata.version,
+                _ if line == RLE => {
+                    compressed = true;
+                    continue;
+                }
+                "compression" => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} is compressed with {}, which isn't supported", path, val),
+                    ))
+                }
+                ENDIAN => {
+                    endian = Some(Endian::from_str(val).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{} has words in the unknown byte order {}", path, val),
+                        )
+                    })?);
+                    continue;
+                }
+                _ => continue,
+            };
+            *field = Some(val.to_string());
+        }
+        Ok(RomHeader {
+            metadata,
+            compressed,
+            endian,
+            words,
+        })
+    }
+}
+
+/// What a ROM's header says, and the words after it.
+#[cfg(feature = "std")]
+struct RomHeader<'a> {
+    metadata: RomMetadata,
+    compressed: bool,
+    /// The byte order of the words, if the ROM has a header.
+    endian: Option<Endian>,
+    words: &'a [u8],
+}
+
+/// Run-length encodes `words` as a series of runs, each a count word and
+/// then either, if the count has `RLE_REPEAT` set, one word to repeat the
+/// rest of the count's times, or otherwise that many words as they are.
+fn rle_encode(words: &[u16]) -> Vec<u16> {
+    let max = (RLE_REPEAT - 1) as usize;
+    let mut out = Vec::new();
+    let mut literals = 0;
+    let mut i = 0;
+    while i < words.len() {
+        let run = words[i..]
+            .iter()
+            .take(max)
+            .take_while(|&&word| word == words[i])
+            .count();
+        // A run of two is no shorter repeated than as it is.
+        if run < 3 {
+            i += run;
+            continue;
+        }
+        for chunk in words[literals..i].chunks(max) {
+            out.push(chunk.len() as u16);
+            out.extend(chunk);
+        }
+        out.extend([RLE_REPEAT | run as u16, words[i]]);
+        i += run;
+        literals = i;
+    }
+    for chunk in words[literals..].chunks(max) {
+        out.push(chunk.len() as u16);
+        out.extend(chunk);
+    }
+    out
+}
+
+/// Expands the words of a ROM run-length encoded as `rle_encode` does back
+/// into the bytes they stand for, in the same byte order. A few bytes of
+/// runs can stand for any number of words, so expanding stops at `limit`
+/// words: with `truncate` the words so far are kept, and otherwise the ROM
+/// is too large.
+#[cfg(feature = "std")]
+fn rle_decode(
+    path: &str,
+    bytes: &[u8],
+    endian: Endian,
+    limit: usize,
+    truncate: bool,
+) -> io::Result<Vec<u8>> {
+    let cut_short = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has compressed words that are cut short", path),
+        )
+    };
+    if bytes.len() % 2 == 1 {
+        return Err(cut_short());
+    }
+    let mut words = bytes
+        .chunks_exact(2)
+        .map(|pair| endian.word([pair[0], pair[1]]));
+    let mut out = Vec::new();
+    while let Some(count) = words.next() {
+        let run = (count & !RLE_REPEAT) as usize;
+        if out.len() / 2 + run > limit {
+            if !truncate {
+                return Err(ArchError::RomTooLarge(limit).into());
+            }
+            log::warn!("{}: loading the first {} words", path, limit);
+        }
+        let run = run.min(limit - out.len() / 2);
+        if count & RLE_REPEAT != 0 {
+            let word = words.next().ok_or_else(cut_short)?;
+            for _ in 0..run {
+                out.extend(endian.bytes(word));
+            }
+        } else {
+            for _ in 0..run {
+                out.extend(endian.bytes(words.next().ok_or_else(cut_short)?));
+            }
+        }
+        if out.len() / 2 == limit && truncate {
+            break;
+        }
+    }
+    Ok(out)
 }
 
 #[derive(Debug)]
 pub struct Memory {
     ram: Vec<u16>,
     next_ins: usize,
+    metadata: RomMetadata,
+    /// The old value of each word written since `start_undo`, oldest first.
+    undo: Option<Vec<(u16, u16)>>,
+    /// The address of each word written since `start_log`, oldest first.
+    log: Option<Vec<u16>>,
+    /// Which words `poison` filled and nothing has written since, or empty
+    /// if memory hasn't been poisoned.
+    uninit: Vec<bool>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
         let ram: Vec<u16> = vec![0; MEM_SIZE as usize];
-        Self { ram, next_ins: 0 }
+        Self {
+            ram,
+            next_ins: 0,
+            metadata: RomMetadata::default(),
+            undo: None,
+            log: None,
+            uninit: Vec::new(),
+        }
     }
 
+    #[cfg(feature = "std")]
     pub fn from_rom_file(path: &str) -> io::Result<Self> {
+        Self::from_rom_file_with(path, RomOptions::default())
+    }
+
+    #[cfg(feature = "std")]
+    /// Loads a ROM as `options` describe. A ROM larger than memory is an
+    /// error unless `options.allow_truncate` is set, in which case only the
+    /// words that fit are loaded.
+    pub fn from_rom_file_with(path: &str, options: RomOptions) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        io::BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+        Self::from_rom_bytes(path, &bytes, options)
+    }
+
+    #[cfg(feature = "std")]
+    /// Loads a ROM already read into `bytes` as `options` describe, naming it
+    /// `path` in errors.
+    pub fn from_rom_bytes(path: &str, bytes: &[u8], options: RomOptions) -> io::Result<Self> {
+        let header = RomMetadata::split_header(path, bytes)?;
+        let endian = match (header.endian, options.endian) {
+            (Some(found), Some(expected)) if found != expected => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} is {}-endian, but {}-endian was asked for",
+                        path,
+                        found.name(),
+                        expected.name()
+                    ),
+                ))
+            }
+            (found, expected) => found.or(expected).unwrap_or_default(),
+        };
+        let bytes = header.words;
+        let expanded;
+        let bytes = if header.compressed {
+            let limit = MEM_SIZE as usize;
+            expanded = rle_decode(path, bytes, endian, limit, options.allow_truncate)?;
+            &expanded[..]
+        } else {
+            bytes
+        };
+        if bytes.len() % 2 == 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is {} bytes long, but a ROM holds whole 16-bit words; is it truncated?",
+                    path,
+                    bytes.len()
+                ),
+            ));
+        }
         let mut mem = Self::new();
-        let file = File::open(path)?;
-        let mut bytes = file.bytes();
-        while let Some(Ok(byte)) = bytes.next() {
-            let upper = byte as u16;
-            let lower = (bytes.next().unwrap().unwrap() as u16) << 8;
-            let word = upper | lower;
-            mem.ram[mem.next_ins] = word;
+        mem.metadata = header.metadata;
+        let words = bytes.len() / 2;
+        if words > mem.ram.len() && !options.allow_truncate {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is {} bytes ({} words), but memory only holds {} words",
+                    path,
+                    bytes.len(),
+                    words,
+                    mem.ram.len()
+                ),
+            ));
+        }
+        if words > mem.ram.len() {
+            log::warn!(
+                "{}: loading the first {} of its {} words",
+                path,
+                mem.ram.len(),
+                words
+            );
+        }
+        for pair in bytes.chunks_exact(2).take(mem.ram.len()) {
+            mem.ram[mem.next_ins] = endian.word([pair[0], pair[1]]);
             mem.next_ins += 1;
         }
         Ok(mem)
     }
 
+    /// The number of words of RAM.
+    pub fn size(&self) -> usize {
+        self.ram.len()
+    }
+
+    /// Grows or shrinks RAM to `words` words. Fails if the program would not
+    /// fit.
+    pub fn resize(&mut self, words: usize) -> Result<(), ArchError> {
+        if words < self.next_ins {
+            return Err(ArchError::MemAddrOutOfRange(words as u16));
+        }
+        self.ram.resize(words, 0);
+        if !self.uninit.is_empty() {
+            self.uninit.resize(words, false);
+        }
+        Ok(())
+    }
+
     pub fn read(&self, addr: u16) -> Result<u16, ArchError> {
-        if addr >= MEM_SIZE {
+        if addr as usize >= self.ram.len() {
             Err(ArchError::MemAddrOutOfRange(addr))
         } else {
             Ok(self.ram[addr as usize])
@@ -371,47 +881,234 @@ impl Memory {
     }
 
     pub fn write(&mut self, addr: u16, val: u16) -> Result<(), ArchError> {
-        if addr >= MEM_SIZE {
+        if addr as usize >= self.ram.len() {
             Err(ArchError::MemAddrOutOfRange(addr))
         } else {
+            if let Some(undo) = &mut self.undo {
+                undo.push((addr, self.ram[addr as usize]));
+            }
+            if let Some(log) = &mut self.log {
+                log.push(addr);
+            }
             self.ram[addr as usize] = val;
+            if let Some(flag) = self.uninit.get_mut(addr as usize) {
+                *flag = false;
+            }
             Ok(())
         }
     }
 
-    pub fn add_ins(&mut self, ins: Instruction) {
-        self.ram[self.next_ins] = ins.word_op_regs;
-        self.ram[self.next_ins + 1] = ins.word_imm;
-        self.next_ins += 2;
+    /// Fills the memory after the program with `pattern`, such as 0xDEAD,
+    /// so that a read of a word nothing has stored to gives an obviously
+    /// wrong value instead of a zero that happens to work. Until they are
+    /// written those words count as uninitialized; see `is_uninit`.
+    pub fn poison(&mut self, pattern: u16) {
+        self.ram[self.next_ins..].fill(pattern);
+        self.uninit = vec![false; self.ram.len()];
+        self.uninit[self.next_ins..].fill(true);
+    }
+
+    /// Whether `addr` still holds the pattern `poison` filled it with.
+    pub fn is_uninit(&self, addr: u16) -> bool {
+        self.uninit.get(addr as usize).copied().unwrap_or(false)
+    }
+
+    /// Stops counting `addr` as uninitialized without changing it.
+    pub fn mark_init(&mut self, addr: u16) {
+        if let Some(flag) = self.uninit.get_mut(addr as usize) {
+            *flag = false;
+        }
+    }
+
+    /// Starts keeping the old value of every word written, for `take_undo`.
+    pub(crate) fn start_undo(&mut self) {
+        self.undo = Some(Vec::new());
+    }
+
+    /// The old values kept since `start_undo`, oldest first, and stops
+    /// keeping them.
+    pub(crate) fn take_undo(&mut self) -> Vec<(u16, u16)> {
+        self.undo.take().unwrap_or_default()
+    }
+
+    /// Starts keeping the address of every word written, for `take_log`.
+    pub(crate) fn start_log(&mut self) {
+        self.log = Some(Vec::new());
     }
 
+    /// The addresses written since `start_log`, oldest first, and stops
+    /// keeping them.
+    pub(crate) fn take_log(&mut self) -> Vec<u16> {
+        self.log.take().unwrap_or_default()
+    }
+
+    /// Writes little endian bytes starting at a byte address, growing the
+    /// program to cover them.
+    pub fn load_bytes(&mut self, byte_addr: u32, data: &[u8]) -> Result<(), ArchError> {
+        for (i, byte) in data.iter().enumerate() {
+            let byte_addr = byte_addr + i as u32;
+            let addr = byte_addr / 2;
+            if addr as usize >= self.ram.len() {
+                return Err(ArchError::MemAddrOutOfRange(addr as u16));
+            }
+            let word = &mut self.ram[addr as usize];
+            if byte_addr.is_multiple_of(2) {
+                *word = (*word & 0xFF00) | *byte as u16;
+            } else {
+                *word = (*word & 0x00FF) | (*byte as u16) << 8;
+            }
+            let end = (addr as usize + 2) & !1;
+            self.next_ins = self.next_ins.max(end);
+        }
+        Ok(())
+    }
+
+    /// Copies `words` into memory starting at `addr`, growing the program to
+    /// cover them. Nothing is written if they would run past the end.
+    pub fn load_at(&mut self, addr: u16, words: &[u16]) -> Result<(), ArchError> {
+        let start = addr as usize;
+        let end = start + words.len();
+        if end > self.ram.len() {
+            return Err(ArchError::MemAddrOutOfRange(
+                self.ram.len().min(0xFFFF) as u16
+            ));
+        }
+        self.ram[start..end].copy_from_slice(words);
+        self.next_ins = self.next_ins.max(end);
+        Ok(())
+    }
+
+    /// Loads several `(address, words)` segments with `load_at`, stopping at
+    /// the first that doesn't fit. Later segments overwrite earlier ones
+    /// where they overlap.
+    pub fn load_segments<'a, I>(&mut self, segments: I) -> Result<(), ArchError>
+    where
+        I: IntoIterator<Item = (u16, &'a [u16])>,
+    {
+        for (addr, words) in segments {
+            self.load_at(addr, words)?;
+        }
+        Ok(())
+    }
+
+    /// `len` words starting at `start` as rows of hex words followed by the
+    /// words that are printable ASCII characters, e.g.
+    ///
+    /// ```text
+    /// 0x0040: 0048 0069 0021 000a 0000 0000 0000 0000  |Hi!.....|
+    /// ```
+    ///
+    /// The dump stops early at the end of memory.
+    pub fn hexdump(&self, start: u16, len: usize) -> String {
+        const WORDS_PER_ROW: usize = 8;
+        let start = start as usize;
+        let end = (start + len).min(self.ram.len());
+        let mut out = String::new();
+        for row_start in (start..end).step_by(WORDS_PER_ROW) {
+            let row = &self.ram[row_start..(row_start + WORDS_PER_ROW).min(end)];
+            let hex: Vec<String> = row.iter().map(|word| format!("{:04x}", word)).collect();
+            let text: String = row
+                .iter()
+                .map(|&word| match word {
+                    0x20..=0x7E => word as u8 as char,
+                    _ => '.',
+                })
+                .collect();
+            out.push_str(&format!(
+                "0x{:04x}: {:<width$}  |{}|\n",
+                row_start,
+                hex.join(" "),
+                text,
+                width = WORDS_PER_ROW * 5 - 1
+            ));
+        }
+        out
+    }
+
+    /// Appends an instruction to the program. Fails if memory is full.
+    pub fn add_ins(&mut self, ins: Instruction) -> Result<(), ArchError> {
+        self.add_words(&[ins.word_op_regs, ins.word_imm])
+    }
+
+    /// Appends data words to the program after the last instruction. Nothing
+    /// is written if they would run past the end of memory.
+    pub fn add_words(&mut self, words: &[u16]) -> Result<(), ArchError> {
+        let end = self.next_ins + words.len();
+        if end > self.ram.len() {
+            return Err(ArchError::ProgramTooLarge(self.ram.len()));
+        }
+        self.ram[self.next_ins..end].copy_from_slice(words);
+        self.next_ins = end;
+        Ok(())
+    }
+
+    /// The words of the assembled program, without the unused memory after it.
+    pub fn program(&self) -> &[u16] {
+        &self.ram[..self.next_ins]
+    }
+
+    pub fn metadata(&self) -> &RomMetadata {
+        &self.metadata
+    }
+
+    pub fn set_metadata(&mut self, metadata: RomMetadata) {
+        self.metadata = metadata;
+    }
+
+    #[cfg(feature = "std")]
     pub fn print_program(&self) {
-        let mut i = 0usize;
-        while i < self.next_ins {
-            println!("0x{:04x} 0x{:04x}", self.ram[i], self.ram[i + 1]);
-            i += 2;
+        for pair in self.program().chunks(2) {
+            let words: Vec<String> = pair.iter().map(|word| format!("0x{:04x}", word)).collect();
+            println!("{}", words.join(" "));
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn save_program(&self, path: &str) -> io::Result<()> {
+        self.save_program_as(path, Endian::default())
+    }
+
+    #[cfg(feature = "std")]
+    /// Writes the program as a ROM with its words in the given byte order.
+    pub fn save_program_as(&self, path: &str, endian: Endian) -> io::Result<()> {
         let mut out_file = fs::OpenOptions::new()
             .write(true)
+            .create(true)
             .truncate(true)
             .open(path)?;
-        out_file.write_all(&self.bytes())?;
+        out_file.write_all(&self.bytes(endian))?;
         Ok(())
     }
 
-    fn bytes(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = Vec::new();
-        for (i, word) in self.ram.iter().enumerate() {
-            let upper = ((*word & 0xFF00) >> 8) as u8;
-            let lower = (*word & 0x00FF) as u8;
-            bytes.push(lower);
-            bytes.push(upper);
-            if i > self.next_ins {
-                break;
-            }
+    /// The program as the bytes of a ROM in the given byte order: its
+    /// metadata's header, if it has any, then two bytes for each word of
+    /// `program` and nothing else, so loading them gives back the same
+    /// program.
+    pub fn bytes(&self, endian: Endian) -> Vec<u8> {
+        self.padded_bytes(endian, 0)
+    }
+
+    /// Like `bytes`, but padded with zero words until the program takes
+    /// `words` words, for ROM chips and simulators that want an image of a set size. A
+    /// program already that long is left as it is.
+    pub fn padded_bytes(&self, endian: Endian, words: usize) -> Vec<u8> {
+        let mut bytes = self.metadata.header(false, endian);
+        let len = bytes.len() + 2 * words.max(self.next_ins);
+        for word in self.program() {
+            bytes.extend(endian.bytes(*word));
+        }
+        bytes.resize(len, 0);
+        bytes
+    }
+
+    /// Like `bytes`, but with the words run-length encoded and the header
+    /// saying so, for programs with long runs of the same word, such as
+    /// zero-filled buffers, that would otherwise make the ROM large. Loading
+    /// the ROM expands them again.
+    pub fn compressed_bytes(&self, endian: Endian) -> Vec<u8> {
+        let mut bytes = self.metadata.header(true, endian);
+        for word in rle_encode(self.program()) {
+            bytes.extend(endian.bytes(word));
         }
         bytes
     }
@@ -426,12 +1123,48 @@ pub enum ArchError {
     MemAddrOutOfRange(u16),
     InvalidInstruction,
     UnresolvableLabel(&'static str),
-    InvalidOperand(&'static str),
-    RepeatedLabel(String, u16, u16),
+    DivideByZero,
+    /// `int` named a service the host doesn't provide.
+    NoService(u16),
+    /// A signed result didn't fit in a word with `Overflow::Trap` in force.
+    Overflow(OpCode),
+    /// The host asked the VM to stop, e.g. with Ctrl+C.
+    Interrupted,
+    /// A device has no input ready. The instruction is retried later.
+    WouldBlock,
+    /// The VM has used up the fuel it was given with `TeenyAT::set_fuel`.
+    /// Nothing ran; giving it more lets it carry on.
+    OutOfFuel,
+    /// A device couldn't reach the host, e.g. because stdout was closed.
+    Io(String),
+    /// The guest stored `val`, which wasn't 0, to the console's assertion
+    /// port with the instruction at `addr`.
+    AssertionFailed {
+        val: u16,
+        addr: u16,
+    },
+    /// With the shadow stack on, the `pop pc` at `addr` popped `found`
+    /// rather than the return address the innermost call pushed, or there
+    /// was no call to return from.
+    ReturnMismatch {
+        addr: u16,
+        found: u16,
+        expected: Option<u16>,
+    },
+    /// With misaligned jumps trapped, the instruction at `addr` sent
+    /// execution to the odd address `target`.
+    MisalignedJump {
+        addr: u16,
+        target: u16,
+    },
+    /// A compressed ROM expanded past the given number of words, all that
+    /// memory holds.
+    RomTooLarge(usize),
+    /// An assembled program grew past the given number of words, all that
+    /// memory holds.
+    ProgramTooLarge(usize),
 }
 
-use std::fmt::{self, Display};
-
 impl Display for ArchError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use ArchError::*;
@@ -449,12 +1182,7 @@ impl Display for ArchError {
                 writeln!(f, "Invalid Register Mnemonic: {}", mnem)?;
             }
             MemAddrOutOfRange(addr) => {
-                writeln!(
-                    f,
-                    "Memory address out of range: {}. Last memory address is at: {}",
-                    addr,
-                    MEM_SIZE - 1
-                )?;
+                writeln!(f, "Memory address out of range: {}", addr)?;
             }
             InvalidInstruction => {
                 writeln!(f, "Invalid Instruction")?;
@@ -462,14 +1190,75 @@ impl Display for ArchError {
             UnresolvableLabel(msg) => {
                 writeln!(f, "{}", msg)?;
             }
-            InvalidOperand(msg) => {
-                writeln!(f, "{}", msg)?;
+            DivideByZero => {
+                writeln!(f, "Division by zero")?;
+            }
+            NoService(number) => {
+                writeln!(f, "No host service {}", number)?;
+            }
+            Overflow(op) => {
+                writeln!(f, "Arithmetic overflow in {}", op.mnemonic())?;
+            }
+            Interrupted => {
+                writeln!(f, "Interrupted")?;
+            }
+            WouldBlock => {
+                writeln!(f, "Waiting for input")?;
+            }
+            OutOfFuel => {
+                writeln!(f, "Out of fuel")?;
+            }
+            Io(msg) => {
+                writeln!(f, "I/O error: {}", msg)?;
+            }
+            AssertionFailed { val, addr } => {
+                writeln!(
+                    f,
+                    "Assertion failed: 0x{:04x} ({}) stored at 0x{:04x}",
+                    val, *val as i16, addr
+                )?;
+            }
+            ReturnMismatch {
+                addr,
+                found,
+                expected: Some(expected),
+            } => {
+                writeln!(
+                    f,
+                    "Return address smashed: the return at 0x{:04x} popped 0x{:04x}, but the call pushed 0x{:04x}",
+                    addr, found, expected
+                )?;
+            }
+            ReturnMismatch {
+                addr,
+                found,
+                expected: None,
+            } => {
+                writeln!(
+                    f,
+                    "Return without a call: the return at 0x{:04x} popped 0x{:04x}",
+                    addr, found
+                )?;
+            }
+            MisalignedJump { addr, target } => {
+                writeln!(
+                    f,
+                    "Misaligned jump: the instruction at 0x{:04x} jumped to the odd address 0x{:04x}",
+                    addr, target
+                )?;
             }
-            RepeatedLabel(lbl, prev, cur) => {
+            RomTooLarge(words) => {
                 writeln!(
                     f,
-                    "Ambiguous label: {}. First appearance: {}, Second appearance: {}",
-                    lbl, prev, cur
+                    "ROM too large: it expands to more than the {} words memory holds",
+                    words
+                )?;
+            }
+            ProgramTooLarge(words) => {
+                writeln!(
+                    f,
+                    "Program exceeds memory: it needs more than the {} words memory holds",
+                    words
                 )?;
             }
         }
@@ -477,8 +1266,199 @@ impl Display for ArchError {
     }
 }
 
+#[cfg(feature = "std")]
+impl From<std::io::Error> for ArchError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::WouldBlock => ArchError::WouldBlock,
+            _ => ArchError::Io(err.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl From<ArchError> for std::io::Error {
     fn from(err: ArchError) -> Self {
-        std::io::Error::new(std::io::ErrorKind::Other, format!("{}", err))
+        std::io::Error::other(format!("{}", err))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    /// A program with a run of zeros, a run of two and some odd words.
+    fn program(metadata: bool) -> Memory {
+        let mut mem = Memory::new();
+        mem.add_words(&[0x1900, 0xffff, 7, 7]).unwrap();
+        mem.add_words(&[0; 500]).unwrap();
+        mem.add_words(&[1, 2, 3, 3, 3, 0xbeef]).unwrap();
+        if metadata {
+            mem.set_metadata(RomMetadata {
+                title: Some("Test".to_string()),
+                author: None,
+                version: Some("1.0".to_string()),
+            });
+        }
+        mem
+    }
+
+    fn load(bytes: &[u8], endian: Option<Endian>, allow_truncate: bool) -> io::Result<Memory> {
+        let options = RomOptions {
+            endian,
+            allow_truncate,
+        };
+        Memory::from_rom_bytes("test.rom", bytes, options)
+    }
+
+    fn header(text: &str) -> Vec<u8> {
+        let mut bytes = ROM_MAGIC.to_vec();
+        bytes.extend((text.len() as u16).to_le_bytes());
+        bytes.extend(text.bytes());
+        bytes
+    }
+
+    #[test]
+    fn plain_round_trip() {
+        for endian in [Endian::Little, Endian::Big] {
+            for metadata in [false, true] {
+                let mem = program(metadata);
+                let bytes = mem.bytes(endian);
+                let header = mem.metadata().header(false, endian).len();
+                assert_eq!(bytes.len(), header + 2 * mem.program().len());
+                for expected in [None, Some(endian)] {
+                    let loaded = load(&bytes, expected, false).unwrap();
+                    assert_eq!(loaded.program(), mem.program());
+                    assert_eq!(loaded.metadata(), mem.metadata());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn little_endian_without_metadata_has_no_header() {
+        let mem = program(false);
+        let bytes = mem.bytes(Endian::Little);
+        assert_eq!(bytes.len(), 2 * mem.program().len());
+        assert_eq!(&bytes[..4], &[0x00, 0x19, 0xff, 0xff]);
+        assert!(mem.bytes(Endian::Big).starts_with(ROM_MAGIC));
+    }
+
+    #[test]
+    fn padded_round_trip() {
+        for endian in [Endian::Little, Endian::Big] {
+            for metadata in [false, true] {
+                let mem = program(metadata);
+                let len = mem.program().len();
+                let loaded = load(&mem.padded_bytes(endian, 1000), None, false).unwrap();
+                assert_eq!(loaded.program().len(), 1000);
+                assert_eq!(&loaded.program()[..len], mem.program());
+                assert!(loaded.program()[len..].iter().all(|&word| word == 0));
+                assert_eq!(loaded.metadata(), mem.metadata());
+                // Padding to less than the program leaves it as it is.
+                assert_eq!(mem.padded_bytes(endian, 10), mem.bytes(endian));
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        for endian in [Endian::Little, Endian::Big] {
+            for metadata in [false, true] {
+                let mem = program(metadata);
+                let bytes = mem.compressed_bytes(endian);
+                assert!(bytes.len() < mem.bytes(endian).len());
+                let loaded = load(&bytes, Some(endian), false).unwrap();
+                assert_eq!(loaded.program(), mem.program());
+                assert_eq!(loaded.metadata(), mem.metadata());
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_run_longer_than_a_count() {
+        let mut mem = Memory::new();
+        mem.add_words(&vec![0; MEM_SIZE as usize]).unwrap();
+        let loaded = load(&mem.compressed_bytes(Endian::Little), None, false).unwrap();
+        assert_eq!(loaded.program(), mem.program());
+    }
+
+    #[test]
+    fn odd_length_is_truncated() {
+        let mut bytes = program(false).bytes(Endian::Little);
+        bytes.pop();
+        assert!(load(&bytes, None, false).is_err());
+    }
+
+    #[test]
+    fn header_cut_short() {
+        let bytes = program(true).bytes(Endian::Little);
+        assert!(load(&bytes[..ROM_MAGIC.len() + 4], None, false).is_err());
+        assert!(load(&bytes[..ROM_MAGIC.len() + 1], None, false).is_err());
+    }
+
+    #[test]
+    fn compressed_words_cut_short() {
+        let bytes = program(false).compressed_bytes(Endian::Little);
+        assert!(load(&bytes[..bytes.len() - 1], None, false).is_err());
+        assert!(load(&bytes[..bytes.len() - 2], None, false).is_err());
+    }
+
+    #[test]
+    fn too_large() {
+        let words = MEM_SIZE as usize + 1;
+        let bytes = vec![0; 2 * words];
+        assert!(load(&bytes, None, false).is_err());
+        let loaded = load(&bytes, None, true).unwrap();
+        assert_eq!(loaded.program().len(), MEM_SIZE as usize);
+    }
+
+    #[test]
+    fn program_too_large() {
+        let mut mem = Memory::new();
+        mem.add_words(&vec![0; MEM_SIZE as usize - 1]).unwrap();
+        let ins = Instruction {
+            word_op_regs: 0,
+            word_imm: 0,
+        };
+        assert!(matches!(
+            mem.add_ins(ins),
+            Err(ArchError::ProgramTooLarge(_))
+        ));
+        assert_eq!(mem.program().len(), MEM_SIZE as usize - 1);
+        mem.add_words(&[1]).unwrap();
+        assert!(mem.add_words(&[2]).is_err());
+    }
+
+    #[test]
+    fn compressed_too_large() {
+        let mut bytes = header("endian=little\ncompression=rle\n");
+        for _ in 0..100 {
+            bytes.extend([0xff, 0xff, 0, 0]);
+        }
+        let err = load(&bytes, None, false).unwrap_err();
+        assert!(err.to_string().contains("ROM too large"));
+        let loaded = load(&bytes, None, true).unwrap();
+        assert_eq!(loaded.program().len(), MEM_SIZE as usize);
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let mut unknown_compression = header("compression=lz4\n");
+        unknown_compression.extend([0, 0]);
+        assert!(load(&unknown_compression, None, false).is_err());
+        assert!(load(&header("endian=middle\n"), None, false).is_err());
+        let mut unknown_key = header("colour=blue\n");
+        unknown_key.extend([1, 0]);
+        assert_eq!(load(&unknown_key, None, false).unwrap().program(), &[1]);
+    }
+
+    #[test]
+    fn byte_order_mismatch() {
+        let bytes = program(false).bytes(Endian::Big);
+        assert!(load(&bytes, Some(Endian::Little), false).is_err());
+        let headerless = program(false).bytes(Endian::Little);
+        let loaded = load(&headerless, Some(Endian::Big), false).unwrap();
+        assert_eq!(loaded.program()[0], 0x0019);
     }
 }
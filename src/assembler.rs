@@ -1,131 +1,675 @@
 use crate::arch::*;
-use std::collections::HashMap;
+use crate::debuginfo::{DebugInfo, Span};
+use crate::stdlib;
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-const OUT: &'static str = "OUT";
-const OUT_ADDR: &'static str = "0x8000";
-const IN: &'static str = "IN";
-const IN_ADDR: &'static str = "0x8001";
-const END: &'static str = "END";
-const END_ADDR: &'static str = "0xffff";
+const OUT_ADDR: u16 = 0x8000;
+const IN_ADDR: u16 = 0x8001;
+const LINE_ADDR: u16 = 0x8002;
+const LINE_MAX_ADDR: u16 = 0x8003;
+const HEX_ADDR: u16 = 0x8004;
+const DEBUG_ADDR: u16 = 0x8005;
+const ASSERT_ADDR: u16 = 0x8006;
+const END_ADDR: u16 = 0xffff;
+const INCLUDE: &str = ".include";
+const BLOCK_COMMENT_START: &str = "/*";
+const BLOCK_COMMENT_END: &str = "*/";
+/// Places a string in the program as data.
+const STRING: &str = ".string";
+/// Places words in the program as data, between instructions if need be.
+const DW: &str = "dw";
+/// Defines a word that stands for others; see `Aliases`.
+const ALIAS: &str = ".alias";
+/// Names a device address; see `Aliases`.
+const PORT: &str = ".port";
+/// Give the program's metadata, kept in the header of its ROM.
+const TITLE: &str = ".title";
+const AUTHOR: &str = ".author";
+const VERSION: &str = ".version";
+/// Separates statements written on the same line.
+const STATEMENT_SEP: &str = "|";
+/// Colon separated directories searched for `.include <...>` files.
+const SEARCH_PATH_VAR: &str = "TEENYAT_PATH";
+
+/// Words that stand for one or more tokens, such as `jmp` for `set pc` and
+/// `OUT` for the console's output port. Aliases are looked up a whole word
+/// at a time, so they never touch labels or other words that merely contain
+/// them, and `.alias name words...` adds more. `.port name address` adds an
+/// alias for a device address, checked against the other ports and, once
+/// the program is assembled, against the program's own addresses.
+struct Aliases {
+    words: HashMap<String, Vec<Token>>,
+    /// The name of each port address.
+    ports: BTreeMap<u16, String>,
+    /// The ports the source declared, with where.
+    declared: Vec<(u16, Span)>,
+}
+
+impl Aliases {
+    fn builtin() -> Self {
+        use Token::*;
+        let jmp = vec![Op(OpCode::Set), Reg(RegMnem::Pc)];
+        let ret = vec![Op(OpCode::Pop), Reg(RegMnem::Pc)];
+        let mut aliases = Aliases {
+            words: HashMap::new(),
+            ports: BTreeMap::new(),
+            declared: Vec::new(),
+        };
+        for (name, toks) in [
+            ("jmp", jmp.clone()),
+            ("JMP", jmp),
+            ("ret", ret.clone()),
+            ("RET", ret),
+        ]
+        .iter()
+        {
+            aliases.words.insert(name.to_string(), toks.clone());
+        }
+        let ports = [
+            ("OUT", OUT_ADDR),
+            ("IN", IN_ADDR),
+            ("LINE", LINE_ADDR),
+            ("LINE_MAX", LINE_MAX_ADDR),
+            ("HEX", HEX_ADDR),
+            ("DEBUG", DEBUG_ADDR),
+            ("ASSERT", ASSERT_ADDR),
+            ("END", END_ADDR),
+        ];
+        for &(name, addr) in ports.iter() {
+            aliases.words.insert(name.to_string(), vec![Imm(addr)]);
+            aliases.ports.insert(addr, name.to_string());
+        }
+        aliases
+    }
+
+    /// The tokens `word` stands for: its alias's if it has one, otherwise
+    /// the one token it reads as.
+    fn expand(&self, word: &str) -> Result<Vec<Token>, String> {
+        if let Some(toks) = self.words.get(word) {
+            return Ok(toks.clone());
+        }
+        Token::parse_str(word, self).map(|tok| vec![tok])
+    }
+
+    /// Checks that `name` is free to be defined as an alias.
+    fn check_name(&self, name: &str, span: Span, debug: &DebugInfo) -> io::Result<()> {
+        let clash = if self.words.contains_key(name) {
+            "is already an alias"
+        } else if name.starts_with(['!', ':', '\'', '"', '.'])
+            || Token::parse_str(name, self).is_ok()
+        {
+            "already means something else"
+        } else {
+            return Ok(());
+        };
+        Err(error_at(
+            debug,
+            span,
+            format!("Parse Error: {} {}", name, clash),
+        ))
+    }
+
+    /// Handles `.alias name words...`, whose words may use aliases defined
+    /// before it.
+    fn define(
+        &mut self,
+        words: &[(usize, &str)],
+        spans: &[Spanned],
+        debug: &DebugInfo,
+    ) -> io::Result<()> {
+        let usage = "Parse Error: expected .alias name words...";
+        if words.len() < 3 {
+            return Err(error_at(debug, spans[0].span, usage));
+        }
+        let name = words[1].1;
+        self.check_name(name, spans[1].span, debug)?;
+        let mut toks = Vec::new();
+        for (&(_, word), spanned) in words[2..].iter().zip(&spans[2..]) {
+            toks.extend(
+                self.expand(word)
+                    .map_err(|msg| error_at(debug, spanned.span, msg))?,
+            );
+        }
+        debug!(target: "asm::parse", "alias {} = {:?}", name, toks);
+        self.words.insert(name.to_string(), toks);
+        Ok(())
+    }
+
+    /// Handles `.port name address`. Each address can have only one name.
+    fn define_port(
+        &mut self,
+        words: &[(usize, &str)],
+        spans: &[Spanned],
+        debug: &DebugInfo,
+    ) -> io::Result<()> {
+        let usage = "Parse Error: expected .port name address";
+        if words.len() != 3 {
+            return Err(error_at(debug, spans[0].span, usage));
+        }
+        let name = words[1].1;
+        self.check_name(name, spans[1].span, debug)?;
+        let addr = match self.expand(words[2].1).as_deref() {
+            Ok([Token::Imm(addr)]) => *addr,
+            Ok(_) => return Err(error_at(debug, spans[2].span, usage)),
+            Err(msg) => return Err(error_at(debug, spans[2].span, msg)),
+        };
+        if let Some(other) = self.ports.get(&addr) {
+            return Err(error_at(
+                debug,
+                spans[1].span,
+                format!("Parse Error: 0x{:04x} is already the port {}", addr, other),
+            ));
+        }
+        debug!(target: "asm::parse", "port {} = 0x{:04x}", name, addr);
+        self.words.insert(name.to_string(), vec![Token::Imm(addr)]);
+        self.ports.insert(addr, name.to_string());
+        self.declared.push((addr, spans[0].span));
+        Ok(())
+    }
+}
+
+/// A line of source along with where it came from, after includes have been
+/// expanded.
+#[derive(Debug)]
+struct SourceLine {
+    file: usize,
+    line: usize,
+    text: String,
+}
+
+/// A token along with where in the source it was read from.
+#[derive(Clone, Debug)]
+struct Spanned {
+    tok: Token,
+    span: Span,
+}
 
 #[derive(Debug)]
 struct UnresolvedIns {
     op: OpCode,
     ra: RegMnem,
     rb: RegMnem,
-    imm: Token,
+    imm: Spanned,
 }
 
 impl UnresolvedIns {
-    fn new(op: OpCode, ra: RegMnem, rb: RegMnem, imm: Token) -> Self {
+    fn new(op: OpCode, ra: RegMnem, rb: RegMnem, imm: Spanned) -> Self {
         Self { op, ra, rb, imm }
     }
 
-    fn resolve(&self, labels: &HashMap<String, u16>) -> Result<Instruction, ArchError> {
-        let imm: u16 = match &self.imm {
-            Token::Imm(imm) => *imm,
-            Token::Label(lbl, _) => labels[lbl],
-            _ => {
-                return Err(ArchError::InvalidOperand(
-                    "Parse Error: operand in immediate/address position not immediate or label",
-                ))
-            }
-        };
+    fn resolve(&self, labels: &HashMap<String, u16>, debug: &DebugInfo) -> io::Result<Instruction> {
+        let imm = resolve_word(
+            &self.imm,
+            labels,
+            debug,
+            "Parse Error: operand in immediate/address position not immediate or label",
+        )?;
         let ins = Instruction::with_vals(self.op, self.ra, self.rb, imm);
         Ok(ins)
     }
 }
 
-pub fn parse_file(path: &str) -> io::Result<Memory> {
-    let path = Path::new(path);
+/// The value of a number or label, failing with `wrong` for any other
+/// token.
+fn resolve_word(
+    tok: &Spanned,
+    labels: &HashMap<String, u16>,
+    debug: &DebugInfo,
+    wrong: &str,
+) -> io::Result<u16> {
+    match &tok.tok {
+        Token::Imm(imm) => Ok(*imm),
+        Token::Label(lbl) => match labels
+            .get(lbl)
+            .copied()
+            .or_else(|| offset_label(lbl, labels))
+        {
+            Some(addr) => Ok(addr),
+            None => {
+                let mut msg = format!("Unknown label {}", lbl);
+                if let Some(near) = suggest(lbl, labels.keys().map(String::as_str)) {
+                    msg.push_str(&format!(", did you mean {}?", near));
+                }
+                Err(error_at(debug, tok.span, msg))
+            }
+        },
+        _ => Err(error_at(debug, tok.span, wrong)),
+    }
+}
+
+/// The address of `!label+n` or `!label-n`: n words after or before a label.
+fn offset_label(lbl: &str, labels: &HashMap<String, u16>) -> Option<u16> {
+    let at = lbl.rfind(['+', '-'])?;
+    let base = labels.get(&lbl[..at])?;
+    let offset = parse_number(&lbl[at..])??;
+    Some(base.wrapping_add(offset))
+}
+
+/// Something that takes up words in the assembled program.
+#[derive(Debug)]
+enum Item {
+    Ins(UnresolvedIns),
+    Data(Vec<u16>),
+    /// A word placed with `dw`, which may be a label's address.
+    Word(Spanned),
+}
+
+/// An assembly error pointing at `span`.
+fn error_at(debug: &DebugInfo, span: Span, msg: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", debug.describe_span(span), msg),
+    )
+}
+
+/// Moves `addr` past `words` words of program, failing at `span` if they
+/// would run past the end of `mem`.
+fn advance(
+    debug: &DebugInfo,
+    mem: &Memory,
+    span: Span,
+    addr: &mut u16,
+    words: usize,
+) -> io::Result<()> {
+    let size = mem.size();
+    let end = *addr as usize + words;
+    if end > size {
+        return Err(error_at(
+            debug,
+            span,
+            ArchError::ProgramTooLarge(size).to_string().trim_end(),
+        ));
+    }
+    *addr = end as u16;
+    Ok(())
+}
+
+/// Assembles a program, also returning the line and label information needed
+/// to debug it at the source level.
+pub fn assemble_file(path: &str) -> io::Result<(Memory, DebugInfo)> {
+    assemble_file_with(path, &[])
+}
+
+/// Like `assemble_file`, but also searching `include_dirs` for included
+/// files, ahead of `TEENYAT_PATH`.
+pub fn assemble_file_with(path: &str, include_dirs: &[PathBuf]) -> io::Result<(Memory, DebugInfo)> {
     let infile = File::open(path)?;
-    let mut lines = read_file(infile)?;
+    let dir = Path::new(path).parent().map(Path::to_path_buf);
+    assemble_lines(path, read_file(infile)?, dir, include_dirs)
+}
+
+/// Assembles a program held in memory rather than a file. `name` stands in
+/// for the file name in error messages; included files are looked for on
+/// the search path and in the standard library only.
+pub fn assemble_str(name: &str, src: &str) -> io::Result<(Memory, DebugInfo)> {
+    let lines = src.lines().map(str::to_string).collect();
+    assemble_lines(name, lines, None, &[])
+}
+
+fn assemble_lines(
+    path: &str,
+    source: Vec<String>,
+    dir: Option<PathBuf>,
+    include_dirs: &[PathBuf],
+) -> io::Result<(Memory, DebugInfo)> {
+    let mut debug = DebugInfo::default();
+    let mut lines: Vec<SourceLine> = Vec::new();
+    read_source(path, source, dir, include_dirs, &mut debug, &mut lines)?;
     preprocess(&mut lines);
-    let mut instructions: Vec<UnresolvedIns> = Vec::new();
+    let mut items: Vec<Item> = Vec::new();
     let mut labels: HashMap<String, u16> = HashMap::new();
+    let mut label_spans: HashMap<String, Span> = HashMap::new();
     let mut next_ins_addr: u16 = 0;
-    for (linenum, line) in lines.iter().enumerate() {
-        let tokens = tokenize(&line, linenum as u16);
+    let mut statements: Vec<Vec<Spanned>> = Vec::new();
+    let mut aliases = Aliases::builtin();
+    let mut metadata = RomMetadata::default();
+    let mut mem = Memory::new();
+    for src in lines.iter() {
+        statements.extend(tokenize(src, &mut aliases, &mut metadata, &debug)?);
+    }
+    for tokens in statements {
+        if !tokens.is_empty() {
+            debug!(
+                target: "asm::parse",
+                "{}: {:?}",
+                debug.describe_span(tokens[0].span),
+                tokens.iter().map(|t| &t.tok).collect::<Vec<_>>()
+            );
+        }
         let mut i = 0;
         while i < tokens.len() {
-            let tok = tokens[i].clone();
-            match tok {
+            let tok = &tokens[i];
+            match &tok.tok {
                 Token::Op(op) => {
-                    handle_op(op, &tokens, &mut instructions, &mut i)?;
-                    next_ins_addr += 2;
+                    debug.add_span(next_ins_addr, tok.span);
+                    handle_op(*op, &tokens, &mut items, &mut i)?;
+                    advance(&debug, &mem, tok.span, &mut next_ins_addr, 2)?;
+                }
+                Token::Data(words) => {
+                    debug.add_data(next_ins_addr, words.len() as u16);
+                    advance(&debug, &mem, tok.span, &mut next_ins_addr, words.len())?;
+                    items.push(Item::Data(words.clone()));
                 }
-                Token::Label(_, _) => {
-                    handle_label(&tok, &mut labels, false, next_ins_addr, linenum)?;
+                Token::Dw => {
+                    let words = &tokens[i + 1..];
+                    if words.is_empty() {
+                        return Err(error_at(
+                            &debug,
+                            tok.span,
+                            "Parse Error: expected dw followed by words",
+                        ));
+                    }
+                    debug.add_data(next_ins_addr, words.len() as u16);
+                    advance(&debug, &mem, tok.span, &mut next_ins_addr, words.len())?;
+                    items.extend(words.iter().cloned().map(Item::Word));
+                    i = tokens.len();
+                }
+                Token::Label(lbl) => {
+                    // Labels are defined at the start of a statement; one
+                    // anywhere else is an operand nothing took.
+                    if tokens[..i]
+                        .iter()
+                        .any(|t| !matches!(t.tok, Token::Label(_)))
+                    {
+                        return Err(error_at(
+                            &debug,
+                            tok.span,
+                            format!(
+                                "Parse Error: {} is not in an immediate position and labels are only defined at the start of a statement",
+                                lbl
+                            ),
+                        ));
+                    }
+                    if let Some(first) = label_spans.get(lbl) {
+                        return Err(error_at(
+                            &debug,
+                            tok.span,
+                            format!(
+                                "Ambiguous label: {}. First appearance: {}",
+                                lbl,
+                                debug.describe_span(*first)
+                            ),
+                        ));
+                    }
+                    debug!(target: "asm::parse", "label {} = 0x{:04x}", lbl, next_ins_addr);
+                    label_spans.insert(lbl.clone(), tok.span);
+                    labels.insert(lbl.clone(), next_ins_addr);
                 }
                 _ => (),
             }
             i += 1;
         }
     }
-    let mut mem = Memory::new();
-    for ins in instructions.iter() {
-        mem.add_ins(ins.resolve(&labels)?);
+    mem.set_metadata(metadata);
+    for item in items.iter() {
+        match item {
+            Item::Ins(ins) => mem.add_ins(ins.resolve(&labels, &debug)?)?,
+            Item::Data(words) => mem.add_words(words)?,
+            Item::Word(word) => {
+                let val = resolve_word(
+                    word,
+                    &labels,
+                    &debug,
+                    "Parse Error: dw takes numbers, characters and labels",
+                )?;
+                mem.add_words(&[val])?;
+            }
+        }
+    }
+    let end = mem.program().len();
+    for &(addr, span) in aliases.declared.iter() {
+        if (addr as usize) < end {
+            return Err(error_at(
+                &debug,
+                span,
+                format!(
+                    "Port {} at 0x{:04x} is inside the program (0x0000 to 0x{:04x})",
+                    aliases.ports[&addr],
+                    addr,
+                    end - 1
+                ),
+            ));
+        }
+    }
+    debug.labels = labels;
+    debug.ports = aliases.ports;
+    Ok((mem, debug))
+}
+
+/// Appends the lines of a source file to `lines`, expanding `.include`
+/// directives in place. Each file is only included once.
+fn read_source(
+    name: &str,
+    text: Vec<String>,
+    dir: Option<PathBuf>,
+    include_dirs: &[PathBuf],
+    debug: &mut DebugInfo,
+    lines: &mut Vec<SourceLine>,
+) -> io::Result<()> {
+    let file = debug.add_file(name);
+    debug!(target: "asm::parse", "reading {} as file {}", name, file);
+    let mut text = text;
+    if let Err(line) = strip_block_comments(&mut text) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}:{}: Unterminated block comment", name, line + 1),
+        ));
+    }
+    for (linenum, text) in text.into_iter().enumerate() {
+        let directive = text.split(';').next().unwrap_or("").trim();
+        if let Some(spec) = directive.strip_prefix(INCLUDE) {
+            let (name, text, inc_dir) = resolve_include(spec.trim(), dir.as_deref(), include_dirs)?;
+            if debug.file_index(&name).is_none() {
+                read_source(&name, text, inc_dir, include_dirs, debug, lines)?;
+            }
+            continue;
+        }
+        lines.push(SourceLine {
+            file,
+            line: linenum + 1,
+            text,
+        });
+    }
+    Ok(())
+}
+
+/// Finds the file named by an include directive. `"file"` is looked up next
+/// to the including file first; `<file>` and anything not found there is
+/// searched for in `include_dirs`, then in `TEENYAT_PATH` and then in the
+/// bundled standard library.
+fn resolve_include(
+    spec: &str,
+    dir: Option<&Path>,
+    include_dirs: &[PathBuf],
+) -> io::Result<(String, Vec<String>, Option<PathBuf>)> {
+    let (name, local) = if let Some(name) = spec.strip_prefix('"').and_then(|s| s.strip_suffix('"'))
+    {
+        (name, true)
+    } else if let Some(name) = spec.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+        (name, false)
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Parse Error: malformed include: {}", spec),
+        ));
+    };
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    if local {
+        candidates.push(dir.unwrap_or_else(|| Path::new("")).join(name));
+    }
+    candidates.extend(include_dirs.iter().map(|dir| dir.join(name)));
+    if let Some(paths) = env::var_os(SEARCH_PATH_VAR) {
+        candidates.extend(env::split_paths(&paths).map(|dir| dir.join(name)));
+    }
+    for path in candidates {
+        if path.is_file() {
+            let text = read_file(File::open(&path)?)?;
+            let dir = path.parent().map(Path::to_path_buf);
+            return Ok((path.to_string_lossy().into_owned(), text, dir));
+        }
+    }
+    if let Some(text) = stdlib::lookup(name) {
+        let text = text.lines().map(str::to_string).collect();
+        return Ok((format!("<{}>", name), text, None));
     }
-    Ok(mem)
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Parse Error: could not find included file {}", spec),
+    ))
 }
 
-fn preprocess(lines: &mut Vec<String>) {
-    for line in lines.iter_mut() {
-        *line = line.trim().to_string();
-        if let Some(i) = line.find(';') {
-            line.truncate(i);
+/// Blanks out `/* ... */` comments, which may span lines, leaving every
+/// other character where it was so line numbers and spans are unchanged.
+/// Text after a `;` is already a comment and cannot open one, and neither
+/// can a `/*` in a string literal. Fails with the
+/// index of the line that opens a comment that is never closed.
+fn strip_block_comments(lines: &mut [String]) -> Result<(), usize> {
+    let mut open: Option<usize> = None;
+    for (linenum, line) in lines.iter_mut().enumerate() {
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line.as_str();
+        while !rest.is_empty() {
+            if open.is_some() {
+                match rest.find(BLOCK_COMMENT_END) {
+                    Some(end) => {
+                        let len = end + BLOCK_COMMENT_END.len();
+                        out.extend(std::iter::repeat_n(' ', len));
+                        rest = &rest[len..];
+                        open = None;
+                    }
+                    None => {
+                        out.extend(std::iter::repeat_n(' ', rest.len()));
+                        rest = "";
+                    }
+                }
+            } else {
+                match block_comment_start(rest) {
+                    Some(start) => {
+                        out.push_str(&rest[..start]);
+                        out.push_str("  ");
+                        rest = &rest[start + BLOCK_COMMENT_START.len()..];
+                        open = Some(linenum);
+                    }
+                    None => {
+                        out.push_str(rest);
+                        rest = "";
+                    }
+                }
+            }
+        }
+        *line = out;
+    }
+    match open {
+        Some(line) => Err(line),
+        None => Ok(()),
+    }
+}
+
+/// Where a block comment opens in `text`, if it does before any line comment
+/// and outside string literals.
+fn block_comment_start(text: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut i = 0;
+    while let Some(chr) = text[i..].chars().next() {
+        if !quoted {
+            if let Some(len) = char_literal_len(&text[i..]) {
+                i += len;
+                continue;
+            }
         }
-        *line = line.replace(',', "");
-        *line = line.replace("jmp", "set pc ");
-        *line = line.replace("JMP", "set pc ");
-        *line = line.replace("ret", "pop pc ");
-        *line = line.replace("RET", "pop pc ");
-        *line = line.replace(OUT, OUT_ADDR);
-        *line = line.replace(IN, IN_ADDR);
-        *line = line.replace(END, END_ADDR);
+        match chr {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return None,
+            _ if !quoted && text[i..].starts_with(BLOCK_COMMENT_START) => return Some(i),
+            _ => {}
+        }
+        i += chr.len_utf8();
+    }
+    None
+}
+
+/// Strips comments and turns commas into spaces, leaving string and
+/// character literals alone. Nothing else in the line moves, so token spans
+/// still point into the original text.
+fn preprocess(lines: &mut [SourceLine]) {
+    for src in lines.iter_mut() {
+        let line = &mut src.text;
+        let mut quoted = false;
+        let mut escaped = false;
+        let mut out = String::with_capacity(line.len());
+        let mut i = 0;
+        while let Some(chr) = line[i..].chars().next() {
+            if !quoted {
+                if let Some(len) = char_literal_len(&line[i..]) {
+                    out.push_str(&line[i..i + len]);
+                    i += len;
+                    continue;
+                }
+            }
+            i += chr.len_utf8();
+            match chr {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                ';' if !quoted => break,
+                ',' if !quoted => {
+                    out.push(' ');
+                    continue;
+                }
+                _ => {}
+            }
+            out.push(chr);
+        }
+        *line = out;
     }
 }
 
 fn handle_op(
     op: OpCode,
-    tokens: &Vec<Token>,
-    instructions: &mut Vec<UnresolvedIns>,
+    tokens: &[Spanned],
+    items: &mut Vec<Item>,
     i: &mut usize,
 ) -> io::Result<()> {
     let num_regs = op.num_regs();
     if num_regs == 0 {
-        handle_op_0reg(op, instructions);
+        handle_op_0reg(op, tokens, items, i);
     } else if num_regs == 1 {
-        handle_op_1reg(op, tokens, instructions, i);
+        handle_op_1reg(op, tokens, items, i);
     } else if num_regs == 2 {
-        handle_op_2reg(op, tokens, instructions, i);
+        handle_op_2reg(op, tokens, items, i);
     }
     Ok(())
 }
 
-fn handle_op_0reg(op: OpCode, instructions: &mut Vec<UnresolvedIns>) {
-    instructions.push(UnresolvedIns::new(
+fn handle_op_0reg(op: OpCode, tokens: &[Spanned], items: &mut Vec<Item>, i: &mut usize) {
+    let imm = if (op == OpCode::Call || op == OpCode::Int) && *i + 1 < tokens.len() {
+        *i += 1;
+        tokens[*i].clone()
+    } else {
+        implicit_imm(&tokens[*i])
+    };
+    items.push(Item::Ins(UnresolvedIns::new(
         op,
         RegMnem::default(),
         RegMnem::default(),
-        Token::Imm(0),
-    ));
+        imm,
+    )));
 }
 
-fn handle_op_1reg(
-    op: OpCode,
-    tokens: &Vec<Token>,
-    instructions: &mut Vec<UnresolvedIns>,
-    i: &mut usize,
-) {
+fn handle_op_1reg(op: OpCode, tokens: &[Spanned], items: &mut Vec<Item>, i: &mut usize) {
     let mut i_ofs = 0usize;
     let ra = match op {
         OpCode::Stor => {
             if *i + 2 < tokens.len() {
-                if let Token::Reg(reg) = tokens[*i + 2] {
+                if let Token::Reg(reg) = tokens[*i + 2].tok {
                     i_ofs += 1;
                     reg
                 } else {
@@ -137,7 +681,7 @@ fn handle_op_1reg(
         }
         _ => {
             if *i + 1 < tokens.len() {
-                if let Token::Reg(reg) = tokens[*i + 1] {
+                if let Token::Reg(reg) = tokens[*i + 1].tok {
                     i_ofs += 1;
                     reg
                 } else {
@@ -149,39 +693,28 @@ fn handle_op_1reg(
         }
     };
     let rb = RegMnem::default();
-    let mut imm = Token::Imm(0);
+    let mut imm = implicit_imm(&tokens[*i]);
     match op {
-        OpCode::Set | OpCode::Load | OpCode::Shl | OpCode::Shr => {
-            imm = if *i + 2 < tokens.len() && tokens[*i + 2].is_imm() {
-                i_ofs += 1;
-                tokens[*i + 2].clone()
-            } else {
-                Token::Imm(0)
-            };
+        OpCode::Set | OpCode::Load | OpCode::Shl | OpCode::Shr
+            if *i + 2 < tokens.len() && tokens[*i + 2].tok.is_value() =>
+        {
+            i_ofs += 1;
+            imm = tokens[*i + 2].clone();
         }
-        OpCode::Stor => {
-            imm = if *i + 1 < tokens.len() {
-                i_ofs += 1;
-                tokens[*i + 1].clone()
-            } else {
-                Token::Imm(0)
-            }
+        OpCode::Stor if *i + 1 < tokens.len() => {
+            i_ofs += 1;
+            imm = tokens[*i + 1].clone();
         }
         _ => {}
     }
     *i += i_ofs;
-    instructions.push(UnresolvedIns::new(op, ra, rb, imm));
+    items.push(Item::Ins(UnresolvedIns::new(op, ra, rb, imm)));
 }
 
-fn handle_op_2reg(
-    op: OpCode,
-    tokens: &Vec<Token>,
-    instructions: &mut Vec<UnresolvedIns>,
-    i: &mut usize,
-) {
+fn handle_op_2reg(op: OpCode, tokens: &[Spanned], items: &mut Vec<Item>, i: &mut usize) {
     let mut i_ofs = 0usize;
     let ra = if *i + 1 < tokens.len() {
-        if let Token::Reg(reg) = tokens[*i + 1] {
+        if let Token::Reg(reg) = tokens[*i + 1].tok {
             i_ofs += 1;
             reg
         } else {
@@ -191,7 +724,7 @@ fn handle_op_2reg(
         RegMnem::default()
     };
     let rb = if *i + 2 < tokens.len() {
-        if let Token::Reg(reg) = tokens[*i + 2] {
+        if let Token::Reg(reg) = tokens[*i + 2].tok {
             i_ofs += 1;
             reg
         } else {
@@ -206,46 +739,32 @@ fn handle_op_2reg(
             if *i + 3 < tokens.len() {
                 tokens[*i + 3].clone()
             } else {
-                Token::Imm(0)
+                implicit_imm(&tokens[*i])
             }
         }
-        _ => Token::Imm(0),
-    };
-    *i += i_ofs;
-    instructions.push(UnresolvedIns::new(op, ra, rb, imm));
-}
-
-fn handle_label(
-    tok: &Token,
-    labels: &mut HashMap<String, u16>,
-    do_eval: bool,
-    addr: u16,
-    line_num: usize,
-) -> io::Result<Option<u16>> {
-    let (lbl, line) = match tok {
-        Token::Label(lbl, line) => (lbl, *line),
-        _ => {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Parse Error: attempted to treat non-label token as label",
-            ))
+        // The condition register is kept in the immediate as its number.
+        OpCode::Cmovz | OpCode::Cmovnz if *i + 3 < tokens.len() => {
+            i_ofs += 1;
+            match tokens[*i + 3].tok {
+                Token::Reg(reg) => Spanned {
+                    tok: Token::Imm(reg.to_int()),
+                    span: tokens[*i + 3].span,
+                },
+                _ => tokens[*i + 3].clone(),
+            }
         }
+        _ => implicit_imm(&tokens[*i]),
     };
-    if do_eval {
-        if let Some(addr) = labels.get(lbl) {
-            Ok(Some(*addr))
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Parse Error: unresolved label",
-            ))
-        }
-    } else {
-        if let Some(_) = labels.insert(lbl.to_string(), addr) {
-            Err(ArchError::RepeatedLabel(lbl.to_string(), line, line_num as u16).into())
-        } else {
-            Ok(None)
-        }
+    *i += i_ofs;
+    items.push(Item::Ins(UnresolvedIns::new(op, ra, rb, imm)));
+}
+
+/// The 0 used for an immediate the source leaves out, attributed to the
+/// instruction's mnemonic.
+fn implicit_imm(op: &Spanned) -> Spanned {
+    Spanned {
+        tok: Token::Imm(0),
+        span: op.span,
     }
 }
 
@@ -264,70 +783,405 @@ fn read_file(file: File) -> io::Result<Vec<String>> {
     Ok(lines_vec)
 }
 
-fn tokenize(line: &str, linenum: u16) -> Vec<Token> {
-    let mut tokens: Vec<Token> = Vec::new();
-    for token in line.split_whitespace() {
-        tokens.push(Token::parse_str(token, linenum));
+/// Splits a preprocessed line into the tokens of each of its `|` separated
+/// statements, expanding aliases and taking note of `.alias` directives and
+/// of metadata.
+fn tokenize(
+    src: &SourceLine,
+    aliases: &mut Aliases,
+    metadata: &mut RomMetadata,
+    debug: &DebugInfo,
+) -> io::Result<Vec<Vec<Spanned>>> {
+    let mut statements: Vec<Vec<Spanned>> = Vec::new();
+    let words = words(&src.text);
+    for statement in words.split(|(_, word)| *word == STATEMENT_SEP) {
+        let spanned: Vec<Spanned> = statement
+            .iter()
+            .map(|&(start, word)| Spanned {
+                tok: Token::Imm(0),
+                span: Span {
+                    file: src.file,
+                    line: src.line,
+                    start,
+                    end: start + word.len(),
+                },
+            })
+            .collect();
+        match statement.first().map(|(_, word)| *word) {
+            Some(ALIAS) => {
+                aliases.define(statement, &spanned, debug)?;
+                continue;
+            }
+            Some(PORT) => {
+                aliases.define_port(statement, &spanned, debug)?;
+                continue;
+            }
+            Some(TITLE | AUTHOR | VERSION) => {
+                metadata_directive(statement, &spanned, metadata, debug)?;
+                continue;
+            }
+            _ => {}
+        }
+        let directive = statement.iter().position(|(_, word)| *word == STRING);
+        let end = directive.unwrap_or(statement.len());
+        let mut tokens: Vec<Spanned> = Vec::new();
+        for (&(_, word), Spanned { span, .. }) in statement[..end].iter().zip(&spanned) {
+            let span = *span;
+            let expanded = aliases
+                .expand(word)
+                .map_err(|msg| error_at(debug, span, msg))?;
+            tokens.extend(expanded.into_iter().map(|tok| Spanned { tok, span }));
+        }
+        if let Some(dir) = directive {
+            tokens.push(string_directive(&statement[dir..], &spanned[dir..], debug)?);
+        }
+        statements.push(tokens);
     }
-    tokens
+    Ok(statements)
+}
+
+/// How a `.string` directive lays its characters out in memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StringLayout {
+    /// One character per word followed by a 0 word.
+    Nul,
+    /// The number of characters followed by one character per word.
+    Len,
+    /// Two characters per word, the first in the high byte, ending with a 0
+    /// byte.
+    Packed,
+}
+
+impl StringLayout {
+    fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "nul" => Some(StringLayout::Nul),
+            "len" => Some(StringLayout::Len),
+            "packed" => Some(StringLayout::Packed),
+            _ => None,
+        }
+    }
+
+    fn encode(self, chars: &[u16]) -> Vec<u16> {
+        match self {
+            StringLayout::Nul => chars.iter().copied().chain([0]).collect(),
+            StringLayout::Len => [chars.len() as u16].iter().chain(chars).copied().collect(),
+            StringLayout::Packed => chars
+                .iter()
+                .copied()
+                .chain([0])
+                .collect::<Vec<u16>>()
+                .chunks(2)
+                .map(|pair| (pair[0] & 0xFF) << 8 | pair.get(1).map_or(0, |chr| chr & 0xFF))
+                .collect(),
+        }
+    }
+}
+
+/// Parses `.string [nul|len|packed] "text"` into the words it places in the
+/// program, padded with a 0 word to an even length so the instructions after
+/// it stay aligned.
+fn string_directive(
+    words: &[(usize, &str)],
+    spans: &[Spanned],
+    debug: &DebugInfo,
+) -> io::Result<Spanned> {
+    let usage = "Parse Error: expected .string [nul|len|packed] \"text\"";
+    let (layout, text) = match words {
+        [_, _] => (StringLayout::Nul, 1),
+        [_, (_, layout), _] => match StringLayout::from_str(layout) {
+            Some(layout) => (layout, 2),
+            None => return Err(error_at(debug, spans[1].span, usage)),
+        },
+        _ => return Err(error_at(debug, spans[0].span, usage)),
+    };
+    let chars = match parse_string(words[text].1) {
+        Some(chars) => chars,
+        None => return Err(error_at(debug, spans[text].span, usage)),
+    };
+    let mut data = layout.encode(&chars);
+    if data.len() % 2 == 1 {
+        data.push(0);
+    }
+    Ok(Spanned {
+        tok: Token::Data(data),
+        span: spans[0].span,
+    })
+}
+
+/// Parses `.title "text"`, `.author "text"` or `.version "text"` into
+/// `metadata`. Each may be given once, in any of the files assembled.
+fn metadata_directive(
+    words: &[(usize, &str)],
+    spans: &[Spanned],
+    metadata: &mut RomMetadata,
+    debug: &DebugInfo,
+) -> io::Result<()> {
+    let directive = words[0].1;
+    let usage = format!("Parse Error: expected {} \"text\"", directive);
+    let text = match words {
+        [_, (_, literal)] => match parse_string(literal) {
+            Some(chars) => chars
+                .iter()
+                .map(|&chr| chr as u8 as char)
+                .collect::<String>(),
+            None => return Err(error_at(debug, spans[1].span, usage)),
+        },
+        _ => return Err(error_at(debug, spans[0].span, usage)),
+    };
+    if text.contains('\n') {
+        return Err(error_at(
+            debug,
+            spans[1].span,
+            format!("Parse Error: {} must be a single line", directive),
+        ));
+    }
+    let field = match directive {
+        TITLE => &mut metadata.title,
+        AUTHOR => &mut metadata.author,
+        _ => &mut metadata.version,
+    };
+    if field.is_some() {
+        return Err(error_at(
+            debug,
+            spans[0].span,
+            format!("Parse Error: {} given twice", directive),
+        ));
+    }
+    *field = Some(text);
+    Ok(())
+}
+
+/// The characters of a double quoted string literal, with escapes replaced.
+fn parse_string(literal: &str) -> Option<Vec<u16>> {
+    let inner = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut chars = Vec::new();
+    let mut iter = inner.chars();
+    while let Some(chr) = iter.next() {
+        if chr == '\\' {
+            chars.push(escape_char(iter.next()?));
+        } else {
+            chars.push(chr as u8 as u16);
+        }
+    }
+    Some(chars)
+}
+
+/// The words of `line` with their byte offsets: string literals, which may
+/// contain spaces, and otherwise whitespace separated runs. Each statement
+/// separator is a word of its own, spaced or not.
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut pos = 0;
+    while let Some(skip) = line[pos..].find(|chr: char| !chr.is_whitespace()) {
+        pos += skip;
+        let rest = &line[pos..];
+        let len = if rest.starts_with('"') {
+            string_len(rest)
+        } else if let Some(len) = char_literal_len(rest) {
+            len
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        for word in split_statements(&rest[..len]) {
+            words.push((word.as_ptr() as usize - line.as_ptr() as usize, word));
+        }
+        pos += len;
+    }
+    words
+}
+
+/// The length of the string literal at the start of `text`, up to and
+/// including its closing quote, or all of `text` if it isn't closed.
+fn string_len(text: &str) -> usize {
+    let mut escaped = false;
+    for (i, chr) in text.char_indices().skip(1) {
+        match chr {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// The length of the character literal at the start of `text`, such as
+/// `'a'`, `' '` or `'\n'`, if there is one. Scanning a line skips over these
+/// whole, so the character in one never ends a word, statement or comment.
+fn char_literal_len(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    if chars.next()?.1 != '\'' {
+        return None;
+    }
+    if chars.next()?.1 == '\\' {
+        chars.next()?;
+    }
+    match chars.next()? {
+        (i, '\'') => Some(i + 1),
+        _ => None,
+    }
+}
+
+/// Splits the statement separators out of a word, leaving character
+/// literals such as `'|'` whole.
+fn split_statements(word: &str) -> Vec<&str> {
+    if word.starts_with('\'') || word.starts_with('"') {
+        return vec![word];
+    }
+    let mut parts = Vec::new();
+    for part in word.split_inclusive(STATEMENT_SEP) {
+        match part.strip_suffix(STATEMENT_SEP) {
+            Some(rest) => parts.extend([rest, &part[rest.len()..]]),
+            None => parts.push(part),
+        }
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
 }
 
 #[derive(Clone, Debug, PartialEq)]
 enum Token {
     Op(OpCode),
     Reg(RegMnem),
-    Label(String, u16),
+    Label(String),
     Imm(u16),
+    /// Words placed in the program as they are, from a `.string`.
+    Data(Vec<u16>),
+    /// `dw`, placing the words after it in the statement as data.
+    Dw,
 }
 
 impl Token {
-    fn parse_str(tok: &str, linenum: u16) -> Token {
+    /// Reads a word of source, failing with a message for a number that
+    /// doesn't fit in a word or a word that means nothing, which suggests
+    /// the nearest mnemonic, register or alias.
+    fn parse_str(tok: &str, aliases: &Aliases) -> Result<Token, String> {
         use Token::*;
-        if tok.starts_with('!') {
-            return Label(tok.to_string(), linenum);
-        }
-        if tok.starts_with(':') {
-            return Label(tok.to_string(), linenum);
+        if tok.starts_with('!') || tok.starts_with(':') {
+            return Ok(Label(tok.to_string()));
         }
         if tok.starts_with('\'') {
             if tok.len() == 3 {
                 if let Some(chr) = tok.chars().nth(1) {
-                    return Imm(chr as u8 as u16);
+                    return Ok(Imm(chr as u8 as u16));
                 }
             } else if tok.len() == 4 {
                 let mut chars = tok.chars();
                 if let Some(esc) = chars.nth(1) {
                     if esc == '\\' {
                         let chr = chars.next().unwrap();
-                        return Imm(escape_char(chr));
+                        return Ok(Imm(escape_char(chr)));
                     }
                 }
             }
         }
+        if tok.eq_ignore_ascii_case(DW) {
+            return Ok(Dw);
+        }
         if let Ok(op) = OpCode::from_str(tok) {
-            return Op(op);
+            return Ok(Op(op));
         }
         if let Ok(reg) = RegMnem::from_str(tok) {
-            return Reg(reg);
+            return Ok(Reg(reg));
         }
-        if let Ok(imm) = tok.parse::<u16>() {
-            return Imm(imm);
+        if let Some(imm) = parse_number(tok) {
+            return imm.map(Imm).ok_or_else(|| {
+                format!(
+                    "Number out of range: {} (must be from -32768 to 65535)",
+                    tok
+                )
+            });
         }
-        if let Ok(imm) = tok.parse::<i16>() {
-            return Imm(imm as u16);
+        // Hex without the 0x, as long as it can't be mistaken for a word
+        // such as `add` with a letter missing.
+        if tok.bytes().any(|b| b.is_ascii_digit()) {
+            if let Ok(imm) = u16::from_str_radix(tok, 16) {
+                return Ok(Imm(imm));
+            }
         }
-        if let Ok(imm) = u16::from_str_radix(tok.trim_start_matches("0x"), 16) {
-            return Imm(imm);
+        let mut msg = format!("Unknown word: {}", tok);
+        let names = known_words(aliases);
+        if let Some(near) = suggest(tok, names.iter().map(String::as_str)) {
+            msg.push_str(&format!(", did you mean {}?", near));
         }
-        Token::Imm(0)
+        Err(msg)
     }
 
-    fn is_imm(&self) -> bool {
-        match self {
-            Token::Imm(_) => true,
-            _ => false,
+    /// Whether the token stands for a word in an immediate position: a
+    /// number, or a label's address.
+    fn is_value(&self) -> bool {
+        matches!(self, Token::Imm(_) | Token::Label(_))
+    }
+}
+
+/// The mnemonics, registers and aliases an unknown word may have been meant
+/// as.
+fn known_words(aliases: &Aliases) -> Vec<String> {
+    let mut words: Vec<String> = (0..64)
+        .filter_map(|code| OpCode::from_int(code).ok())
+        .map(|op| op.mnemonic())
+        .collect();
+    words.push(DW.to_string());
+    words.extend(REG_NAMES.iter().map(|reg| reg.to_string()));
+    words.extend(aliases.words.keys().cloned());
+    words
+}
+
+/// The candidate closest to `word`, if any is close enough to be a likely
+/// typo: at most one edit away for short words, and a third of the length
+/// for longer ones. Case is ignored, since mnemonics and registers ignore it.
+fn suggest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let lower = word.to_ascii_lowercase();
+    let limit = (word.len() / 3).max(1);
+    candidates
+        .filter(|&cand| cand != word)
+        .map(|cand| (edit_distance(&lower, &cand.to_ascii_lowercase()), cand))
+        .filter(|&(dist, _)| dist <= limit)
+        .min()
+        .map(|(_, cand)| cand)
+}
+
+/// The Levenshtein distance between two strings, counting bytes.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.as_bytes();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.bytes().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let next = (row[j + 1] + 1)
+                .min(row[j] + 1)
+                .min(diag + usize::from(ca != cb));
+            diag = row[j + 1];
+            row[j + 1] = next;
         }
     }
+    row[b.len()]
+}
+
+/// The value of a decimal number, optionally signed, or of a `0x` hex
+/// number. `Some(None)` is a number that doesn't fit in a word: decimals run
+/// from -32768 to 65535, so a word can be written as signed or unsigned, and
+/// hex numbers up to 0xFFFF.
+fn parse_number(tok: &str) -> Option<Option<u16>> {
+    if let Some(hex) = tok.strip_prefix("0x") {
+        if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        return Some(u16::from_str_radix(hex, 16).ok());
+    }
+    let digits = tok.strip_prefix(['-', '+']).unwrap_or(tok);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // Too many digits for an i64 is out of range too.
+    let val = tok.parse::<i64>().unwrap_or(i64::MAX);
+    if (-32768..=0xFFFF).contains(&val) {
+        Some(Some(val as u16))
+    } else {
+        Some(None)
+    }
 }
 
 fn escape_char(chr: char) -> u16 {
@@ -344,3 +1198,122 @@ fn escape_char(chr: char) -> u16 {
         _ => 0,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selftest::run_source;
+
+    /// The words `src` assembles to.
+    fn program(src: &str) -> Vec<u16> {
+        assemble_str("test.tat", src).unwrap().0.program().to_vec()
+    }
+
+    fn error(src: &str) -> String {
+        assemble_str("test.tat", src).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn errors_give_line_and_column() {
+        let src = "set ax, 1\nset bx, 2\n    frob ax\n";
+        assert_eq!(error(src), "test.tat:3:5: Unknown word: frob");
+        assert_eq!(
+            error("set ax, 1 | set bx, 99999"),
+            "test.tat:1:21: Number out of range: 99999 (must be from -32768 to 65535)"
+        );
+    }
+
+    #[test]
+    fn unknown_words_suggest_the_nearest() {
+        assert_eq!(
+            error("ad ax, 1"),
+            "test.tat:1:1: Unknown word: ad, did you mean add?"
+        );
+    }
+
+    #[test]
+    fn separators_in_literals() {
+        assert_eq!(
+            words("set ax, '|'|stor OUT, ax"),
+            [
+                (0, "set"),
+                (4, "ax,"),
+                (8, "'|'"),
+                (11, "|"),
+                (12, "stor"),
+                (17, "OUT,"),
+                (22, "ax"),
+            ]
+        );
+        assert_eq!(words(".string \"a|b\""), [(0, ".string"), (8, "\"a|b\"")]);
+        let src = "
+            set ax, !text | set cx, '|' | set dx, 0
+            !loop pload bx, ax | je bx, dx, !done
+            stor OUT, bx | stor OUT, cx | inc ax | jmp !loop
+            !done set ax, 0 | stor END, ax
+            !text .string \"a|b\"";
+        assert_eq!(run_source(src, ""), Ok((0, "a|||b|".to_string())));
+    }
+
+    #[test]
+    fn block_comments() {
+        // Comments don't nest: the first `*/` closes one.
+        assert_eq!(
+            program("set ax, 1 /* open\n /* inner */ set bx, 2"),
+            program("set ax, 1\nset bx, 2")
+        );
+        assert_eq!(
+            program("set ax, '/' /* ; \"*/ | set bx, 2 ; /* not a comment"),
+            program("set ax, '/' | set bx, 2")
+        );
+        assert_eq!(
+            error("set ax, 1\n/* never\nclosed\n"),
+            "test.tat:2: Unterminated block comment"
+        );
+        // Block comments leave columns where they were.
+        assert_eq!(error("/* a */ frob"), "test.tat:1:9: Unknown word: frob");
+    }
+
+    #[test]
+    fn aliases_replace_whole_words() {
+        let src = "
+            .alias x ax
+            .alias five 5
+            set x, 0x5 | set bx, five | add x, bx
+            jmp !xit
+            set x, 1
+            !xit stor END, x";
+        assert_eq!(run_source(src, ""), Ok((10, String::new())));
+        assert_eq!(
+            error(".alias x ax\n.alias x bx"),
+            "test.tat:2:8: Parse Error: x is already an alias"
+        );
+        assert_eq!(
+            error(".alias add ax"),
+            "test.tat:1:8: Parse Error: add already means something else"
+        );
+    }
+
+    #[test]
+    fn ports() {
+        assert_eq!(
+            program(".port LED 0x9000\nstor LED, ax"),
+            program("stor 0x9000, ax")
+        );
+        assert_eq!(
+            error(".port LED 0x8000"),
+            "test.tat:1:7: Parse Error: 0x8000 is already the port OUT"
+        );
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(program("dw 1, 'a', !end | !end"), [1, 'a' as u16, 3]);
+        assert_eq!(program(".string \"ab\""), ['a' as u16, 'b' as u16, 0, 0]);
+        assert_eq!(
+            program(".string len \"ab\""),
+            [2, 'a' as u16, 'b' as u16, 0]
+        );
+        assert_eq!(program(".string packed \"abc\""), [0x6162, 0x6300]);
+    }
+}
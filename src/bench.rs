@@ -0,0 +1,78 @@
+//! Workloads built into `teenyat bench`, for measuring how fast the VM
+//! executes instructions.
+//!
+//! Each workload is one of the programs in `bench/`, which repeat their work
+//! forever. Running one for a fixed time and counting the instructions
+//! executed gives a rate that can be compared from build to build, so a
+//! change that slows down dispatch shows up.
+
+use crate::assembler;
+use crate::vm::{StopReason, TeenyAT};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How many instructions run between looks at the time.
+const SLICE: u64 = 10_000;
+
+pub struct Workload {
+    pub name: &'static str,
+    pub source: &'static str,
+}
+
+pub const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "loop",
+        source: include_str!("../bench/loop.tat"),
+    },
+    Workload {
+        name: "memcpy",
+        source: include_str!("../bench/memcpy.tat"),
+    },
+    Workload {
+        name: "sieve",
+        source: include_str!("../bench/sieve.tat"),
+    },
+];
+
+/// How much of a workload ran in how long.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub elapsed: Duration,
+}
+
+impl Sample {
+    /// Millions of instructions executed per second.
+    pub fn mips(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64() / 1e6
+    }
+}
+
+/// Runs `workload` for about `duration`, with nothing attached but the
+/// console, which the workloads don't use.
+pub fn run(workload: &Workload, duration: Duration) -> io::Result<Sample> {
+    let (mem, _) = assembler::assemble_str(workload.name, workload.source)?;
+    let mut vm = TeenyAT::new(mem);
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let run = vm.run_for(SLICE);
+        match run.reason {
+            StopReason::OutOfSteps => {}
+            StopReason::Fault(err) => {
+                return Err(io::Error::other(format!(
+                    "{} at 0x{:04x}",
+                    err.to_string().trim_end(),
+                    vm.ins_addr()
+                )))
+            }
+            reason => return Err(io::Error::other(format!("stopped early: {:?}", reason))),
+        }
+    }
+    let stats = vm.stats();
+    Ok(Sample {
+        instructions: stats.instructions,
+        cycles: stats.cycles,
+        elapsed: start.elapsed(),
+    })
+}
@@ -0,0 +1,134 @@
+//! Translates Brainfuck into TeenyAT assembly.
+//!
+//! The tape starts at `TAPE_LABEL`, just after the program, so a long
+//! program can't run into it, and its pointer lives in `ex`. Cells hold
+//! values from 0 to 255 and wrap in both directions. `.` and `,` use the
+//! character output and input ports. Runs of `+`, `-`, `<` and `>` are folded
+//! into a single addition.
+
+use std::io;
+
+const TAPE_LABEL: &str = "!bf_tape";
+
+pub fn compile(name: &str, src: &str) -> io::Result<String> {
+    let mut out = vec![
+        format!("; translated from {} by teenyat bf", name),
+        format!("    set ex, {}", TAPE_LABEL),
+    ];
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut loops = 0;
+    let cmds: Vec<(usize, char)> = src
+        .lines()
+        .enumerate()
+        .flat_map(|(linenum, line)| line.chars().map(move |c| (linenum + 1, c)))
+        .filter(|(_, c)| "+-<>.,[]".contains(*c))
+        .collect();
+    let mut i = 0;
+    while i < cmds.len() {
+        let (line, cmd) = cmds[i];
+        let mut run = 1;
+        if "+-<>".contains(cmd) {
+            while i + run < cmds.len() && cmds[i + run].1 == cmd {
+                run += 1;
+            }
+        }
+        i += run;
+        let ins: Vec<String> = match cmd {
+            '+' | '-' => {
+                let delta = if cmd == '+' {
+                    run % 256
+                } else {
+                    256 - run % 256
+                };
+                vec![
+                    "pload ax, ex".to_string(),
+                    format!("set bx, {}", delta),
+                    "add ax, bx".to_string(),
+                    "set bx, 255".to_string(),
+                    "and ax, bx".to_string(),
+                    "pstor ex, ax".to_string(),
+                ]
+            }
+            '>' => vec![format!("set bx, {}", run), "add ex, bx".to_string()],
+            '<' => vec![format!("set bx, {}", run), "sub ex, bx".to_string()],
+            '.' => vec!["pload ax, ex".to_string(), "stor OUT, ax".to_string()],
+            ',' => vec!["load ax, IN".to_string(), "pstor ex, ax".to_string()],
+            '[' => {
+                loops += 1;
+                open.push((loops, line));
+                out.push(format!("!bf_{}_top", loops));
+                vec![
+                    "pload ax, ex".to_string(),
+                    "set bx, 0".to_string(),
+                    format!("je  ax, bx, !bf_{}_done", loops),
+                ]
+            }
+            _ => {
+                let (index, _) = open.pop().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Brainfuck error on line {}: unmatched `]`", line),
+                    )
+                })?;
+                out.push(format!("    je  pc, pc, !bf_{}_top", index));
+                out.push(format!("!bf_{}_done", index));
+                Vec::new()
+            }
+        };
+        out.extend(ins.iter().map(|ins| format!("    {}", ins)));
+    }
+    if let Some((_, line)) = open.pop() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Brainfuck error on line {}: unmatched `[`", line),
+        ));
+    }
+    out.push("    set ax, 0".to_string());
+    out.push("    stor END, ax".to_string());
+    out.push(TAPE_LABEL.to_string());
+    let mut asm = out.join("\n");
+    asm.push('\n');
+    Ok(asm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selftest::run_source;
+
+    /// Translates and runs `src` with `input`, returning its output.
+    fn run(src: &str, input: &str) -> String {
+        let (code, output) = run_source(&compile("test.bf", src).unwrap(), input).unwrap();
+        assert_eq!(code, 0);
+        output
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(run("++++++++[>++++++++<-]>+.+.", ""), "AB");
+    }
+
+    #[test]
+    fn cells_wrap() {
+        assert_eq!(run(&format!("-{}.", "+".repeat(66)), ""), "A");
+        assert_eq!(run(&format!("{}.", "+".repeat(321)), ""), "A");
+    }
+
+    #[test]
+    fn input() {
+        assert_eq!(run(",+.>,+.", "ab"), "bc");
+    }
+
+    #[test]
+    fn tape_after_long_program() {
+        assert_eq!(run(&"+.>".repeat(1400), ""), "\u{1}".repeat(1400));
+    }
+
+    #[test]
+    fn unmatched_brackets() {
+        let err = compile("test.bf", "+\n]").unwrap_err().to_string();
+        assert!(err.contains("line 2: unmatched `]`"));
+        let err = compile("test.bf", "[[]").unwrap_err().to_string();
+        assert!(err.contains("line 1: unmatched `[`"));
+    }
+}
@@ -0,0 +1,514 @@
+//! `teenyat-gui`, a window showing a running program's registers,
+//! disassembly, memory, stack, console and display devices, with buttons to run, pause and
+//! step it. Built with `--features gui`.
+
+use eframe::egui;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+use std::process;
+use teenyat::arch::{ArchError, Instruction, Memory, RomOptions, REG_NAMES};
+use teenyat::config::MachineConfig;
+use teenyat::debugger;
+use teenyat::debuginfo::DebugInfo;
+use teenyat::devices::{self, EventQueue, InputEvent};
+use teenyat::feed::{InputFeed, SharedBuf};
+use teenyat::{assembler, vm::TeenyAT};
+
+const USAGE: &str = "usage: teenyat-gui <file.tat|file.rom> [--config machine.toml]";
+/// How many instructions to run between redraws while running.
+const STEPS_PER_FRAME: usize = 20_000;
+/// Instructions shown before and after pc in the disassembly.
+const LIST_BEFORE: u16 = 8;
+const LIST_AFTER: u16 = 24;
+const MEM_ROWS: usize = 32;
+const STACK_WORDS: u16 = 32;
+/// How many screen pixels each pixel of a display device takes up.
+const DISPLAY_SCALE: f32 = 2.0;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| usage_error(USAGE));
+    let mut machine = MachineConfig::default();
+    while let Some(opt) = args.next() {
+        match (opt.as_str(), args.next()) {
+            ("--config", Some(file)) => {
+                machine = MachineConfig::load(&file).unwrap_or_else(|err| fail(&err))
+            }
+            _ => usage_error(USAGE),
+        }
+    }
+    let app = App::load(path, machine).unwrap_or_else(|err| fail(&err));
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([1100.0, 720.0]),
+        ..Default::default()
+    };
+    let title = format!("teenyat - {}", app.path);
+    if let Err(err) = eframe::run_native(&title, options, Box::new(|_| Ok(Box::new(app)))) {
+        fail(&err);
+    }
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(2);
+}
+
+fn fail(err: &dyn std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1);
+}
+
+/// Loads a `.tat` file by assembling it, or a `.rom` with the labels from the
+/// symbol file beside it, if there is one.
+fn load(path: &str) -> io::Result<(Memory, DebugInfo)> {
+    if path.ends_with(".tat") {
+        return assembler::assemble_file(path);
+    }
+    let memory = Memory::from_rom_file_with(path, RomOptions::default())?;
+    let sym = format!(
+        "{}.sym",
+        path.rsplit_once('.').map_or(path, |(stem, _)| stem)
+    );
+    let debug = if Path::new(&sym).exists() {
+        DebugInfo::load_symbols(&sym)?
+    } else {
+        DebugInfo::default()
+    };
+    Ok((memory, debug))
+}
+
+#[derive(PartialEq)]
+enum State {
+    Paused,
+    Running,
+    /// Running, but the console is waiting for a line of input.
+    Waiting,
+    Exited(u16),
+    Faulted(String),
+}
+
+struct App {
+    path: String,
+    machine: MachineConfig,
+    vm: TeenyAT,
+    debug: DebugInfo,
+    feed: InputFeed,
+    output: SharedBuf,
+    state: State,
+    breakpoints: BTreeSet<u16>,
+    /// What's typed in the console's input line.
+    input: String,
+    /// What's typed in the memory panel's address box.
+    mem_addr: String,
+    mem_start: u16,
+    /// The textures showing display devices, by base address.
+    screens: BTreeMap<u16, egui::TextureHandle>,
+    /// The display pixel last reported to event devices as under the mouse.
+    pointer: Option<(u16, u16)>,
+    /// The modifier keys last reported to event devices as held down.
+    modifiers: egui::Modifiers,
+}
+
+impl App {
+    fn load(path: String, machine: MachineConfig) -> io::Result<Self> {
+        let (memory, debug) = load(&path)?;
+        let feed = InputFeed::new();
+        let output = SharedBuf::default();
+        let mut vm = TeenyAT::with_config(memory, &machine)?;
+        vm.set_io(Box::new(feed.reader()), Box::new(output.clone()));
+        Ok(Self {
+            path,
+            machine,
+            vm,
+            debug,
+            feed,
+            output,
+            state: State::Paused,
+            breakpoints: BTreeSet::new(),
+            input: String::new(),
+            mem_addr: String::new(),
+            mem_start: 0,
+            screens: BTreeMap::new(),
+            pointer: None,
+            modifiers: egui::Modifiers::NONE,
+        })
+    }
+
+    /// Starts the program again from the beginning, keeping the breakpoints.
+    fn reset(&mut self) {
+        match Self::load(self.path.clone(), self.machine.clone()) {
+            Ok(fresh) => {
+                let breakpoints = std::mem::take(&mut self.breakpoints);
+                *self = fresh;
+                self.breakpoints = breakpoints;
+            }
+            Err(err) => self.state = State::Faulted(err.to_string()),
+        }
+    }
+
+    /// Executes one instruction, updating the state if the program stopped.
+    fn step(&mut self) -> bool {
+        match self.vm.step() {
+            Ok(()) => match self.vm.exit_code() {
+                Some(code) => {
+                    self.state = State::Exited(code);
+                    false
+                }
+                None => true,
+            },
+            Err(ArchError::WouldBlock) => {
+                self.state = State::Waiting;
+                false
+            }
+            Err(err) => {
+                let addr = debugger::describe(&self.debug, self.vm.ins_addr());
+                self.state = State::Faulted(format!("{} at {}", err.to_string().trim_end(), addr));
+                false
+            }
+        }
+    }
+
+    /// Runs a frame's worth of instructions, stopping at breakpoints.
+    fn run_slice(&mut self) {
+        self.state = State::Running;
+        for _ in 0..STEPS_PER_FRAME {
+            if !self.step() {
+                return;
+            }
+            if self.breakpoints.contains(&self.vm.pc()) {
+                self.state = State::Paused;
+                return;
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        match &self.state {
+            State::Paused => "Paused".to_string(),
+            State::Running => "Running".to_string(),
+            State::Waiting => "Waiting for input".to_string(),
+            State::Exited(code) => format!("Exited with code {}", code),
+            State::Faulted(msg) => format!("Fault: {}", msg),
+        }
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self.state, State::Exited(_) | State::Faulted(_))
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let running = matches!(self.state, State::Running | State::Waiting);
+            let finished = self.finished();
+            if running {
+                if ui.button("Pause").clicked() {
+                    self.state = State::Paused;
+                }
+            } else if ui
+                .add_enabled(!finished, egui::Button::new("Run"))
+                .clicked()
+            {
+                self.state = State::Running;
+            }
+            if ui
+                .add_enabled(!running && !finished, egui::Button::new("Step"))
+                .clicked()
+                && self.step()
+            {
+                self.state = State::Paused;
+            }
+            if ui.button("Reset").clicked() {
+                self.reset();
+            }
+            ui.separator();
+            ui.label(self.status());
+            ui.separator();
+            ui.label(format!("{} instructions", self.vm.stats().instructions));
+        });
+    }
+
+    fn registers(&self, ui: &mut egui::Ui) {
+        ui.heading("Registers");
+        egui::Grid::new("registers").show(ui, |ui| {
+            for (name, reg) in REG_NAMES.iter().zip(self.vm.registers().iter()) {
+                ui.monospace(*name);
+                ui.monospace(format!("0x{:04x}", reg.val));
+                ui.monospace(format!("{}", reg.val as i16));
+                ui.end_row();
+            }
+        });
+        ui.separator();
+        ui.heading("Stack");
+        let sp = self.vm.registers()[7].val;
+        let top = (self.vm.memory().size() as u16).min(sp.saturating_add(STACK_WORDS));
+        if sp >= top {
+            ui.label("(empty)");
+        }
+        for addr in sp..top {
+            if let Ok(val) = self.vm.read_mem(addr) {
+                ui.monospace(format!("0x{:04x}  0x{:04x}", addr, val));
+            }
+        }
+    }
+
+    /// The instructions around pc. Clicking one toggles a breakpoint on it.
+    fn disassembly(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Program");
+        let pc = self.vm.pc();
+        let start = pc.saturating_sub(LIST_BEFORE * 2);
+        egui::ScrollArea::vertical()
+            .id_salt("program")
+            .show(ui, |ui| {
+                let mut addr = start;
+                for _ in 0..LIST_BEFORE + LIST_AFTER {
+                    if let Some((lbl, 0)) = self.debug.label_before(addr) {
+                        ui.monospace(lbl);
+                    }
+                    let words = (
+                        self.vm.read_mem(addr),
+                        self.vm.read_mem(addr.wrapping_add(1)),
+                    );
+                    let (text, len) = match words {
+                        (Ok(word), _) if self.debug.is_data(addr) => {
+                            (format!("dw 0x{:04x}", word), 1)
+                        }
+                        (Ok(op), Ok(imm)) => (
+                            Instruction::new(op, imm)
+                                .disassemble()
+                                .unwrap_or_else(|| format!("0x{:04x} 0x{:04x}", op, imm)),
+                            2,
+                        ),
+                        _ => break,
+                    };
+                    let marker = match (addr == pc, self.breakpoints.contains(&addr)) {
+                        (true, true) => "*>",
+                        (true, false) => "=>",
+                        (false, true) => "* ",
+                        (false, false) => "  ",
+                    };
+                    let line = match self.debug.location(addr) {
+                        Some((file, line)) => format!("  ; {}:{}", file, line),
+                        None => String::new(),
+                    };
+                    let label =
+                        egui::RichText::new(format!("{} 0x{:04x}  {}{}", marker, addr, text, line))
+                            .monospace();
+                    if ui.selectable_label(addr == pc, label).clicked()
+                        && !self.breakpoints.remove(&addr)
+                    {
+                        self.breakpoints.insert(addr);
+                    }
+                    addr = addr.wrapping_add(len);
+                }
+            });
+    }
+
+    fn memory(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Memory");
+        ui.horizontal(|ui| {
+            ui.label("Address");
+            let response = ui.text_edit_singleline(&mut self.mem_addr);
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                let text = self.mem_addr.trim();
+                let parsed = match text.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16).ok(),
+                    None => text.parse().ok(),
+                };
+                if let Some(addr) = parsed.or_else(|| self.debug.label_addr(text)) {
+                    self.mem_start = addr;
+                }
+            }
+        });
+        let dump = self.vm.memory().hexdump(self.mem_start & !7, MEM_ROWS * 8);
+        egui::ScrollArea::both().id_salt("memory").show(ui, |ui| {
+            ui.monospace(dump);
+        });
+    }
+
+    /// What each device with a picture, such as a sprite display, shows.
+    /// Returns the display pixel under the mouse, if it's over one.
+    fn displays(&mut self, ui: &mut egui::Ui) -> Option<(u16, u16)> {
+        let mut pointer = None;
+        for (base, device) in self.vm.devices() {
+            let frame = match device.frame() {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let rgb: Vec<u8> = frame
+                .pixels
+                .iter()
+                .flat_map(|pixel| pixel.to_be_bytes()[1..].to_vec())
+                .collect();
+            let image = egui::ColorImage::from_rgb([frame.width, frame.height], &rgb);
+            let texture = self.screens.entry(base).or_insert_with(|| {
+                ui.ctx().load_texture(
+                    format!("display 0x{:04x}", base),
+                    image.clone(),
+                    egui::TextureOptions::NEAREST,
+                )
+            });
+            texture.set(image, egui::TextureOptions::NEAREST);
+            ui.label(format!("Display at 0x{:04x}", base));
+            let size = egui::vec2(frame.width as f32, frame.height as f32) * DISPLAY_SCALE;
+            let response = ui.add(
+                egui::Image::from_texture(&*texture)
+                    .fit_to_exact_size(size)
+                    .sense(egui::Sense::click()),
+            );
+            if let Some(pos) = response.hover_pos() {
+                let pixel = (pos - response.rect.min) / DISPLAY_SCALE;
+                pointer = Some((pixel.x as u16, pixel.y as u16));
+            }
+        }
+        pointer
+    }
+
+    /// Passes keys pressed while no text box has focus, and mouse movement
+    /// and clicks over a display, to any event devices.
+    fn send_input(&mut self, ctx: &egui::Context, pointer: Option<(u16, u16)>) {
+        let queues: Vec<EventQueue> = self
+            .vm
+            .devices()
+            .filter_map(|(_, device)| device.event_queue())
+            .collect();
+        if queues.is_empty() {
+            return;
+        }
+        let mut events = Vec::new();
+        let typing = ctx.wants_keyboard_input();
+        ctx.input(|input| {
+            for event in input.events.iter() {
+                if let egui::Event::Key {
+                    key,
+                    pressed,
+                    repeat: false,
+                    ..
+                } = event
+                {
+                    match key_code(*key) {
+                        Some(code) if !typing && *pressed => events.push(InputEvent::KeyDown(code)),
+                        Some(code) if !typing => events.push(InputEvent::KeyUp(code)),
+                        _ => {}
+                    }
+                }
+            }
+            // egui reports modifiers as state rather than as key events. Keys
+            // held while typing into a text box count as released.
+            let now = if typing {
+                egui::Modifiers::NONE
+            } else {
+                input.modifiers
+            };
+            let modifiers = [
+                (now.shift, self.modifiers.shift, devices::KEY_SHIFT),
+                (now.ctrl, self.modifiers.ctrl, devices::KEY_CTRL),
+                (now.alt, self.modifiers.alt, devices::KEY_ALT),
+            ];
+            for (down, was_down, code) in modifiers {
+                match (down, was_down) {
+                    (true, false) => events.push(InputEvent::KeyDown(code)),
+                    (false, true) => events.push(InputEvent::KeyUp(code)),
+                    _ => {}
+                }
+            }
+            self.modifiers = now;
+            let (x, y) = match pointer {
+                Some(at) => at,
+                None => return,
+            };
+            if self.pointer != pointer {
+                events.push(InputEvent::MouseMove { x, y });
+            }
+            let buttons = [
+                (egui::PointerButton::Primary, 1),
+                (egui::PointerButton::Secondary, 2),
+                (egui::PointerButton::Middle, 3),
+            ];
+            for (button, num) in buttons {
+                if input.pointer.button_pressed(button) {
+                    events.push(InputEvent::MouseDown { button: num, x, y });
+                }
+                if input.pointer.button_released(button) {
+                    events.push(InputEvent::MouseUp { button: num, x, y });
+                }
+            }
+        });
+        self.pointer = pointer;
+        for queue in queues.iter() {
+            for event in events.iter() {
+                queue.push(*event);
+            }
+        }
+    }
+
+    fn console(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Console");
+        let text = self.output.text();
+        egui::ScrollArea::vertical()
+            .id_salt("console")
+            .max_height(140.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                ui.add(
+                    egui::Label::new(egui::RichText::new(text).monospace())
+                        .wrap_mode(egui::TextWrapMode::Extend),
+                );
+            });
+        ui.horizontal(|ui| {
+            let response = ui.text_edit_singleline(&mut self.input);
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Send").clicked() || entered {
+                self.feed.push(format!("{}\n", self.input).as_bytes());
+                self.input.clear();
+                if self.state == State::Waiting {
+                    self.state = State::Running;
+                }
+            }
+        });
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.state == State::Running {
+            self.run_slice();
+            ctx.request_repaint();
+        }
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| self.controls(ui));
+        egui::TopBottomPanel::bottom("console").show(ctx, |ui| self.console(ui));
+        egui::SidePanel::left("registers").show(ctx, |ui| self.registers(ui));
+        egui::SidePanel::right("memory")
+            .min_width(420.0)
+            .show(ctx, |ui| self.memory(ui));
+        let pointer = egui::CentralPanel::default()
+            .show(ctx, |ui| {
+                let pointer = self.displays(ui);
+                self.disassembly(ui);
+                pointer
+            })
+            .inner;
+        self.send_input(ctx, pointer);
+    }
+}
+
+/// The code an event device reports for a key: its character in lower case,
+/// or one of the `devices::KEY_` codes. Modifier keys are reported apart.
+fn key_code(key: egui::Key) -> Option<u16> {
+    use egui::Key;
+    Some(match key {
+        Key::ArrowLeft => devices::KEY_LEFT,
+        Key::ArrowUp => devices::KEY_UP,
+        Key::ArrowRight => devices::KEY_RIGHT,
+        Key::ArrowDown => devices::KEY_DOWN,
+        Key::Backspace => 8,
+        Key::Tab => 9,
+        Key::Enter => 13,
+        Key::Escape => 27,
+        Key::Space => 32,
+        _ => match key.name().as_bytes() {
+            [chr] if chr.is_ascii_graphic() => chr.to_ascii_lowercase() as u16,
+            _ => return None,
+        },
+    })
+}
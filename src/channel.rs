@@ -0,0 +1,91 @@
+//! Character I/O and device events carried over channels.
+//!
+//! `TeenyAT::connect_channels` wires a VM to a `HostLink`, after which the VM
+//! can be moved to a worker thread while the thread holding the link feeds it
+//! input, collects its output and watches its device traffic.
+
+use crate::vm::PortAccess;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+/// The host's side of a VM connected with `TeenyAT::connect_channels`.
+pub struct HostLink {
+    /// Bytes for the console to read. Dropping it gives the guest end of file.
+    pub input: Sender<u8>,
+    /// Bytes the guest writes to the console.
+    pub output: Receiver<u8>,
+    /// Every load or store that reaches a device, in order.
+    pub events: Receiver<PortAccess>,
+}
+
+/// Console input read from a channel. Reads wait for a byte to arrive, and
+/// see end of file once the sender is dropped.
+pub struct ChannelReader {
+    rx: Receiver<u8>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    pub fn new(rx: Receiver<u8>) -> Self {
+        Self {
+            rx,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(out.len());
+        out[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for ChannelReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+            match self.rx.recv() {
+                Ok(byte) => self.buf.push(byte),
+                Err(_) => return Ok(&[]),
+            }
+            self.buf.extend(self.rx.try_iter());
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+/// Console output sent down a channel a byte at a time. Output is dropped once
+/// the receiving end has gone away.
+pub struct ChannelWriter {
+    tx: Sender<u8>,
+}
+
+impl ChannelWriter {
+    pub fn new(tx: Sender<u8>) -> Self {
+        Self { tx }
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            let _ = self.tx.send(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! The time devices see.
+//!
+//! Timers, the `rtc` device and sleeping go by a `Clock` rather than the
+//! host's. By default the clock is virtual: it advances with the cycles the
+//! program executes (see `OpCode::cycles`) and jumps ahead when the program
+//! sleeps, so a run does the same thing however fast the host is and a
+//! transcript replays exactly. A real clock follows the host's time instead,
+//! for programs that should keep pace with the world outside.
+
+use serde::Deserialize;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The cycles per second a clock counts unless the machine's `cycle_hz` says
+/// otherwise.
+pub const DEFAULT_HZ: u64 = 1_000_000;
+
+/// What a `Clock` follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockMode {
+    /// The cycles executed, plus any time slept.
+    #[default]
+    Virtual,
+    /// The host's clock.
+    Real,
+}
+
+impl ClockMode {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "virtual" => Some(ClockMode::Virtual),
+            "real" => Some(ClockMode::Real),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    mode: ClockMode,
+    hz: u64,
+    /// The cycles the CPU had executed when the VM last said.
+    executed: u64,
+    /// The cycles skipped by sleeping on a virtual clock.
+    slept: u64,
+    start: Instant,
+}
+
+impl Clock {
+    /// A clock counting `hz` cycles per second, or `DEFAULT_HZ` if `hz` is
+    /// 0, starting from 0 now.
+    pub fn new(mode: ClockMode, hz: u64) -> Self {
+        Self {
+            mode,
+            hz: if hz == 0 { DEFAULT_HZ } else { hz },
+            executed: 0,
+            slept: 0,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn mode(&self) -> ClockMode {
+        self.mode
+    }
+
+    /// The time since the clock started, in cycles.
+    pub fn cycles(&self) -> u64 {
+        match self.mode {
+            ClockMode::Virtual => self.executed + self.slept,
+            ClockMode::Real => (self.start.elapsed().as_secs_f64() * self.hz as f64) as u64,
+        }
+    }
+
+    /// The time since the clock started, in microseconds.
+    pub fn micros(&self) -> u64 {
+        (self.cycles() as u128 * 1_000_000 / self.hz as u128) as u64
+    }
+
+    /// Passes `micros` microseconds: at once on a virtual clock, by blocking
+    /// the thread on a real one.
+    pub fn sleep(&mut self, micros: u64) {
+        match self.mode {
+            ClockMode::Virtual => {
+                self.slept += (micros as u128 * self.hz as u128 / 1_000_000) as u64;
+            }
+            ClockMode::Real => thread::sleep(Duration::from_micros(micros)),
+        }
+    }
+
+    /// The cycles skipped by sleeping, which a VM pacing itself to the host's
+    /// clock still has to wait out.
+    pub(crate) fn slept(&self) -> u64 {
+        self.slept
+    }
+
+    /// Tells the clock how many cycles the CPU has executed.
+    pub(crate) fn set_executed(&mut self, cycles: u64) {
+        self.executed = cycles;
+    }
+}
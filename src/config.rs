@@ -0,0 +1,255 @@
+//! Machine configuration files.
+//!
+//! A config describes the machine a program runs on, for example:
+//!
+//! ```toml
+//! memory_size = 16384   # words of RAM
+//! clock_hz = 1000       # instructions per second, 0 runs flat out
+//! cycle_hz = 0          # cycles per second; if set, replaces clock_hz
+//! clock = "virtual"     # or "real"; what timers go by, see clock
+//! halt_address = 0xFFFF
+//! overflow = "wrap"     # or "saturate" or "trap"; see vm::Overflow
+//! vector_table = 0x7000 # interrupt handler addresses; see TeenyAT::raise
+//! seed = 42             # for repeatable random numbers; omit to use the clock
+//! poison = 0xDEAD       # fill memory after the program with this, not 0
+//! shadow_stack = false  # check each ret against the calls; see Cpu::with_shadow_stack
+//! trap_misaligned = false # stop on a jump to an odd address; see Cpu::with_misaligned_trap
+//!
+//! [wait_states]         # extra cycles per memory access; see vm::WaitStates
+//! rom_end = 0x1000      # addresses below this are ROM
+//! rom = 1
+//! ram = 0
+//! device = 4
+//!
+//! [[device]]
+//! kind = "console"
+//! address = 0x8000
+//! shared = false        # one device for every VM `teenyat multi` runs
+//! ```
+//!
+//! Leaving out the `[[device]]` tables attaches the console at 0x8000.
+//! `[[plugin]]` tables load devices from shared libraries; see `plugin`.
+
+use crate::arch::MEM_SIZE;
+use crate::clock::ClockMode;
+use crate::devices::{self, Device, Shared};
+#[cfg(not(target_os = "wasi"))]
+use crate::plugin::PluginDevice;
+use crate::vm::{Overflow, WaitStates, VECTOR_TABLE};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use toml::value::Table;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineConfig {
+    pub memory_size: u32,
+    pub clock_hz: u64,
+    /// Paces execution by the estimated cycles of each instruction (see
+    /// `OpCode::cycles`) rather than by instruction count.
+    pub cycle_hz: u64,
+    /// What timers, sleeping and the `rtc` device go by.
+    pub clock: ClockMode,
+    pub halt_address: u16,
+    pub overflow: Overflow,
+    pub wait_states: WaitStates,
+    pub vector_table: u16,
+    /// Seeds the random number generators, the `int 2` service and any
+    /// `rng` device without a seed of its own, so that runs are repeatable.
+    /// Without it they are seeded from the clock.
+    pub seed: Option<u64>,
+    /// Fills memory after the program with this word instead of zeros and
+    /// warns about reads of words nothing has stored to; see
+    /// `Memory::poison`.
+    pub poison: Option<u16>,
+    /// Stops the program when a return doesn't match the call it should
+    /// return from; see `Cpu::with_shadow_stack`.
+    pub shadow_stack: bool,
+    /// Stops the program when it jumps to an odd address; see
+    /// `Cpu::with_misaligned_trap`.
+    pub trap_misaligned: bool,
+    #[serde(rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+    #[serde(rename = "plugin")]
+    pub plugins: Vec<PluginConfig>,
+}
+
+/// Devices paired with their base addresses, ready to attach to a VM.
+pub type DeviceSet = Vec<(u16, Box<dyn Device>)>;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceConfig {
+    pub kind: String,
+    pub address: u16,
+    /// When several VMs are built from the config, attach the one device to
+    /// all of them; see `build_device_sets`.
+    #[serde(default)]
+    pub shared: bool,
+    /// Settings particular to the kind of device.
+    #[serde(flatten)]
+    pub options: Table,
+}
+
+/// A device loaded from a shared library, mapped where it asks to be unless
+/// `address` is given.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    pub path: String,
+    pub address: Option<u16>,
+    /// Attach the one device to every VM built from the config, as for
+    /// `DeviceConfig::shared`.
+    #[serde(default)]
+    pub shared: bool,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            memory_size: 0x8000,
+            clock_hz: 0,
+            cycle_hz: 0,
+            clock: ClockMode::Virtual,
+            halt_address: 0xFFFF,
+            overflow: Overflow::Wrap,
+            wait_states: WaitStates::default(),
+            vector_table: VECTOR_TABLE,
+            seed: None,
+            poison: None,
+            shadow_stack: false,
+            trap_misaligned: false,
+            devices: vec![DeviceConfig {
+                kind: "console".to_string(),
+                address: 0x8000,
+                shared: false,
+                options: Table::new(),
+            }],
+            plugins: Vec::new(),
+        }
+    }
+}
+
+impl MachineConfig {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|err| invalid(format!("{}: {}", path, err)))
+    }
+
+    /// Creates the configured devices, paired with their base addresses.
+    pub fn build_devices(&self) -> io::Result<DeviceSet> {
+        self.build_devices_sharing(&mut vec![None; self.devices.len() + self.plugins.len()])
+    }
+
+    /// Creates the devices for `count` VMs running side by side. Devices
+    /// marked `shared` are created once and attached to every VM (see
+    /// `devices::Shared`); each VM gets its own of the rest.
+    pub fn build_device_sets(&self, count: usize) -> io::Result<Vec<DeviceSet>> {
+        let mut shared = vec![None; self.devices.len() + self.plugins.len()];
+        (0..count)
+            .map(|_| self.build_devices_sharing(&mut shared))
+            .collect()
+    }
+
+    /// Creates the configured devices, taking the shared ones from `shared`,
+    /// which has a slot for each device and then each plugin holding the
+    /// device and where it asked to be mapped, if they were created before,
+    /// and leaving them there otherwise.
+    fn build_devices_sharing(&self, shared: &mut [Option<(u16, Shared)>]) -> io::Result<DeviceSet> {
+        if self.memory_size == 0 || self.memory_size > MEM_SIZE as u32 {
+            return Err(invalid(format!(
+                "memory_size must be between 1 and {} words, not {}",
+                MEM_SIZE, self.memory_size
+            )));
+        }
+        let mut built: Vec<(u16, Box<dyn Device>)> = Vec::new();
+        for (dev, slot) in self.devices.iter().zip(shared.iter_mut()) {
+            let mut options = dev.options.clone();
+            if let Some(seed) = self.seed {
+                options
+                    .entry("seed")
+                    .or_insert(toml::Value::Integer(seed as i64));
+            }
+            let device: Box<dyn Device> = match slot {
+                Some((_, device)) => Box::new(device.clone()),
+                None if dev.shared => {
+                    let device = Shared::new(devices::create(&dev.kind, &options)?);
+                    *slot = Some((dev.address, device.clone()));
+                    Box::new(device)
+                }
+                None => devices::create(&dev.kind, &options)?,
+            };
+            self.place(&mut built, &dev.kind, dev.address, device)?;
+        }
+        #[cfg(target_os = "wasi")]
+        if let Some(plugin) = self.plugins.first() {
+            return Err(invalid(format!(
+                "{}: plugins can't be loaded under WASI",
+                plugin.path
+            )));
+        }
+        #[cfg(not(target_os = "wasi"))]
+        for (plugin, slot) in self
+            .plugins
+            .iter()
+            .zip(shared[self.devices.len()..].iter_mut())
+        {
+            let (base, device): (u16, Box<dyn Device>) = match slot {
+                Some((base, device)) => (*base, Box::new(device.clone())),
+                None => {
+                    let (base, device) = PluginDevice::load(&plugin.path)?;
+                    if plugin.shared {
+                        let device = Shared::new(Box::new(device));
+                        *slot = Some((base, device.clone()));
+                        (base, Box::new(device))
+                    } else {
+                        (base, Box::new(device))
+                    }
+                }
+            };
+            let base = plugin.address.unwrap_or(base);
+            self.place(&mut built, &plugin.path, base, device)?;
+        }
+        Ok(built)
+    }
+
+    /// Adds a device to `built`, checking that it doesn't overlap anything.
+    fn place(
+        &self,
+        built: &mut Vec<(u16, Box<dyn Device>)>,
+        name: &str,
+        base: u16,
+        device: Box<dyn Device>,
+    ) -> io::Result<()> {
+        let start = base as u32;
+        let end = start + device.ports() as u32;
+        if end > 0x10000 {
+            return Err(invalid(format!(
+                "{} at 0x{:04x} runs past the end of the address space",
+                name, base
+            )));
+        }
+        let halt = self.halt_address as u32;
+        if start <= halt && halt < end {
+            return Err(invalid(format!(
+                "{} at 0x{:04x} overlaps the halt address",
+                name, base
+            )));
+        }
+        for (other_base, other) in built.iter() {
+            let other_end = *other_base as u32 + other.ports() as u32;
+            if start < other_end && (*other_base as u32) < end {
+                return Err(invalid(format!(
+                    "{} at 0x{:04x} overlaps the device at 0x{:04x}",
+                    name, base, other_base
+                )));
+            }
+        }
+        built.push((base, device));
+        Ok(())
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,1109 @@
+//! The processor: fetch, decode and execute, independent of the host.
+//!
+//! A `Cpu` holds the registers and memory and executes one instruction at a
+//! time. Anything beyond memory, such as devices and host services, is
+//! reached through a `System`, so this module and `arch` build without `std`
+//! for embedded hosts and constrained plugin environments; turn off the
+//! default `std` feature to get just them. `vm::TeenyAT` wraps a `Cpu` with
+//! devices, console I/O, pacing and the other things a desktop host needs.
+
+use crate::arch::OpCode::*;
+use crate::arch::RegMnem::*;
+use crate::arch::*;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use log::{debug, trace, warn};
+#[cfg(feature = "std")]
+use serde::Deserialize;
+
+/// Where the interrupt handlers' addresses are kept unless the machine config
+/// says otherwise; see `Cpu::raise`.
+pub const VECTOR_TABLE: u16 = 0x7000;
+/// How many interrupt vectors there are.
+pub const VECTORS: u16 = 8;
+const END_PROG_ADDR: u16 = 0xFFFF;
+/// How many recently executed instructions are kept for crash reports.
+const HISTORY_LEN: usize = 64;
+/// How many words from the top of the stack `Display` shows.
+const STACK_PREVIEW: u16 = 4;
+
+/// What a `Cpu` reaches beyond its memory. Every method has a default that
+/// attaches nothing, so `()` is a machine with only memory.
+pub trait System {
+    /// Loads from the device mapped at `addr`, or returns None if there is
+    /// none and the load should go to memory. `regs` holds the registers,
+    /// indexed by number as in `REG_NAMES`, with pc already past the
+    /// instruction doing the load.
+    fn read(
+        &mut self,
+        _addr: u16,
+        _mem: &mut Memory,
+        _regs: &[u16; 8],
+    ) -> Result<Option<u16>, ArchError> {
+        Ok(None)
+    }
+
+    /// Stores to the device mapped at `addr`, returning false if there is
+    /// none and the store should go to memory. `regs` is as for `read`.
+    fn write(
+        &mut self,
+        _addr: u16,
+        _val: u16,
+        _mem: &mut Memory,
+        _regs: &[u16; 8],
+    ) -> Result<bool, ArchError> {
+        Ok(false)
+    }
+
+    /// Runs host service `number` for `int`. `regs` holds the registers,
+    /// indexed by number as in `REG_NAMES`; changes to them are kept.
+    fn service(
+        &mut self,
+        number: u16,
+        _regs: &mut [u16; 8],
+        _mem: &mut Memory,
+    ) -> Result<(), ArchError> {
+        Err(ArchError::NoService(number))
+    }
+
+    /// Called after every instruction with the cycles executed so far.
+    /// Returns the interrupt vectors to raise, one bit per vector.
+    fn interrupts(&mut self, _cycles: u64) -> u16 {
+        0
+    }
+
+    /// Called once when the program ends.
+    fn halted(&mut self) -> Result<(), ArchError> {
+        Ok(())
+    }
+}
+
+impl System for () {}
+
+/// A subroutine call that hasn't returned yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// The address of the `call` instruction, or for an interrupt, of the
+    /// instruction it arrived before.
+    pub call_site: u16,
+    /// Where the return address was pushed.
+    pub slot: u16,
+    /// The return address pushed.
+    pub ret: u16,
+}
+
+/// Counts of what a VM has done, returned by `TeenyAT::run` once the program
+/// ends and available at any time from `stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunStats {
+    /// The program's exit code, or 0 while it is still running.
+    pub exit_code: u16,
+    pub instructions: u64,
+    /// Estimated clock cycles; see `OpCode::cycles`.
+    pub cycles: u64,
+    /// Instructions after which execution did not continue with the next
+    /// one: jumps whose condition held, calls, and `set`s or `pop`s of `pc`.
+    pub branches_taken: u64,
+    /// Loads from and stores to memory, including the stack.
+    pub mem_reads: u64,
+    pub mem_writes: u64,
+    /// Bytes devices took from the VM's input and gave to its output. A bare
+    /// `Cpu` has no input or output and leaves this at 0.
+    pub io_bytes: u64,
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Exit code: {}", self.exit_code)?;
+        writeln!(f, "Instructions: {}", self.instructions)?;
+        writeln!(f, "Cycles: {}", self.cycles)?;
+        writeln!(f, "Branches taken: {}", self.branches_taken)?;
+        writeln!(
+            f,
+            "Memory: {} reads, {} writes",
+            self.mem_reads, self.mem_writes
+        )?;
+        writeln!(f, "I/O: {} bytes", self.io_bytes)
+    }
+}
+
+/// What `add`, `sub`, `mult`, `divs`, `neg`, `inc` and `dec` do when the
+/// signed result doesn't fit in a word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(Deserialize), serde(rename_all = "lowercase"))]
+pub enum Overflow {
+    /// Keep the low 16 bits, as the hardware does.
+    #[default]
+    Wrap,
+    /// Clamp to -32768 or 32767.
+    Saturate,
+    /// Stop with `ArchError::Overflow`.
+    Trap,
+}
+
+impl Overflow {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "wrap" => Some(Overflow::Wrap),
+            "saturate" => Some(Overflow::Saturate),
+            "trap" => Some(Overflow::Trap),
+            _ => None,
+        }
+    }
+}
+
+/// Extra cycles charged for each memory access, on top of the estimates of
+/// `OpCode::cycles`, by the region accessed. Instruction fetches count as one
+/// access. Everything is 0 by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "std",
+    derive(Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct WaitStates {
+    /// Addresses below this are ROM; the rest of memory is RAM.
+    pub rom_end: u16,
+    pub rom: u64,
+    pub ram: u64,
+    /// Accesses that reach a device.
+    pub device: u64,
+}
+
+impl WaitStates {
+    /// The extra cycles for accessing `addr`, which may be a device port.
+    pub fn cost(&self, addr: u16, device: bool) -> u64 {
+        if device {
+            self.device
+        } else if addr < self.rom_end {
+            self.rom
+        } else {
+            self.ram
+        }
+    }
+}
+
+pub struct Cpu {
+    mem: Memory,
+    ins: Instruction,
+    ins_addr: u16,
+    pc: Register,
+    r1: Register,
+    r2: Register,
+    r3: Register,
+    r4: Register,
+    r5: Register,
+    r6: Register,
+    sp: Register,
+    op_code: OpCode,
+    ra: RegMnem,
+    rb: RegMnem,
+    imm: u16,
+    addr: u16,
+    exit_code: Option<u16>,
+    call_stack: Vec<Frame>,
+    written: Vec<u16>,
+    history: VecDeque<(u16, Instruction)>,
+    halt_addr: u16,
+    pub(crate) stats: RunStats,
+    wait_states: WaitStates,
+    /// Wait cycles charged so far to the instruction being executed.
+    waited: u64,
+    overflow: Overflow,
+    /// Whether every `pop pc` must return from the innermost call; see
+    /// `with_shadow_stack`.
+    shadow_stack: bool,
+    /// Whether jumping to an odd address stops the program; see
+    /// `with_misaligned_trap`.
+    trap_misaligned: bool,
+    /// Whether `ei` has enabled interrupts. They start disabled.
+    irq_enabled: bool,
+    /// One bit per vector raised but not yet handled.
+    irq_pending: u16,
+    vector_table: u16,
+    /// How to undo each of the last steps, newest last; see `set_journal`.
+    journal: VecDeque<Undo>,
+    journal_len: usize,
+}
+
+/// What a step changed, kept to undo it.
+struct Undo {
+    regs: [u16; 8],
+    ins: Instruction,
+    ins_addr: u16,
+    exit_code: Option<u16>,
+    stats: RunStats,
+    irq_enabled: bool,
+    irq_pending: u16,
+    /// The call stack before the step, if the step changed it.
+    call_stack: Option<Vec<Frame>>,
+    /// The old values of the words the step wrote, oldest first.
+    mem: Vec<(u16, u16)>,
+}
+
+impl Cpu {
+    pub fn new(program: Memory) -> Self {
+        let mut sp = Register::new(RegMnem::Sp);
+        sp.val = program.size() as u16;
+        Self {
+            mem: program,
+            ins: Instruction::new(0, 0),
+            ins_addr: 0,
+            pc: Register::new(RegMnem::Pc),
+            r1: Register::new(RegMnem::R1),
+            r2: Register::new(RegMnem::R2),
+            r3: Register::new(RegMnem::R3),
+            r4: Register::new(RegMnem::R4),
+            r5: Register::new(RegMnem::R5),
+            r6: Register::new(RegMnem::R6),
+            sp,
+            op_code: OpCode::Set,
+            ra: RegMnem::default(),
+            rb: RegMnem::default(),
+            imm: 0,
+            addr: 0,
+            exit_code: None,
+            call_stack: Vec::new(),
+            written: Vec::new(),
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            halt_addr: END_PROG_ADDR,
+            stats: RunStats::default(),
+            wait_states: WaitStates::default(),
+            waited: 0,
+            overflow: Overflow::Wrap,
+            shadow_stack: false,
+            trap_misaligned: false,
+            irq_enabled: false,
+            irq_pending: 0,
+            vector_table: VECTOR_TABLE,
+            journal: VecDeque::new(),
+            journal_len: 0,
+        }
+    }
+
+    /// Chooses what arithmetic does on signed overflow. The default is to
+    /// wrap.
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Charges extra cycles for memory accesses. There are no wait states by
+    /// default.
+    pub fn with_wait_states(mut self, wait_states: WaitStates) -> Self {
+        self.wait_states = wait_states;
+        self
+    }
+
+    /// Checks every `pop pc` (`ret`) and `iret` against the calls made,
+    /// stopping with `ArchError::ReturnMismatch` if it doesn't pop the
+    /// return address the innermost call or interrupt pushed, from where it
+    /// pushed it. This catches a routine that overwrites its return address
+    /// or leaves words on the stack, at the cost of `pop pc` no longer
+    /// working as a computed jump. Off by default.
+    pub fn with_shadow_stack(mut self, on: bool) -> Self {
+        self.shadow_stack = on;
+        self
+    }
+
+    /// Stops with `ArchError::MisalignedJump` when an instruction sends
+    /// execution anywhere but the next instruction and the address it goes
+    /// to is odd. Instructions are two words, so in a program that keeps
+    /// them at even addresses an odd target is the middle of one, whose
+    /// words decode as nonsense. Off by default, since data placed with an
+    /// odd number of words moves the instructions after it to odd addresses.
+    pub fn with_misaligned_trap(mut self, on: bool) -> Self {
+        self.trap_misaligned = on;
+        self
+    }
+
+    /// Moves the address whose loads and stores end the program from 0xFFFF.
+    pub fn with_halt_address(mut self, addr: u16) -> Self {
+        self.halt_addr = addr;
+        self
+    }
+
+    /// Moves the interrupt vector table from `VECTOR_TABLE`.
+    pub fn with_vector_table(mut self, addr: u16) -> Self {
+        self.vector_table = addr;
+        self
+    }
+
+    /// Keeps what the last `len` steps changed in registers and memory, so
+    /// that `step_back` can undo them. 0, the default, keeps nothing.
+    pub fn set_journal(&mut self, len: usize) {
+        self.journal_len = len;
+        while self.journal.len() > len {
+            self.journal.pop_front();
+        }
+    }
+
+    /// How many steps `step_back` can undo.
+    pub fn journaled(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Undoes the last step kept in the journal, returning false if there
+    /// is none. Registers, memory, interrupt state and the counts in `stats`
+    /// go back to what they were; what devices did, such as printing or
+    /// consuming input, stays done.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.journal.pop_back() {
+            Some(undo) => undo,
+            None => return false,
+        };
+        for &(addr, old) in undo.mem.iter().rev() {
+            // The step wrote these, so they're in range.
+            let _ = self.mem.write(addr, old);
+        }
+        for (&reg, &val) in [Pc, R1, R2, R3, R4, R5, R6, Sp]
+            .iter()
+            .zip(undo.regs.iter())
+        {
+            self.reg_mut(reg).val = val;
+        }
+        self.ins = undo.ins;
+        self.ins_addr = undo.ins_addr;
+        self.exit_code = undo.exit_code;
+        self.stats = undo.stats;
+        self.irq_enabled = undo.irq_enabled;
+        self.irq_pending = undo.irq_pending;
+        if let Some(call_stack) = undo.call_stack {
+            self.call_stack = call_stack;
+        }
+        self.history.pop_back();
+        self.written.clear();
+        true
+    }
+
+    /// Executes a single instruction, reaching devices and host services
+    /// through `system`.
+    pub fn step<S: System>(&mut self, system: &mut S) -> Result<(), ArchError> {
+        if self.journal_len == 0 {
+            return self.execute_step(system);
+        }
+        let mut undo = Undo {
+            regs: self.registers().map(|reg| reg.val),
+            ins: self.ins,
+            ins_addr: self.ins_addr,
+            exit_code: self.exit_code,
+            stats: self.stats.clone(),
+            irq_enabled: self.irq_enabled,
+            irq_pending: self.irq_pending,
+            call_stack: Some(self.call_stack.clone()),
+            mem: Vec::new(),
+        };
+        self.mem.start_undo();
+        let result = self.execute_step(system);
+        undo.mem = self.mem.take_undo();
+        if result != Err(ArchError::WouldBlock) {
+            if undo.call_stack.as_ref() == Some(&self.call_stack) {
+                undo.call_stack = None;
+            }
+            if self.journal.len() == self.journal_len {
+                self.journal.pop_front();
+            }
+            self.journal.push_back(undo);
+        }
+        result
+    }
+
+    fn execute_step<S: System>(&mut self, system: &mut S) -> Result<(), ArchError> {
+        self.written.clear();
+        self.waited = 0;
+        self.fetch()?;
+        self.decode()?;
+        trace!(
+            target: "vm::dispatch",
+            "0x{:04x}: {}",
+            self.ins_addr,
+            self.ins.disassemble().unwrap_or_default()
+        );
+        let mut result = self.execute(system);
+        let jumped = self.pc.val != self.ins_addr.wrapping_add(2);
+        if result.is_ok() && self.trap_misaligned && jumped && self.pc.val % 2 == 1 {
+            result = Err(ArchError::MisalignedJump {
+                addr: self.ins_addr,
+                target: self.pc.val,
+            });
+        }
+        if result.is_ok() && self.exit_code.is_some() {
+            result = system.halted();
+        }
+        match &result {
+            Ok(()) => {
+                self.stats.instructions += 1;
+                self.stats.cycles += self.op_code.cycles() + self.waited;
+                if self.pc.val != self.ins_addr.wrapping_add(2) {
+                    self.stats.branches_taken += 1;
+                }
+                self.irq_pending |= system.interrupts(self.stats.cycles);
+            }
+            Err(ArchError::WouldBlock) => {}
+            Err(err) => debug!(
+                target: "vm::dispatch",
+                "fault at 0x{:04x}: {}",
+                self.ins_addr,
+                err.to_string().trim_end()
+            ),
+        }
+        if result.is_ok() {
+            result = self.enter_interrupt();
+        }
+        if result == Err(ArchError::WouldBlock) {
+            // The device refused before anything changed, so running the
+            // instruction again later picks up where this left off.
+            self.pc.val = self.ins_addr;
+            self.history.pop_back();
+        }
+        result
+    }
+
+    /// Raises interrupt `vector` (0 to 7). Once interrupts are enabled, the
+    /// CPU pushes pc, disables interrupts and continues at the handler whose
+    /// address is at `vector_table + vector`, lowest vectors first. A
+    /// handler address of 0 means there is no handler and the interrupt is
+    /// dropped. Devices raise interrupts through `System::interrupts`.
+    pub fn raise(&mut self, vector: u16) {
+        self.irq_pending |= 1 << (vector % VECTORS);
+    }
+
+    /// Whether the guest has interrupts enabled.
+    pub fn interrupts_enabled(&self) -> bool {
+        self.irq_enabled
+    }
+
+    /// Jumps to the handler for the lowest pending interrupt, if interrupts
+    /// are enabled and one is pending. This happens between instructions, so
+    /// a debugger sees pc at the handler before it runs.
+    fn enter_interrupt(&mut self) -> Result<(), ArchError> {
+        if !self.irq_enabled || self.irq_pending == 0 {
+            return Ok(());
+        }
+        let vector = self.irq_pending.trailing_zeros() as u16;
+        self.irq_pending &= !(1 << vector);
+        let entry = self.vector_table.wrapping_add(vector);
+        let handler = self.mem.read(entry)?;
+        self.stats.mem_reads += 1;
+        // A vector the program never installed has no handler, even when
+        // memory was poisoned rather than zeroed.
+        let handler = if self.mem.is_uninit(entry) {
+            0
+        } else {
+            handler
+        };
+        if handler == 0 {
+            return Ok(());
+        }
+        debug!(
+            target: "vm::dispatch",
+            "interrupt {} at 0x{:04x} -> 0x{:04x}",
+            vector,
+            self.pc.val,
+            handler
+        );
+        self.sp.val = self.sp.val.wrapping_sub(1);
+        self.mem.write(self.sp.val, self.pc.val)?;
+        self.written.push(self.sp.val);
+        self.stats.mem_writes += 1;
+        self.waited += self.wait_states.cost(self.sp.val, false);
+        self.call_stack.push(Frame {
+            call_site: self.pc.val,
+            slot: self.sp.val,
+            ret: self.pc.val,
+        });
+        self.irq_enabled = false;
+        self.pc.val = handler;
+        Ok(())
+    }
+
+    /// The estimated clock cycles executed so far; see `OpCode::cycles`.
+    pub fn cycles(&self) -> u64 {
+        self.stats.cycles
+    }
+
+    /// What the CPU has done so far.
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            exit_code: self.exit_code.unwrap_or(0),
+            ..self.stats.clone()
+        }
+    }
+
+    /// The exit code once the program has ended, or None while it can still run.
+    pub fn exit_code(&self) -> Option<u16> {
+        self.exit_code
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc.val
+    }
+
+    /// The address of the instruction executed most recently, or that was
+    /// being executed when a step failed.
+    pub fn ins_addr(&self) -> u16 {
+        self.ins_addr
+    }
+
+    /// The calls that haven't returned yet, outermost first. A `pop pc`
+    /// returns from the call whose return address it pops, along with any
+    /// calls made inside it.
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    pub fn registers(&self) -> [&Register; 8] {
+        [
+            &self.pc, &self.r1, &self.r2, &self.r3, &self.r4, &self.r5, &self.r6, &self.sp,
+        ]
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.mem
+    }
+
+    pub fn read_mem(&self, addr: u16) -> Result<u16, ArchError> {
+        self.mem.read(addr)
+    }
+
+    /// The addresses and instructions of the last steps, oldest first.
+    pub fn history(&self) -> &VecDeque<(u16, Instruction)> {
+        &self.history
+    }
+
+    /// The memory addresses the last step stored to, including those a
+    /// device or host service filled in, such as a line of input, but not
+    /// device ports.
+    pub fn written(&self) -> &[u16] {
+        &self.written
+    }
+
+    /// Overwrites a register. Setting pc makes execution continue there.
+    pub fn set_register(&mut self, reg: RegMnem, val: u16) {
+        self.reg_mut(reg).val = val;
+    }
+
+    /// Overwrites a word of memory, bypassing any device mapped there.
+    pub fn write_mem(&mut self, addr: u16, val: u16) -> Result<(), ArchError> {
+        self.mem.write(addr, val)
+    }
+
+    fn fetch(&mut self) -> Result<(), ArchError> {
+        self.ins_addr = self.pc.val;
+        let word1 = self.mem.read(self.pc.val)?;
+        let word2 = self.mem.read(self.pc.val.wrapping_add(1))?;
+        self.check_init(self.pc.val);
+        self.check_init(self.pc.val.wrapping_add(1));
+        self.ins = Instruction::new(word1, word2);
+        self.waited += self.wait_states.cost(self.ins_addr, false);
+        self.pc.val = self.pc.val.wrapping_add(2);
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back((self.ins_addr, self.ins));
+        Ok(())
+    }
+
+    fn decode(&mut self) -> Result<(), ArchError> {
+        self.op_code = self.ins.get_op_code()?;
+        let num_regs = self.op_code.num_regs();
+        if num_regs >= 1 {
+            self.ra = self.ins.get_ra()?;
+        }
+        if num_regs == 2 {
+            self.rb = self.ins.get_rb()?;
+        }
+        self.imm = self.ins.word_imm;
+        self.addr = self.imm;
+        Ok(())
+    }
+
+    fn execute<S: System>(&mut self, system: &mut S) -> Result<(), ArchError> {
+        match self.op_code {
+            Set => self.set(),
+            Copy => self.copy(),
+            Load => self.load(system)?,
+            Stor => self.stor(system)?,
+            PLoad => self.pload(system)?,
+            PStor => self.pstor(system)?,
+            Push => self.push()?,
+            Pop => self.pop()?,
+            Add => self.add()?,
+            Sub => self.sub()?,
+            Mult => self.mult()?,
+            Div => self.div()?,
+            Mod => self.divmod()?,
+            Neg => self.neg()?,
+            Inc => self.inc()?,
+            Dec => self.dec()?,
+            And => self.and(),
+            Or => self.or(),
+            Xor => self.xor(),
+            Inv => self.inv(),
+            Shl => self.shl(),
+            Shr => self.shr(),
+            Call => self.call()?,
+            Jl => self.jl(),
+            Jle => self.jle(),
+            Je => self.je(),
+            Jne => self.jne(),
+            Jge => self.jge(),
+            Jg => self.jg(),
+            Ei => self.irq_enabled = true,
+            Di => self.irq_enabled = false,
+            Iret => self.iret()?,
+            Int => self.int(system)?,
+            Divs => self.divs()?,
+            Mods => self.mods()?,
+            Cmovz => self.cmov(true)?,
+            Cmovnz => self.cmov(false)?,
+            Sext => self.sext(),
+        }
+        Ok(())
+    }
+
+    fn get_ra(&mut self) -> &mut Register {
+        self.reg_mut(self.ra)
+    }
+
+    fn reg_mut(&mut self, reg: RegMnem) -> &mut Register {
+        match reg {
+            _R0 | Pc => &mut self.pc,
+            R1 | Ax => &mut self.r1,
+            R2 | Bx => &mut self.r2,
+            R3 | Cx => &mut self.r3,
+            R4 | Dx => &mut self.r4,
+            R5 | Ex => &mut self.r5,
+            R6 | Fx => &mut self.r6,
+            R7 | Sp => &mut self.sp,
+        }
+    }
+
+    fn rb_val(&self) -> u16 {
+        match self.rb {
+            _R0 | Pc => self.pc.val,
+            R1 | Ax => self.r1.val,
+            R2 | Bx => self.r2.val,
+            R3 | Cx => self.r3.val,
+            R4 | Dx => self.r4.val,
+            R5 | Ex => self.r5.val,
+            R6 | Fx => self.r6.val,
+            R7 | Sp => self.sp.val,
+        }
+    }
+
+    fn set(&mut self) {
+        let imm = self.imm;
+        let ra = self.get_ra();
+        ra.val = imm;
+    }
+
+    fn copy(&mut self) {
+        let rb_val = self.rb_val();
+        let ra = self.get_ra();
+        ra.val = rb_val;
+    }
+
+    /// Copies rb to ra if the register numbered by the immediate is zero, or
+    /// with `zero` false, if it isn't.
+    fn cmov(&mut self, zero: bool) -> Result<(), ArchError> {
+        let cond = self.reg_mut(RegMnem::from_int(self.imm)?).val;
+        if (cond == 0) == zero {
+            self.copy();
+        }
+        Ok(())
+    }
+
+    fn load<S: System>(&mut self, system: &mut S) -> Result<(), ArchError> {
+        let addr = self.addr;
+        if addr == self.halt_addr {
+            self.exit_code = Some(self.get_ra().val);
+        } else {
+            let val = self.read_bus(system, addr)?;
+            self.get_ra().val = val;
+        }
+        Ok(())
+    }
+
+    fn stor<S: System>(&mut self, system: &mut S) -> Result<(), ArchError> {
+        let val = self.get_ra().val;
+        if self.addr == self.halt_addr {
+            self.exit_code = Some(val);
+        } else {
+            self.write_bus(system, self.addr, val)?;
+        }
+        Ok(())
+    }
+
+    fn pload<S: System>(&mut self, system: &mut S) -> Result<(), ArchError> {
+        let rb = self.rb_val();
+        if rb == self.halt_addr {
+            self.exit_code = Some(self.get_ra().val);
+        } else {
+            let val = self.read_bus(system, rb)?;
+            self.get_ra().val = val;
+        }
+        Ok(())
+    }
+
+    fn pstor<S: System>(&mut self, system: &mut S) -> Result<(), ArchError> {
+        let rb = self.rb_val();
+        let addr = self.get_ra().val;
+        if addr == self.halt_addr {
+            self.exit_code = Some(addr);
+        } else {
+            self.write_bus(system, addr, rb)?;
+        }
+        Ok(())
+    }
+
+    /// Reads from the device mapped at `addr`, or from memory if there is none.
+    fn read_bus<S: System>(&mut self, system: &mut S, addr: u16) -> Result<u16, ArchError> {
+        let regs = self.registers().map(|reg| reg.val);
+        self.mem.start_log();
+        let read = system.read(addr, &mut self.mem, &regs);
+        self.written.extend(self.mem.take_log());
+        if let Some(val) = read? {
+            self.waited += self.wait_states.cost(addr, true);
+            trace!(target: "vm::mem", "port read 0x{:04x} -> 0x{:04x}", addr, val);
+            return Ok(val);
+        }
+        let val = self.mem.read(addr)?;
+        self.check_init(addr);
+        self.stats.mem_reads += 1;
+        self.waited += self.wait_states.cost(addr, false);
+        trace!(target: "vm::mem", "read 0x{:04x} -> 0x{:04x}", addr, val);
+        Ok(val)
+    }
+
+    /// Warns about the first read of each word of poisoned memory that
+    /// nothing has stored to; see `Memory::poison`.
+    fn check_init(&mut self, addr: u16) {
+        if self.mem.is_uninit(addr) {
+            warn!(
+                target: "vm::mem",
+                "0x{:04x} read by the instruction at 0x{:04x} before anything was stored there",
+                addr,
+                self.ins_addr
+            );
+            self.mem.mark_init(addr);
+        }
+    }
+
+    /// Writes to the device mapped at `addr`, or to memory if there is none.
+    fn write_bus<S: System>(
+        &mut self,
+        system: &mut S,
+        addr: u16,
+        val: u16,
+    ) -> Result<(), ArchError> {
+        let regs = self.registers().map(|reg| reg.val);
+        self.mem.start_log();
+        let wrote = system.write(addr, val, &mut self.mem, &regs);
+        self.written.extend(self.mem.take_log());
+        if wrote? {
+            trace!(target: "vm::mem", "port write 0x{:04x} <- 0x{:04x}", addr, val);
+            self.waited += self.wait_states.cost(addr, true);
+            return Ok(());
+        }
+        trace!(target: "vm::mem", "write 0x{:04x} <- 0x{:04x}", addr, val);
+        self.stats.mem_writes += 1;
+        self.waited += self.wait_states.cost(addr, false);
+        self.mem.write(addr, val)?;
+        self.written.push(addr);
+        Ok(())
+    }
+
+    /// Runs the host service numbered by the immediate.
+    fn int<S: System>(&mut self, system: &mut S) -> Result<(), ArchError> {
+        let number = self.imm;
+        let mut regs = self.registers().map(|reg| reg.val);
+        debug!(target: "vm::dispatch", "host service {}", number);
+        self.mem.start_log();
+        let result = system.service(number, &mut regs, &mut self.mem);
+        self.written.extend(self.mem.take_log());
+        result?;
+        for (&reg, &val) in [Pc, R1, R2, R3, R4, R5, R6, Sp].iter().zip(regs.iter()) {
+            self.reg_mut(reg).val = val;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self) -> Result<(), ArchError> {
+        let ra = self.get_ra();
+        let temp = ra.val;
+        self.sp.val = self.sp.val.wrapping_sub(1);
+        self.mem.write(self.sp.val, temp)?;
+        self.written.push(self.sp.val);
+        self.stats.mem_writes += 1;
+        self.waited += self.wait_states.cost(self.sp.val, false);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<(), ArchError> {
+        let addr = self.sp.val;
+        let val = self.mem.read(addr)?;
+        self.check_init(addr);
+        if self.ra == Pc && self.shadow_stack {
+            match self.call_stack.last() {
+                Some(frame) if frame.slot == addr && frame.ret == val => {}
+                frame => {
+                    return Err(ArchError::ReturnMismatch {
+                        addr: self.ins_addr,
+                        found: val,
+                        expected: frame.map(|frame| frame.ret),
+                    })
+                }
+            }
+        }
+        self.get_ra().val = val;
+        self.stats.mem_reads += 1;
+        self.waited += self.wait_states.cost(addr, false);
+        self.sp.val = self.sp.val.wrapping_add(1);
+        if self.ra == Pc {
+            if let Some(i) = self.call_stack.iter().rposition(|f| f.slot == addr) {
+                self.call_stack.truncate(i);
+            }
+        }
+        Ok(())
+    }
+
+    /// Picks the result of a signed operation according to the overflow
+    /// policy, given its checked, wrapping and saturating versions.
+    fn arith(&self, checked: Option<i16>, wrapped: i16, saturated: i16) -> Result<u16, ArchError> {
+        match (checked, self.overflow) {
+            (Some(val), _) => Ok(val as u16),
+            (None, Overflow::Wrap) => Ok(wrapped as u16),
+            (None, Overflow::Saturate) => Ok(saturated as u16),
+            (None, Overflow::Trap) => Err(ArchError::Overflow(self.op_code)),
+        }
+    }
+
+    fn add(&mut self) -> Result<(), ArchError> {
+        let (a, b) = (self.get_ra().val as i16, self.rb_val() as i16);
+        let val = self.arith(a.checked_add(b), a.wrapping_add(b), a.saturating_add(b))?;
+        self.get_ra().val = val;
+        Ok(())
+    }
+
+    fn sub(&mut self) -> Result<(), ArchError> {
+        let (a, b) = (self.get_ra().val as i16, self.rb_val() as i16);
+        let val = self.arith(a.checked_sub(b), a.wrapping_sub(b), a.saturating_sub(b))?;
+        self.get_ra().val = val;
+        Ok(())
+    }
+
+    fn mult(&mut self) -> Result<(), ArchError> {
+        let (a, b) = (self.get_ra().val as i16, self.rb_val() as i16);
+        let val = self.arith(a.checked_mul(b), a.wrapping_mul(b), a.saturating_mul(b))?;
+        self.get_ra().val = val;
+        Ok(())
+    }
+
+    fn div(&mut self) -> Result<(), ArchError> {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        ra.val = ra.val.checked_div(rb).ok_or(ArchError::DivideByZero)?;
+        Ok(())
+    }
+
+    fn divmod(&mut self) -> Result<(), ArchError> {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        ra.val = ra.val.checked_rem(rb).ok_or(ArchError::DivideByZero)?;
+        Ok(())
+    }
+
+    fn divs(&mut self) -> Result<(), ArchError> {
+        let (a, b) = (self.get_ra().val as i16, self.rb_val() as i16);
+        if b == 0 {
+            return Err(ArchError::DivideByZero);
+        }
+        // Only -32768 / -1 overflows.
+        let val = self.arith(a.checked_div(b), a.wrapping_div(b), a.saturating_div(b))?;
+        self.get_ra().val = val;
+        Ok(())
+    }
+
+    fn mods(&mut self) -> Result<(), ArchError> {
+        let (a, b) = (self.get_ra().val as i16, self.rb_val() as i16);
+        if b == 0 {
+            return Err(ArchError::DivideByZero);
+        }
+        self.get_ra().val = a.wrapping_rem(b) as u16;
+        Ok(())
+    }
+
+    fn neg(&mut self) -> Result<(), ArchError> {
+        let a = self.get_ra().val as i16;
+        let val = self.arith(a.checked_neg(), a.wrapping_neg(), a.saturating_neg())?;
+        self.get_ra().val = val;
+        Ok(())
+    }
+
+    fn inc(&mut self) -> Result<(), ArchError> {
+        let a = self.get_ra().val as i16;
+        let val = self.arith(a.checked_add(1), a.wrapping_add(1), a.saturating_add(1))?;
+        self.get_ra().val = val;
+        Ok(())
+    }
+
+    fn dec(&mut self) -> Result<(), ArchError> {
+        let a = self.get_ra().val as i16;
+        let val = self.arith(a.checked_sub(1), a.wrapping_sub(1), a.saturating_sub(1))?;
+        self.get_ra().val = val;
+        Ok(())
+    }
+
+    fn and(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        ra.val &= rb;
+    }
+
+    fn or(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        ra.val |= rb;
+    }
+
+    fn xor(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        ra.val ^= rb;
+    }
+
+    fn inv(&mut self) {
+        let ra = self.get_ra();
+        ra.val = !ra.val;
+    }
+
+    fn sext(&mut self) {
+        let ra = self.get_ra();
+        ra.val = ra.val as u8 as i8 as u16;
+    }
+
+    fn shl(&mut self) {
+        let imm = self.imm;
+        let ra = self.get_ra();
+        ra.val = ra.val.checked_shl(imm as u32).unwrap_or(0);
+    }
+
+    fn shr(&mut self) {
+        let imm = self.imm;
+        let ra = self.get_ra();
+        ra.val = ra.val.checked_shr(imm as u32).unwrap_or(0);
+    }
+    fn call(&mut self) -> Result<(), ArchError> {
+        self.sp.val = self.sp.val.wrapping_sub(1);
+        self.mem.write(self.sp.val, self.pc.val)?;
+        self.written.push(self.sp.val);
+        self.stats.mem_writes += 1;
+        self.waited += self.wait_states.cost(self.sp.val, false);
+        self.call_stack.push(Frame {
+            call_site: self.ins_addr,
+            slot: self.sp.val,
+            ret: self.pc.val,
+        });
+        self.pc.val = self.addr;
+        Ok(())
+    }
+
+    fn iret(&mut self) -> Result<(), ArchError> {
+        self.ra = Pc;
+        self.pop()?;
+        self.irq_enabled = true;
+        Ok(())
+    }
+
+    fn jl(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        if (ra.val as i16) < (rb as i16) {
+            self.pc.val = self.addr;
+        }
+    }
+
+    fn jle(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        if (ra.val as i16) <= (rb as i16) {
+            self.pc.val = self.addr;
+        }
+    }
+
+    fn je(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        if (ra.val as i16) == (rb as i16) {
+            self.pc.val = self.addr;
+        }
+    }
+
+    fn jne(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        if (ra.val as i16) != (rb as i16) {
+            self.pc.val = self.addr;
+        }
+    }
+
+    fn jge(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        if (ra.val as i16) >= (rb as i16) {
+            self.pc.val = self.addr;
+        }
+    }
+
+    fn jg(&mut self) {
+        let rb = self.rb_val();
+        let ra = self.get_ra();
+        if (ra.val as i16) > (rb as i16) {
+            self.pc.val = self.addr;
+        }
+    }
+}
+
+/// The whole machine state in a fixed layout, for logs and golden-file tests:
+/// every register in hex and signed decimal, whether the program has ended,
+/// the instruction at pc and the words at the top of the stack. TeenyAT has
+/// no flags register; the jumps compare registers directly.
+impl fmt::Display for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, reg) in REG_NAMES.iter().zip(self.registers().iter()) {
+            writeln!(f, "{} = 0x{:04x} ({})", name, reg.val, reg.val as i16)?;
+        }
+        match self.exit_code {
+            Some(code) => writeln!(f, "status: exited with code {}", code)?,
+            None => writeln!(f, "status: running")?,
+        }
+        let pc = self.pc.val;
+        let next = match (self.mem.read(pc), self.mem.read(pc.wrapping_add(1))) {
+            (Ok(op), Ok(imm)) => {
+                let ins = Instruction::new(op, imm);
+                ins.disassemble()
+                    .unwrap_or_else(|| format!("(bad instruction 0x{:04x} 0x{:04x})", op, imm))
+            }
+            _ => "(out of memory)".to_string(),
+        };
+        writeln!(f, "next: 0x{:04x}  {}", pc, next)?;
+        writeln!(f, "stack:")?;
+        for i in 0..STACK_PREVIEW {
+            let addr = self.sp.val.wrapping_add(i);
+            match self.mem.read(addr) {
+                Ok(val) => writeln!(
+                    f,
+                    "  sp+{} 0x{:04x}: 0x{:04x} ({})",
+                    i, addr, val, val as i16
+                )?,
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+}
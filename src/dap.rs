@@ -0,0 +1,587 @@
+//! A Debug Adapter Protocol server, so editors such as VS Code can set
+//! breakpoints in `.tat` files, step through them and inspect the machine.
+//!
+//! The adapter speaks DAP over stdin/stdout and debugs a single thread: the
+//! program being run. Guest output is forwarded as `output` events and guest
+//! input comes from the optional `input` string in the launch arguments.
+
+use crate::arch::ArchError;
+use crate::assembler;
+use crate::debuginfo::DebugInfo;
+use crate::expr;
+use crate::feed::SharedBuf;
+use crate::vm::TeenyAT;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+const THREAD_ID: u64 = 1;
+const REGISTERS_REF: u64 = 1;
+const STACK_REF: u64 = 2;
+const STACK_PREVIEW: usize = 16;
+const STEPS_PER_POLL: usize = 10_000;
+
+pub fn serve() -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new().spawn(move || {
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+        while let Ok(Some(msg)) = read_message(&mut reader) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    })?;
+    let mut session = Session::new(io::stdout());
+    loop {
+        let msg = if session.running {
+            match rx.try_recv() {
+                Ok(msg) => Some(msg),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match rx.recv() {
+                Ok(msg) => Some(msg),
+                Err(_) => break,
+            }
+        };
+        if let Some(msg) = msg {
+            if !session.handle(&msg)? {
+                break;
+            }
+        }
+        if session.running {
+            session.run_slice()?;
+        }
+    }
+    Ok(())
+}
+
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(val) = line.strip_prefix("Content-Length:") {
+            len = val.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "DAP message without a Content-Length header",
+        )
+    })?;
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+struct Session<W: Write> {
+    out: W,
+    seq: u64,
+    vm: Option<TeenyAT>,
+    debug: DebugInfo,
+    breakpoints: HashSet<u16>,
+    stop_on_entry: bool,
+    running: bool,
+    guest_out: SharedBuf,
+    /// The step request being carried out while running, if any.
+    stepping: Option<Step>,
+}
+
+/// Where a step request stops, unless a breakpoint, a fault or the end of the
+/// program comes first. Lines are (file, line) pairs, and code without line
+/// information counts as a new line at every instruction.
+#[derive(Clone, Copy)]
+enum Step {
+    /// `stepIn`: at the start of any other line, entering calls.
+    In { line: Option<(usize, usize)> },
+    /// `next`: at the start of another line once back at this call depth or
+    /// shallower, so calls run to completion.
+    Over {
+        line: Option<(usize, usize)>,
+        depth: usize,
+    },
+    /// `stepOut`: once the call stack is shallower than this, i.e. after
+    /// the subroutine at this depth returns.
+    Out { depth: usize },
+}
+
+impl<W: Write> Session<W> {
+    fn new(out: W) -> Self {
+        Self {
+            out,
+            seq: 1,
+            vm: None,
+            debug: DebugInfo::default(),
+            breakpoints: HashSet::new(),
+            stop_on_entry: false,
+            running: false,
+            guest_out: SharedBuf::default(),
+            stepping: None,
+        }
+    }
+
+    fn send(&mut self, mut msg: Value) -> io::Result<()> {
+        msg["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn respond(&mut self, req: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn respond_err(&mut self, req: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.running = false;
+        self.stepping = None;
+        self.flush_guest_output()?;
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+
+    fn terminated(&mut self, exit_code: Option<u16>) -> io::Result<()> {
+        self.running = false;
+        self.stepping = None;
+        self.flush_guest_output()?;
+        if let Some(code) = exit_code {
+            self.event("exited", json!({ "exitCode": code }))?;
+        }
+        self.vm = None;
+        self.event("terminated", json!({}))
+    }
+
+    fn flush_guest_output(&mut self) -> io::Result<()> {
+        let bytes = self.guest_out.take();
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        self.event("output", json!({ "category": "stdout", "output": text }))
+    }
+
+    /// Handles one request, returning false once the client disconnects.
+    fn handle(&mut self, req: &Value) -> io::Result<bool> {
+        let args = &req["arguments"];
+        match req["command"].as_str().unwrap_or("") {
+            "initialize" => {
+                self.respond(
+                    req,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                        "supportsReadMemoryRequest": true,
+                    }),
+                )?;
+            }
+            "launch" => self.launch(req)?,
+            "setBreakpoints" => self.set_breakpoints(req)?,
+            "setExceptionBreakpoints" => self.respond(req, json!({ "breakpoints": [] }))?,
+            "configurationDone" => {
+                self.respond(req, json!({}))?;
+                let at_breakpoint = self
+                    .vm
+                    .as_ref()
+                    .is_some_and(|vm| self.breakpoints.contains(&vm.pc()));
+                if self.stop_on_entry {
+                    self.stopped("entry")?;
+                } else if at_breakpoint {
+                    self.stopped("breakpoint")?;
+                } else {
+                    self.running = true;
+                }
+            }
+            "threads" => {
+                self.respond(
+                    req,
+                    json!({ "threads": [{ "id": THREAD_ID, "name": "TeenyAT" }] }),
+                )?;
+            }
+            "stackTrace" => {
+                let frames = self.stack_frames();
+                let total = frames.len();
+                self.respond(req, json!({ "stackFrames": frames, "totalFrames": total }))?;
+            }
+            "scopes" => {
+                self.respond(
+                    req,
+                    json!({ "scopes": [
+                        { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                        { "name": "Stack", "variablesReference": STACK_REF, "expensive": false },
+                    ]}),
+                )?;
+            }
+            "variables" => {
+                let vars = self.variables(args["variablesReference"].as_u64().unwrap_or(0));
+                self.respond(req, json!({ "variables": vars }))?;
+            }
+            "evaluate" => {
+                let expr = args["expression"].as_str().unwrap_or("");
+                match self.evaluate(expr) {
+                    Ok(val) => self.respond(
+                        req,
+                        json!({
+                            "result": format_word(val),
+                            "variablesReference": 0,
+                            "memoryReference": memory_reference(val),
+                        }),
+                    )?,
+                    Err(err) => self.respond_err(req, &err)?,
+                }
+            }
+            "readMemory" => self.read_memory(req)?,
+            "continue" => {
+                self.respond(req, json!({ "allThreadsContinued": true }))?;
+                self.stepping = None;
+                self.running = true;
+            }
+            "next" | "stepIn" | "stepOut" => self.step(req)?,
+            "pause" => {
+                self.respond(req, json!({}))?;
+                self.stopped("pause")?;
+            }
+            "disconnect" | "terminate" => {
+                self.respond(req, json!({}))?;
+                return Ok(false);
+            }
+            _ => {
+                let msg = format!("Unsupported request: {}", req["command"]);
+                self.respond_err(req, &msg)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, req: &Value) -> io::Result<()> {
+        let args = &req["arguments"];
+        let program = match args["program"].as_str() {
+            Some(program) => program.to_string(),
+            None => return self.respond_err(req, "launch requires a `program` path"),
+        };
+        let (mem, debug) = match assembler::assemble_file(&program) {
+            Ok(assembled) => assembled,
+            Err(err) => return self.respond_err(req, &err.to_string()),
+        };
+        let input = args["input"].as_str().unwrap_or("").as_bytes().to_vec();
+        let mut vm = TeenyAT::new(mem);
+        vm.set_io(
+            Box::new(io::Cursor::new(input)),
+            Box::new(self.guest_out.clone()),
+        );
+        self.vm = Some(vm);
+        self.debug = debug;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.respond(req, json!({}))?;
+        self.event("initialized", json!({}))
+    }
+
+    /// Starts a `next`, `stepIn` or `stepOut`, which `run_slice` carries
+    /// out like `continue` until `step_done`.
+    fn step(&mut self, req: &Value) -> io::Result<()> {
+        let (line, depth) = match &self.vm {
+            Some(vm) => (self.source_line(vm.pc()), vm.call_stack().len()),
+            None => return self.respond_err(req, "No program is running"),
+        };
+        let step = match req["command"].as_str() {
+            Some("stepIn") => Step::In { line },
+            Some("next") => Step::Over { line, depth },
+            _ if depth == 0 => return self.respond_err(req, "Not inside a subroutine"),
+            _ => Step::Out { depth },
+        };
+        self.respond(req, json!({}))?;
+        self.stepping = Some(step);
+        self.running = true;
+        Ok(())
+    }
+
+    /// Answers `readMemory` with up to `count` bytes from `offset` bytes
+    /// past the reference, stopping at the end of memory.
+    fn read_memory(&mut self, req: &Value) -> io::Result<()> {
+        let args = &req["arguments"];
+        let reference = args["memoryReference"].as_str().unwrap_or("");
+        let base = match parse_reference(reference) {
+            Some(base) => base,
+            None => {
+                let msg = format!("Bad memory reference `{}`", reference);
+                return self.respond_err(req, &msg);
+            }
+        };
+        let vm = match &self.vm {
+            Some(vm) => vm,
+            None => return self.respond_err(req, "No program is running"),
+        };
+        let start = base + args["offset"].as_i64().unwrap_or(0);
+        if start < 0 {
+            return self.respond_err(req, "Memory starts at address 0");
+        }
+        let count = args["count"].as_i64().unwrap_or(0).max(0);
+        let mut bytes = Vec::new();
+        for addr in start..start + count {
+            let word = match u16::try_from(addr / 2) {
+                Ok(word) => vm.read_mem(word),
+                Err(_) => break,
+            };
+            match word {
+                Ok(word) => bytes.push(if addr % 2 == 0 {
+                    word as u8
+                } else {
+                    (word >> 8) as u8
+                }),
+                Err(_) => break,
+            }
+        }
+        let body = json!({
+            "address": format!("0x{:x}", start),
+            "unreadableBytes": count - bytes.len() as i64,
+            "data": base64(&bytes),
+        });
+        self.respond(req, body)
+    }
+
+    fn set_breakpoints(&mut self, req: &Value) -> io::Result<()> {
+        let args = &req["arguments"];
+        let requested: Vec<u64> = args["breakpoints"]
+            .as_array()
+            .map(|bps| bps.iter().filter_map(|bp| bp["line"].as_u64()).collect())
+            .unwrap_or_default();
+        let file = args["source"]["path"]
+            .as_str()
+            .and_then(|path| self.debug.file_index(path));
+        let debug = &self.debug;
+        self.breakpoints
+            .retain(|addr| debug.location(*addr).map(|(f, _)| debug.file_index(f)) != Some(file));
+        let mut result = Vec::new();
+        for line in requested {
+            match file.and_then(|file| self.debug.addr_for_line(file, line as usize)) {
+                Some(addr) => {
+                    self.breakpoints.insert(addr);
+                    let actual = self.debug.location(addr).map_or(line as usize, |(_, l)| l);
+                    result.push(json!({ "verified": true, "line": actual }));
+                }
+                None => result.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No instruction on or after this line",
+                })),
+            }
+        }
+        self.respond(req, json!({ "breakpoints": result }))
+    }
+
+    /// Executes one instruction, reporting whether the program kept running.
+    fn exec(&mut self) -> io::Result<bool> {
+        let vm = match self.vm.as_mut() {
+            Some(vm) => vm,
+            None => return Ok(false),
+        };
+        match vm.step() {
+            Ok(()) => {
+                if let Some(code) = vm.exit_code() {
+                    self.terminated(Some(code))?;
+                    return Ok(false);
+                }
+                Ok(true)
+            }
+            Err(err) => {
+                self.fault(err)?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn fault(&mut self, err: ArchError) -> io::Result<()> {
+        self.flush_guest_output()?;
+        let pc = self.vm.as_ref().map_or(0, |vm| vm.pc());
+        let msg = format!("Fault near 0x{:04x}: {}", pc, err);
+        self.event("output", json!({ "category": "stderr", "output": msg }))?;
+        self.terminated(None)
+    }
+
+    fn run_slice(&mut self) -> io::Result<()> {
+        for _ in 0..STEPS_PER_POLL {
+            if !self.exec()? {
+                return Ok(());
+            }
+            let pc = self.vm.as_ref().map_or(0, |vm| vm.pc());
+            if self.breakpoints.contains(&pc) {
+                return self.stopped("breakpoint");
+            }
+            if self.step_done() {
+                return self.stopped("step");
+            }
+        }
+        self.flush_guest_output()
+    }
+
+    /// Whether the step request being carried out has got where it was going.
+    fn step_done(&self) -> bool {
+        let (step, vm) = match (self.stepping, &self.vm) {
+            (Some(step), Some(vm)) => (step, vm),
+            _ => return false,
+        };
+        let depth = vm.call_stack().len();
+        let now = self.source_line(vm.pc());
+        let new_line = |line: Option<(usize, usize)>| now.is_none() || now != line;
+        match step {
+            Step::In { line } => new_line(line),
+            Step::Over { line, depth: start } => depth <= start && new_line(line),
+            Step::Out { depth: start } => depth < start,
+        }
+    }
+
+    /// The file and line the instruction at `addr` was assembled from.
+    fn source_line(&self, addr: u16) -> Option<(usize, usize)> {
+        self.debug.span(addr).map(|span| (span.file, span.line))
+    }
+
+    /// The call stack, innermost first, as `debugger::backtrace` shows it:
+    /// pc, then the `call` each outer frame is waiting to return from.
+    fn stack_frames(&self) -> Vec<Value> {
+        let vm = match &self.vm {
+            Some(vm) => vm,
+            None => return Vec::new(),
+        };
+        let sites = vm.call_stack().iter().rev().map(|frame| frame.call_site);
+        std::iter::once(vm.pc())
+            .chain(sites)
+            .enumerate()
+            .map(|(id, addr)| self.frame(id, addr))
+            .collect()
+    }
+
+    fn frame(&self, id: usize, addr: u16) -> Value {
+        let name = match self.debug.label_before(addr) {
+            Some((lbl, 0)) => lbl.to_string(),
+            Some((lbl, ofs)) => format!("{}+{}", lbl, ofs),
+            None => format!("0x{:04x}", addr),
+        };
+        let (path, line) = self.debug.location(addr).unwrap_or(("", 0));
+        json!({
+            "id": id,
+            "name": name,
+            "source": { "path": path },
+            "line": line,
+            "column": 1,
+        })
+    }
+
+    fn variables(&self, reference: u64) -> Vec<Value> {
+        let vm = match &self.vm {
+            Some(vm) => vm,
+            None => return Vec::new(),
+        };
+        match reference {
+            REGISTERS_REF => vm
+                .registers()
+                .iter()
+                .map(|reg| {
+                    json!({
+                        "name": format!("{:?}", reg.mnem).to_lowercase(),
+                        "value": format_word(reg.val),
+                        "variablesReference": 0,
+                    })
+                })
+                .collect(),
+            STACK_REF => {
+                // The stack starts at the top of memory, however much the
+                // machine was given.
+                let sp = vm.registers()[7].val as usize;
+                let top = vm.memory().size();
+                (sp..top.min(sp + STACK_PREVIEW))
+                    .map(|addr| addr as u16)
+                    .filter_map(|addr| {
+                        vm.read_mem(addr).ok().map(|val| {
+                            json!({
+                                "name": format!("0x{:04x}", addr),
+                                "value": format_word(val),
+                                "variablesReference": 0,
+                            })
+                        })
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Evaluates an expression as the debugger's `display` does; see `expr`.
+    fn evaluate(&self, text: &str) -> Result<u16, String> {
+        let vm = self.vm.as_ref().ok_or("No program is running")?;
+        expr::parse(text, &self.debug)?.eval(vm)
+    }
+}
+
+/// DAP addresses memory in bytes, so word `addr` is referred to by the
+/// address of its low byte, `2 * addr`, and its high byte follows, as in an
+/// Intel HEX image.
+fn memory_reference(addr: u16) -> String {
+    format!("0x{:x}", 2 * addr as u32)
+}
+
+/// The byte address in a memory reference, written in hex with `0x` or in
+/// decimal.
+fn parse_reference(text: &str) -> Option<i64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Standard base64 with padding, as `readMemory` sends its data.
+fn base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(DIGITS[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn format_word(val: u16) -> String {
+    format!("0x{:04x} ({})", val, val as i16)
+}
@@ -0,0 +1,696 @@
+//! The interactive debugger started by `-d`.
+//!
+//! Anywhere a command expects an address it also accepts a label, with or
+//! without its `!` or `:` prefix and optionally with an offset (`!table+2`),
+//! or a source line as `file.tat:12`.
+
+use crate::arch::{ArchError, Instruction, RegMnem, REG_NAMES};
+use crate::debuginfo::DebugInfo;
+use crate::expr::{self, Expr};
+#[cfg(not(target_os = "wasi"))]
+use crate::memview::{self, View};
+use crate::vm::TeenyAT;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+const HELP: &str = "\
+step [n]            run to the start of the nth source line (default 1)
+stepi [n]           execute n instructions (default 1)
+continue            run until a breakpoint, a watchpoint or the end
+finish              run until the current subroutine returns
+reverse-stepi [n]   undo the last n instructions (default 1)
+reverse-continue    go back to the last breakpoint or watchpoint hit
+break [addr] [if <expr>]
+                    set a breakpoint, optionally only stopping when expr
+                    is nonzero, or list them
+break io [addr]     stop after any access to a device port, or to the port
+                    at addr
+delete <addr>       remove a breakpoint
+delete io [addr]    remove a port breakpoint
+watch [addr]        stop when the word at addr changes, or list watchpoints
+unwatch <addr>      remove a watchpoint
+display [expr]      show expr after every step or stop, or show them all
+undisplay <n>       stop showing display n
+backtrace           show the calls that haven't returned yet
+regs                show the registers
+set mem <addr> <expr>
+                    store the value of expr at addr
+set reg <reg> <expr>
+                    set a register; setting pc moves execution
+state               show registers, the next instruction and the stack
+mem <addr> [n]      show n words of memory (default 8)
+view [addr]         browse memory full screen, highlighting words written
+                    since the last step or continue
+list [addr] [n]     disassemble n instructions (default 8) from addr or pc
+quit                stop debugging
+Addresses can be numbers, labels such as !main, labels with an offset such
+as !table+2 or source lines such as prog.tat:12. Expressions combine
+numbers, registers, labels and memory reads such as [!counter] with
++ - * == != < <= > >= && ||.";
+/// How many instructions `reverse-stepi` and `reverse-continue` can undo.
+const JOURNAL_LEN: usize = 4096;
+/// How many words from the top of the stack a crash report shows.
+const CRASH_STACK_WORDS: usize = 16;
+/// How many source lines to show either side of the current one.
+const CONTEXT_LINES: usize = 2;
+
+/// When `Debugger::resume` should stop if nothing else stops it first.
+enum Until {
+    /// After this many instructions.
+    Steps(usize),
+    /// On reaching the start of a new source line this many times.
+    Lines(usize),
+    /// When the call stack is shallower than this, i.e. after the
+    /// subroutine at this depth returns.
+    Returned(usize),
+    /// Only at breakpoints, watchpoints, faults or the end.
+    Stopped,
+}
+
+/// An expression kept with its text for listing: a breakpoint's condition
+/// or a display.
+struct Saved {
+    text: String,
+    expr: Expr,
+}
+
+pub struct Debugger {
+    vm: TeenyAT,
+    debug: DebugInfo,
+    breakpoints: BTreeMap<u16, Option<Saved>>,
+    /// Port addresses to stop at after an access to.
+    io_breaks: BTreeSet<u16>,
+    /// Whether to stop after an access to any port.
+    io_break_all: bool,
+    /// Watched addresses and the value each had when last checked.
+    watches: BTreeMap<u16, u16>,
+    /// Expressions to show whenever execution stops.
+    displays: Vec<Saved>,
+    /// Memory stored to since execution last resumed.
+    written: BTreeSet<u16>,
+    /// The lines of each file in `debug.files`, or None if it can't be read.
+    sources: Vec<Option<Vec<String>>>,
+}
+
+impl Debugger {
+    pub fn new(mut vm: TeenyAT, debug: DebugInfo) -> Self {
+        vm.set_journal(JOURNAL_LEN);
+        let sources = debug
+            .files
+            .iter()
+            .map(|file| {
+                fs::read_to_string(file)
+                    .ok()
+                    .map(|text| text.lines().map(str::to_string).collect())
+            })
+            .collect();
+        Self {
+            vm,
+            debug,
+            breakpoints: BTreeMap::new(),
+            io_breaks: BTreeSet::new(),
+            io_break_all: false,
+            watches: BTreeMap::new(),
+            displays: Vec::new(),
+            written: BTreeSet::new(),
+            sources,
+        }
+    }
+
+    /// Reads commands from stdin until the program ends or the user quits,
+    /// returning the program's exit code.
+    pub fn run(&mut self) -> io::Result<u16> {
+        println!("teenyat debugger. Type `help` for a list of commands.");
+        self.show_pc();
+        let stdin = io::stdin();
+        loop {
+            if let Some(code) = self.vm.exit_code() {
+                println!("Program exited with code {}", code);
+                return Ok(code);
+            }
+            print!("(tdb) ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if stdin.read_line(&mut line)? == 0 {
+                return Ok(0);
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            match self.command(&words) {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                Err(msg) => println!("{}", msg),
+            }
+        }
+    }
+
+    /// Carries out one command, returning false if the user asked to quit.
+    fn command(&mut self, words: &[&str]) -> Result<bool, String> {
+        let arg = |i: usize| words.get(i).copied();
+        match words[0] {
+            "help" | "h" | "?" => println!("{}", HELP),
+            "step" | "s" => {
+                let count = arg(1).map(parse_num).transpose()?.unwrap_or(1);
+                self.resume(Until::Lines(count as usize));
+            }
+            "stepi" | "si" => {
+                let count = arg(1).map(parse_num).transpose()?.unwrap_or(1);
+                self.resume(Until::Steps(count as usize));
+            }
+            "continue" | "c" => self.resume(Until::Stopped),
+            "reverse-stepi" | "rsi" => {
+                let count = arg(1).map(parse_num).transpose()?.unwrap_or(1);
+                self.rewind(Some(count as usize));
+            }
+            "reverse-continue" | "rc" => self.rewind(None),
+            "finish" | "f" => {
+                let depth = self.vm.call_stack().len();
+                if depth == 0 {
+                    return Err("Not inside a subroutine".to_string());
+                }
+                self.resume(Until::Returned(depth));
+            }
+            "break" | "b" => match arg(1) {
+                Some("io") => match arg(2) {
+                    Some(text) => {
+                        let addr = self.parse_addr(text)?;
+                        self.io_breaks.insert(addr);
+                        println!("Breakpoint on port {}", self.describe(addr));
+                    }
+                    None => {
+                        self.io_break_all = true;
+                        println!("Breakpoint on every port");
+                    }
+                },
+                Some(text) => {
+                    let addr = self.parse_addr(text)?;
+                    let cond = match arg(2) {
+                        Some("if") => {
+                            let text = words[3..].join(" ");
+                            let expr = expr::parse(&text, &self.debug)?;
+                            Some(Saved { text, expr })
+                        }
+                        Some(other) => return Err(format!("Expected `if`, not `{}`", other)),
+                        None => None,
+                    };
+                    self.breakpoints.insert(addr, cond);
+                    println!("Breakpoint at {}", self.describe_breakpoint(addr));
+                }
+                None => {
+                    for addr in self.breakpoints.keys() {
+                        println!("  {}", self.describe_breakpoint(*addr));
+                    }
+                    if self.io_break_all {
+                        println!("  io (every port)");
+                    }
+                    for addr in self.io_breaks.iter() {
+                        println!("  io {}", self.describe(*addr));
+                    }
+                }
+            },
+            "delete" | "d" if arg(1) == Some("io") => match arg(2) {
+                Some(text) => {
+                    let addr = self.parse_addr(text)?;
+                    if !self.io_breaks.remove(&addr) {
+                        return Err(format!("No breakpoint on port {}", self.describe(addr)));
+                    }
+                }
+                None => self.io_break_all = false,
+            },
+            "delete" | "d" => {
+                let addr = self.parse_addr(arg(1).ok_or("usage: delete <addr>")?)?;
+                if self.breakpoints.remove(&addr).is_none() {
+                    return Err(format!("No breakpoint at {}", self.describe(addr)));
+                }
+            }
+            "watch" | "w" => match arg(1) {
+                Some(text) => {
+                    let addr = self.parse_addr(text)?;
+                    let val = self.vm.read_mem(addr).map_err(|err| error_text(&err))?;
+                    self.watches.insert(addr, val);
+                    println!("Watching {} = {}", self.describe(addr), format_word(val));
+                }
+                None => {
+                    for (addr, val) in self.watches.iter() {
+                        println!("  {} = {}", self.describe(*addr), format_word(*val));
+                    }
+                }
+            },
+            "unwatch" => {
+                let addr = self.parse_addr(arg(1).ok_or("usage: unwatch <addr>")?)?;
+                if self.watches.remove(&addr).is_none() {
+                    return Err(format!("Not watching {}", self.describe(addr)));
+                }
+            }
+            "display" => {
+                if words.len() > 1 {
+                    let text = words[1..].join(" ");
+                    let expr = expr::parse(&text, &self.debug)?;
+                    self.displays.push(Saved { text, expr });
+                }
+                self.show_displays();
+            }
+            "undisplay" => {
+                let num = parse_num(arg(1).ok_or("usage: undisplay <n>")?)? as usize;
+                if num == 0 || num > self.displays.len() {
+                    return Err(format!("No display {}", num));
+                }
+                self.displays.remove(num - 1);
+            }
+            "backtrace" | "bt" => println!("{}", backtrace(&self.vm, &self.debug, self.vm.pc())),
+            "regs" | "r" => self.show_regs(),
+            "state" => print!("{}", self.vm),
+            "set" => match arg(1) {
+                Some("mem") if words.len() >= 4 => {
+                    let addr = self.parse_addr(words[2])?;
+                    let val = expr::parse(&words[3..].join(" "), &self.debug)?.eval(&self.vm)?;
+                    self.vm
+                        .write_mem(addr, val)
+                        .map_err(|err| error_text(&err))?;
+                    println!("{} = {}", self.describe(addr), format_word(val));
+                }
+                Some("reg") if words.len() >= 4 => {
+                    let reg = RegMnem::from_str(words[2])
+                        .map_err(|_| format!("Unknown register `{}`", words[2]))?;
+                    let val = expr::parse(&words[3..].join(" "), &self.debug)?.eval(&self.vm)?;
+                    self.vm.set_register(reg, val);
+                    println!(
+                        "{} = {}",
+                        REG_NAMES[reg.to_int() as usize],
+                        format_word(val)
+                    );
+                    if reg == RegMnem::Pc {
+                        self.show_pc();
+                    }
+                }
+                _ => return Err("usage: set mem <addr> <expr> or set reg <reg> <expr>".to_string()),
+            },
+            "mem" | "x" => {
+                let addr = self.parse_addr(arg(1).ok_or("usage: mem <addr> [n]")?)?;
+                let count = arg(2).map(parse_num).transpose()?.unwrap_or(8);
+                self.show_mem(addr, count);
+            }
+            #[cfg(target_os = "wasi")]
+            "view" | "v" => {
+                return Err("view needs a terminal, which WASI doesn't provide".to_string())
+            }
+            #[cfg(not(target_os = "wasi"))]
+            "view" | "v" => {
+                let start = match arg(1) {
+                    Some(text) => self.parse_addr(text)?,
+                    None => self.vm.pc(),
+                };
+                let view = View {
+                    memory: self.vm.memory(),
+                    debug: &self.debug,
+                    written: &self.written,
+                    pc: self.vm.pc(),
+                    sp: self.vm.registers()[7].val,
+                };
+                memview::show(&view, start, &|text| self.parse_addr(text))
+                    .map_err(|err| err.to_string())?;
+            }
+            "list" | "l" => {
+                let addr = match arg(1) {
+                    Some(text) => self.parse_addr(text)?,
+                    None => self.vm.pc(),
+                };
+                let count = arg(2).map(parse_num).transpose()?.unwrap_or(8);
+                self.list(addr, count);
+            }
+            "quit" | "q" => return Ok(false),
+            other => return Err(format!("Unknown command `{}`. Type `help`.", other)),
+        }
+        Ok(true)
+    }
+
+    /// Runs until `until` says to stop, or earlier at breakpoints,
+    /// watchpoints, faults and the end of the program.
+    fn resume(&mut self, until: Until) {
+        let mut steps = 0;
+        let mut lines = 0;
+        let mut line = self.source_line(self.vm.pc());
+        // Forget a Ctrl+C pressed at the prompt.
+        self.vm.take_interrupt();
+        self.written.clear();
+        loop {
+            if self.vm.take_interrupt() {
+                println!("Interrupted");
+                break;
+            }
+            match until {
+                Until::Steps(limit) if steps >= limit => break,
+                Until::Lines(limit) if lines >= limit => break,
+                Until::Returned(depth) if self.vm.call_stack().len() < depth => {
+                    println!("Returned to {}", self.describe(self.vm.pc()));
+                    break;
+                }
+                _ => {}
+            }
+            let result = self.vm.step();
+            self.written.extend(self.vm.written());
+            if let Err(err) = result {
+                println!("{}", crash_report(&self.vm, &self.debug, &err));
+                break;
+            }
+            steps += 1;
+            // Code without line information counts as a line of its own at
+            // every instruction, so stepping through it can't run away.
+            let now = self.source_line(self.vm.pc());
+            if now.is_none() || now != line {
+                lines += 1;
+            }
+            line = now;
+            if self.vm.exit_code().is_some() {
+                return;
+            }
+            if self.check_watches() | self.port_hit() {
+                break;
+            }
+            if self.breakpoint_hit() {
+                break;
+            }
+        }
+        self.show_pc();
+        self.show_displays();
+    }
+
+    /// Undoes `limit` instructions, or with no limit, goes back until pc
+    /// is at a breakpoint. Either way it stops early if a watched word
+    /// changes or the journal runs out. Devices aren't rewound.
+    fn rewind(&mut self, limit: Option<usize>) {
+        let mut steps = 0;
+        self.written.clear();
+        while limit != Some(steps) {
+            if !self.vm.step_back() {
+                println!(
+                    "Reached the start of the history (up to {} instructions are kept)",
+                    JOURNAL_LEN
+                );
+                break;
+            }
+            steps += 1;
+            if self.check_watches() {
+                break;
+            }
+            if limit.is_none() && self.breakpoint_hit() {
+                break;
+            }
+        }
+        self.show_pc();
+        self.show_displays();
+    }
+
+    /// Reports the port accesses made by the last step that there are
+    /// breakpoints on, returning whether there were any.
+    fn port_hit(&self) -> bool {
+        let mut hit = false;
+        for access in self.vm.port_accesses() {
+            if self.io_break_all || self.io_breaks.contains(&access.addr) {
+                println!(
+                    "Port {} {} {} by {}",
+                    if access.write { "write" } else { "read" },
+                    self.describe(access.addr),
+                    format_word(access.val),
+                    self.describe(self.vm.ins_addr())
+                );
+                hit = true;
+            }
+        }
+        hit
+    }
+
+    /// Whether execution should stop for a breakpoint at the current pc,
+    /// reporting it if so.
+    fn breakpoint_hit(&self) -> bool {
+        let pc = self.vm.pc();
+        let hit = match self.breakpoints.get(&pc) {
+            None => false,
+            Some(None) => true,
+            Some(Some(cond)) => match cond.expr.eval(&self.vm) {
+                Ok(val) => val != 0,
+                Err(msg) => {
+                    println!("Could not evaluate `{}`: {}", cond.text, msg);
+                    true
+                }
+            },
+        };
+        if hit {
+            println!("Breakpoint at {}", self.describe_breakpoint(pc));
+        }
+        hit
+    }
+
+    /// A breakpoint as the source line it's on, followed by its address and
+    /// condition.
+    fn describe_breakpoint(&self, addr: u16) -> String {
+        let place = match self.debug.location(addr) {
+            Some((file, line)) => format!("{}:{} ({})", file, line, self.describe(addr)),
+            None => self.describe(addr),
+        };
+        match self.breakpoints.get(&addr) {
+            Some(Some(cond)) => format!("{} if {}", place, cond.text),
+            _ => place,
+        }
+    }
+
+    /// Reports watched words that have changed, returning whether any had.
+    fn check_watches(&mut self) -> bool {
+        let mut changed = Vec::new();
+        for (addr, last) in self.watches.iter_mut() {
+            if let Ok(val) = self.vm.read_mem(*addr) {
+                if val != *last {
+                    changed.push((*addr, *last, val));
+                    *last = val;
+                }
+            }
+        }
+        for (addr, old, new) in changed.iter() {
+            println!(
+                "Watchpoint {}: {} -> {}",
+                self.describe(*addr),
+                format_word(*old),
+                format_word(*new)
+            );
+        }
+        !changed.is_empty()
+    }
+
+    /// Parses an address written as a number, a label with an optional
+    /// offset or a `file:line`.
+    fn parse_addr(&self, text: &str) -> Result<u16, String> {
+        if let Some((file, line)) = text.rsplit_once(':') {
+            if !file.is_empty() {
+                return self.line_addr(file, line);
+            }
+        }
+        let (base, offset) = match text.split_once('+') {
+            Some((base, offset)) => (base, parse_num(offset)?),
+            None => (text, 0),
+        };
+        let addr = match parse_num(base) {
+            Ok(addr) => addr,
+            Err(_) => self
+                .debug
+                .label_addr(base)
+                .or_else(|| self.debug.port_addr(base))
+                .ok_or_else(|| format!("Unknown label `{}`", base))?,
+        };
+        Ok(addr.wrapping_add(offset))
+    }
+
+    /// The file index and line the instruction at `addr` came from.
+    fn source_line(&self, addr: u16) -> Option<(usize, usize)> {
+        self.debug.span(addr).map(|span| (span.file, span.line))
+    }
+
+    /// The first instruction on or after `line` of `file`, which can be
+    /// named by its path or just its file name.
+    fn line_addr(&self, file: &str, line: &str) -> Result<u16, String> {
+        let line = line
+            .parse::<usize>()
+            .map_err(|_| format!("Expected a line number, not `{}`", line))?;
+        let index = self
+            .debug
+            .file_index(file)
+            .or_else(|| {
+                self.debug
+                    .files
+                    .iter()
+                    .position(|name| Path::new(name).file_name() == Some(file.as_ref()))
+            })
+            .ok_or_else(|| format!("Unknown source file `{}`", file))?;
+        self.debug
+            .addr_for_line(index, line)
+            .ok_or_else(|| format!("No instruction on or after {}:{}", file, line))
+    }
+
+    fn describe(&self, addr: u16) -> String {
+        describe(&self.debug, addr)
+    }
+
+    fn disassemble_at(&self, addr: u16) -> String {
+        let words = (
+            self.vm.read_mem(addr),
+            self.vm.read_mem(addr.wrapping_add(1)),
+        );
+        match words {
+            (Ok(op), Ok(imm)) => disassemble(&Instruction::new(op, imm)),
+            _ => "(out of memory)".to_string(),
+        }
+    }
+
+    /// Shows the next instruction and, when the source is available, the
+    /// lines around the one it came from.
+    fn show_pc(&self) {
+        let pc = self.vm.pc();
+        println!("{}: {}", self.describe(pc), self.disassemble_at(pc));
+        let span = match self.debug.span(pc) {
+            Some(span) => span,
+            None => return,
+        };
+        let text = match self.sources.get(span.file) {
+            Some(Some(text)) => text,
+            _ => return,
+        };
+        println!("{}:{}", self.debug.files[span.file], span.line);
+        let first = span.line.saturating_sub(CONTEXT_LINES).max(1);
+        let last = (span.line + CONTEXT_LINES).min(text.len());
+        for num in first..=last {
+            let marker = if num == span.line { "=>" } else { "  " };
+            println!("{} {:4}  {}", marker, num, text[num - 1]);
+        }
+    }
+
+    /// Shows the value of each display, numbered for `undisplay`.
+    fn show_displays(&self) {
+        for (i, display) in self.displays.iter().enumerate() {
+            match display.expr.eval(&self.vm) {
+                Ok(val) => println!("{}: {} = {}", i + 1, display.text, format_word(val)),
+                Err(msg) => println!("{}: {} ({})", i + 1, display.text, msg),
+            }
+        }
+    }
+
+    fn show_regs(&self) {
+        for (name, reg) in REG_NAMES.iter().zip(self.vm.registers().iter()) {
+            println!("  {} = {}", name, format_word(reg.val));
+        }
+    }
+
+    fn show_mem(&self, addr: u16, count: u16) {
+        if self.debug.symbolize(addr).is_some() {
+            println!("{}:", self.describe(addr));
+        }
+        print!("{}", self.vm.memory().hexdump(addr, count as usize));
+        if addr as usize + count as usize > self.vm.memory().size() {
+            println!("(end of memory)");
+        }
+    }
+
+    fn list(&self, addr: u16, count: u16) {
+        let mut at = addr;
+        for _ in 0..count {
+            if let Some((lbl, 0)) = self.debug.label_before(at) {
+                println!("{}", lbl);
+            }
+            let marker = if at == self.vm.pc() { "=>" } else { "  " };
+            let bp = if self.breakpoints.contains_key(&at) {
+                "*"
+            } else {
+                " "
+            };
+            // Data placed with `dw` or `.string` is shown a word at a time
+            // rather than decoded.
+            let (text, len) = if self.debug.is_data(at) {
+                let word = self.vm.read_mem(at).unwrap_or(0);
+                (format!("dw 0x{:04x}", word), 1)
+            } else {
+                (self.disassemble_at(at), 2)
+            };
+            println!("{}{} 0x{:04x}  {}", marker, bp, at, text);
+            at = at.wrapping_add(len);
+        }
+    }
+}
+
+/// An address in hex, with the label it falls under if there is one.
+pub fn describe(debug: &DebugInfo, addr: u16) -> String {
+    if let Some(port) = debug.ports.get(&addr) {
+        return format!("0x{:04x} <{}>", addr, port);
+    }
+    match debug.symbolize(addr) {
+        Some(sym) => format!("0x{:04x} <{}>", addr, sym),
+        None => format!("0x{:04x}", addr),
+    }
+}
+
+/// The call stack, innermost first, for a program stopped at `addr`. Each
+/// outer frame shows the `call` that is waiting to return.
+pub fn backtrace(vm: &TeenyAT, debug: &DebugInfo, addr: u16) -> String {
+    let mut lines = vec![format!("#0 {}", describe(debug, addr))];
+    for (i, frame) in vm.call_stack().iter().rev().enumerate() {
+        lines.push(format!("#{} {}", i + 1, describe(debug, frame.call_site)));
+    }
+    lines.join("\n")
+}
+
+fn disassemble(ins: &Instruction) -> String {
+    ins.disassemble().unwrap_or_else(|| {
+        format!(
+            "(bad instruction 0x{:04x} 0x{:04x})",
+            ins.word_op_regs, ins.word_imm
+        )
+    })
+}
+
+/// Describes a fault: the error, the instructions leading up to it, the
+/// call stack and the words on the stack.
+pub fn crash_report(vm: &TeenyAT, debug: &DebugInfo, err: &ArchError) -> String {
+    let mut lines = vec![
+        format!("Fault: {}", error_text(err)),
+        "Last instructions executed:".to_string(),
+    ];
+    for (addr, ins) in vm.history() {
+        lines.push(format!(
+            "  {}  {}",
+            describe(debug, *addr),
+            disassemble(ins)
+        ));
+    }
+    lines.push("Backtrace:".to_string());
+    lines.push(backtrace(vm, debug, vm.ins_addr()));
+    let sp = vm.registers()[7].val;
+    if (sp as usize) < vm.memory().size() {
+        lines.push("Stack:".to_string());
+        lines.push(
+            vm.memory()
+                .hexdump(sp, CRASH_STACK_WORDS)
+                .trim_end()
+                .to_string(),
+        );
+    }
+    lines.join("\n")
+}
+
+/// Parses a decimal or `0x` prefixed hex number.
+fn parse_num(text: &str) -> Result<u16, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse::<u16>(),
+    };
+    parsed.map_err(|_| format!("Expected a number, not `{}`", text))
+}
+
+/// An `ArchError` as a message, without the newline its Display ends with.
+fn error_text(err: &ArchError) -> String {
+    err.to_string().trim_end().to_string()
+}
+
+fn format_word(val: u16) -> String {
+    format!("0x{:04x} ({})", val, val as i16)
+}
@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+
+/// A stretch of source text: a file (an index into `DebugInfo::files`), a
+/// 1-based line and a range of bytes within that line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub file: usize,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Maps assembled addresses back to the source they were assembled from.
+#[derive(Debug, Default, Clone)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub labels: HashMap<String, u16>,
+    /// The names of device ports, the console's and those declared with
+    /// `.port`, by address.
+    pub ports: BTreeMap<u16, String>,
+    lines: BTreeMap<u16, Span>,
+    /// Where the program holds data placed with `dw` or `.string` rather
+    /// than instructions, as the length of each stretch by its address.
+    data: BTreeMap<u16, u16>,
+}
+
+/// How a stretch of data is written in a symbol file, after its address.
+const DATA_MARK: &str = ".data";
+
+impl DebugInfo {
+    /// Registers a source file, returning its index.
+    pub fn add_file(&mut self, name: &str) -> usize {
+        self.files.push(name.to_string());
+        self.files.len() - 1
+    }
+
+    /// The index of a source file, matching paths that name the same file.
+    pub fn file_index(&self, name: &str) -> Option<usize> {
+        let canonical = fs::canonicalize(name).ok();
+        self.files.iter().position(|file| {
+            file == name || (canonical.is_some() && fs::canonicalize(file).ok() == canonical)
+        })
+    }
+
+    /// Records that the instruction at `addr` was assembled from the
+    /// mnemonic at `span`.
+    pub fn add_span(&mut self, addr: u16, span: Span) {
+        self.lines.insert(addr, span);
+    }
+
+    /// Records that the `len` words from `addr` on are data.
+    pub fn add_data(&mut self, addr: u16, len: u16) {
+        if len > 0 {
+            self.data.insert(addr, len);
+        }
+    }
+
+    /// Whether the word at `addr` is data rather than part of an
+    /// instruction.
+    pub fn is_data(&self, addr: u16) -> bool {
+        self.data
+            .range(..=addr)
+            .next_back()
+            .is_some_and(|(start, len)| (addr - start) < *len)
+    }
+
+    /// The length of the stretch of data starting at `addr`, if one does.
+    pub fn data_len(&self, addr: u16) -> Option<u16> {
+        self.data.get(&addr).copied()
+    }
+
+    /// The file name and line of the instruction at `addr`.
+    pub fn location(&self, addr: u16) -> Option<(&str, usize)> {
+        self.lines
+            .get(&addr)
+            .map(|span| (self.files[span.file].as_str(), span.line))
+    }
+
+    /// Where in the source the instruction at `addr` came from.
+    pub fn span(&self, addr: u16) -> Option<Span> {
+        self.lines.get(&addr).copied()
+    }
+
+    /// `span` as `file:line:column`, the usual form for diagnostics.
+    pub fn describe_span(&self, span: Span) -> String {
+        let file = self.files.get(span.file).map_or("?", String::as_str);
+        format!("{}:{}:{}", file, span.line, span.start + 1)
+    }
+
+    /// The address of the first instruction on or after `line` of `file`.
+    pub fn addr_for_line(&self, file: usize, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .filter(|(_, span)| span.file == file && span.line >= line)
+            .min_by_key(|(addr, span)| (span.line, **addr))
+            .map(|(addr, _)| *addr)
+    }
+
+    /// The addresses of the instructions assembled from `line` of `file`, in
+    /// order.
+    pub fn addrs_on_line(&self, file: usize, line: usize) -> Vec<u16> {
+        self.lines
+            .iter()
+            .filter(|(_, span)| span.file == file && span.line == line)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// The address of a label, written with either prefix or none.
+    pub fn label_addr(&self, name: &str) -> Option<u16> {
+        let bare = name.trim_start_matches(['!', ':']);
+        [name.to_string(), format!("!{}", bare), format!(":{}", bare)]
+            .iter()
+            .find_map(|name| self.labels.get(name).copied())
+    }
+
+    /// The address of a port named by `.port` or of a console port.
+    pub fn port_addr(&self, name: &str) -> Option<u16> {
+        self.ports
+            .iter()
+            .find(|(_, port)| *port == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// `addr` as the closest label before it, e.g. `!loop+4`. Addresses past
+    /// the last instruction aren't treated as part of its routine.
+    pub fn symbolize(&self, addr: u16) -> Option<String> {
+        if let Some(last) = self.lines.keys().next_back() {
+            if addr > last.saturating_add(1) && !self.labels.values().any(|a| *a == addr) {
+                return None;
+            }
+        }
+        self.label_before(addr).map(|(lbl, ofs)| {
+            if ofs == 0 {
+                lbl.to_string()
+            } else {
+                format!("{}+{}", lbl, ofs)
+            }
+        })
+    }
+
+    /// The labels sorted by address, then by name.
+    pub fn symbols(&self) -> Vec<(u16, &str)> {
+        let mut symbols: Vec<(u16, &str)> = self
+            .labels
+            .iter()
+            .map(|(lbl, addr)| (*addr, lbl.as_str()))
+            .collect();
+        symbols.sort();
+        symbols
+    }
+
+    /// Writes the labels to a symbol file, one `0x1234 !label` per line, for
+    /// tools working with the assembled image rather than the source. Each
+    /// stretch of data follows as `0x1234 .data <length>`, so that the image
+    /// can be disassembled without decoding it.
+    pub fn save_symbols(&self, path: &str) -> io::Result<()> {
+        let mut text: String = self
+            .symbols()
+            .iter()
+            .map(|(addr, lbl)| format!("0x{:04x} {}\n", addr, lbl))
+            .collect();
+        for (addr, len) in self.data.iter() {
+            text.push_str(&format!("0x{:04x} {} {}\n", addr, DATA_MARK, len));
+        }
+        fs::write(path, text)
+    }
+
+    /// Reads the labels from a symbol file written by `save_symbols`.
+    pub fn load_symbols(path: &str) -> io::Result<Self> {
+        let mut debug = Self::default();
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let parsed = line.split_once(' ').and_then(|(addr, lbl)| {
+                let addr = u16::from_str_radix(addr.strip_prefix("0x")?, 16).ok()?;
+                Some((addr, lbl.trim()))
+            });
+            let data = parsed.and_then(|(addr, lbl)| {
+                let len = lbl.strip_prefix(DATA_MARK)?.trim().parse().ok()?;
+                Some((addr, len))
+            });
+            match (parsed, data) {
+                (_, Some((addr, len))) => debug.add_data(addr, len),
+                (Some((addr, lbl)), None) => {
+                    debug.labels.insert(lbl.to_string(), addr);
+                }
+                (None, _) if line.trim().is_empty() => {}
+                (None, _) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}:{}: expected `0x<addr> <label>`", path, i + 1),
+                    ))
+                }
+            }
+        }
+        Ok(debug)
+    }
+
+    /// The closest label at or before `addr`, with the offset from it.
+    pub fn label_before(&self, addr: u16) -> Option<(&str, u16)> {
+        self.labels
+            .iter()
+            .filter(|(_, a)| **a <= addr)
+            .max_by(|(la, a), (lb, b)| a.cmp(b).then_with(|| lb.cmp(la)))
+            .map(|(lbl, a)| (lbl.as_str(), addr - a))
+    }
+}
@@ -0,0 +1,119 @@
+use super::{Bus, Device};
+use crate::arch::{ArchError, Instruction, REG_NAMES};
+
+/// What the input port reads as once input has run out.
+pub const EOF: u16 = 0xFFFF;
+
+/// Character output on port 0 and character input, a byte per read, on
+/// port 1. Port 2 takes the address of a line buffer, and loading from it
+/// reads a whole line of input into the buffer, keeping at most as many
+/// characters as were last stored to port 3 (no limit until then). Words
+/// stored to port 4 are printed as four hex digits. Storing to port 5 prints
+/// the value stored, the instruction storing it and the registers to stderr,
+/// for debugging from inside the guest. Storing anything but 0 to port 6
+/// stops the program with `ArchError::AssertionFailed`.
+pub struct Console {
+    line_addr: u16,
+    line_max: u16,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            line_addr: 0,
+            line_max: 0xFFFF,
+        }
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Console {
+    fn ports(&self) -> u16 {
+        7
+    }
+
+    fn read(&mut self, port: u16, bus: &mut Bus) -> Result<u16, ArchError> {
+        match port {
+            1 => {
+                let byte = match bus.input.fill_buf()?.first() {
+                    Some(&byte) => byte,
+                    None => return Ok(EOF),
+                };
+                bus.input.consume(1);
+                Ok(byte as u16)
+            }
+            2 => read_line_into(bus, self.line_addr, self.line_max),
+            3 => Ok(self.line_max),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError> {
+        match port {
+            0 => {
+                bus.output.write_all(&[val as u8])?;
+                bus.output.flush()?;
+            }
+            2 => self.line_addr = val,
+            3 => self.line_max = val,
+            4 => {
+                write!(bus.output, "{:04x}", val)?;
+                bus.output.flush()?;
+            }
+            5 => eprint!("{}", dump(val, bus)),
+            6 if val != 0 => {
+                return Err(ArchError::AssertionFailed {
+                    val,
+                    addr: bus.regs[0].wrapping_sub(2),
+                })
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// What a store to the debug port prints: the value stored, the address and
+/// text of the instruction storing it, and the registers.
+fn dump(val: u16, bus: &Bus) -> String {
+    let addr = bus.regs[0].wrapping_sub(2);
+    let ins = match (bus.mem.read(addr), bus.mem.read(addr.wrapping_add(1))) {
+        (Ok(op), Ok(imm)) => Instruction::new(op, imm).disassemble().unwrap_or_default(),
+        _ => String::new(),
+    };
+    let mut text = format!(
+        "debug 0x{:04x} ({}) at 0x{:04x}  {}\n ",
+        val, val as i16, addr, ins
+    );
+    for (name, reg) in REG_NAMES.iter().zip(bus.regs.iter()) {
+        text.push_str(&format!(" {}=0x{:04x}", name, reg));
+    }
+    text.push('\n');
+    text
+}
+
+/// Reads a line of input into memory at `addr`, one character per word
+/// without the newline, keeping at most `max` of them and following them
+/// with a 0 word. Returns how many were kept, or `EOF` once input has run
+/// out.
+pub(crate) fn read_line_into(bus: &mut Bus, addr: u16, max: u16) -> Result<u16, ArchError> {
+    let mut line = String::new();
+    if bus.input.read_line(&mut line)? == 0 {
+        return Ok(EOF);
+    }
+    let line = line.trim_end_matches(['\n', '\r']);
+    let mut addr = addr;
+    let mut kept = 0;
+    for byte in line.bytes().take(max as usize) {
+        bus.mem.write(addr, byte as u16)?;
+        addr = addr.wrapping_add(1);
+        kept += 1;
+    }
+    bus.mem.write(addr, 0)?;
+    Ok(kept)
+}
@@ -0,0 +1,67 @@
+use super::{Bus, Device};
+use crate::arch::ArchError;
+
+const SOURCE: u16 = 0;
+const DEST: u16 = 1;
+const LENGTH: u16 = 2;
+const CONTROL: u16 = 3;
+
+/// Copies blocks of memory for the guest.
+///
+/// Ports 0, 1 and 2 hold the source address, destination address and length
+/// in words. Storing anything to port 3 starts the copy, and port 3 reads as
+/// 1 once it has finished. Overlapping blocks are copied as if through a
+/// temporary buffer.
+#[derive(Debug)]
+pub struct Dma {
+    source: u16,
+    dest: u16,
+    length: u16,
+    done: bool,
+}
+
+impl Default for Dma {
+    fn default() -> Self {
+        Self {
+            source: 0,
+            dest: 0,
+            length: 0,
+            done: true,
+        }
+    }
+}
+
+impl Device for Dma {
+    fn ports(&self) -> u16 {
+        4
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            SOURCE => self.source,
+            DEST => self.dest,
+            LENGTH => self.length,
+            _ => self.done as u16,
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError> {
+        match port {
+            SOURCE => self.source = val,
+            DEST => self.dest = val,
+            LENGTH => self.length = val,
+            CONTROL => {
+                self.done = false;
+                let block = (0..self.length)
+                    .map(|i| bus.mem.read(self.source.wrapping_add(i)))
+                    .collect::<Result<Vec<u16>, ArchError>>()?;
+                for (i, word) in block.into_iter().enumerate() {
+                    bus.mem.write(self.dest.wrapping_add(i as u16), word)?;
+                }
+                self.done = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use super::{Bus, Device, VECTOR_KEYBOARD};
+use crate::arch::ArchError;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+const NEXT: u16 = 0;
+const COUNT: u16 = 1;
+const X: u16 = 2;
+const Y: u16 = 3;
+
+/// How many events are kept before new ones are dropped.
+const CAPACITY: usize = 256;
+
+/// Key codes for keys without a character. Other keys use the ASCII code of
+/// their character, with letters in lower case.
+pub const KEY_LEFT: u16 = 0x100;
+pub const KEY_UP: u16 = 0x101;
+pub const KEY_RIGHT: u16 = 0x102;
+pub const KEY_DOWN: u16 = 0x103;
+pub const KEY_SHIFT: u16 = 0x104;
+pub const KEY_CTRL: u16 = 0x105;
+pub const KEY_ALT: u16 = 0x106;
+
+/// Something the user did, as a host reports it to the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    KeyDown(u16),
+    KeyUp(u16),
+    MouseMove { x: u16, y: u16 },
+    MouseDown { button: u16, x: u16, y: u16 },
+    MouseUp { button: u16, x: u16, y: u16 },
+}
+
+impl InputEvent {
+    /// The word the guest reads: the kind in the top four bits and the key
+    /// code or mouse button below.
+    fn word(&self) -> u16 {
+        let (kind, code) = match *self {
+            InputEvent::KeyDown(key) => (1, key),
+            InputEvent::KeyUp(key) => (2, key),
+            InputEvent::MouseMove { .. } => (3, 0),
+            InputEvent::MouseDown { button, .. } => (4, button),
+            InputEvent::MouseUp { button, .. } => (5, button),
+        };
+        kind << 12 | (code & 0xFFF)
+    }
+
+    fn position(&self) -> Option<(u16, u16)> {
+        match *self {
+            InputEvent::MouseMove { x, y }
+            | InputEvent::MouseDown { x, y, .. }
+            | InputEvent::MouseUp { x, y, .. } => Some((x, y)),
+            _ => None,
+        }
+    }
+}
+
+/// The host's end of an `EventDevice`. Clones share the same queue.
+#[derive(Debug, Clone, Default)]
+pub struct EventQueue {
+    shared: Arc<Mutex<Queue>>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    events: VecDeque<InputEvent>,
+    /// Whether events have arrived since the device last raised an interrupt.
+    arrived: bool,
+}
+
+impl EventQueue {
+    /// Queues an event for the guest, dropping it if the queue is full.
+    pub fn push(&self, event: InputEvent) {
+        let mut queue = self.shared.lock().unwrap();
+        if queue.events.len() < CAPACITY {
+            queue.events.push_back(event);
+            queue.arrived = true;
+        }
+    }
+
+    /// Takes every event waiting, for devices that keep their own queue.
+    pub(super) fn take(&self) -> Vec<InputEvent> {
+        let mut queue = self.shared.lock().unwrap();
+        queue.arrived = false;
+        queue.events.drain(..).collect()
+    }
+}
+
+/// A queue of keyboard and mouse events from the host.
+///
+/// Loading from port 0 takes the oldest event, as a word with its kind in
+/// the top four bits (1 key down, 2 key up, 3 mouse move, 4 mouse button
+/// down, 5 mouse button up) and the key code or mouse button (1 left, 2
+/// right, 3 middle) in the rest, or 0 if there are none. Port 1 reads as
+/// the number of events waiting. Ports 2 and 3 are the x and y of the last
+/// mouse event taken, in display pixels. New events raise
+/// `VECTOR_KEYBOARD`.
+#[derive(Debug, Default)]
+pub struct EventDevice {
+    queue: EventQueue,
+    x: u16,
+    y: u16,
+}
+
+impl EventDevice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Device for EventDevice {
+    fn ports(&self) -> u16 {
+        4
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            NEXT => {
+                let event = self.queue.shared.lock().unwrap().events.pop_front();
+                match event {
+                    Some(event) => {
+                        if let Some((x, y)) = event.position() {
+                            self.x = x;
+                            self.y = y;
+                        }
+                        event.word()
+                    }
+                    None => 0,
+                }
+            }
+            COUNT => self.queue.shared.lock().unwrap().events.len() as u16,
+            X => self.x,
+            Y => self.y,
+            _ => 0,
+        })
+    }
+
+    fn write(&mut self, _port: u16, _val: u16, _bus: &mut Bus) -> Result<(), ArchError> {
+        Ok(())
+    }
+
+    fn interrupt(&mut self, _cycles: u64) -> Option<u16> {
+        let mut queue = self.queue.shared.lock().unwrap();
+        if !queue.arrived {
+            return None;
+        }
+        queue.arrived = false;
+        Some(VECTOR_KEYBOARD)
+    }
+
+    fn event_queue(&self) -> Option<EventQueue> {
+        Some(self.queue.clone())
+    }
+}
@@ -0,0 +1,116 @@
+use super::{Bus, Device, Frame, PALETTE};
+use crate::arch::ArchError;
+
+const ADDRESS: u16 = 0;
+const MODE: u16 = 1;
+const PRESENT: u16 = 2;
+/// The first of the 16 palette entries.
+const PALETTE_BASE: u16 = 4;
+
+/// One word per pixel, RGB565.
+const DIRECT: u16 = 0;
+/// Four pixels per word, each an index into the palette.
+const INDEXED: u16 = 1;
+
+/// A display whose pixels live in guest memory.
+///
+/// Port 0 holds the address of the pixels, row by row, and port 1 the mode.
+/// In mode 0 each pixel is a word of RGB565 colour. In mode 1 a word holds
+/// four pixels, leftmost in the top four bits, each picking one of the 16
+/// palette entries at ports 4 to 19, which are RGB565 colours the guest can
+/// change at any time; a full screen then takes a quarter of the memory,
+/// and rewriting the palette recolours it without touching the pixels.
+/// Storing to port 2 draws a frame; port 2 reads as the number drawn.
+#[derive(Debug)]
+pub struct Framebuffer {
+    address: u16,
+    mode: u16,
+    palette: [u16; 16],
+    frame: Frame,
+    frames: u16,
+    /// Where to save each frame, if anywhere.
+    image: Option<String>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u16, height: u16, image: Option<&str>) -> Self {
+        let mut palette = [0; 16];
+        for (entry, color) in palette.iter_mut().zip(PALETTE.iter()) {
+            *entry = to_rgb565(*color);
+        }
+        Self {
+            address: 0,
+            mode: DIRECT,
+            palette,
+            frame: Frame::new(width as usize, height as usize),
+            frames: 0,
+            image: image.map(str::to_string),
+        }
+    }
+
+    fn draw(&mut self, bus: &Bus) -> Result<(), ArchError> {
+        let (width, height) = (self.frame.width, self.frame.height);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = y * width + x;
+                let color = if self.mode == INDEXED {
+                    let word = bus
+                        .mem
+                        .read(self.address.wrapping_add((pixel / 4) as u16))?;
+                    let index = (word >> (12 - 4 * (pixel % 4))) & 0xF;
+                    self.palette[index as usize]
+                } else {
+                    bus.mem.read(self.address.wrapping_add(pixel as u16))?
+                };
+                self.frame.set(x, y, from_rgb565(color));
+            }
+        }
+        self.frames = self.frames.wrapping_add(1);
+        if let Some(path) = &self.image {
+            self.frame.save_ppm(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn to_rgb565(color: u32) -> u16 {
+    let (r, g, b) = (color >> 16, (color >> 8) & 0xFF, color & 0xFF);
+    ((r >> 3) << 11 | (g >> 2) << 5 | (b >> 3)) as u16
+}
+
+fn from_rgb565(color: u16) -> u32 {
+    let color = color as u32;
+    let (r, g, b) = (color >> 11, (color >> 5) & 0x3F, color & 0x1F);
+    (r * 255 / 31) << 16 | (g * 255 / 63) << 8 | (b * 255 / 31)
+}
+
+impl Device for Framebuffer {
+    fn ports(&self) -> u16 {
+        PALETTE_BASE + 16
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            ADDRESS => self.address,
+            MODE => self.mode,
+            PRESENT => self.frames,
+            PALETTE_BASE.. => self.palette[(port - PALETTE_BASE) as usize],
+            _ => 0,
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError> {
+        match port {
+            ADDRESS => self.address = val,
+            MODE => self.mode = val,
+            PRESENT => self.draw(bus)?,
+            PALETTE_BASE.. => self.palette[(port - PALETTE_BASE) as usize] = val,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn frame(&self) -> Option<&Frame> {
+        Some(&self.frame)
+    }
+}
@@ -0,0 +1,70 @@
+use super::{Bus, Device};
+use crate::arch::ArchError;
+
+const ALLOC: u16 = 0;
+const BREAK: u16 = 1;
+
+/// A bump allocator for memory above the program image.
+///
+/// Storing n to port 0 allocates a block of n words; port 0 then reads as
+/// the block's address, or 0 if there wasn't room for it. Port 1 reads as
+/// the break, the first address not yet allocated, which starts just after
+/// the program. Storing an address returned earlier to port 1 frees that
+/// block and every block allocated after it. Blocks are never allocated in
+/// the top `stack` words of memory, which are left to the stack.
+#[derive(Debug)]
+pub struct Heap {
+    stack: u16,
+    /// The break, once the program's size is known.
+    brk: Option<u16>,
+    last: u16,
+}
+
+impl Heap {
+    pub fn new(stack: u16) -> Self {
+        Self {
+            stack,
+            brk: None,
+            last: 0,
+        }
+    }
+
+    fn brk(&mut self, bus: &Bus) -> u16 {
+        *self.brk.get_or_insert(bus.mem.program().len() as u16)
+    }
+}
+
+impl Device for Heap {
+    fn ports(&self) -> u16 {
+        2
+    }
+
+    fn read(&mut self, port: u16, bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            ALLOC => self.last,
+            _ => self.brk(bus),
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError> {
+        let brk = self.brk(bus);
+        match port {
+            ALLOC => {
+                let limit = bus.mem.size().saturating_sub(self.stack as usize);
+                let end = brk as usize + val as usize;
+                if end <= limit {
+                    self.last = brk;
+                    self.brk = Some(end as u16);
+                } else {
+                    self.last = 0;
+                }
+            }
+            BREAK => {
+                let start = bus.mem.program().len() as u16;
+                self.brk = Some(val.clamp(start, brk));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use super::{Bus, Device};
+use crate::arch::ArchError;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+const COMMAND: u16 = 0;
+const DATA: u16 = 1;
+
+const COLUMNS: usize = 16;
+/// How many characters of display memory each line has, of which `COLUMNS`
+/// are shown at a time.
+const LINE_LEN: u8 = 40;
+/// Where the second line starts in display memory.
+const LINE_2: u8 = 0x40;
+/// Display memory in one line mode, all of it one long line.
+const ONE_LINE_LEN: u8 = 80;
+/// Words of character generator memory: eight rows for each of the eight
+/// characters a program can design.
+const CGRAM_LEN: usize = 64;
+/// How often a display shown in the terminal is redrawn at most.
+const REDRAW: Duration = Duration::from_millis(20);
+
+/// A 16x2 character LCD driven like the Hitachi HD44780 found on countless
+/// embedded boards, drawn in the terminal.
+///
+/// Storing to port 0 runs a command: 0x01 clears the display, 0x02 sends the
+/// cursor home, 0x04 to 0x07 set the entry mode (bit 1 moves the cursor
+/// right after each character, bit 0 shifts the display with it), 0x08 to
+/// 0x0F turn the display (bit 2), cursor (bit 1) and blinking (bit 0) on or
+/// off, 0x10 to 0x1F move the cursor or (with bit 3) shift the display, left
+/// or (with bit 2) right, 0x20 to 0x3F set the function (bit 3 chooses two
+/// lines), 0x40 to 0x7F set the address in character generator memory and
+/// 0x80 to 0xFF the address in display memory, where the second line starts
+/// at 0x40. Loading from port 0 gives the address counter; the busy flag in
+/// bit 7 is never set, since commands finish at once. Storing to port 1
+/// writes a character at the address counter and loading from it reads one,
+/// moving the counter as the entry mode says.
+///
+/// As on the real part, the display starts off and in one line mode, so
+/// programs begin with the usual initialization, such as 0x38, 0x0C, 0x06
+/// and 0x01. Characters 0x20 to 0x7E show as ASCII and the rest, including
+/// the eight a program can design, as a shaded block.
+///
+/// When stderr is a terminal the display is drawn in its top right corner
+/// and redrawn as it changes. Otherwise its final contents are printed to
+/// stderr when the program ends.
+#[derive(Debug)]
+pub struct Lcd {
+    ddram: [u8; 0x80],
+    cgram: [u8; CGRAM_LEN],
+    /// The address counter, in character generator memory if `in_cgram`.
+    addr: u8,
+    in_cgram: bool,
+    increment: bool,
+    shift_with_entry: bool,
+    display_on: bool,
+    cursor: bool,
+    blink: bool,
+    two_lines: bool,
+    /// How far the display has been shifted left.
+    shift: u8,
+    /// Whether the terminal is drawn on as the display changes.
+    live: bool,
+    dirty: bool,
+    drawn: Option<Instant>,
+}
+
+impl Lcd {
+    pub fn new() -> Self {
+        Self {
+            ddram: [b' '; 0x80],
+            cgram: [0; CGRAM_LEN],
+            addr: 0,
+            in_cgram: false,
+            increment: true,
+            shift_with_entry: false,
+            display_on: false,
+            cursor: false,
+            blink: false,
+            two_lines: false,
+            shift: 0,
+            live: cfg!(not(target_os = "wasi")) && io::stderr().is_terminal(),
+            dirty: false,
+            drawn: None,
+        }
+    }
+
+    fn line_len(&self) -> u8 {
+        if self.two_lines {
+            LINE_LEN
+        } else {
+            ONE_LINE_LEN
+        }
+    }
+
+    fn command(&mut self, cmd: u8) {
+        match cmd {
+            0x80..=0xFF => {
+                self.addr = cmd & 0x7F;
+                self.in_cgram = false;
+            }
+            0x40..=0x7F => {
+                self.addr = cmd & 0x3F;
+                self.in_cgram = true;
+            }
+            0x20..=0x3F => self.two_lines = cmd & 0x08 != 0,
+            0x10..=0x1F => {
+                let right = cmd & 0x04 != 0;
+                if cmd & 0x08 != 0 {
+                    self.shift_display(!right);
+                } else {
+                    self.move_addr(right);
+                }
+            }
+            0x08..=0x0F => {
+                self.display_on = cmd & 0x04 != 0;
+                self.cursor = cmd & 0x02 != 0;
+                self.blink = cmd & 0x01 != 0;
+            }
+            0x04..=0x07 => {
+                self.increment = cmd & 0x02 != 0;
+                self.shift_with_entry = cmd & 0x01 != 0;
+            }
+            0x02..=0x03 => {
+                self.addr = 0;
+                self.in_cgram = false;
+                self.shift = 0;
+            }
+            0x01 => {
+                self.ddram = [b' '; 0x80];
+                self.addr = 0;
+                self.in_cgram = false;
+                self.increment = true;
+                self.shift = 0;
+            }
+            _ => {}
+        }
+        self.dirty = true;
+    }
+
+    /// Moves the address counter a character right or left, wrapping
+    /// around the memory it points into.
+    fn move_addr(&mut self, right: bool) {
+        if self.in_cgram {
+            let len = CGRAM_LEN as u8;
+            self.addr = if right {
+                (self.addr + 1) % len
+            } else {
+                (self.addr + len - 1) % len
+            };
+            return;
+        }
+        let len = self.line_len();
+        let (line, col) = if self.two_lines && self.addr >= LINE_2 {
+            (1, (self.addr - LINE_2).min(len - 1))
+        } else {
+            (0, self.addr.min(len - 1))
+        };
+        let (line, col) = match (right, col) {
+            (true, col) if col == len - 1 => (line ^ self.two_lines as u8, 0),
+            (true, col) => (line, col + 1),
+            (false, 0) => (line ^ self.two_lines as u8, len - 1),
+            (false, col) => (line, col - 1),
+        };
+        self.addr = line * LINE_2 + col;
+    }
+
+    fn shift_display(&mut self, left: bool) {
+        let len = self.line_len();
+        self.shift = if left {
+            (self.shift + 1) % len
+        } else {
+            (self.shift + len - 1) % len
+        };
+    }
+
+    fn write_data(&mut self, val: u8) {
+        if self.in_cgram {
+            self.cgram[self.addr as usize] = val & 0x1F;
+        } else {
+            self.ddram[self.addr as usize] = val;
+            if self.shift_with_entry {
+                self.shift_display(self.increment);
+            }
+        }
+        self.move_addr(self.increment);
+        self.dirty = true;
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let val = if self.in_cgram {
+            self.cgram[self.addr as usize]
+        } else {
+            self.ddram[self.addr as usize]
+        };
+        self.move_addr(self.increment);
+        val
+    }
+
+    /// The display memory address shown at `col` of `line`, if the line is
+    /// shown at all.
+    fn shown_at(&self, line: u8, col: u8) -> Option<u8> {
+        if line == 1 && !self.two_lines {
+            return None;
+        }
+        let len = self.line_len();
+        Some(line * LINE_2 + (col + self.shift) % len)
+    }
+
+    /// The display as four lines of text, framed. The cursor is underlined,
+    /// or shown in reverse when blinking, using terminal escapes if `styled`.
+    fn render(&self, styled: bool) -> Vec<String> {
+        let mut lines = vec![format!("┌{}┐", "─".repeat(COLUMNS))];
+        for line in 0..2 {
+            let mut text = String::from("│");
+            for col in 0..COLUMNS as u8 {
+                let addr = self.shown_at(line, col);
+                let chr = match addr {
+                    Some(addr) if self.display_on => match self.ddram[addr as usize] {
+                        chr @ 0x20..=0x7E => chr as char,
+                        _ => '▒',
+                    },
+                    _ => ' ',
+                };
+                let at_cursor = self.display_on && !self.in_cgram && addr == Some(self.addr);
+                if styled && at_cursor && (self.cursor || self.blink) {
+                    let style = if self.blink { 7 } else { 4 };
+                    let _ = write!(text, "\x1b[{}m{}\x1b[0m", style, chr);
+                } else {
+                    text.push(chr);
+                }
+            }
+            text.push('│');
+            lines.push(text);
+        }
+        lines.push(format!("└{}┘", "─".repeat(COLUMNS)));
+        lines
+    }
+
+    /// Draws the display in the terminal's top right corner, leaving the
+    /// cursor where it was.
+    fn draw_live(&mut self) -> io::Result<()> {
+        self.dirty = false;
+        self.drawn = Some(Instant::now());
+        #[cfg(not(target_os = "wasi"))]
+        let width = crossterm::terminal::size().map_or(80, |(width, _)| width as usize);
+        #[cfg(target_os = "wasi")]
+        let width = 80;
+        let col = width.saturating_sub(COLUMNS + 2) + 1;
+        let mut out = String::from("\x1b7");
+        for (row, line) in self.render(true).iter().enumerate() {
+            let _ = write!(out, "\x1b[{};{}H{}", row + 1, col, line);
+        }
+        out.push_str("\x1b8");
+        let mut stderr = io::stderr().lock();
+        stderr.write_all(out.as_bytes())?;
+        stderr.flush()
+    }
+}
+
+impl Default for Lcd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Lcd {
+    fn ports(&self) -> u16 {
+        2
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            COMMAND => self.addr as u16,
+            DATA => self.read_data() as u16,
+            _ => 0,
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, _bus: &mut Bus) -> Result<(), ArchError> {
+        match port {
+            COMMAND => self.command(val as u8),
+            DATA => self.write_data(val as u8),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn interrupt(&mut self, _cycles: u64) -> Option<u16> {
+        if self.live && self.dirty && self.drawn.is_none_or(|at| at.elapsed() >= REDRAW) {
+            let _ = self.draw_live();
+        }
+        None
+    }
+
+    fn halted(&mut self) -> Result<(), ArchError> {
+        if self.live {
+            if self.dirty {
+                self.draw_live()?;
+            }
+        } else if self.dirty || self.drawn.is_some() {
+            eprintln!("{}", self.render(false).join("\n"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use super::{Bus, Device, VECTOR_DEVICE};
+use crate::arch::ArchError;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+/// Exchanges words with another guest.
+///
+/// Storing to port 0 sends a word and loading from it receives one, waiting
+/// until one arrives. Port 1 reads as the number of words waiting to be
+/// received. If the other side has gone away, sends are dropped and receives
+/// read as 0. Under `run_async` a receive with nothing waiting yields instead
+/// of blocking the thread. Words arriving raise `VECTOR_DEVICE`.
+pub struct Mailbox {
+    tx: Sender<u16>,
+    rx: Receiver<u16>,
+    pending: VecDeque<u16>,
+}
+
+impl Mailbox {
+    /// A mailbox that sends through the file at `send` and receives from the
+    /// one at `receive`, normally named pipes shared with another teenyat.
+    /// Words travel as two little endian bytes. Fails where threads aren't
+    /// available, as under WASI.
+    pub fn from_pipes(send: &str, receive: &str) -> io::Result<Self> {
+        let (out_tx, out_rx) = mpsc::channel::<u16>();
+        let (in_tx, in_rx) = mpsc::channel();
+        // Opening a pipe waits for the other end, so both ends are opened on
+        // their own threads to let two VMs start in either order.
+        let send = send.to_string();
+        thread::Builder::new().spawn(move || {
+            let mut pipe = match OpenOptions::new().write(true).open(&send) {
+                Ok(pipe) => pipe,
+                Err(_) => return,
+            };
+            for word in out_rx {
+                if pipe.write_all(&word.to_le_bytes()).is_err() {
+                    return;
+                }
+            }
+        })?;
+        let receive = receive.to_string();
+        thread::Builder::new().spawn(move || {
+            let mut pipe = match File::open(&receive) {
+                Ok(pipe) => pipe,
+                Err(_) => return,
+            };
+            let mut buf = [0u8; 2];
+            while pipe.read_exact(&mut buf).is_ok() {
+                if in_tx.send(u16::from_le_bytes(buf)).is_err() {
+                    return;
+                }
+            }
+        })?;
+        Ok(Self {
+            tx: out_tx,
+            rx: in_rx,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl Device for Mailbox {
+    fn ports(&self) -> u16 {
+        2
+    }
+
+    fn read(&mut self, port: u16, bus: &mut Bus) -> Result<u16, ArchError> {
+        self.pending.extend(self.rx.try_iter());
+        if port != 0 {
+            return Ok(self.pending.len() as u16);
+        }
+        if let Some(word) = self.pending.pop_front() {
+            return Ok(word);
+        }
+        if bus.nonblocking {
+            return match self.rx.try_recv() {
+                Ok(word) => Ok(word),
+                Err(TryRecvError::Empty) => Err(ArchError::WouldBlock),
+                Err(TryRecvError::Disconnected) => Ok(0),
+            };
+        }
+        Ok(self.rx.recv().unwrap_or(0))
+    }
+
+    fn write(&mut self, port: u16, val: u16, _bus: &mut Bus) -> Result<(), ArchError> {
+        if port == 0 {
+            let _ = self.tx.send(val);
+        }
+        Ok(())
+    }
+
+    fn interrupt(&mut self, _cycles: u64) -> Option<u16> {
+        let waiting = self.pending.len();
+        self.pending.extend(self.rx.try_iter());
+        if self.pending.len() > waiting {
+            Some(VECTOR_DEVICE)
+        } else {
+            None
+        }
+    }
+}
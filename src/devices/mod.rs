@@ -0,0 +1,259 @@
+//! Memory-mapped devices.
+//!
+//! A device occupies a run of consecutive addresses starting at the base it
+//! is attached at. Loads and stores to those addresses are handed to the
+//! device instead of memory, with the offset from the base as the port.
+
+mod console;
+mod dma;
+mod events;
+mod framebuffer;
+mod heap;
+mod lcd;
+mod mailbox;
+mod ps2;
+mod rng;
+mod rtc;
+mod shared;
+mod spi;
+mod sprites;
+mod timer;
+mod turtle;
+
+pub(crate) use console::read_line_into;
+pub use console::{Console, EOF};
+pub use dma::Dma;
+pub use events::{
+    EventDevice, EventQueue, InputEvent, KEY_ALT, KEY_CTRL, KEY_DOWN, KEY_LEFT, KEY_RIGHT,
+    KEY_SHIFT, KEY_UP,
+};
+pub use framebuffer::Framebuffer;
+pub use heap::Heap;
+pub use lcd::Lcd;
+pub use mailbox::Mailbox;
+pub use ps2::Ps2Keyboard;
+pub use rng::Rng;
+pub use rtc::Rtc;
+pub use shared::Shared;
+pub use spi::{Eeprom, Slave, Spi, Thermometer};
+pub use sprites::SpriteDisplay;
+pub use timer::Timer;
+pub use turtle::Turtle;
+
+use crate::arch::{ArchError, Memory};
+use crate::clock::Clock;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use toml::value::Table;
+
+/// The parts of the machine a device can reach while handling an access.
+pub struct Bus<'a> {
+    pub mem: &'a mut Memory,
+    pub input: &'a mut dyn BufRead,
+    pub output: &'a mut dyn Write,
+    /// The registers, indexed by number as in `REG_NAMES`, as the
+    /// instruction making the access found them; pc already holds the
+    /// address of the next instruction.
+    pub regs: [u16; 8],
+    /// The machine's clock, for devices that tell or pass the time.
+    pub clock: &'a mut Clock,
+    /// Whether the VM is running under `run_async`. Devices that would wait
+    /// for outside input should then return `ArchError::WouldBlock`, and the
+    /// access is retried later.
+    pub nonblocking: bool,
+}
+
+/// Devices are `Send` so that a VM can be moved to a worker thread.
+pub trait Device: Send {
+    /// The number of consecutive addresses the device occupies.
+    fn ports(&self) -> u16;
+
+    fn read(&mut self, port: u16, bus: &mut Bus) -> Result<u16, ArchError>;
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError>;
+
+    /// Called after every instruction with the time on the machine's clock,
+    /// in cycles (see `Clock::cycles`). Returns the vector of an interrupt to
+    /// raise, such as `VECTOR_TIMER`, if the device wants the guest's
+    /// attention.
+    fn interrupt(&mut self, _cycles: u64) -> Option<u16> {
+        None
+    }
+
+    /// Called once when the program ends, for devices that save what the
+    /// guest produced.
+    fn halted(&mut self) -> Result<(), ArchError> {
+        Ok(())
+    }
+
+    /// The picture a display device is showing, for hosts that draw it.
+    fn frame(&self) -> Option<&Frame> {
+        None
+    }
+
+    /// Where a host sends keyboard and mouse events, for devices that take
+    /// them.
+    fn event_queue(&self) -> Option<EventQueue> {
+        None
+    }
+}
+
+/// The interrupt vectors the built in devices raise; see `TeenyAT::raise`.
+pub const VECTOR_TIMER: u16 = 0;
+pub const VECTOR_KEYBOARD: u16 = 1;
+pub const VECTOR_DEVICE: u16 = 2;
+
+/// The 16 colours of the display devices' 4-bit pixels, as `0xRRGGBB`.
+const PALETTE: [u32; 16] = [
+    0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8, 0xff004d,
+    0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+];
+
+/// An image produced by a display device, row by row, each pixel `0xRRGGBB`.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Frame {
+    /// A black frame.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    /// Sets a pixel, ignoring coordinates outside the frame.
+    pub fn set(&mut self, x: usize, y: usize, color: u32) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    /// Writes the frame as a binary PPM image.
+    pub fn save_ppm(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in self.pixels.iter() {
+            out.write_all(&pixel.to_be_bytes()[1..])?;
+        }
+        out.flush()
+    }
+}
+
+/// Creates a built in device from the name used in machine configs and the
+/// other settings in its `[[device]]` table.
+pub fn create(kind: &str, options: &Table) -> io::Result<Box<dyn Device>> {
+    match kind {
+        "console" => Ok(Box::new(Console::new())),
+        "dma" => Ok(Box::new(Dma::default())),
+        "events" => Ok(Box::new(EventDevice::new())),
+        "framebuffer" => Ok(Box::new(Framebuffer::new(
+            int_option(kind, options, "width", 160)?,
+            int_option(kind, options, "height", 120)?,
+            options.get("image").and_then(|val| val.as_str()),
+        ))),
+        "heap" => Ok(Box::new(Heap::new(int_option(
+            kind, options, "stack", 1024,
+        )?))),
+        "lcd" => Ok(Box::new(Lcd::new())),
+        "mailbox" => Ok(Box::new(Mailbox::from_pipes(
+            string_option(kind, options, "send")?,
+            string_option(kind, options, "receive")?,
+        )?)),
+        "ps2" => Ok(Box::new(Ps2Keyboard::new())),
+        "rng" => Ok(Box::new(Rng::new(u64_option(kind, options, "seed")?))),
+        "rtc" => Ok(Box::new(Rtc::new(
+            u64_option(kind, options, "epoch")?.unwrap_or(0),
+        ))),
+        "spi" => Ok(Box::new(spi(kind, options)?)),
+        "sprites" => Ok(Box::new(SpriteDisplay::new(
+            options.get("image").and_then(|val| val.as_str()),
+        ))),
+        "timer" => Ok(Box::new(Timer::default())),
+        "turtle" => Ok(Box::new(Turtle::new(
+            string_option(kind, options, "svg")?,
+            int_option(kind, options, "width", 400)?,
+            int_option(kind, options, "height", 400)?,
+        ))),
+        _ => Err(invalid(format!("Unknown device kind: {}", kind))),
+    }
+}
+
+/// An `Spi` bus with the chips named in `slaves` attached, the EEPROM and
+/// then the thermometer unless it says otherwise.
+fn spi(kind: &str, options: &Table) -> io::Result<Spi> {
+    let names = match options.get("slaves") {
+        None => vec!["eeprom", "thermometer"],
+        Some(val) => val
+            .as_array()
+            .and_then(|names| names.iter().map(|name| name.as_str()).collect())
+            .ok_or_else(|| invalid(format!("{} needs `slaves` to be a list of names", kind)))?,
+    };
+    let mut slaves: Vec<Box<dyn Slave>> = Vec::new();
+    for name in names {
+        slaves.push(match name {
+            "eeprom" => Box::new(Eeprom::new(
+                options.get("eeprom").and_then(|val| val.as_str()),
+            )?),
+            "thermometer" => {
+                let celsius = match options.get("celsius") {
+                    None => 21.0,
+                    Some(val) => val
+                        .as_float()
+                        .or_else(|| val.as_integer().map(|n| n as f64))
+                        .ok_or_else(|| {
+                            invalid(format!("{} needs `celsius` to be a number", kind))
+                        })?,
+                };
+                Box::new(Thermometer::new(celsius))
+            }
+            _ => return Err(invalid(format!("Unknown {} slave: {}", kind, name))),
+        });
+    }
+    Ok(Spi::new(slaves))
+}
+
+fn string_option<'a>(kind: &str, options: &'a Table, key: &str) -> io::Result<&'a str> {
+    options
+        .get(key)
+        .and_then(|val| val.as_str())
+        .ok_or_else(|| invalid(format!("{} needs a `{}` string", kind, key)))
+}
+
+/// A whole number setting that fits in a word, or `default` if it's absent.
+fn int_option(kind: &str, options: &Table, key: &str, default: u16) -> io::Result<u16> {
+    match options.get(key) {
+        None => Ok(default),
+        Some(val) => val
+            .as_integer()
+            .filter(|n| (0..=0xFFFF).contains(n))
+            .map(|n| n as u16)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "{} needs `{}` to be a number up to 65535",
+                    kind, key
+                ))
+            }),
+    }
+}
+
+/// A setting such as `seed` that may be any whole number that fits in 64
+/// bits.
+fn u64_option(kind: &str, options: &Table, key: &str) -> io::Result<Option<u64>> {
+    match options.get(key) {
+        None => Ok(None),
+        Some(val) => val
+            .as_integer()
+            .map(|n| Some(n as u64))
+            .ok_or_else(|| invalid(format!("{} needs `{}` to be a number", kind, key))),
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
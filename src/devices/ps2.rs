@@ -0,0 +1,148 @@
+use super::{
+    Bus, Device, EventQueue, InputEvent, KEY_ALT, KEY_CTRL, KEY_DOWN, KEY_LEFT, KEY_RIGHT,
+    KEY_SHIFT, KEY_UP, VECTOR_KEYBOARD,
+};
+use crate::arch::ArchError;
+use std::collections::VecDeque;
+
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+
+/// How many scancode bytes are kept before whole key presses are dropped.
+const CAPACITY: usize = 64;
+
+/// Prefix of the keys added to the PC keyboard after the original ones.
+const EXTENDED: u8 = 0xE0;
+/// Prefix of a key's code when it is released.
+const RELEASE: u8 = 0xF0;
+
+/// A keyboard that sends raw PS/2 scancodes (set 2), as the hardware does,
+/// rather than characters.
+///
+/// Pressing a key queues its make code; releasing it queues 0xF0 followed by
+/// the same code. Keys added after the original PC keyboard, such as the
+/// arrows, start with 0xE0 (releasing the left arrow gives 0xE0 0xF0 0x6B).
+/// Loading from port 0 takes the oldest byte, or 0 if there are none, and
+/// port 1 reads as the number of bytes waiting. New bytes raise
+/// `VECTOR_KEYBOARD`. Nothing is translated: shift is a key like any other,
+/// so the guest keeps track of it and maps codes to characters itself.
+///
+/// Hosts send keys through the same `EventQueue` as for `EventDevice`; keys
+/// without a scancode here, and mouse events, are ignored.
+#[derive(Debug, Default)]
+pub struct Ps2Keyboard {
+    queue: EventQueue,
+    bytes: VecDeque<u8>,
+    arrived: bool,
+}
+
+impl Ps2Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turns the key events the host has sent since last time into bytes.
+    fn take_events(&mut self) {
+        for event in self.queue.take() {
+            let (key, released) = match event {
+                InputEvent::KeyDown(key) => (key, false),
+                InputEvent::KeyUp(key) => (key, true),
+                _ => continue,
+            };
+            let Some((extended, code)) = scancode(key) else {
+                continue;
+            };
+            let mut bytes = Vec::with_capacity(3);
+            if extended {
+                bytes.push(EXTENDED);
+            }
+            if released {
+                bytes.push(RELEASE);
+            }
+            bytes.push(code);
+            if self.bytes.len() + bytes.len() <= CAPACITY {
+                self.bytes.extend(bytes);
+                self.arrived = true;
+            }
+        }
+    }
+}
+
+/// The make codes of the letters a to z and the digits 0 to 9.
+const LETTERS: [u8; 26] = [
+    0x1C, 0x32, 0x21, 0x23, 0x24, 0x2B, 0x34, 0x33, 0x43, 0x3B, 0x42, 0x4B, 0x3A, 0x31, 0x44, 0x4D,
+    0x15, 0x2D, 0x1B, 0x2C, 0x3C, 0x2A, 0x1D, 0x22, 0x35, 0x1A,
+];
+const DIGITS: [u8; 10] = [0x45, 0x16, 0x1E, 0x26, 0x25, 0x2E, 0x36, 0x3D, 0x3E, 0x46];
+
+/// The set 2 make code of a key, as sent by an event device (see
+/// `KEY_LEFT`), and whether it is an extended key.
+fn scancode(key: u16) -> Option<(bool, u8)> {
+    let code = match key {
+        KEY_LEFT => return Some((true, 0x6B)),
+        KEY_UP => return Some((true, 0x75)),
+        KEY_RIGHT => return Some((true, 0x74)),
+        KEY_DOWN => return Some((true, 0x72)),
+        KEY_SHIFT => 0x12,
+        KEY_CTRL => 0x14,
+        KEY_ALT => 0x11,
+        8 => 0x66,
+        9 => 0x0D,
+        13 => 0x5A,
+        27 => 0x76,
+        32 => 0x29,
+        0..=0xFF => {
+            let chr = key as u8;
+            match chr {
+                b'a'..=b'z' => LETTERS[(chr - b'a') as usize],
+                b'0'..=b'9' => DIGITS[(chr - b'0') as usize],
+                b'`' => 0x0E,
+                b'-' => 0x4E,
+                b'=' => 0x55,
+                b'[' => 0x54,
+                b']' => 0x5B,
+                b'\\' => 0x5D,
+                b';' => 0x4C,
+                b'\'' => 0x52,
+                b',' => 0x41,
+                b'.' => 0x49,
+                b'/' => 0x4A,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some((false, code))
+}
+
+impl Device for Ps2Keyboard {
+    fn ports(&self) -> u16 {
+        2
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        self.take_events();
+        Ok(match port {
+            DATA => self.bytes.pop_front().unwrap_or(0) as u16,
+            STATUS => self.bytes.len() as u16,
+            _ => 0,
+        })
+    }
+
+    fn write(&mut self, _port: u16, _val: u16, _bus: &mut Bus) -> Result<(), ArchError> {
+        Ok(())
+    }
+
+    fn interrupt(&mut self, _cycles: u64) -> Option<u16> {
+        self.take_events();
+        if !self.arrived {
+            return None;
+        }
+        self.arrived = false;
+        Some(VECTOR_KEYBOARD)
+    }
+
+    fn event_queue(&self) -> Option<EventQueue> {
+        Some(self.queue.clone())
+    }
+}
@@ -0,0 +1,57 @@
+use super::{Bus, Device};
+use crate::arch::ArchError;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A random number generator.
+///
+/// Each load from port 0 gives a pseudo-random word, and storing to it seeds
+/// the generator with the word stored, so a program can make its own runs
+/// repeatable.
+#[derive(Debug)]
+pub struct Rng {
+    /// Xorshift state, never 0.
+    state: u32,
+}
+
+impl Rng {
+    /// A generator seeded with `seed`, or from the clock if there is none.
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.subsec_nanos() as u64)
+        });
+        let mut rng = Self { state: 0 };
+        rng.seed(seed);
+        rng
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        let state = (seed ^ (seed >> 32)) as u32;
+        // Xorshift never leaves 0, so it mustn't start there.
+        self.state = if state == 0 { 0x9E37_79B9 } else { state };
+    }
+
+    /// The next pseudo-random word.
+    pub fn next_word(&mut self) -> u16 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 16) as u16
+    }
+}
+
+impl Device for Rng {
+    fn ports(&self) -> u16 {
+        1
+    }
+
+    fn read(&mut self, _port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(self.next_word())
+    }
+
+    fn write(&mut self, _port: u16, val: u16, _bus: &mut Bus) -> Result<(), ArchError> {
+        self.seed(val as u64);
+        Ok(())
+    }
+}
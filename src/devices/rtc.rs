@@ -0,0 +1,87 @@
+use super::{Bus, Device};
+use crate::arch::ArchError;
+use crate::clock::ClockMode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MILLIS_LO: u16 = 0;
+const MILLIS_HI: u16 = 1;
+const SECONDS_LO: u16 = 2;
+const SECONDS_HI: u16 = 3;
+const SLEEP: u16 = 4;
+
+/// Tells the time and sleeps, by the machine's clock (see `Clock`).
+///
+/// A load from port 0 gives the low word of the milliseconds since the
+/// program started and takes note of the time, and ports 1 to 3 give the
+/// high word of those milliseconds and the low and high words of the seconds
+/// since 1970, as of that load. On a virtual clock the seconds count from
+/// `epoch` when the program starts; on a real clock they are the host's.
+/// Storing n to port 4 sleeps for n milliseconds.
+#[derive(Debug)]
+pub struct Rtc {
+    epoch: u64,
+    millis: u32,
+    seconds: u32,
+    /// When a sleep under `run_async` ends, in microseconds on the clock.
+    wake: Option<u64>,
+}
+
+impl Rtc {
+    pub fn new(epoch: u64) -> Self {
+        Self {
+            epoch,
+            millis: 0,
+            seconds: 0,
+            wake: None,
+        }
+    }
+
+    fn latch(&mut self, bus: &Bus) {
+        let micros = bus.clock.micros();
+        self.millis = (micros / 1000) as u32;
+        self.seconds = match bus.clock.mode() {
+            ClockMode::Virtual => self.epoch + micros / 1_000_000,
+            ClockMode::Real => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+        } as u32;
+    }
+}
+
+impl Device for Rtc {
+    fn ports(&self) -> u16 {
+        5
+    }
+
+    fn read(&mut self, port: u16, bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            MILLIS_LO => {
+                self.latch(bus);
+                self.millis as u16
+            }
+            MILLIS_HI => (self.millis >> 16) as u16,
+            SECONDS_LO => self.seconds as u16,
+            SECONDS_HI => (self.seconds >> 16) as u16,
+            _ => 0,
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError> {
+        if port != SLEEP {
+            return Ok(());
+        }
+        let micros = val as u64 * 1000;
+        if !bus.nonblocking || bus.clock.mode() == ClockMode::Virtual {
+            bus.clock.sleep(micros);
+            return Ok(());
+        }
+        // An async host mustn't have its thread blocked, so the store is
+        // retried until the time has passed.
+        let wake = *self.wake.get_or_insert(bus.clock.micros() + micros);
+        if bus.clock.micros() < wake {
+            return Err(ArchError::WouldBlock);
+        }
+        self.wake = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use super::{Bus, Device, EventQueue};
+use crate::arch::ArchError;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// One device attached to several VMs, such as an arena that two guests'
+/// programs compete in.
+///
+/// Clones share the device: each VM it is attached to reaches the same one,
+/// and sees what the others stored to it. An interrupt the device raises goes
+/// to whichever VM asks first, and the device hears of each VM halting, so a
+/// device that saves what it was given saves it again each time. The
+/// device's frame isn't available through a `Shared`, since the lock can't
+/// be held while a host draws it.
+#[derive(Clone)]
+pub struct Shared {
+    device: Arc<Mutex<Box<dyn Device>>>,
+    ports: u16,
+}
+
+impl Shared {
+    pub fn new(device: Box<dyn Device>) -> Self {
+        Self {
+            ports: device.ports(),
+            device: Arc::new(Mutex::new(device)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<dyn Device>> {
+        // A VM that panicked part way through an access leaves nothing the
+        // others can't carry on with.
+        self.device.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Device for Shared {
+    fn ports(&self) -> u16 {
+        self.ports
+    }
+
+    fn read(&mut self, port: u16, bus: &mut Bus) -> Result<u16, ArchError> {
+        self.lock().read(port, bus)
+    }
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError> {
+        self.lock().write(port, val, bus)
+    }
+
+    fn interrupt(&mut self, cycles: u64) -> Option<u16> {
+        self.lock().interrupt(cycles)
+    }
+
+    fn halted(&mut self) -> Result<(), ArchError> {
+        self.lock().halted()
+    }
+
+    fn event_queue(&self) -> Option<EventQueue> {
+        self.lock().event_queue()
+    }
+}
@@ -0,0 +1,299 @@
+use super::{Bus, Device, VECTOR_DEVICE};
+use crate::arch::ArchError;
+use std::fmt;
+use std::fs;
+use std::io;
+
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+const DIVIDER: u16 = 2;
+const SELECT: u16 = 3;
+
+/// Status bit set while a byte is being shifted.
+const BUSY: u16 = 1;
+/// Status bit set when a byte was stored while the last was still being
+/// shifted, cleared once the status is read.
+const COLLISION: u16 = 2;
+
+/// A chip on an `Spi` bus.
+///
+/// SPI is full duplex: every byte the controller shifts out to a chip shifts
+/// one back in, so a chip answers each byte with a byte, whether or not
+/// either side means anything by it.
+pub trait Slave: fmt::Debug + Send {
+    /// Called when the chip's select line goes active, starting a new
+    /// command.
+    fn select(&mut self) {}
+
+    /// Takes the byte the controller shifted out and gives the one shifted
+    /// back in.
+    fn exchange(&mut self, byte: u8) -> u8;
+
+    /// Called when the chip's select line goes inactive, ending the command.
+    fn deselect(&mut self) {}
+
+    /// Called once when the program ends.
+    fn halted(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An SPI bus controller, with the chips in `slaves` attached, for
+/// programs that talk to peripherals a byte at a time as microcontroller
+/// firmware does.
+///
+/// Storing n to port 3 selects the nth chip (counting from 1), ending the
+/// command of any chip selected before, and storing 0 deselects them all.
+/// Storing a byte to port 0 starts shifting it out to the selected chip,
+/// one bit every `divider` cycles on the machine's clock, where the divider
+/// is whatever was last stored to port 2 (4 at first, 0 counting as 1).
+/// Port 1 reads as the status: bit 0 is set while a byte is being shifted
+/// and bit 1 if a byte was stored before the last was done, in which case
+/// the new one was dropped; reading the status clears bit 1. Once a byte is
+/// done, `VECTOR_DEVICE` is raised and port 0 reads as the byte the chip
+/// shifted back in, or 0xFF if no chip was selected.
+#[derive(Debug)]
+pub struct Spi {
+    slaves: Vec<Box<dyn Slave>>,
+    /// The selected chip, as an index into `slaves`.
+    selected: Option<usize>,
+    divider: u16,
+    received: u8,
+    /// The byte being shifted in and the cycle count at which it is done.
+    shifting: Option<(u8, u64)>,
+    collision: bool,
+    done: bool,
+}
+
+impl Spi {
+    pub fn new(slaves: Vec<Box<dyn Slave>>) -> Self {
+        Self {
+            slaves,
+            selected: None,
+            divider: 4,
+            received: 0xFF,
+            shifting: None,
+            collision: false,
+            done: false,
+        }
+    }
+
+    /// Finishes the byte being shifted if its time has come.
+    fn update(&mut self, cycles: u64) {
+        if let Some((byte, at)) = self.shifting {
+            if cycles >= at {
+                self.received = byte;
+                self.shifting = None;
+                self.done = true;
+            }
+        }
+    }
+
+    fn select(&mut self, chip: u16) {
+        if let Some(index) = self.selected.take() {
+            self.slaves[index].deselect();
+        }
+        let index = (chip as usize).wrapping_sub(1);
+        if index < self.slaves.len() {
+            self.slaves[index].select();
+            self.selected = Some(index);
+        }
+    }
+}
+
+impl Device for Spi {
+    fn ports(&self) -> u16 {
+        4
+    }
+
+    fn read(&mut self, port: u16, bus: &mut Bus) -> Result<u16, ArchError> {
+        self.update(bus.clock.cycles());
+        Ok(match port {
+            DATA => self.received as u16,
+            STATUS => {
+                let busy = if self.shifting.is_some() { BUSY } else { 0 };
+                let collision = if self.collision { COLLISION } else { 0 };
+                self.collision = false;
+                busy | collision
+            }
+            DIVIDER => self.divider,
+            SELECT => self.selected.map_or(0, |index| index as u16 + 1),
+            _ => 0,
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError> {
+        let cycles = bus.clock.cycles();
+        self.update(cycles);
+        match port {
+            DATA if self.shifting.is_some() => self.collision = true,
+            DATA => {
+                let byte = match self.selected {
+                    Some(index) => self.slaves[index].exchange(val as u8),
+                    None => 0xFF,
+                };
+                let at = cycles + 8 * self.divider.max(1) as u64;
+                self.shifting = Some((byte, at));
+            }
+            DIVIDER => self.divider = val,
+            SELECT => self.select(val),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn interrupt(&mut self, cycles: u64) -> Option<u16> {
+        self.update(cycles);
+        if !self.done {
+            return None;
+        }
+        self.done = false;
+        Some(VECTOR_DEVICE)
+    }
+
+    fn halted(&mut self) -> Result<(), ArchError> {
+        for slave in self.slaves.iter_mut() {
+            slave.halted()?;
+        }
+        Ok(())
+    }
+}
+
+const EEPROM_SIZE: usize = 0x8000;
+/// Writes wrap around within a page rather than running into the next.
+const EEPROM_PAGE: usize = 64;
+
+/// A 32 KiB serial EEPROM in the style of the 25LC256.
+///
+/// Each command starts with an instruction byte: 0x03 reads, sending back the
+/// bytes from the 16-bit address that follows (high byte first) onwards; 0x02
+/// writes the bytes after the address, wrapping around within its 64 byte
+/// page; 0x05 sends back the status, whose bit 1 is the write enable latch;
+/// 0x06 sets the latch and 0x04 clears it. A write is ignored unless the
+/// latch was set, and the latch clears when a write's command ends. The
+/// contents are loaded from `file` if it exists and saved back to it when the
+/// program ends.
+#[derive(Debug)]
+pub struct Eeprom {
+    data: Vec<u8>,
+    file: Option<String>,
+    write_enabled: bool,
+    /// The bytes of the current command so far.
+    command: Vec<u8>,
+    addr: usize,
+    wrote: bool,
+}
+
+impl Eeprom {
+    pub fn new(file: Option<&str>) -> io::Result<Self> {
+        let mut data = vec![0xFF; EEPROM_SIZE];
+        if let Some(path) = file {
+            match fs::read(path) {
+                Ok(bytes) => {
+                    let len = bytes.len().min(EEPROM_SIZE);
+                    data[..len].copy_from_slice(&bytes[..len]);
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Self {
+            data,
+            file: file.map(str::to_string),
+            write_enabled: false,
+            command: Vec::new(),
+            addr: 0,
+            wrote: false,
+        })
+    }
+}
+
+impl Slave for Eeprom {
+    fn select(&mut self) {
+        self.command.clear();
+    }
+
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let status = (self.write_enabled as u8) << 1;
+        if self.command.len() < 3 {
+            self.command.push(byte);
+            match self.command[..] {
+                [0x06] => self.write_enabled = true,
+                [0x04] => self.write_enabled = false,
+                [0x05, ..] => return status,
+                [0x03 | 0x02, hi, lo] => {
+                    self.addr = u16::from_be_bytes([hi, lo]) as usize % EEPROM_SIZE;
+                }
+                _ => {}
+            }
+            return 0xFF;
+        }
+        match self.command[0] {
+            0x05 => return status,
+            0x03 => {
+                let val = self.data[self.addr];
+                self.addr = (self.addr + 1) % EEPROM_SIZE;
+                return val;
+            }
+            0x02 if self.write_enabled => {
+                self.data[self.addr] = byte;
+                let page = self.addr - self.addr % EEPROM_PAGE;
+                self.addr = page + (self.addr + 1) % EEPROM_PAGE;
+                self.wrote = true;
+            }
+            _ => {}
+        }
+        0xFF
+    }
+
+    fn deselect(&mut self) {
+        if self.wrote {
+            self.write_enabled = false;
+            self.wrote = false;
+        }
+    }
+
+    fn halted(&mut self) -> io::Result<()> {
+        match &self.file {
+            Some(path) => fs::write(path, &self.data),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A temperature sensor in the style of the TC72, reading a fixed
+/// temperature.
+///
+/// Every pair of bytes exchanged after the chip is selected gives the
+/// temperature as a signed 16-bit number of 1/256 degrees Celsius, high
+/// byte first, so the high byte alone is the whole degrees. The bytes sent to
+/// it are ignored.
+#[derive(Debug)]
+pub struct Thermometer {
+    reading: [u8; 2],
+    next: usize,
+}
+
+impl Thermometer {
+    pub fn new(celsius: f64) -> Self {
+        let reading = (celsius * 256.0)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        Self {
+            reading: reading.to_be_bytes(),
+            next: 0,
+        }
+    }
+}
+
+impl Slave for Thermometer {
+    fn select(&mut self) {
+        self.next = 0;
+    }
+
+    fn exchange(&mut self, _byte: u8) -> u8 {
+        let byte = self.reading[self.next];
+        self.next = (self.next + 1) % 2;
+        byte
+    }
+}
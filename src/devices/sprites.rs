@@ -0,0 +1,172 @@
+use super::{Bus, Device, Frame, PALETTE};
+use crate::arch::ArchError;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 120;
+const TILE: usize = 8;
+const MAP_WIDTH: usize = WIDTH / TILE;
+const MAP_HEIGHT: usize = HEIGHT / TILE;
+/// Words of pattern data per tile: 64 pixels at four to a word.
+const TILE_WORDS: u16 = 16;
+const SPRITES: u16 = 8;
+
+const TILES: u16 = 0;
+const MAP: u16 = 1;
+const PRESENT: u16 = 2;
+/// The first sprite's registers. Each sprite has four: x, y, tile and flags.
+const SPRITE_BASE: u16 = 4;
+
+const VISIBLE: u16 = 1;
+const FLIP_X: u16 = 2;
+const FLIP_Y: u16 = 4;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Sprite {
+    x: u16,
+    y: u16,
+    tile: u16,
+    flags: u16,
+}
+
+/// A 160x120 display built from 8x8 tiles, with eight hardware sprites.
+///
+/// Tiles live in guest memory: port 0 holds the address of the tile
+/// patterns, 16 words per tile with four pixels to a word (leftmost pixel
+/// in the top four bits), each pixel an index into `PALETTE`. Port 1 holds
+/// the address of the 20x15 word tile map, row by row, each word the
+/// number of the tile to show there. Storing to port 2 draws a frame from
+/// the current memory and registers; port 2 reads as the number of frames
+/// drawn.
+///
+/// Sprite n's registers are ports 4 + 4n to 7 + 4n: x, y (signed, so a
+/// sprite can hang off the edge), tile, and flags (1 visible, 2 flip
+/// horizontally, 4 flip vertically). Colour 0 in a sprite is transparent
+/// and lower numbered sprites are drawn on top.
+#[derive(Debug)]
+pub struct SpriteDisplay {
+    tiles: u16,
+    map: u16,
+    sprites: [Sprite; SPRITES as usize],
+    frame: Frame,
+    frames: u16,
+    /// Where to save each frame, if anywhere.
+    image: Option<String>,
+}
+
+impl SpriteDisplay {
+    pub fn new(image: Option<&str>) -> Self {
+        Self {
+            tiles: 0,
+            map: 0,
+            sprites: [Sprite::default(); SPRITES as usize],
+            frame: Frame::new(WIDTH, HEIGHT),
+            frames: 0,
+            image: image.map(str::to_string),
+        }
+    }
+
+    /// The colour number of pixel (x, y) of `tile`.
+    fn tile_pixel(&self, tile: u16, x: usize, y: usize, bus: &Bus) -> Result<u8, ArchError> {
+        let pixel = y * TILE + x;
+        let addr = self
+            .tiles
+            .wrapping_add(tile.wrapping_mul(TILE_WORDS))
+            .wrapping_add((pixel / 4) as u16);
+        let word = bus.mem.read(addr)?;
+        Ok(((word >> (12 - 4 * (pixel % 4))) & 0xF) as u8)
+    }
+
+    fn draw(&mut self, bus: &Bus) -> Result<(), ArchError> {
+        for row in 0..MAP_HEIGHT {
+            for col in 0..MAP_WIDTH {
+                let cell = self.map.wrapping_add((row * MAP_WIDTH + col) as u16);
+                let tile = bus.mem.read(cell)?;
+                for y in 0..TILE {
+                    for x in 0..TILE {
+                        let color = self.tile_pixel(tile, x, y, bus)?;
+                        self.frame
+                            .set(col * TILE + x, row * TILE + y, PALETTE[color as usize]);
+                    }
+                }
+            }
+        }
+        for sprite in self.sprites.iter().rev() {
+            if sprite.flags & VISIBLE == 0 {
+                continue;
+            }
+            for y in 0..TILE {
+                for x in 0..TILE {
+                    let sx = if sprite.flags & FLIP_X != 0 {
+                        TILE - 1 - x
+                    } else {
+                        x
+                    };
+                    let sy = if sprite.flags & FLIP_Y != 0 {
+                        TILE - 1 - y
+                    } else {
+                        y
+                    };
+                    let color = self.tile_pixel(sprite.tile, sx, sy, bus)?;
+                    let px = sprite.x as i16 as isize + x as isize;
+                    let py = sprite.y as i16 as isize + y as isize;
+                    if color != 0 && px >= 0 && py >= 0 {
+                        self.frame
+                            .set(px as usize, py as usize, PALETTE[color as usize]);
+                    }
+                }
+            }
+        }
+        self.frames = self.frames.wrapping_add(1);
+        if let Some(path) = &self.image {
+            self.frame.save_ppm(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Device for SpriteDisplay {
+    fn ports(&self) -> u16 {
+        SPRITE_BASE + SPRITES * 4
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            TILES => self.tiles,
+            MAP => self.map,
+            PRESENT => self.frames,
+            SPRITE_BASE.. => {
+                let sprite = &self.sprites[((port - SPRITE_BASE) / 4) as usize];
+                match (port - SPRITE_BASE) % 4 {
+                    0 => sprite.x,
+                    1 => sprite.y,
+                    2 => sprite.tile,
+                    _ => sprite.flags,
+                }
+            }
+            _ => 0,
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, bus: &mut Bus) -> Result<(), ArchError> {
+        match port {
+            TILES => self.tiles = val,
+            MAP => self.map = val,
+            PRESENT => self.draw(bus)?,
+            SPRITE_BASE.. => {
+                let sprite = &mut self.sprites[((port - SPRITE_BASE) / 4) as usize];
+                match (port - SPRITE_BASE) % 4 {
+                    0 => sprite.x = val,
+                    1 => sprite.y = val,
+                    2 => sprite.tile = val,
+                    _ => sprite.flags = val,
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn frame(&self) -> Option<&Frame> {
+        Some(&self.frame)
+    }
+}
@@ -0,0 +1,56 @@
+use super::{Bus, Device, VECTOR_TIMER};
+use crate::arch::ArchError;
+
+const PERIOD: u16 = 0;
+const TICKS: u16 = 1;
+
+/// Raises the timer interrupt at a steady rate.
+///
+/// Storing n to port 0 starts the timer, raising `VECTOR_TIMER` every n
+/// cycles on the machine's clock (see `Clock`), and storing 0 stops it. Port 1
+/// counts the interrupts raised; storing to it sets the count.
+#[derive(Debug, Default)]
+pub struct Timer {
+    period: u16,
+    ticks: u16,
+    /// The cycle count at which to raise the next interrupt, once known.
+    next: Option<u64>,
+}
+
+impl Device for Timer {
+    fn ports(&self) -> u16 {
+        2
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            PERIOD => self.period,
+            _ => self.ticks,
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, _bus: &mut Bus) -> Result<(), ArchError> {
+        match port {
+            PERIOD => {
+                self.period = val;
+                self.next = None;
+            }
+            TICKS => self.ticks = val,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn interrupt(&mut self, cycles: u64) -> Option<u16> {
+        if self.period == 0 {
+            return None;
+        }
+        let next = *self.next.get_or_insert(cycles + self.period as u64);
+        if cycles < next {
+            return None;
+        }
+        self.next = Some(next + self.period as u64);
+        self.ticks = self.ticks.wrapping_add(1);
+        Some(VECTOR_TIMER)
+    }
+}
@@ -0,0 +1,159 @@
+use super::{Bus, Device};
+use crate::arch::ArchError;
+use std::fmt::Write as _;
+use std::fs;
+
+const FORWARD: u16 = 0;
+const TURN: u16 = 1;
+const PEN: u16 = 2;
+const COLOR: u16 = 3;
+const X: u16 = 4;
+const Y: u16 = 5;
+const HEADING: u16 = 6;
+
+/// A line the turtle drew, in canvas coordinates with y pointing up.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    from: (f64, f64),
+    to: (f64, f64),
+    color: u16,
+}
+
+/// Logo-style turtle graphics, drawn to an SVG file when the program ends.
+///
+/// The turtle starts in the middle of the canvas facing up with its pen
+/// down. Storing n to port 0 moves it n steps forward (negative values, as
+/// signed words, move it back), drawing a line if the pen is down, and
+/// storing to port 1 turns it clockwise by that many degrees. Port 2 is the
+/// pen: 0 lifts it and anything else puts it down. Port 3 is the pen colour
+/// as `0x0RGB`, four bits per channel. Ports 4 and 5 are the turtle's x and
+/// y from the middle of the canvas and port 6 its heading in degrees
+/// clockwise from up; all three can be stored to, which moves or turns the
+/// turtle without drawing.
+#[derive(Debug)]
+pub struct Turtle {
+    path: String,
+    width: u16,
+    height: u16,
+    x: f64,
+    y: f64,
+    heading: f64,
+    pen: bool,
+    color: u16,
+    lines: Vec<Line>,
+}
+
+impl Turtle {
+    /// A turtle drawing on a `width` by `height` canvas saved to `path`.
+    pub fn new(path: &str, width: u16, height: u16) -> Self {
+        Self {
+            path: path.to_string(),
+            width,
+            height,
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            pen: true,
+            color: 0,
+            lines: Vec::new(),
+        }
+    }
+
+    fn forward(&mut self, steps: i16) {
+        let angle = self.heading.to_radians();
+        let from = (self.x, self.y);
+        self.x += steps as f64 * angle.sin();
+        self.y += steps as f64 * angle.cos();
+        if self.pen {
+            self.lines.push(Line {
+                from,
+                to: (self.x, self.y),
+                color: self.color,
+            });
+        }
+    }
+
+    /// The drawing as an SVG document, with the canvas's middle at the
+    /// centre of the image.
+    pub fn svg(&self) -> String {
+        let (w, h) = (self.width as f64, self.height as f64);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             viewBox=\"{} {} {} {}\">\n",
+            self.width,
+            self.height,
+            -w / 2.0,
+            -h / 2.0,
+            w,
+            h
+        );
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\"/>",
+            -w / 2.0,
+            -h / 2.0,
+            w,
+            h
+        );
+        for line in self.lines.iter() {
+            let _ = writeln!(
+                svg,
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#{}\"/>",
+                line.from.0,
+                flip(line.from.1),
+                line.to.0,
+                flip(line.to.1),
+                hex_color(line.color)
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// A y coordinate for SVG, where y points down, without printing `-0.00`.
+fn flip(y: f64) -> f64 {
+    0.0 - y
+}
+
+/// `0x0RGB` as the six digit hex colour SVG expects.
+fn hex_color(color: u16) -> String {
+    let channel = |shift: u16| ((color >> shift) & 0xF) * 0x11;
+    format!("{:02x}{:02x}{:02x}", channel(8), channel(4), channel(0))
+}
+
+impl Device for Turtle {
+    fn ports(&self) -> u16 {
+        7
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        Ok(match port {
+            PEN => self.pen as u16,
+            COLOR => self.color,
+            X => self.x.round() as i16 as u16,
+            Y => self.y.round() as i16 as u16,
+            HEADING => self.heading.round() as u16,
+            _ => 0,
+        })
+    }
+
+    fn write(&mut self, port: u16, val: u16, _bus: &mut Bus) -> Result<(), ArchError> {
+        match port {
+            FORWARD => self.forward(val as i16),
+            TURN => self.heading = (self.heading + val as i16 as f64).rem_euclid(360.0),
+            PEN => self.pen = val != 0,
+            COLOR => self.color = val & 0xFFF,
+            X => self.x = val as i16 as f64,
+            Y => self.y = val as i16 as f64,
+            HEADING => self.heading = (val as i16 as f64).rem_euclid(360.0),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn halted(&mut self) -> Result<(), ArchError> {
+        fs::write(&self.path, self.svg())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! Expressions over the machine state, used by the debugger and the DAP
+//! server.
+//!
+//! Operands are numbers (decimal or `0x` hex), registers, labels and memory
+//! reads written `[addr]`. The operators, from loosest to tightest binding,
+//! are `||`, `&&`, the comparisons `== != < <= > >=`, `+ -`, `*` and unary
+//! `-`. Arithmetic wraps and comparisons are signed, like the jump
+//! instructions; comparisons and logical operators produce 1 or 0.
+
+use crate::arch::RegMnem;
+use crate::debuginfo::DebugInfo;
+use crate::vm::TeenyAT;
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Num(u16),
+    Reg(usize),
+    Mem(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+}
+
+const LEVELS: &[&[(&str, BinOp)]] = &[
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[
+        ("==", BinOp::Eq),
+        ("!=", BinOp::Ne),
+        ("<=", BinOp::Le),
+        (">=", BinOp::Ge),
+        ("<", BinOp::Lt),
+        (">", BinOp::Gt),
+    ],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul)],
+];
+
+/// Parses an expression, resolving labels with `debug`.
+pub fn parse(text: &str, debug: &DebugInfo) -> Result<Expr, String> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        debug,
+    };
+    let expr = parser.binary(0)?;
+    parser.skip_space();
+    if parser.pos < text.len() {
+        return Err(format!("Unexpected `{}`", &text[parser.pos..]));
+    }
+    Ok(expr)
+}
+
+impl Expr {
+    pub fn eval(&self, vm: &TeenyAT) -> Result<u16, String> {
+        Ok(match self {
+            Expr::Num(val) => *val,
+            Expr::Reg(reg) => vm.registers()[*reg].val,
+            Expr::Mem(addr) => vm
+                .read_mem(addr.eval(vm)?)
+                .map_err(|err| err.to_string().trim_end().to_string())?,
+            Expr::Neg(inner) => inner.eval(vm)?.wrapping_neg(),
+            Expr::Binary(op, lhs, rhs) => {
+                let a = lhs.eval(vm)?;
+                // Short circuit so `[ptr] != 0 && [[ptr]] == 5` is safe.
+                match op {
+                    BinOp::Or if a != 0 => return Ok(1),
+                    BinOp::And if a == 0 => return Ok(0),
+                    _ => {}
+                }
+                let b = rhs.eval(vm)?;
+                let (sa, sb) = (a as i16, b as i16);
+                match op {
+                    BinOp::Or | BinOp::And => (b != 0) as u16,
+                    BinOp::Eq => (a == b) as u16,
+                    BinOp::Ne => (a != b) as u16,
+                    BinOp::Lt => (sa < sb) as u16,
+                    BinOp::Le => (sa <= sb) as u16,
+                    BinOp::Gt => (sa > sb) as u16,
+                    BinOp::Ge => (sa >= sb) as u16,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Mul => a.wrapping_mul(b),
+                }
+            }
+        })
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    debug: &'a DebugInfo,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        while self.rest().starts_with(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn eat(&mut self, tok: &str) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(tok) {
+            self.pos += tok.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            for (tok, op) in LEVELS[level] {
+                if self.eat(tok) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let inner = self.binary(0)?;
+            return self.close(")").map(|_| inner);
+        }
+        if self.eat("[") {
+            let inner = self.binary(0)?;
+            return self.close("]").map(|_| Expr::Mem(Box::new(inner)));
+        }
+        self.skip_space();
+        let rest = self.rest();
+        let prefix = if rest.starts_with(['!', ':']) { 1 } else { 0 };
+        let len = rest[prefix..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(rest.len(), |len| prefix + len);
+        let word = &rest[..len];
+        if word.is_empty() {
+            return Err(match self.rest().chars().next() {
+                Some(c) => format!("Unexpected `{}`", c),
+                None => "Unexpected end of expression".to_string(),
+            });
+        }
+        self.pos += len;
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            let parsed = match word.strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16),
+                None => word.parse::<u16>(),
+            };
+            return parsed
+                .map(Expr::Num)
+                .map_err(|_| format!("Invalid number `{}`", word));
+        }
+        if let Ok(reg) = RegMnem::from_str(word) {
+            return Ok(Expr::Reg(reg.to_int() as usize));
+        }
+        self.debug
+            .label_addr(word)
+            .map(Expr::Num)
+            .ok_or_else(|| format!("Unknown register or label `{}`", word))
+    }
+
+    fn close(&mut self, tok: &str) -> Result<(), String> {
+        if self.eat(tok) {
+            Ok(())
+        } else {
+            Err(format!("Expected `{}`", tok))
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Character input supplied by the host as it arrives.
+//!
+//! An `InputFeed` stands in for stdin when the VM is driven by something
+//! other than a terminal, such as a network connection. The host pushes text
+//! into it and the guest reads it through the console, a line at a time as a
+//! terminal would deliver it. While no complete line is waiting, reads fail
+//! with `WouldBlock`, which `run_async` and `run_for` treat as waiting for
+//! input rather than as an error.
+//!
+//! A `SharedBuf` is the other direction: it stands in for stdout and keeps
+//! what the guest prints for the host to show.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// The host's end of the feed. Clones share the same queue.
+#[derive(Clone, Default)]
+pub struct InputFeed {
+    shared: Arc<Mutex<Queue>>,
+}
+
+#[derive(Default)]
+struct Queue {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+/// The VM's end of the feed, to be given to `TeenyAT::set_io`.
+pub struct FeedReader {
+    shared: Arc<Mutex<Queue>>,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl InputFeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues text for the guest. It becomes readable once a newline arrives
+    /// or the feed is closed.
+    pub fn push(&self, text: &[u8]) {
+        self.shared.lock().unwrap().bytes.extend(text);
+    }
+
+    /// Ends the input. Once the queue is drained the guest sees end of file.
+    pub fn close(&self) {
+        self.shared.lock().unwrap().closed = true;
+    }
+
+    pub fn reader(&self) -> FeedReader {
+        FeedReader {
+            shared: Arc::clone(&self.shared),
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for FeedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for FeedReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            let mut queue = self.shared.lock().unwrap();
+            let len = match queue.bytes.iter().position(|&b| b == b'\n') {
+                Some(newline) => newline + 1,
+                None if queue.closed => queue.bytes.len(),
+                None => return Err(io::ErrorKind::WouldBlock.into()),
+            };
+            self.line = queue.bytes.drain(..len).collect();
+            self.pos = 0;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.line.len());
+    }
+}
+
+/// Output kept for the host. Clones share the same bytes, so one can be
+/// given to `TeenyAT::set_io` and another kept to read them.
+#[derive(Clone, Default)]
+pub struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuf {
+    /// Everything written so far, as text.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+
+    /// Everything written since the last `take`, which is forgotten.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Memory image formats other than the native `.rom`, so assembled programs can
+//! be loaded into other tools such as a student's Logisim build of the CPU, and
+//! images produced by other tools can be run.
+
+use crate::arch::Memory;
+use crate::debuginfo::DebugInfo;
+use std::fs;
+use std::io;
+
+/// Words per line in the text image formats.
+const WORDS_PER_LINE: usize = 8;
+/// The column a listing's comments start at, unless the code runs past it.
+const LISTING_COLUMN: usize = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Rom,
+    Logisim,
+    Hex,
+    C,
+    Rust,
+}
+
+impl ImageFormat {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Option<ImageFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "rom" => Some(ImageFormat::Rom),
+            "logisim" => Some(ImageFormat::Logisim),
+            "hex" => Some(ImageFormat::Hex),
+            "c" => Some(ImageFormat::C),
+            "rust" => Some(ImageFormat::Rust),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Rom => "rom",
+            ImageFormat::Logisim => "img",
+            ImageFormat::Hex => "mem",
+            ImageFormat::C => "c",
+            ImageFormat::Rust => "rs",
+        }
+    }
+}
+
+/// A Logisim-evolution "v2.0 raw" image. Runs of repeated words are written
+/// using Logisim's `count*value` shorthand.
+pub fn logisim_image(words: &[u16]) -> String {
+    let mut entries: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        let mut run = 1;
+        while i + run < words.len() && words[i + run] == words[i] {
+            run += 1;
+        }
+        if run > 1 {
+            entries.push(format!("{}*{:x}", run, words[i]));
+        } else {
+            entries.push(format!("{:x}", words[i]));
+        }
+        i += run;
+    }
+    let mut out = String::from("v2.0 raw\n");
+    for line in entries.chunks(WORDS_PER_LINE) {
+        out.push_str(&line.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+/// One four digit hex word per line, as read by Verilog's `$readmemh`.
+pub fn hex_image(words: &[u16]) -> String {
+    words.iter().map(|word| format!("{:04x}\n", word)).collect()
+}
+
+/// A C source file defining the program as `const uint16_t rom[]`.
+pub fn c_array(words: &[u16]) -> String {
+    let mut out = String::from("#include <stdint.h>\n\n");
+    out.push_str(&format!("const uint16_t rom[{}] = {{\n", words.len()));
+    out.push_str(&array_body(words));
+    out.push_str("};\n");
+    out
+}
+
+/// A Rust source file defining the program as `pub const ROM: [u16; N]`.
+pub fn rust_array(words: &[u16]) -> String {
+    let mut out = format!("pub const ROM: [u16; {}] = [\n", words.len());
+    out.push_str(&array_body(words));
+    out.push_str("];\n");
+    out
+}
+
+/// The comma separated elements of a C or Rust array, one line per
+/// `WORDS_PER_LINE` words.
+fn array_body(words: &[u16]) -> String {
+    words
+        .chunks(WORDS_PER_LINE)
+        .map(|line| {
+            let entries: Vec<String> = line.iter().map(|word| format!("0x{:04x},", word)).collect();
+            format!("    {}\n", entries.join(" "))
+        })
+        .collect()
+}
+
+/// The source of a program, file 0 of `debug`, with each line that
+/// assembled to instructions followed by a comment giving their addresses
+/// and words, e.g. `; 0x0004: 0x0023 0x8000`. Everything else, comments and
+/// blank lines included, is left as it was.
+pub fn listing(source: &str, words: &[u16], debug: &DebugInfo) -> String {
+    let mut out = String::new();
+    for (i, line) in source.lines().enumerate() {
+        let addrs = debug.addrs_on_line(0, i + 1);
+        if addrs.is_empty() {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        let code: Vec<String> = addrs
+            .iter()
+            .map(|&addr| {
+                let word = |ofs: u16| words.get((addr + ofs) as usize).copied().unwrap_or(0);
+                format!("0x{:04x}: 0x{:04x} 0x{:04x}", addr, word(0), word(1))
+            })
+            .collect();
+        let width = line.trim_end().len();
+        out.push_str(&format!(
+            "{}{:pad$}; {}\n",
+            line.trim_end(),
+            "",
+            code.join(" | "),
+            pad = LISTING_COLUMN.saturating_sub(width).max(2)
+        ));
+    }
+    out
+}
+
+/// Loads an Intel HEX file. Addresses in the file are byte addresses and words
+/// are little endian, the same layout as a `.rom` file.
+pub fn load_ihex(path: &str) -> io::Result<Memory> {
+    let mut mem = Memory::new();
+    let mut base: u32 = 0;
+    for (linenum, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bytes = match line.strip_prefix(':') {
+            Some(rest) => record_bytes(rest, linenum)?,
+            None => return Err(bad_record(linenum, "record does not start with ':'")),
+        };
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(bad_record(
+                linenum,
+                "record length does not match its byte count",
+            ));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(bad_record(linenum, "checksum mismatch"));
+        }
+        let addr = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => mem.load_bytes(base + addr, data)?,
+            0x01 => break,
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            0x03 | 0x05 => {}
+            kind => {
+                return Err(bad_record(
+                    linenum,
+                    &format!("unsupported record type {:02x}", kind),
+                ))
+            }
+        }
+    }
+    Ok(mem)
+}
+
+/// Loads a Motorola S-record file, with the same addressing as Intel HEX.
+pub fn load_srec(path: &str) -> io::Result<Memory> {
+    let mut mem = Memory::new();
+    for (linenum, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut chars = line.chars();
+        let kind = match (chars.next(), chars.next()) {
+            (Some('S'), Some(kind)) => kind,
+            _ => return Err(bad_record(linenum, "record does not start with 'S'")),
+        };
+        let bytes = record_bytes(chars.as_str(), linenum)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(bad_record(
+                linenum,
+                "record length does not match its byte count",
+            ));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+            return Err(bad_record(linenum, "checksum mismatch"));
+        }
+        let addr_len = match kind {
+            '1' => 2,
+            '2' => 3,
+            '3' => 4,
+            '0' | '5' | '6' => continue,
+            '7' | '8' | '9' => break,
+            _ => {
+                return Err(bad_record(
+                    linenum,
+                    &format!("unsupported record type S{}", kind),
+                ))
+            }
+        };
+        if bytes.len() < addr_len + 2 {
+            return Err(bad_record(linenum, "record too short for its address"));
+        }
+        let addr = bytes[1..=addr_len]
+            .iter()
+            .fold(0u32, |addr, b| (addr << 8) | *b as u32);
+        mem.load_bytes(addr, &bytes[addr_len + 1..bytes.len() - 1])?;
+    }
+    Ok(mem)
+}
+
+fn record_bytes(hex: &str, linenum: usize) -> io::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(bad_record(linenum, "odd number of hex digits"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| bad_record(linenum, "invalid hex digit"))
+        })
+        .collect()
+}
+
+fn bad_record(linenum: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid record on line {}: {}", linenum + 1, msg),
+    )
+}
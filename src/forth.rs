@@ -0,0 +1,488 @@
+//! A small Forth-style front end that compiles to TeenyAT assembly.
+//!
+//! ```text
+//! variable count
+//! : squared ( n -- n*n ) dup * ;
+//! : squares ( n -- ) 1 do i squared . loop cr ;
+//! 6 squares
+//! ." done" cr
+//! ```
+//!
+//! The data stack is the hardware stack, so literals and primitives compile
+//! straight to `push`/`pop`, and colon definitions are called with `call`.
+//! Because `call` leaves its return address on that same stack, every word
+//! moves it onto a separate return stack in memory, addressed by `ex`, on
+//! entry and back again before `ret`. Counted loops keep their index and
+//! limit on the return stack as well.
+//!
+//! Top level code runs in order as the main program. Truth values are -1 and
+//! 0, as in standard Forth.
+
+use std::collections::HashMap;
+use std::io;
+
+const RSTACK_BASE: u16 = 0x7000;
+const VAR_BASE: u16 = 0x6000;
+
+/// Primitive words and the instructions they compile to.
+const PRIMITIVES: &[(&str, &[&str])] = &[
+    ("+", &["pop bx", "pop ax", "add ax, bx", "push ax"]),
+    ("-", &["pop bx", "pop ax", "sub ax, bx", "push ax"]),
+    ("*", &["pop bx", "pop ax", "mult ax, bx", "push ax"]),
+    ("/", &["pop bx", "pop ax", "div ax, bx", "push ax"]),
+    ("mod", &["pop bx", "pop ax", "mod ax, bx", "push ax"]),
+    ("and", &["pop bx", "pop ax", "and ax, bx", "push ax"]),
+    ("or", &["pop bx", "pop ax", "or ax, bx", "push ax"]),
+    ("xor", &["pop bx", "pop ax", "xor ax, bx", "push ax"]),
+    ("invert", &["pop ax", "inv ax", "push ax"]),
+    ("negate", &["pop ax", "neg ax", "push ax"]),
+    ("1+", &["pop ax", "inc ax", "push ax"]),
+    ("1-", &["pop ax", "dec ax", "push ax"]),
+    ("dup", &["pop ax", "push ax", "push ax"]),
+    ("drop", &["pop ax"]),
+    ("swap", &["pop bx", "pop ax", "push bx", "push ax"]),
+    (
+        "over",
+        &["pop bx", "pop ax", "push ax", "push bx", "push ax"],
+    ),
+    (
+        "rot",
+        &[
+            "pop cx", "pop bx", "pop ax", "push bx", "push cx", "push ax",
+        ],
+    ),
+    ("@", &["pop ax", "pload bx, ax", "push bx"]),
+    ("!", &["pop ax", "pop bx", "pstor ax, bx"]),
+    ("emit", &["pop ax", "stor OUT, ax"]),
+    ("key", &["load ax, IN", "push ax"]),
+    ("cr", &["set ax, 10", "stor OUT, ax"]),
+    ("space", &["set ax, 32", "stor OUT, ax"]),
+    ("i", &["pload ax, ex", "push ax"]),
+    ("bye", &["set ax, 0", "stor END, ax"]),
+];
+
+/// Comparison words and the jump that makes them true.
+const COMPARISONS: &[(&str, &str)] = &[
+    ("=", "je "),
+    ("<>", "jne"),
+    ("<", "jl "),
+    (">", "jg "),
+    ("<=", "jle"),
+    (">=", "jge"),
+];
+
+/// An open control structure waiting for its closing word.
+enum Control {
+    If(String),
+    Else(String),
+    Begin(String),
+    While(String, String),
+    Do(String),
+}
+
+struct Compiler {
+    main: Vec<String>,
+    defs: Vec<String>,
+    words: HashMap<String, usize>,
+    values: HashMap<String, u16>,
+    vars: u16,
+    labels: usize,
+    current: Option<usize>,
+    control: Vec<Control>,
+    uses_print: bool,
+}
+
+impl Compiler {
+    fn emit(&mut self, ins: &str) {
+        let line = format!("    {}", ins);
+        if self.current.is_some() {
+            self.defs.push(line);
+        } else {
+            self.main.push(line);
+        }
+    }
+
+    fn place(&mut self, label: &str) {
+        if self.current.is_some() {
+            self.defs.push(label.to_string());
+        } else {
+            self.main.push(label.to_string());
+        }
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("!fl_{}", self.labels)
+    }
+
+    /// Pops the top of the data stack and jumps to `label` if it is zero.
+    fn jump_if_zero(&mut self, label: &str) {
+        self.emit("pop ax");
+        self.emit("set bx, 0");
+        self.emit(&format!("je  ax, bx, {}", label));
+    }
+
+    fn jump(&mut self, label: &str) {
+        self.emit(&format!("je  pc, pc, {}", label));
+    }
+
+    fn word(&mut self, word: &str, toks: &mut Tokens) -> Result<(), String> {
+        let lower = word.to_ascii_lowercase();
+        if let Some((_, body)) = PRIMITIVES.iter().find(|(name, _)| *name == lower) {
+            for ins in body.iter() {
+                self.emit(ins);
+            }
+            return Ok(());
+        }
+        if let Some((_, jump)) = COMPARISONS.iter().find(|(name, _)| *name == lower) {
+            let done = self.label();
+            self.emit("pop bx");
+            self.emit("pop ax");
+            self.emit("set cx, -1");
+            self.emit(&format!("{} ax, bx, {}", jump, done));
+            self.emit("set cx, 0");
+            self.place(&done);
+            self.emit("push cx");
+            return Ok(());
+        }
+        match lower.as_str() {
+            "." => {
+                self.uses_print = true;
+                self.emit("pop ax");
+                self.emit("call !std_print_num");
+                self.emit("set ax, 32");
+                self.emit("stor OUT, ax");
+            }
+            ".\"" => {
+                let text = toks.until('"')?;
+                for chr in text.bytes() {
+                    self.emit(&format!("set ax, {}", chr));
+                    self.emit("stor OUT, ax");
+                }
+            }
+            ":" => {
+                if self.current.is_some() {
+                    return Err("nested `:` definition".to_string());
+                }
+                let name = toks.next_word().ok_or("`:` without a name")?;
+                let index = self.words.len();
+                self.words.insert(name.to_ascii_lowercase(), index);
+                self.current = Some(index);
+                self.defs.push(String::new());
+                self.defs.push(format!("; : {}", name));
+                self.place(&format!("!fw_{}", index));
+                // move the return address off the data stack
+                self.emit("pop dx");
+                self.emit("dec ex");
+                self.emit("pstor ex, dx");
+            }
+            ";" => {
+                if self.current.is_none() {
+                    return Err("`;` outside a definition".to_string());
+                }
+                if !self.control.is_empty() {
+                    return Err("unterminated control structure before `;`".to_string());
+                }
+                self.emit("pload dx, ex");
+                self.emit("inc ex");
+                self.emit("push dx");
+                self.emit("ret");
+                self.current = None;
+            }
+            "variable" => {
+                let name = toks.next_word().ok_or("`variable` without a name")?;
+                self.values
+                    .insert(name.to_ascii_lowercase(), VAR_BASE + self.vars);
+                self.vars += 1;
+            }
+            "constant" => {
+                let name = toks.next_word().ok_or("`constant` without a name")?;
+                let val = self
+                    .pop_literal()
+                    .ok_or("`constant` needs a literal value")?;
+                self.values.insert(name.to_ascii_lowercase(), val);
+            }
+            "if" => {
+                let label = self.label();
+                self.jump_if_zero(&label);
+                self.control.push(Control::If(label));
+            }
+            "else" => {
+                let skip = match self.control.pop() {
+                    Some(Control::If(skip)) => skip,
+                    _ => return Err("`else` without `if`".to_string()),
+                };
+                let done = self.label();
+                self.jump(&done);
+                self.place(&skip);
+                self.control.push(Control::Else(done));
+            }
+            "then" => match self.control.pop() {
+                Some(Control::If(label)) | Some(Control::Else(label)) => self.place(&label),
+                _ => return Err("`then` without `if`".to_string()),
+            },
+            "begin" => {
+                let top = self.label();
+                self.place(&top);
+                self.control.push(Control::Begin(top));
+            }
+            "until" => match self.control.pop() {
+                Some(Control::Begin(top)) => self.jump_if_zero(&top),
+                _ => return Err("`until` without `begin`".to_string()),
+            },
+            "while" => match self.control.pop() {
+                Some(Control::Begin(top)) => {
+                    let done = self.label();
+                    self.jump_if_zero(&done);
+                    self.control.push(Control::While(top, done));
+                }
+                _ => return Err("`while` without `begin`".to_string()),
+            },
+            "repeat" => match self.control.pop() {
+                Some(Control::While(top, done)) => {
+                    self.jump(&top);
+                    self.place(&done);
+                }
+                _ => return Err("`repeat` without `begin ... while`".to_string()),
+            },
+            "do" => {
+                // ( limit start -- ) with the index on top of the return stack
+                let top = self.label();
+                self.emit("pop ax");
+                self.emit("pop bx");
+                self.emit("dec ex");
+                self.emit("pstor ex, bx");
+                self.emit("dec ex");
+                self.emit("pstor ex, ax");
+                self.place(&top);
+                self.control.push(Control::Do(top));
+            }
+            "loop" => match self.control.pop() {
+                Some(Control::Do(top)) => {
+                    self.emit("pload ax, ex");
+                    self.emit("inc ax");
+                    self.emit("pstor ex, ax");
+                    self.emit("copy cx, ex");
+                    self.emit("inc cx");
+                    self.emit("pload bx, cx");
+                    self.emit(&format!("jl  ax, bx, {}", top));
+                    self.emit("inc ex");
+                    self.emit("inc ex");
+                }
+                _ => return Err("`loop` without `do`".to_string()),
+            },
+            _ => {
+                if let Some(index) = self.words.get(&lower) {
+                    let call = format!("call !fw_{}", index);
+                    self.emit(&call);
+                } else if let Some(val) = self.values.get(&lower) {
+                    let set = format!("set ax, {}", val);
+                    self.emit(&set);
+                    self.emit("push ax");
+                } else if let Some(val) = parse_number(word) {
+                    self.emit(&format!("set ax, {}", val));
+                    self.emit("push ax");
+                } else {
+                    return Err(format!("unknown word `{}`", word));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the code for a just compiled literal, returning its value.
+    fn pop_literal(&mut self) -> Option<u16> {
+        let code = if self.current.is_some() {
+            &mut self.defs
+        } else {
+            &mut self.main
+        };
+        let len = code.len();
+        if len < 2 || code[len - 1] != "    push ax" {
+            return None;
+        }
+        let val = code[len - 2].strip_prefix("    set ax, ")?.parse().ok()?;
+        code.truncate(len - 2);
+        Some(val)
+    }
+}
+
+fn parse_number(word: &str) -> Option<u16> {
+    if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix('$')) {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    word.parse::<u16>()
+        .ok()
+        .or_else(|| word.parse::<i16>().ok().map(|val| val as u16))
+}
+
+/// Splits source into whitespace separated words, skipping comments.
+struct Tokens<'a> {
+    src: &'a str,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next_word(&mut self) -> Option<&'a str> {
+        loop {
+            let trimmed = self.src.trim_start_matches(|c: char| {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                c.is_whitespace()
+            });
+            let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+            if end == 0 {
+                self.src = trimmed;
+                return None;
+            }
+            let word = &trimmed[..end];
+            self.src = &trimmed[end..];
+            match word {
+                "\\" => {
+                    let eol = self.src.find('\n').unwrap_or(self.src.len());
+                    self.src = &self.src[eol..];
+                }
+                "(" => {
+                    if self.until(')').is_err() {
+                        return None;
+                    }
+                }
+                _ => return Some(word),
+            }
+        }
+    }
+
+    /// Consumes text up to and including `delim`, returning what came before.
+    fn until(&mut self, delim: char) -> Result<&'a str, String> {
+        let src = self.src.strip_prefix(' ').unwrap_or(self.src);
+        match src.find(delim) {
+            Some(end) => {
+                self.line += src[..end].matches('\n').count();
+                self.src = &src[end + 1..];
+                Ok(&src[..end])
+            }
+            None => Err(format!("missing closing `{}`", delim)),
+        }
+    }
+}
+
+/// Compiles Forth source to TeenyAT assembly. `name` is only used in the
+/// generated header comment.
+pub fn compile(name: &str, src: &str) -> io::Result<String> {
+    let mut compiler = Compiler {
+        main: Vec::new(),
+        defs: Vec::new(),
+        words: HashMap::new(),
+        values: HashMap::new(),
+        vars: 0,
+        labels: 0,
+        current: None,
+        control: Vec::new(),
+        uses_print: false,
+    };
+    let mut toks = Tokens { src, line: 1 };
+    while let Some(word) = toks.next_word() {
+        let line = toks.line;
+        compiler
+            .word(word, &mut toks)
+            .map_err(|msg| compile_error(Some(line), &msg))?;
+    }
+    if compiler.current.is_some() {
+        return Err(compile_error(None, "definition missing its closing `;`"));
+    }
+    if !compiler.control.is_empty() {
+        return Err(compile_error(None, "unterminated control structure"));
+    }
+    let mut out = vec![
+        format!("; compiled from {} by teenyat forth", name),
+        format!("    set ex, {}", RSTACK_BASE),
+    ];
+    out.append(&mut compiler.main);
+    out.push("    set ax, 0".to_string());
+    out.push("    stor END, ax".to_string());
+    out.append(&mut compiler.defs);
+    if compiler.uses_print {
+        out.push(String::new());
+        out.push(".include <std/print.tat>".to_string());
+    }
+    let mut asm = out.join("\n");
+    asm.push('\n');
+    Ok(asm)
+}
+
+fn compile_error(line: Option<usize>, msg: &str) -> io::Error {
+    let msg = match line {
+        Some(line) => format!("Forth error on line {}: {}", line, msg),
+        None => format!("Forth error: {}", msg),
+    };
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selftest::run_source;
+
+    /// Compiles and runs `src` with `input`, returning its output.
+    fn run(src: &str, input: &str) -> String {
+        let (code, output) = run_source(&compile("test.fth", src).unwrap(), input).unwrap();
+        assert_eq!(code, 0);
+        output
+    }
+
+    fn error(src: &str) -> String {
+        compile("test.fth", src).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn arithmetic_and_printing() {
+        assert_eq!(run("2 3 + 4 * . -3 . 7 negate . cr", ""), "20 -3 -7 \n");
+    }
+
+    #[test]
+    fn recursion() {
+        let src = ": fact dup 1 > if dup 1- fact * then ; 6 fact .";
+        assert_eq!(run(src, ""), "720 ");
+    }
+
+    #[test]
+    fn counted_loops() {
+        let src = ": squares 1 do i dup * . loop ; 5 squares";
+        assert_eq!(run(src, ""), "1 4 9 16 ");
+    }
+
+    #[test]
+    fn variables_and_constants() {
+        let src = "variable x variable y 10 constant ten
+                   5 x ! ten y ! x @ y @ * . y @ 1+ .";
+        assert_eq!(run(src, ""), "50 11 ");
+    }
+
+    #[test]
+    fn conditionals_use_signed_comparisons() {
+        let src = ": sign dup 0 < if drop 45 else 0 > if 43 else 48 then then emit ;
+                   -5 sign 5 sign 0 sign -1 1 < . 1 -1 < .";
+        assert_eq!(run(src, ""), "-+0-1 0 ");
+    }
+
+    #[test]
+    fn indefinite_loops() {
+        let src = "0 begin 1+ dup 3 = until . 0 begin dup 4 < while 1+ repeat .";
+        assert_eq!(run(src, ""), "3 4 ");
+    }
+
+    #[test]
+    fn strings_and_input() {
+        assert_eq!(run(".\" got \" key emit key emit cr", "ok"), "got ok\n");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(error("1 frobnicate").contains("unknown word `frobnicate`"));
+        assert!(error("1 then").contains("`then` without `if`"));
+        assert!(error("1 ;").contains("`;` outside a definition"));
+        assert!(error(": f 1 if ;").contains("unterminated control structure before `;`"));
+        assert!(error(": f 1").contains("definition missing its closing `;`"));
+    }
+}
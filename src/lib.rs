@@ -0,0 +1,70 @@
+//! The TeenyAT virtual machine, its assembler and the tools built on them.
+//!
+//! A program is assembled into a `Memory` image with `assembler::assemble_file`
+//! (or loaded with `Memory::from_rom_file` and the loaders in `formats`) and
+//! run by a `vm::TeenyAT`.
+//!
+//! `arch` and `cpu`, the instruction set, memory and processor, build without
+//! `std` when the default `std` feature is turned off; everything else, from
+//! file loading to devices, needs it.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+pub mod arch;
+#[cfg(feature = "std")]
+pub mod assembler;
+#[cfg(feature = "std")]
+pub mod bench;
+#[cfg(feature = "std")]
+pub mod bf;
+#[cfg(feature = "std")]
+pub mod channel;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod dap;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod debuginfo;
+#[cfg(feature = "std")]
+pub mod devices;
+#[cfg(feature = "std")]
+pub mod expr;
+#[cfg(feature = "std")]
+pub mod feed;
+#[cfg(feature = "std")]
+pub mod formats;
+#[cfg(feature = "std")]
+pub mod forth;
+#[cfg(feature = "std")]
+pub mod manifest;
+#[cfg(all(feature = "std", not(target_os = "wasi")))]
+mod memview;
+#[cfg(all(feature = "std", not(target_os = "wasi")))]
+pub mod plugin;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod scheduler;
+#[cfg(feature = "std")]
+pub mod selftest;
+#[cfg(feature = "std")]
+mod stdlib;
+#[cfg(feature = "std")]
+pub mod syscall;
+#[cfg(feature = "std")]
+pub mod teenyc;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod transcript;
+#[cfg(feature = "std")]
+pub mod vm;
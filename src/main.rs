@@ -1,37 +1,672 @@
-mod arch;
-mod assembler;
-mod vm;
-
+use log::{warn, LevelFilter, Log, Metadata, Record};
+use std::collections::HashSet;
 use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use teenyat::arch::{Endian, RomOptions};
+use teenyat::clock::ClockMode;
+use teenyat::config::{self, MachineConfig};
+use teenyat::debugger::{self, Debugger};
+use teenyat::debuginfo::DebugInfo;
+use teenyat::formats::{self, ImageFormat};
+use teenyat::manifest::{Manifest, MANIFEST};
+use teenyat::profile::Profile;
+use teenyat::scheduler::Scheduler;
+use teenyat::trace;
+use teenyat::transcript::{self, Transcript};
+use teenyat::vm::Overflow;
+use teenyat::{analysis, arch, assembler, bench, bf, dap, forth, selftest, teenyc, vm};
 
 fn main() {
-    let mut args = env::args();
+    log::set_logger(&StderrLogger)
+        .map(|()| log::set_max_level(LevelFilter::Warn))
+        .expect("the logger is only set once");
+    let mut args = env::args().collect::<Vec<_>>().into_iter();
     if args.len() >= 2 {
         args.next();
-        let path = args.next().unwrap();
+        let mut path = args.next().unwrap();
+        if path == "dap" {
+            or_exit(dap::serve());
+            return;
+        }
+        if path == "build" {
+            or_exit(build(args));
+            return;
+        }
+        if path == "run" {
+            // Run the project's entry as if it had been named along with
+            // the manifest's flags, ahead of any given here.
+            let manifest = or_exit(load_manifest());
+            let mut run_args = Vec::new();
+            if let Some(config) = &manifest.config {
+                run_args.extend(["--config".to_string(), config.clone()]);
+            }
+            run_args.extend(manifest.run.iter().cloned());
+            run_args.extend(args);
+            path = manifest.entry;
+            args = run_args.into_iter();
+        }
+        if path == "diff" {
+            match (args.next(), args.next()) {
+                (Some(a), Some(b)) => {
+                    let same = or_exit(diff(&a, &b));
+                    process::exit(if same { 0 } else { 1 });
+                }
+                _ => usage_error("usage: teenyat diff <a.rom> <b.rom>"),
+            }
+        }
+        if path == "trace-diff" {
+            match (args.next(), args.next()) {
+                (Some(a), Some(b)) => {
+                    let same = or_exit(trace_diff(&a, &b));
+                    process::exit(if same { 0 } else { 1 });
+                }
+                _ => usage_error("usage: teenyat trace-diff <a.log> <b.log>"),
+            }
+        }
+        if path == "multi" {
+            let usage =
+                "usage: teenyat multi <a.tat> <b.tat>... [--config machine.toml] [--budget n]";
+            let mut files = Vec::new();
+            let mut machine = MachineConfig::default();
+            let mut budget = MULTI_BUDGET;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--config" => match args.next() {
+                        Some(path) => machine = or_exit(MachineConfig::load(&path)),
+                        None => usage_error(usage),
+                    },
+                    "--budget" => match args.next().and_then(|n| n.parse().ok()) {
+                        Some(n) if n > 0 => budget = n,
+                        _ => usage_error(usage),
+                    },
+                    _ if arg.starts_with('-') => usage_error(usage),
+                    _ => files.push(arg),
+                }
+            }
+            if files.is_empty() {
+                usage_error(usage);
+            }
+            let all_ok = or_exit(multi(&files, &machine, budget));
+            process::exit(if all_ok { 0 } else { 1 });
+        }
+        if path == "bench" {
+            let usage = "usage: teenyat bench [--time 2s]";
+            let time = match (args.next().as_deref(), args.next()) {
+                (None, _) => BENCH_TIME,
+                (Some("--time"), Some(time)) => {
+                    parse_duration(&time).unwrap_or_else(|| usage_error(usage))
+                }
+                _ => usage_error(usage),
+            };
+            run_bench(time);
+            return;
+        }
+        if path == "selftest" {
+            process::exit(if run_selftest() { 0 } else { 1 });
+        }
+        if path == "size" {
+            match args.next() {
+                Some(file) => or_exit(size(&file)),
+                None => usage_error("usage: teenyat size <file.rom>"),
+            }
+            return;
+        }
+        if path == "info" {
+            match args.next() {
+                Some(file) => or_exit(info(&file)),
+                None => usage_error("usage: teenyat info <file.rom>"),
+            }
+            return;
+        }
+        if path == "profile" {
+            let usage = "usage: teenyat profile <file.tat> [--input file] [--config machine.toml]";
+            let file = args.next().unwrap_or_else(|| usage_error(usage));
+            let mut input = None;
+            let mut machine = MachineConfig::default();
+            while let Some(opt) = args.next() {
+                match (opt.as_str(), args.next()) {
+                    ("--input", Some(path)) => input = Some(path),
+                    ("--config", Some(path)) => machine = or_exit(MachineConfig::load(&path)),
+                    _ => usage_error(usage),
+                }
+            }
+            let code = or_exit(profile(&file, input.as_deref(), &machine));
+            process::exit(code as i32);
+        }
+        if path == "hexdump" {
+            let usage = "usage: teenyat hexdump <file.rom> [start] [words]";
+            let file = args.next().unwrap_or_else(|| usage_error(usage));
+            let mut num = || {
+                args.next().map(|text| {
+                    let parsed = match text.strip_prefix("0x") {
+                        Some(hex) => usize::from_str_radix(hex, 16),
+                        None => text.parse(),
+                    };
+                    parsed.unwrap_or_else(|_| usage_error(usage))
+                })
+            };
+            let start = num();
+            let len = num();
+            or_exit(hexdump(&file, start, len));
+            return;
+        }
+        if path == "symbols" {
+            let file = args.next();
+            let by_name = match args.next().as_deref() {
+                None => false,
+                Some("--by-name") => true,
+                Some(_) => usage_error("usage: teenyat symbols <file.rom> [--by-name]"),
+            };
+            match file {
+                Some(file) => or_exit(symbols(&file, by_name)),
+                None => usage_error("usage: teenyat symbols <file.rom> [--by-name]"),
+            }
+            return;
+        }
+        if path == "list" {
+            match args.next() {
+                Some(file) => or_exit(list(&file)),
+                None => usage_error("usage: teenyat list <file.tat>"),
+            }
+            return;
+        }
+        if path == "cfg" {
+            match args.next() {
+                Some(file) => or_exit(cfg(&file)),
+                None => usage_error("usage: teenyat cfg <file.tat>"),
+            }
+            return;
+        }
+        if path == "cc" || path == "forth" || path == "bf" {
+            match args.next() {
+                Some(source) => {
+                    let out = or_exit(compile_source(&source));
+                    println!("Wrote {}", out);
+                }
+                None => usage_error(&format!("usage: teenyat {} <file>", path)),
+            }
+            return;
+        }
         let mut debug_mode = false;
-        if let Some(s) = args.next() {
-            debug_mode = s == "-d";
-            if s == "-a" {
-                assemble(path).unwrap();
-                return;
+        let mut assemble_only = false;
+        let mut show_stats = false;
+        let mut machine = MachineConfig::default();
+        let mut plugins = Vec::new();
+        let mut overflow_flag = None;
+        let mut timeout = None;
+        let mut log_level = LevelFilter::Warn;
+        let mut cycle_hz = None;
+        let mut seed = None;
+        let mut clock = None;
+        let mut poison = false;
+        let mut shadow_stack = false;
+        let mut trap_misaligned = false;
+        let mut format = ImageFormat::Rom;
+        let mut endian = None;
+        let mut pad = None;
+        let mut compress = false;
+        let mut allow_truncate = false;
+        let mut console = ConsoleOptions::default();
+        while let Some(s) = args.next() {
+            match s.as_str() {
+                "-d" => debug_mode = true,
+                "-a" => assemble_only = true,
+                "--stats" => show_stats = true,
+                "--config" => match args.next() {
+                    Some(file) => machine = or_exit(MachineConfig::load(&file)),
+                    None => usage_error("--config expects a file"),
+                },
+                "--overflow" => {
+                    overflow_flag = match args.next().as_deref().and_then(Overflow::from_str) {
+                        Some(overflow) => Some(overflow),
+                        None => usage_error("--overflow expects one of: wrap, saturate, trap"),
+                    }
+                }
+                "--mhz" => match args.next().and_then(|mhz| mhz.parse::<f64>().ok()) {
+                    Some(mhz) if mhz > 0.0 => cycle_hz = Some((mhz * 1e6) as u64),
+                    _ => usage_error("--mhz expects a clock rate in MHz, such as 1 or 0.5"),
+                },
+                "--clock" => match args.next().as_deref().and_then(ClockMode::from_str) {
+                    Some(mode) => clock = Some(mode),
+                    None => usage_error("--clock expects virtual or real"),
+                },
+                "--seed" => match args.next().and_then(|seed| seed.parse::<u64>().ok()) {
+                    Some(n) => seed = Some(n),
+                    None => usage_error("--seed expects a whole number"),
+                },
+                "--timeout" => match args.next().as_deref().and_then(parse_duration) {
+                    Some(limit) => timeout = Some(limit),
+                    None => usage_error("--timeout expects a duration such as 5s or 500ms"),
+                },
+                "--endian" => match args.next().as_deref().and_then(Endian::from_str) {
+                    Some(order) => endian = Some(order),
+                    None => usage_error("--endian expects little or big"),
+                },
+                "--pad" => match args.next().and_then(|words| words.parse::<usize>().ok()) {
+                    Some(words) if words <= arch::MEM_SIZE as usize => pad = Some(words),
+                    _ => usage_error("--pad expects a number of words no larger than memory"),
+                },
+                "--compress" => compress = true,
+                "--allow-truncate" => allow_truncate = true,
+                "--poison" => poison = true,
+                "--shadow-stack" => shadow_stack = true,
+                "--trap-misaligned" => trap_misaligned = true,
+                _ if assembler_flag(&s, &mut args) => {}
+                "--stdin" => match args.next() {
+                    Some(file) => console.stdin = Some(file),
+                    None => usage_error("--stdin expects a file"),
+                },
+                "--stdout" => match args.next() {
+                    Some(file) => console.stdout = Some(file),
+                    None => usage_error("--stdout expects a file"),
+                },
+                "--echo" => console.echo = true,
+                "--record" => match args.next() {
+                    Some(file) => console.transcript = TranscriptMode::Record(file),
+                    None => usage_error("--record expects a file"),
+                },
+                "--replay" => match args.next() {
+                    Some(file) => console.transcript = TranscriptMode::Replay(file),
+                    None => usage_error("--replay expects a file"),
+                },
+                "--trace" => match args.next() {
+                    Some(file) => console.trace = Some(file),
+                    None => usage_error("--trace expects a file"),
+                },
+                "--log-level" => match args.next().and_then(|level| level.parse().ok()) {
+                    Some(level) => log_level = level,
+                    None => usage_error(
+                        "--log-level expects one of: off, error, warn, info, debug, trace",
+                    ),
+                },
+                "--plugin" => match args.next() {
+                    Some(path) => plugins.push(config::PluginConfig {
+                        path,
+                        address: None,
+                        shared: false,
+                    }),
+                    None => usage_error("--plugin expects a shared library"),
+                },
+                "--format" => {
+                    format = match args.next().as_deref().and_then(ImageFormat::from_str) {
+                        Some(format) => format,
+                        None => usage_error("--format expects one of: rom, logisim, hex, c, rust"),
+                    }
+                }
+                _ => usage_error(&format!("Unknown option: {}", s)),
             }
         }
-        run(path, debug_mode).unwrap();
+        machine.plugins.extend(plugins);
+        log::set_max_level(log_level);
+        if let Some(overflow) = overflow_flag {
+            machine.overflow = overflow;
+        }
+        if let Some(hz) = cycle_hz {
+            machine.cycle_hz = hz;
+        }
+        if seed.is_some() {
+            machine.seed = seed;
+        }
+        if let Some(mode) = clock {
+            machine.clock = mode;
+        }
+        if poison && machine.poison.is_none() {
+            machine.poison = Some(POISON);
+        }
+        if shadow_stack {
+            machine.shadow_stack = true;
+        }
+        if trap_misaligned {
+            machine.trap_misaligned = true;
+        }
+        if pad.is_some() && format != ImageFormat::Rom {
+            usage_error("--pad only applies to --format rom");
+        }
+        if compress && format != ImageFormat::Rom {
+            usage_error("--compress only applies to --format rom");
+        }
+        if compress && pad.is_some() {
+            usage_error("--compress and --pad can't be used together");
+        }
+        if assemble_only {
+            let rom = RomLayout {
+                endian: endian.unwrap_or_default(),
+                pad: pad.unwrap_or(0),
+                compress,
+            };
+            or_exit(assemble(path, format, rom, show_stats));
+            return;
+        }
+        if let Some(limit) = timeout {
+            if let Err(err) = start_watchdog(limit) {
+                usage_error(&format!("--timeout is not available here: {}", err));
+            }
+        }
+        let code = or_exit(run(
+            path,
+            debug_mode,
+            show_stats,
+            RomOptions {
+                endian,
+                allow_truncate,
+            },
+            &machine,
+            &console,
+        ));
+        process::exit(code as i32);
     } else {
         let path = console_input();
-        run(path, false).unwrap();
+        let code = or_exit(run(
+            path,
+            false,
+            false,
+            RomOptions::default(),
+            &MachineConfig::default(),
+            &ConsoleOptions::default(),
+        ));
+        process::exit(code as i32);
+    }
+}
+
+/// What `--poison` fills memory with, unless the machine config says.
+const POISON: u16 = 0xDEAD;
+
+/// Whether `--strict` was given, making the assembler's warnings about
+/// immediates out of range errors.
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Where the console's input and output go.
+#[derive(Default)]
+struct ConsoleOptions {
+    /// Read input from this file rather than stdin.
+    stdin: Option<String>,
+    /// Write output to this file rather than stdout.
+    stdout: Option<String>,
+    /// Write output to stdout as well as to the `stdout` file.
+    echo: bool,
+    transcript: TranscriptMode,
+    /// Write an instruction trace to this file.
+    trace: Option<String>,
+}
+
+/// Writes everything to both of two writers, for `--stdout` with `--echo`.
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+/// What `--record` or `--replay` asked for.
+#[derive(Default)]
+enum TranscriptMode {
+    #[default]
+    Off,
+    /// Record the program's input and output to this file.
+    Record(String),
+    /// Feed the program the input recorded in this file.
+    Replay(String),
+}
+
+/// Directories searched for included files: the project manifest's, then
+/// those given with `-I`, in order.
+static INCLUDE_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+fn include_dirs() -> Vec<PathBuf> {
+    INCLUDE_DIRS.lock().unwrap().clone()
+}
+
+/// Takes note of `--strict` or `-I dir`, which change how every source file
+/// is assembled, returning whether `arg` was one of them.
+fn assembler_flag(arg: &str, args: &mut impl Iterator<Item = String>) -> bool {
+    match arg {
+        "--strict" => STRICT.store(true, Ordering::SeqCst),
+        "-I" => match args.next() {
+            Some(dir) => INCLUDE_DIRS.lock().unwrap().push(PathBuf::from(dir)),
+            None => usage_error("-I expects a directory"),
+        },
+        _ => return false,
+    }
+    true
+}
+
+/// Reads `teenyat.toml` from the current directory and takes note of its
+/// include directories.
+fn load_manifest() -> std::io::Result<Manifest> {
+    let manifest = Manifest::load(MANIFEST).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => {
+            std::io::Error::new(err.kind(), format!("No {} in this directory", MANIFEST))
+        }
+        _ => err,
+    })?;
+    *INCLUDE_DIRS.lock().unwrap() = manifest.include_dirs();
+    Ok(manifest)
+}
+
+/// Builds the project in the current directory, writing its image and
+/// symbol file where the manifest says. Nothing is done if the image is
+/// newer than the manifest and every file that went into it, as listed in
+/// the `.deps` file beside it. `args` may add `-I` directories after the
+/// manifest's and turn on `--strict`.
+fn build(mut args: impl Iterator<Item = String>) -> std::io::Result<()> {
+    let manifest = load_manifest()?;
+    while let Some(arg) = args.next() {
+        if !assembler_flag(&arg, &mut args) {
+            usage_error("usage: teenyat build [-I dir]... [--strict]");
+        }
+    }
+    let output = manifest.output();
+    let stem = output
+        .rsplit_once('.')
+        .map_or(output.as_str(), |(stem, _)| stem);
+    let deps_path = format!("{}.deps", stem);
+    if up_to_date(&output, &deps_path) {
+        println!("{} is up to date", output);
+        return Ok(());
+    }
+    let (mem, debug) = load_program(&manifest.entry)?;
+    if let Some(dir) = Path::new(&output).parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    mem.save_program_as(&output, Endian::default())?;
+    debug.save_symbols(&sidecar_path(&output))?;
+    // The standard library is built in, so only files on disk are listed.
+    let mut deps = vec![MANIFEST.to_string(), manifest.entry.clone()];
+    for file in debug.files.iter() {
+        if Path::new(file).is_file() && !deps.contains(file) {
+            deps.push(file.clone());
+        }
+    }
+    fs::write(&deps_path, deps.join("\n") + "\n")?;
+    println!("Wrote {}", output);
+    Ok(())
+}
+
+/// Whether the image at `output` is newer than every file listed in the
+/// dependency file at `deps_path`.
+fn up_to_date(output: &str, deps_path: &str) -> bool {
+    let modified = |path: &str| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let (Some(built), Ok(deps)) = (modified(output), fs::read_to_string(deps_path)) else {
+        return false;
+    };
+    deps.lines()
+        .all(|dep| modified(dep).is_some_and(|changed| changed <= built))
+}
+
+/// Exit status when a guest's assertion fails, as when a C program's
+/// `assert` aborts it.
+const ASSERT_STATUS: i32 = 134;
+
+/// Exit status when `--timeout` stops a program, as with coreutils' `timeout`.
+const TIMEOUT_STATUS: i32 = 124;
+
+/// Parses a duration such as `5s`, `500ms` or `2m`. A bare number is seconds.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (num, unit) = text.split_at(split);
+    let num: f64 = num.parse().ok()?;
+    let secs = match unit {
+        "" | "s" => num,
+        "ms" => num / 1000.0,
+        "m" => num * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Ends the process once `limit` has passed, even if the program is blocked
+/// waiting for input. Fails where threads aren't available, as under WASI.
+fn start_watchdog(limit: Duration) -> std::io::Result<()> {
+    thread::Builder::new().spawn(move || {
+        thread::sleep(limit);
+        eprintln!("Timed out after {:?}", limit);
+        process::exit(TIMEOUT_STATUS);
+    })?;
+    Ok(())
+}
+
+/// Writes log records to stderr as `[LEVEL target] message`.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
     }
+
+    fn flush(&self) {}
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(2);
 }
 
-fn assemble(path: String) -> std::io::Result<()> {
-    let mem = assembler::parse_file(&path)?;
-    let out_path = path.replace(".tat", ".rom");
-    mem.save_program(&out_path)?;
+/// The value of `result`, or if it failed, its error printed on stderr and
+/// an exit with status 1, so that mistakes in a program or its files are
+/// reported as messages rather than panics.
+fn or_exit<T>(result: std::io::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err.to_string().trim_end());
+        process::exit(1);
+    })
+}
+
+/// How `-a` lays out a `.rom`.
+struct RomLayout {
+    endian: Endian,
+    /// The number of words to pad the program to.
+    pad: usize,
+    compress: bool,
+}
+
+fn assemble(
+    path: String,
+    format: ImageFormat,
+    rom: RomLayout,
+    show_stats: bool,
+) -> std::io::Result<()> {
+    let (mem, debug) = assemble_checked(&path)?;
+    let out_path = path.replace(".tat", &format!(".{}", format.extension()));
+    match format {
+        ImageFormat::Rom if rom.compress => fs::write(&out_path, mem.compressed_bytes(rom.endian))?,
+        ImageFormat::Rom => fs::write(&out_path, mem.padded_bytes(rom.endian, rom.pad))?,
+        ImageFormat::Logisim => fs::write(&out_path, formats::logisim_image(mem.program()))?,
+        ImageFormat::Hex => fs::write(&out_path, formats::hex_image(mem.program()))?,
+        ImageFormat::C => fs::write(&out_path, formats::c_array(mem.program()))?,
+        ImageFormat::Rust => fs::write(&out_path, formats::rust_array(mem.program()))?,
+    }
+    debug.save_symbols(&sidecar_path(&out_path))?;
     mem.print_program();
+    if show_stats {
+        print!("{}", analysis::stats(mem.program(), &debug));
+    }
     Ok(())
 }
 
+/// Assembles a `.tat` file, warning about any of its instructions that can
+/// never run, about mistakes in its use of the stack and about immediates
+/// out of range, which are errors with `--strict`. Unreachable code in
+/// included files is left alone, since a library routine going unused is
+/// normal.
+fn assemble_checked(path: &str) -> std::io::Result<(arch::Memory, DebugInfo)> {
+    let (mem, debug) = assembler::assemble_file_with(path, &include_dirs())?;
+    let own: Vec<u16> = (0..mem.program().len() as u16)
+        .filter(|&addr| debug.span(addr).is_some_and(|span| span.file == 0))
+        .collect();
+    let program = analysis::decode(mem.program(), &debug);
+    let entries = analysis::entry_points(mem.program(), &program, &debug);
+    for (first, last) in analysis::unreachable(&program, &entries, &own) {
+        let count = (last - first) / 2 + 1;
+        warn!(
+            target: "asm::flow",
+            "{}: {} unreachable instruction{} (0x{:04x} to 0x{:04x}); is a label or jump missing?",
+            debug.describe_span(debug.span(first).unwrap()),
+            count,
+            if count == 1 { "" } else { "s" },
+            first,
+            last
+        );
+    }
+    for warning in analysis::check_stack(&program) {
+        if let Some(span) = debug.span(warning.addr()) {
+            warn!(target: "asm::stack", "{}: {}", debug.describe_span(span), warning);
+        }
+    }
+    for warning in analysis::check_targets(&program, mem.program().len()) {
+        if let Some(span) = debug.span(warning.addr) {
+            warn!(target: "asm::align", "{}: {}", debug.describe_span(span), warning);
+        }
+    }
+    let strict = STRICT.load(Ordering::SeqCst);
+    let mut errors = 0;
+    for warning in analysis::check_immediates(&program) {
+        if let Some(span) = debug.span(warning.addr()) {
+            let msg = format!("{}: {}", debug.describe_span(span), warning);
+            if strict {
+                eprintln!("{}", msg);
+                errors += 1;
+            } else {
+                warn!(target: "asm::range", "{}", msg);
+            }
+        }
+    }
+    if errors > 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{}: {} immediate{} out of range, which --strict makes an error",
+                path,
+                errors,
+                if errors == 1 { "" } else { "s" }
+            ),
+        ));
+    }
+    Ok((mem, debug))
+}
+
 fn console_input() -> String {
     println!("Enter the name of the file to run: ");
     let mut buf = String::new();
@@ -39,19 +674,496 @@ fn console_input() -> String {
     buf
 }
 
-fn run(path: String, debug_mode: bool) -> std::io::Result<()> {
-    let program = if path.ends_with(".tat") {
-        assembler::parse_file(&path)?
+const FORTH_EXTENSIONS: &[&str] = &[".fth", ".fs", ".4th"];
+
+fn is_forth(path: &str) -> bool {
+    FORTH_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+/// Compiles a teenyC, Forth or Brainfuck file to a `.tat` file next to it,
+/// returning its path.
+fn compile_source(path: &str) -> std::io::Result<String> {
+    let src = fs::read_to_string(path)?;
+    let asm = if is_forth(path) {
+        forth::compile(path, &src)?
+    } else if path.ends_with(".bf") {
+        bf::compile(path, &src)?
+    } else {
+        teenyc::compile(path, &src)?
+    };
+    let stem = path.rsplit_once('.').map_or(path, |(stem, _)| stem);
+    let out_path = format!("{}.tat", stem);
+    fs::write(&out_path, asm)?;
+    Ok(out_path)
+}
+
+/// Prints statistics about a program without running it.
+fn size(path: &str) -> std::io::Result<()> {
+    let (mem, debug) = load_program(path)?;
+    print!("{}", analysis::stats(mem.program(), &debug));
+    Ok(())
+}
+
+/// Prints what a program says about itself, how big it is and where it
+/// starts.
+fn info(path: &str) -> std::io::Result<()> {
+    let (mem, debug) = load_program(path)?;
+    for (key, val) in mem.metadata().fields() {
+        if let Some(val) = val {
+            println!("{:<8} {}", key, val);
+        }
+    }
+    let words = mem.program().len();
+    println!("{:<8} {} words ({} bytes)", "size", words, 2 * words);
+    let entry = debug
+        .labels
+        .iter()
+        .filter(|&(_, &addr)| addr == 0)
+        .map(|(name, _)| name.as_str())
+        .min();
+    match entry {
+        Some(name) => println!("{:<8} 0x0000 ({})", "entry", name),
+        None => println!("{:<8} 0x0000", "entry"),
+    }
+    Ok(())
+}
+
+/// Writes the source of a program next to it as `<stem>.lst`, with the
+/// address and words of each line's instructions added as comments.
+fn list(path: &str) -> std::io::Result<()> {
+    let (mem, debug) = assemble_checked(path)?;
+    let source = fs::read_to_string(path)?;
+    let stem = path.rsplit_once('.').map_or(path, |(stem, _)| stem);
+    let out_path = format!("{}.lst", stem);
+    fs::write(&out_path, formats::listing(&source, mem.program(), &debug))?;
+    println!("Wrote {}", out_path);
+    Ok(())
+}
+
+/// Writes the control flow graph of a program next to it as `<stem>.dot`.
+fn cfg(path: &str) -> std::io::Result<()> {
+    let (mem, debug) = load_program(path)?;
+    let program = analysis::decode(mem.program(), &debug);
+    let stem = path.rsplit_once('.').map_or(path, |(stem, _)| stem);
+    let out_path = format!("{}.dot", stem);
+    let entries = analysis::entry_points(mem.program(), &program, &debug);
+    fs::write(&out_path, analysis::dot(&program, &entries, &debug.labels))?;
+    println!("Wrote {}", out_path);
+    Ok(())
+}
+
+/// Runs a program, counting how often each instruction executes, and writes
+/// the counts next to the source as `<stem>.prof`.
+fn profile(path: &str, input: Option<&str>, machine: &MachineConfig) -> std::io::Result<u16> {
+    let (program, debug) = load_program(path)?;
+    let mut vm = vm::TeenyAT::with_config(program, machine)?;
+    if let Some(input) = input {
+        let file = fs::File::open(input)?;
+        vm.set_io(
+            Box::new(std::io::BufReader::new(file)),
+            Box::new(std::io::stdout()),
+        );
+    }
+    let (result, profile) = Profile::run(&mut vm);
+    let stem = path.rsplit_once('.').map_or(path, |(stem, _)| stem);
+    let out_path = format!("{}.prof", stem);
+    fs::write(&out_path, profile.annotate(&debug))?;
+    let (count, cycles) = profile.total();
+    eprintln!(
+        "{} instructions, about {} cycles; wrote {}",
+        count, cycles, out_path
+    );
+    result.map_err(|err| {
+        eprintln!("{}", debugger::crash_report(&vm, &debug, &err));
+        err.into()
+    })
+}
+
+/// Prints a hexdump of a program, by default all of it.
+fn hexdump(path: &str, start: Option<usize>, len: Option<usize>) -> std::io::Result<()> {
+    let (mem, _) = load_program(path)?;
+    let start = start.unwrap_or(0).min(0xFFFF);
+    let len = len.unwrap_or_else(|| mem.program().len().saturating_sub(start));
+    print!("{}", mem.hexdump(start as u16, len));
+    Ok(())
+}
+
+/// Where the symbol file for an image lives: next to it, with a `.sym`
+/// extension.
+fn sidecar_path(path: &str) -> String {
+    let stem = path.rsplit_once('.').map_or(path, |(stem, _)| stem);
+    format!("{}.sym", stem)
+}
+
+/// Lists a program's labels, sorted by address or by name.
+fn symbols(path: &str, by_name: bool) -> std::io::Result<()> {
+    let (_, debug) = load_program(path)?;
+    let mut symbols = debug.symbols();
+    if by_name {
+        symbols.sort_by_key(|(_, lbl)| lbl.trim_start_matches(['!', ':']));
+    }
+    if symbols.is_empty() {
+        println!("No symbols for {}", path);
+    }
+    for (addr, lbl) in symbols {
+        println!("0x{:04x}  {}", addr, lbl);
+    }
+    Ok(())
+}
+
+/// Prints the instructions that differ between two programs, returning
+/// whether they are the same.
+fn diff(a_path: &str, b_path: &str) -> std::io::Result<bool> {
+    let (a, _) = load_program(a_path)?;
+    let (b, _) = load_program(b_path)?;
+    let (a, b) = (a.program(), b.program());
+    let addrs = analysis::diff(a, b);
+    for &addr in &addrs {
+        println!(
+            "0x{:04x}  {:<36}| {}",
+            addr,
+            diff_side(a, addr),
+            diff_side(b, addr)
+        );
+    }
+    if addrs.is_empty() {
+        println!("{} and {} are identical", a_path, b_path);
+    } else {
+        println!("{} instructions differ", addrs.len());
+    }
+    Ok(addrs.is_empty())
+}
+
+/// How many instructions each program in `teenyat multi` runs a turn
+/// unless told otherwise.
+const MULTI_BUDGET: u64 = 100;
+
+/// Writes a program's output to stdout a line at a time, each line starting
+/// with the program's name, so that several programs' output can be told
+/// apart.
+struct Labelled {
+    label: String,
+    line: Vec<u8>,
+}
+
+impl Labelled {
+    fn new(name: &str) -> Self {
+        Self {
+            label: format!("[{}] ", name),
+            line: Vec::new(),
+        }
+    }
+
+    fn finish_line(&mut self) -> std::io::Result<()> {
+        let mut out = std::io::stdout().lock();
+        out.write_all(self.label.as_bytes())?;
+        out.write_all(&self.line)?;
+        self.line.clear();
+        out.flush()
+    }
+}
+
+impl Write for Labelled {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            self.line.push(byte);
+            if byte == b'\n' {
+                self.finish_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Labelled {
+    /// Writes the last line, if the program didn't end it.
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.line.push(b'\n');
+            let _ = self.finish_line();
+        }
+    }
+}
+
+/// Runs several programs on machines built from `machine`, taking turns of
+/// `budget` instructions, with devices marked `shared` in the config
+/// attached to all of them. Each program's console output is printed
+/// labelled with its name, and it reads no input. Returns whether every
+/// program ended without a fault.
+fn multi(files: &[String], machine: &MachineConfig, budget: u64) -> std::io::Result<bool> {
+    let mut scheduler = Scheduler::new(budget);
+    let mut debug_infos = Vec::new();
+    for (file, devices) in files.iter().zip(machine.build_device_sets(files.len())?) {
+        let (program, debug) = load_program(file)?;
+        let mut vm = vm::TeenyAT::with_devices(program, machine, devices)?;
+        vm.set_io(
+            Box::new(Cursor::new(Vec::new())),
+            Box::new(Labelled::new(file)),
+        );
+        scheduler.add(vm);
+        debug_infos.push(debug);
+    }
+    let interrupt = Arc::new(AtomicBool::new(false));
+    #[cfg(not(target_os = "wasi"))]
+    {
+        let flag = Arc::clone(&interrupt);
+        let _ = ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst));
+    }
+    scheduler.set_interrupt(interrupt);
+    if scheduler.run().is_err() {
+        println!();
+        println!("Interrupted");
+        process::exit(130);
+    }
+    // Dropping each program's output writes out a last unfinished line
+    // before the results.
+    for index in 0..scheduler.len() {
+        scheduler
+            .vm_mut(index)
+            .set_io(Box::new(std::io::empty()), Box::new(std::io::sink()));
+    }
+    let mut all_ok = true;
+    for (index, (file, debug)) in files.iter().zip(debug_infos.iter()).enumerate() {
+        match scheduler.outcome(index) {
+            Some(Ok(code)) => println!("{}: exited with {}", file, code),
+            Some(Err(err)) => {
+                eprintln!(
+                    "{}: {}",
+                    file,
+                    debugger::crash_report(scheduler.vm(index), debug, err)
+                );
+                all_ok = false;
+            }
+            None => unreachable!("the scheduler runs until every program ends"),
+        }
+    }
+    Ok(all_ok)
+}
+
+/// How long `teenyat bench` runs each workload unless told otherwise.
+const BENCH_TIME: Duration = Duration::from_secs(2);
+
+/// Runs each workload in `bench` for `time` and prints how fast it went.
+fn run_bench(time: Duration) {
+    for workload in bench::WORKLOADS {
+        match bench::run(workload, time) {
+            Ok(sample) => println!(
+                "{:<8} {:>8.2} MIPS  ({} instructions, {} cycles in {:.2}s)",
+                workload.name,
+                sample.mips(),
+                sample.instructions,
+                sample.cycles,
+                sample.elapsed.as_secs_f64()
+            ),
+            Err(err) => {
+                eprintln!("{}: {}", workload.name, err);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Runs the programs in `selftest`, printing how each went and any opcode
+/// none of them ran. Returns whether all passed.
+fn run_selftest() -> bool {
+    let mut executed = HashSet::new();
+    let mut failed = 0;
+    for case in selftest::CASES {
+        match selftest::check(case, &mut executed) {
+            Ok(()) => println!("ok     {}", case.name),
+            Err(msg) => {
+                println!("FAILED {}: {}", case.name, msg);
+                failed += 1;
+            }
+        }
+    }
+    let missing = selftest::missing(&executed);
+    if !missing.is_empty() {
+        let names: Vec<String> = missing.iter().map(|op| op.mnemonic()).collect();
+        println!("FAILED not every opcode ran: {}", names.join(", "));
+        failed += 1;
+    }
+    if failed == 0 {
+        println!("All {} self tests passed", selftest::CASES.len());
+    }
+    failed == 0
+}
+
+/// Prints where two instruction traces written by `--trace` first differ,
+/// with the step before for context. Returns whether they're the same.
+fn trace_diff(a_path: &str, b_path: &str) -> std::io::Result<bool> {
+    let a = trace::parse(&fs::read_to_string(a_path)?)?;
+    let b = trace::parse(&fs::read_to_string(b_path)?)?;
+    let divergence = match trace::diverge(&a, &b) {
+        Some(divergence) => divergence,
+        None => {
+            println!(
+                "{} and {} are identical ({} steps)",
+                a_path,
+                b_path,
+                a.len()
+            );
+            return Ok(true);
+        }
+    };
+    let index = divergence.index;
+    println!("--- {}\n+++ {}", a_path, b_path);
+    if index > 0 {
+        println!("  {}", a[index - 1]);
+    }
+    for (marker, steps) in [("-", &a), ("+", &b)] {
+        match steps.get(index) {
+            Some(step) => println!("{} {}", marker, step),
+            None => println!("{} (ends after {} steps)", marker, steps.len()),
+        }
+    }
+    println!(
+        "First difference at step {}: {}",
+        a.get(index)
+            .or_else(|| b.get(index))
+            .map_or(0, |step| step.step),
+        divergence.fields.join(", ")
+    );
+    Ok(false)
+}
+
+fn diff_side(words: &[u16], addr: u16) -> String {
+    let addr = addr as usize;
+    match words.get(addr..addr + 2) {
+        Some(pair) => {
+            let ins = arch::Instruction::new(pair[0], pair[1]);
+            format!(
+                "{:04x} {:04x}  {}",
+                pair[0],
+                pair[1],
+                ins.disassemble().unwrap_or_else(|| "??".to_string())
+            )
+        }
+        None => "(missing)".to_string(),
+    }
+}
+
+/// Assembles, compiles or loads the program at `path` according to its
+/// extension. Labels for an assembled image come from its symbol file, if
+/// there is one.
+fn load_program(path: &str) -> std::io::Result<(arch::Memory, DebugInfo)> {
+    load_program_with(path, RomOptions::default())
+}
+
+/// Like `load_program`, reading `.rom` files as `rom` says.
+fn load_program_with(path: &str, rom: RomOptions) -> std::io::Result<(arch::Memory, DebugInfo)> {
+    let image_symbols = || {
+        let sym = sidecar_path(path);
+        if std::path::Path::new(&sym).exists() {
+            DebugInfo::load_symbols(&sym)
+        } else {
+            Ok(DebugInfo::default())
+        }
+    };
+    Ok(if path.ends_with(".tat") {
+        assemble_checked(path)?
+    } else if path.ends_with(".tc") || path.ends_with(".bf") || is_forth(path) {
+        assembler::assemble_file_with(&compile_source(path)?, &include_dirs())?
     } else if path.ends_with(".rom") {
-        arch::Memory::from_rom_file(&path)?
+        (
+            arch::Memory::from_rom_file_with(path, rom)?,
+            image_symbols()?,
+        )
+    } else if path.ends_with(".hex") || path.ends_with(".ihex") {
+        (formats::load_ihex(path)?, image_symbols()?)
+    } else if path.ends_with(".srec") || path.ends_with(".s19") {
+        (formats::load_srec(path)?, image_symbols()?)
     } else {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
-            "Input file must be either an assembly file (.tat) or a rom file (.rom)",
+            "Input file must be an assembly file (.tat), a teenyC file (.tc), a Forth file (.fth), \
+             a Brainfuck file (.bf), a rom file (.rom), an Intel HEX file (.hex) or an S-record file (.srec)",
         ));
+    })
+}
+
+fn run(
+    path: String,
+    debug_mode: bool,
+    show_stats: bool,
+    rom: RomOptions,
+    machine: &MachineConfig,
+    console: &ConsoleOptions,
+) -> std::io::Result<u16> {
+    let (program, debug) = load_program_with(&path, rom)?;
+    let mut vm = vm::TeenyAT::with_config(program, machine)?;
+    let input: Box<dyn BufRead + Send> = match (&console.stdin, &console.transcript) {
+        (Some(_), TranscriptMode::Replay(_)) => {
+            usage_error("--stdin and --replay can't both supply input")
+        }
+        (Some(file), _) => Box::new(BufReader::new(fs::File::open(file)?)),
+        (None, TranscriptMode::Replay(file)) => {
+            let entries = transcript::parse(&fs::read_to_string(file)?)?;
+            Box::new(Cursor::new(transcript::input(&entries)))
+        }
+        (None, _) => Box::new(BufReader::new(std::io::stdin())),
     };
-    let mut vm = vm::TeenyAT::new(program);
-    vm.debug_mode = debug_mode;
-    vm.run()?;
-    Ok(())
+    let output: Box<dyn Write + Send> = match &console.stdout {
+        Some(file) => {
+            let file = BufWriter::new(fs::File::create(file)?);
+            if console.echo {
+                Box::new(Tee(file, std::io::stdout()))
+            } else {
+                Box::new(file)
+            }
+        }
+        None if console.echo => usage_error("--echo needs --stdout"),
+        None => Box::new(std::io::stdout()),
+    };
+    vm.set_io(input, output);
+    if let TranscriptMode::Record(file) = &console.transcript {
+        vm.set_transcript(Transcript::create(file)?);
+    }
+    if let Some(file) = &console.trace {
+        vm.set_trace_as(
+            Box::new(BufWriter::new(fs::File::create(file)?)),
+            trace::Format::from_path(file),
+        );
+    }
+    // The first Ctrl+C pauses the program in the debugger. A second one
+    // before the VM has noticed the first exits as usual. WASI has no
+    // signals to catch.
+    let interrupt = Arc::new(AtomicBool::new(false));
+    #[cfg(not(target_os = "wasi"))]
+    {
+        let flag = Arc::clone(&interrupt);
+        let _ = ctrlc::set_handler(move || {
+            if flag.swap(true, Ordering::SeqCst) {
+                process::exit(130);
+            }
+        });
+    }
+    vm.set_interrupt(interrupt);
+    if debug_mode {
+        return Debugger::new(vm, debug).run();
+    }
+    match vm.run() {
+        Ok(stats) => {
+            if show_stats {
+                eprint!("{}", stats);
+            }
+            Ok(stats.exit_code)
+        }
+        Err(arch::ArchError::Interrupted) => {
+            println!();
+            Debugger::new(vm, debug).run()
+        }
+        Err(err) => {
+            eprintln!("{}", debugger::crash_report(&vm, &debug, &err));
+            // The report already says what went wrong.
+            match err {
+                arch::ArchError::AssertionFailed { .. } => process::exit(ASSERT_STATUS),
+                _ => process::exit(1),
+            }
+        }
+    }
 }
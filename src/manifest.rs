@@ -0,0 +1,67 @@
+//! Project manifests.
+//!
+//! A `teenyat.toml` in a project's directory lets `teenyat build` and
+//! `teenyat run` work without naming files or flags each time:
+//!
+//! ```toml
+//! entry = "src/main.tat"     # the program to assemble or compile
+//! include = ["lib"]          # directories searched for included files
+//! output = "build/game.rom"  # where `build` writes the image
+//! config = "machine.toml"    # the machine to run on; see config
+//! run = ["--mhz", "1"]       # flags `run` adds before any given to it
+//! ```
+//!
+//! Only `entry` is required. Paths are relative to the manifest's directory.
+
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The name a manifest goes by.
+pub const MANIFEST: &str = "teenyat.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub entry: String,
+    #[serde(default)]
+    pub include: Vec<String>,
+    pub output: Option<String>,
+    pub config: Option<String>,
+    #[serde(default)]
+    pub run: Vec<String>,
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        })
+    }
+
+    /// The include directories as paths.
+    pub fn include_dirs(&self) -> Vec<PathBuf> {
+        self.include.iter().map(PathBuf::from).collect()
+    }
+
+    /// Where `build` writes the image: `output`, or the entry with a `.rom`
+    /// extension.
+    pub fn output(&self) -> String {
+        match &self.output {
+            Some(output) => output.clone(),
+            None => {
+                let stem = self
+                    .entry
+                    .rsplit_once('.')
+                    .map_or(self.entry.as_str(), |(stem, _)| stem);
+                format!("{}.rom", stem)
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! The debugger's full screen memory viewer, opened with `view`.
+//!
+//! Memory is shown eight words to a row, as by `Memory::hexdump`, with the
+//! words stored to since the debugger last resumed highlighted, the word at
+//! pc in bold and the one at sp underlined.
+
+use crate::arch::Memory;
+use crate::debuginfo::DebugInfo;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{
+    Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor,
+};
+use crossterm::{cursor, execute, queue, terminal};
+use std::collections::BTreeSet;
+use std::io::{self, IsTerminal, Write};
+
+const WORDS_PER_ROW: usize = 8;
+const HELP: &str = "up/down/pgup/pgdn scroll  g go to  p pc  s sp  q back";
+
+/// What the viewer shows.
+pub(crate) struct View<'a> {
+    pub memory: &'a Memory,
+    pub debug: &'a DebugInfo,
+    /// Addresses to highlight as recently written.
+    pub written: &'a BTreeSet<u16>,
+    pub pc: u16,
+    pub sp: u16,
+}
+
+/// Puts the terminal back the way it was, however the viewer exits.
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Shows memory from the row holding `start` until the user presses `q`.
+/// `parse_addr` turns what the user types after `g` into an address.
+pub(crate) fn show(
+    view: &View,
+    start: u16,
+    parse_addr: &dyn Fn(&str) -> Result<u16, String>,
+) -> io::Result<()> {
+    if !io::stdout().is_terminal() {
+        return Err(io::Error::other("view needs a terminal"));
+    }
+    let rows_total = view.memory.size().div_ceil(WORDS_PER_ROW);
+    let mut top = start as usize / WORDS_PER_ROW;
+    let mut status = String::new();
+    let _screen = Screen::enter()?;
+    loop {
+        let (_, height) = terminal::size()?;
+        let page = (height as usize).saturating_sub(2).max(1);
+        top = top.min(rows_total.saturating_sub(page));
+        draw(view, top, page, &status)?;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        status.clear();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Up | KeyCode::Char('k') => top = top.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => top += 1,
+            KeyCode::PageUp => top = top.saturating_sub(page),
+            KeyCode::PageDown | KeyCode::Char(' ') => top += page,
+            KeyCode::Home => top = 0,
+            KeyCode::End => top = rows_total,
+            KeyCode::Char('p') => top = view.pc as usize / WORDS_PER_ROW,
+            KeyCode::Char('s') => top = view.sp as usize / WORDS_PER_ROW,
+            KeyCode::Char('g') => match prompt(height)?.map(|text| parse_addr(text.trim())) {
+                Some(Ok(addr)) => top = addr as usize / WORDS_PER_ROW,
+                Some(Err(msg)) => status = msg,
+                None => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+fn draw(view: &View, top: usize, page: usize, status: &str) -> io::Result<()> {
+    let mut out = io::stdout();
+    queue!(
+        out,
+        terminal::Clear(terminal::ClearType::All),
+        cursor::MoveTo(0, 0),
+        SetAttribute(Attribute::Reverse),
+        Print(format!("{:<78}", format!(" Memory  {}", HELP))),
+        SetAttribute(Attribute::Reset)
+    )?;
+    for (line, row) in (top..).take(page).enumerate() {
+        let start = row * WORDS_PER_ROW;
+        if start >= view.memory.size() {
+            break;
+        }
+        queue!(
+            out,
+            cursor::MoveTo(0, line as u16 + 1),
+            Print(format!("0x{:04x}:", start))
+        )?;
+        let mut text = String::new();
+        let mut labels = Vec::new();
+        for addr in start..(start + WORDS_PER_ROW).min(view.memory.size()) {
+            let addr = addr as u16;
+            let word = view.memory.read(addr).unwrap_or(0);
+            queue!(out, Print(" "))?;
+            if view.written.contains(&addr) {
+                queue!(
+                    out,
+                    SetBackgroundColor(Color::Yellow),
+                    SetForegroundColor(Color::Black)
+                )?;
+            }
+            if addr == view.pc {
+                queue!(out, SetAttribute(Attribute::Bold))?;
+            }
+            if addr == view.sp {
+                queue!(out, SetAttribute(Attribute::Underlined))?;
+            }
+            queue!(
+                out,
+                Print(format!("{:04x}", word)),
+                SetAttribute(Attribute::Reset)
+            )?;
+            text.push(match word {
+                0x20..=0x7E => word as u8 as char,
+                _ => '.',
+            });
+            labels.extend(
+                view.debug
+                    .labels
+                    .iter()
+                    .filter(|(_, at)| **at == addr)
+                    .map(|(name, _)| name.as_str()),
+            );
+        }
+        labels.sort_unstable();
+        queue!(out, Print(format!("  |{}|  {}", text, labels.join(" "))))?;
+    }
+    let (_, height) = terminal::size()?;
+    queue!(
+        out,
+        cursor::MoveTo(0, height.saturating_sub(1)),
+        Print(status)
+    )?;
+    out.flush()
+}
+
+/// Reads an address at the bottom of the screen, or None if Esc is pressed.
+fn prompt(height: u16) -> io::Result<Option<String>> {
+    let mut text = String::new();
+    let mut out = io::stdout();
+    loop {
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(1)),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            Print(format!("go to: {}", text)),
+            cursor::Show
+        )?;
+        out.flush()?;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Enter => break,
+            KeyCode::Esc => {
+                execute!(out, cursor::Hide)?;
+                return Ok(None);
+            }
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Char(chr) => text.push(chr),
+            _ => {}
+        }
+    }
+    execute!(out, cursor::Hide)?;
+    Ok(Some(text))
+}
@@ -0,0 +1,117 @@
+//! Devices loaded from shared libraries.
+//!
+//! A plugin exports `teenyat_device_init`, which fills in a `PluginDesc`
+//! describing the addresses it wants and the callbacks that implement it and
+//! returns 0 on success. `examples/plugins/counter.c` is a complete example.
+//!
+//! ```c
+//! typedef struct {
+//!     uint32_t abi_version;   /* set by the host to PLUGIN_ABI_VERSION */
+//!     uint16_t base;          /* where the device asks to be mapped */
+//!     uint16_t ports;         /* how many addresses it occupies */
+//!     void *state;
+//!     uint16_t (*read)(void *state, uint16_t port);
+//!     void (*write)(void *state, uint16_t port, uint16_t val);
+//!     void (*destroy)(void *state);   /* may be NULL */
+//! } PluginDesc;
+//!
+//! int teenyat_device_init(PluginDesc *desc);
+//! ```
+
+use crate::arch::ArchError;
+use crate::devices::{Bus, Device};
+use libloading::{Library, Symbol};
+use log::info;
+use std::ffi::c_void;
+use std::io;
+use std::ptr;
+
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+const INIT_SYMBOL: &[u8] = b"teenyat_device_init";
+
+#[repr(C)]
+struct PluginDesc {
+    abi_version: u32,
+    base: u16,
+    ports: u16,
+    state: *mut c_void,
+    read: Option<extern "C" fn(*mut c_void, u16) -> u16>,
+    write: Option<extern "C" fn(*mut c_void, u16, u16)>,
+    destroy: Option<extern "C" fn(*mut c_void)>,
+}
+
+pub struct PluginDevice {
+    desc: PluginDesc,
+    // Dropped after `desc.destroy` has run, since the callbacks live in it.
+    _lib: Library,
+}
+
+impl PluginDevice {
+    /// Loads a plugin, returning it with the base address it asked for.
+    pub fn load(path: &str) -> io::Result<(u16, Self)> {
+        let fail = |msg: String| io::Error::other(format!("Plugin {}: {}", path, msg));
+        let mut desc = PluginDesc {
+            abi_version: PLUGIN_ABI_VERSION,
+            base: 0,
+            ports: 0,
+            state: ptr::null_mut(),
+            read: None,
+            write: None,
+            destroy: None,
+        };
+        // Safety: loading a library runs its initialisers, and the init
+        // function must have the documented signature. Both are the plugin
+        // author's responsibility, as with any native plugin system.
+        let lib = unsafe { Library::new(path) }.map_err(|err| fail(err.to_string()))?;
+        let status = unsafe {
+            let init: Symbol<unsafe extern "C" fn(*mut PluginDesc) -> i32> =
+                lib.get(INIT_SYMBOL).map_err(|err| fail(err.to_string()))?;
+            init(&mut desc)
+        };
+        if status != 0 {
+            return Err(fail(format!(
+                "initialisation failed with status {}",
+                status
+            )));
+        }
+        let device = Self { desc, _lib: lib };
+        if device.desc.read.is_none() || device.desc.write.is_none() {
+            return Err(fail("missing read or write callback".to_string()));
+        }
+        info!(
+            "loaded plugin {} with {} ports at 0x{:04x}",
+            path, device.desc.ports, device.desc.base
+        );
+        Ok((device.desc.base, device))
+    }
+}
+
+// Safety: the host only calls into a plugin from whichever thread owns the VM,
+// one call at a time, so its state is never shared between threads. Plugins
+// must not rely on being called from the thread that loaded them.
+unsafe impl Send for PluginDevice {}
+
+impl Device for PluginDevice {
+    fn ports(&self) -> u16 {
+        self.desc.ports
+    }
+
+    fn read(&mut self, port: u16, _bus: &mut Bus) -> Result<u16, ArchError> {
+        let read = self.desc.read.expect("checked in load");
+        Ok(read(self.desc.state, port))
+    }
+
+    fn write(&mut self, port: u16, val: u16, _bus: &mut Bus) -> Result<(), ArchError> {
+        let write = self.desc.write.expect("checked in load");
+        write(self.desc.state, port, val);
+        Ok(())
+    }
+}
+
+impl Drop for PluginDevice {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.desc.destroy {
+            destroy(self.desc.state);
+        }
+    }
+}
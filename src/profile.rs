@@ -0,0 +1,86 @@
+//! Execution profiles annotated onto the program's source.
+
+use crate::arch::ArchError;
+use crate::debuginfo::DebugInfo;
+use crate::stdlib;
+use crate::vm::TeenyAT;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+/// How often each instruction ran and the cycles it accounted for.
+#[derive(Debug, Default)]
+pub struct Profile {
+    /// Keyed by instruction address: (executions, estimated cycles).
+    pub counts: BTreeMap<u16, (u64, u64)>,
+}
+
+impl Profile {
+    /// Runs `vm` to completion, counting every instruction executed. Returns
+    /// the exit code with the profile, or the fault along with the profile
+    /// up to it.
+    pub fn run(vm: &mut TeenyAT) -> (Result<u16, ArchError>, Self) {
+        let mut profile = Self::default();
+        let result = loop {
+            let before = vm.cycles();
+            if let Err(err) = vm.step() {
+                break Err(err);
+            }
+            if let Some((addr, _)) = vm.history().back() {
+                let cycles = vm.cycles() - before;
+                let entry = profile.counts.entry(*addr).or_insert((0, 0));
+                entry.0 += 1;
+                entry.1 += cycles;
+            }
+            if let Some(code) = vm.exit_code() {
+                break Ok(code);
+            }
+        };
+        (result, profile)
+    }
+
+    pub fn total(&self) -> (u64, u64) {
+        self.counts
+            .values()
+            .fold((0, 0), |(n, c), (dn, dc)| (n + dn, c + dc))
+    }
+
+    /// Every line of the program's source, prefixed with how many
+    /// instructions it executed and their estimated cycles.
+    pub fn annotate(&self, debug: &DebugInfo) -> String {
+        let mut per_line: BTreeMap<(&str, usize), (u64, u64)> = BTreeMap::new();
+        for (addr, (count, cycles)) in self.counts.iter() {
+            if let Some(loc) = debug.location(*addr) {
+                let entry = per_line.entry(loc).or_insert((0, 0));
+                entry.0 += count;
+                entry.1 += cycles;
+            }
+        }
+        let (total_count, total_cycles) = self.total();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} instructions executed, about {} cycles",
+            total_count, total_cycles
+        );
+        for file in debug.files.iter() {
+            let _ = writeln!(out, "\n{:>10} {:>10}  {}", "count", "cycles", file);
+            let source = fs::read_to_string(file)
+                .ok()
+                .or_else(|| stdlib::lookup(file).map(String::from))
+                .unwrap_or_default();
+            for (i, text) in source.lines().enumerate() {
+                match per_line.get(&(file.as_str(), i + 1)) {
+                    Some((count, cycles)) => {
+                        let _ =
+                            writeln!(out, "{:>10} {:>10}  {:>5}: {}", count, cycles, i + 1, text);
+                    }
+                    None => {
+                        let _ = writeln!(out, "{:>10} {:>10}  {:>5}: {}", "", "", i + 1, text);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,129 @@
+//! Runs several VMs side by side on one thread.
+//!
+//! A `Scheduler` takes turns among its VMs, giving each up to a fixed number
+//! of instructions per turn, until every one has ended. Each VM keeps its own
+//! memory, console and devices; guests that should meet, such as two bots in
+//! a game, share a device (see `devices::Shared` and
+//! `MachineConfig::build_device_sets`). Taking turns by instruction count
+//! rather than by thread keeps a run the same from one time to the next.
+
+use crate::arch::ArchError;
+use crate::vm::{StopReason, TeenyAT};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait when every VM still running is waiting for input.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How a VM ended: with its exit code, or with the error that stopped it.
+pub type Outcome = Result<u16, ArchError>;
+
+pub struct Scheduler {
+    vms: Vec<(TeenyAT, Option<Outcome>)>,
+    budget: u64,
+    interrupt: Option<Arc<AtomicBool>>,
+}
+
+/// What happened in a round of turns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Round {
+    /// How many VMs are still running.
+    pub running: usize,
+    /// How many instructions ran in all.
+    pub steps: u64,
+}
+
+impl Scheduler {
+    /// A scheduler giving each VM up to `budget` instructions a turn.
+    pub fn new(budget: u64) -> Self {
+        Self {
+            vms: Vec::new(),
+            budget: budget.max(1),
+            interrupt: None,
+        }
+    }
+
+    /// Adds `vm`, which takes its turns after those added before it, and
+    /// returns its index. The VM is made nonblocking (see
+    /// `TeenyAT::set_nonblocking`), so that one waiting for input doesn't
+    /// hold up the others.
+    pub fn add(&mut self, mut vm: TeenyAT) -> usize {
+        vm.set_nonblocking(true);
+        self.vms.push((vm, None));
+        self.vms.len() - 1
+    }
+
+    /// Gives the scheduler a flag that another thread, or a signal handler,
+    /// can raise to stop `run` with `ArchError::Interrupted`.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+
+    pub fn len(&self) -> usize {
+        self.vms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vms.is_empty()
+    }
+
+    pub fn vm(&self, index: usize) -> &TeenyAT {
+        &self.vms[index].0
+    }
+
+    pub fn vm_mut(&mut self, index: usize) -> &mut TeenyAT {
+        &mut self.vms[index].0
+    }
+
+    /// How the VM at `index` ended, or None while it is still running.
+    pub fn outcome(&self, index: usize) -> Option<&Outcome> {
+        self.vms[index].1.as_ref()
+    }
+
+    /// Gives each VM still running one turn, in the order they were added.
+    pub fn round(&mut self) -> Round {
+        let mut round = Round {
+            running: 0,
+            steps: 0,
+        };
+        for (vm, outcome) in self.vms.iter_mut() {
+            if outcome.is_some() {
+                continue;
+            }
+            let turn = vm.run_for(self.budget);
+            round.steps += turn.steps;
+            match turn.reason {
+                StopReason::Exited(code) => *outcome = Some(Ok(code)),
+                StopReason::Fault(err) => *outcome = Some(Err(err)),
+                StopReason::Interrupted => *outcome = Some(Err(ArchError::Interrupted)),
+                StopReason::OutOfSteps | StopReason::Blocked | StopReason::OutOfFuel => {
+                    round.running += 1
+                }
+            }
+        }
+        round
+    }
+
+    /// Takes rounds until every VM has ended. While all of those still
+    /// running are waiting for input, it checks back every millisecond. A VM
+    /// out of fuel (see `TeenyAT::set_fuel`) counts as waiting, so hosts that
+    /// meter their VMs call `round` themselves and refuel between rounds.
+    pub fn run(&mut self) -> Result<(), ArchError> {
+        loop {
+            if let Some(flag) = &self.interrupt {
+                if flag.swap(false, Ordering::SeqCst) {
+                    return Err(ArchError::Interrupted);
+                }
+            }
+            let round = self.round();
+            if round.running == 0 {
+                return Ok(());
+            }
+            if round.steps == 0 {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Programs built into `teenyat selftest`, which between them run every
+//! opcode and console port and check what comes out, along with the example
+//! programs shipped at the top of the repository.
+//!
+//! Each case assembles one of the programs in `selftest/`, checks that the
+//! assembler finds none of its instructions unreachable and that its ROM
+//! loads back as the same program in either byte order, runs it with
+//! the input given, and compares its output, exit code or fault, registers
+//! and the memory words named in the case. A quick way to see that a build
+//! for a new platform works.
+
+use crate::arch::{Endian, Memory, OpCode, RomOptions, REG_NAMES};
+use crate::feed::SharedBuf;
+use crate::vm::{StopReason, TeenyAT};
+use crate::{analysis, assembler};
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+/// How many instructions a case may run before it counts as stuck.
+const MAX_STEPS: u64 = 100_000;
+
+/// A program and what running it should give.
+pub struct Case {
+    pub name: &'static str,
+    pub source: &'static str,
+    pub input: &'static str,
+    pub output: &'static str,
+    pub exit_code: u16,
+    /// Registers by name, with their values once the program has ended.
+    pub regs: &'static [(&'static str, u16)],
+    /// Memory words, with their values once the program has ended.
+    pub mem: &'static [(u16, u16)],
+    /// An interrupt the host raises before the program starts.
+    pub raise: Option<u16>,
+    /// The fault the program should stop with, instead of exiting.
+    pub fault: Option<&'static str>,
+}
+
+pub const CASES: &[Case] = &[
+    Case {
+        name: "moves",
+        source: include_str!("../selftest/moves.tat"),
+        input: "",
+        output: "",
+        exit_code: 0,
+        regs: &[
+            ("bx", 0x1234),
+            ("cx", 0x1234),
+            ("dx", 0x0101),
+            ("ex", 0),
+            ("fx", 0x1234),
+        ],
+        mem: &[(0x100, 0x1234), (0x101, 0x1234)],
+        raise: None,
+        fault: None,
+    },
+    Case {
+        name: "arith",
+        source: include_str!("../selftest/arith.tat"),
+        input: "",
+        output: "",
+        exit_code: 0,
+        regs: &[],
+        mem: &[
+            (0x100, 12),
+            (0x101, 2),
+            (0x102, 35),
+            (0x103, 9),
+            (0x104, 2),
+            (0x105, 0xfffd),
+            (0x106, 0xffff),
+            (0x107, 0xfffb),
+            (0x108, 0),
+            (0x109, 0xffff),
+            (0x10a, 0xff80),
+            (0x10b, 0xfffd),
+            (0x10c, 1),
+            (0x10d, 3),
+            (0x10e, 0xffff),
+            (0x10f, 3),
+            (0x110, 1),
+            (0x111, 0x8000),
+            (0x112, 0),
+        ],
+        raise: None,
+        fault: None,
+    },
+    Case {
+        name: "logic",
+        source: include_str!("../selftest/logic.tat"),
+        input: "",
+        output: "",
+        exit_code: 0,
+        regs: &[],
+        mem: &[
+            (0x100, 0x00f0),
+            (0x101, 0x0fff),
+            (0x102, 0x0f0f),
+            (0x103, 0xf00f),
+            (0x104, 0xff00),
+            (0x105, 0x00ff),
+            (0x106, 0x00ff),
+            (0x107, 1),
+        ],
+        raise: None,
+        fault: None,
+    },
+    Case {
+        name: "jumps",
+        source: include_str!("../selftest/jumps.tat"),
+        input: "",
+        output: "",
+        exit_code: 0,
+        regs: &[("dx", 0x55), ("ex", 6), ("fx", 0)],
+        mem: &[],
+        raise: None,
+        fault: None,
+    },
+    Case {
+        name: "interrupts",
+        source: include_str!("../selftest/interrupts.tat"),
+        input: "typed\n",
+        output: "int\ntyped",
+        exit_code: 0,
+        regs: &[("cx", 5), ("ex", 1)],
+        mem: &[(0x200, 't' as u16), (0x205, 0)],
+        raise: Some(2),
+        fault: None,
+    },
+    Case {
+        name: "console",
+        source: include_str!("../selftest/console.tat"),
+        input: "ab\nline two\n",
+        output: "ab\nbeef",
+        exit_code: 7,
+        regs: &[("bx", 4), ("cx", 4), ("dx", 0xffff)],
+        mem: &[(0x200, 'l' as u16), (0x203, 'e' as u16), (0x204, 0)],
+        raise: None,
+        fault: None,
+    },
+    Case {
+        name: "divzero",
+        source: include_str!("../selftest/divzero.tat"),
+        input: "",
+        output: "",
+        exit_code: 0,
+        regs: &[("ax", 0xfff9)],
+        mem: &[],
+        raise: None,
+        fault: Some("Division by zero"),
+    },
+    Case {
+        name: "chars",
+        source: include_str!("../selftest/chars.tat"),
+        input: "",
+        output: " ;,|/'",
+        exit_code: 0,
+        regs: &[],
+        mem: &[],
+        raise: None,
+        fault: None,
+    },
+    Case {
+        name: "helloworld",
+        source: include_str!("../helloworld.tat"),
+        input: "",
+        output: "Hello World!\r\nHello World!\r\nHello World!\r\nHello World!\r\n\
+                 Hello World!\r\nHello World!\r\nHello World!\r\nHello World!\r\n\
+                 Hello World!\r\nHello World!\r\n",
+        exit_code: 0,
+        regs: &[("bx", 0)],
+        mem: &[],
+        raise: None,
+        fault: None,
+    },
+    Case {
+        name: "print_alpha",
+        source: include_str!("../print_alpha.tat"),
+        input: "w",
+        output: "Enter lowercase letter: xyz",
+        exit_code: 0,
+        regs: &[("ax", 'z' as u16)],
+        mem: &[],
+        raise: None,
+        fault: None,
+    },
+];
+
+/// Runs `case`, adding the opcodes it executed to `executed`, and describes
+/// the first thing that didn't come out as expected.
+pub fn check(case: &Case, executed: &mut HashSet<OpCode>) -> Result<(), String> {
+    let (mem, debug) = assembler::assemble_str(case.name, case.source)
+        .map_err(|err| format!("doesn't assemble: {}", err))?;
+    let program = analysis::decode(mem.program(), &debug);
+    let entries = analysis::entry_points(mem.program(), &program, &debug);
+    let own: Vec<u16> = program
+        .keys()
+        .copied()
+        .filter(|&addr| debug.span(addr).is_some_and(|span| span.file == 0))
+        .collect();
+    if let Some((first, _)) = analysis::unreachable(&program, &entries, &own).first() {
+        return Err(format!("unreachable instructions from 0x{:04x}", first));
+    }
+    round_trip(case.name, &mem)?;
+    let mut vm = TeenyAT::new(mem);
+    let output = SharedBuf::default();
+    vm.set_io(
+        Box::new(Cursor::new(case.input.as_bytes())),
+        Box::new(output.clone()),
+    );
+    let ops = Arc::new(Mutex::new(HashSet::new()));
+    let seen = ops.clone();
+    vm.set_post_hook(Box::new(move |_, ins, _| {
+        if let Ok(op) = ins.get_op_code() {
+            seen.lock().unwrap().insert(op);
+        }
+    }));
+    if let Some(vector) = case.raise {
+        vm.raise(vector);
+    }
+    let run = vm.run_for(MAX_STEPS);
+    executed.extend(ops.lock().unwrap().iter());
+    match run.reason {
+        StopReason::Fault(err) if case.fault == Some(err.to_string().trim_end()) => {}
+        _ if case.fault.is_some() => {
+            return Err(format!(
+                "stopped with {:?}, expected the fault {:?}",
+                run.reason,
+                case.fault.unwrap()
+            ))
+        }
+        StopReason::Exited(code) if code == case.exit_code => {}
+        StopReason::Exited(code) => {
+            return Err(format!(
+                "exit code was {}, expected {}",
+                code, case.exit_code
+            ))
+        }
+        StopReason::OutOfSteps => {
+            return Err(format!("still running after {} instructions", MAX_STEPS))
+        }
+        StopReason::Fault(err) => {
+            return Err(format!(
+                "{} at 0x{:04x}",
+                err.to_string().trim_end(),
+                vm.ins_addr()
+            ))
+        }
+        reason => return Err(format!("stopped early: {:?}", reason)),
+    }
+    let output = output.text();
+    if output != case.output {
+        return Err(format!(
+            "output was {:?}, expected {:?}",
+            output, case.output
+        ));
+    }
+    let regs = vm.registers();
+    for &(name, expected) in case.regs {
+        let num = REG_NAMES.iter().position(|reg| *reg == name).unwrap();
+        if regs[num].val != expected {
+            return Err(format!(
+                "{} was 0x{:04x}, expected 0x{:04x}",
+                name, regs[num].val, expected
+            ));
+        }
+    }
+    for &(addr, expected) in case.mem {
+        let val = vm.read_mem(addr).map_err(|err| err.to_string())?;
+        if val != expected {
+            return Err(format!(
+                "[0x{:04x}] was 0x{:04x}, expected 0x{:04x}",
+                addr, val, expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `mem`'s program saved as a ROM is exactly its header and
+/// words and loads back unchanged, compressed or not, in both byte orders.
+fn round_trip(name: &str, mem: &Memory) -> Result<(), String> {
+    for endian in [Endian::Little, Endian::Big] {
+        let bytes = mem.bytes(endian);
+        let header = mem.metadata().header(false, endian).len();
+        if bytes.len() != header + 2 * mem.program().len() {
+            return Err(format!(
+                "{:?} ROM is {} bytes for {} words and a {} byte header",
+                endian,
+                bytes.len(),
+                mem.program().len(),
+                header
+            ));
+        }
+        let options = RomOptions {
+            endian: Some(endian),
+            ..RomOptions::default()
+        };
+        for (kind, bytes) in [
+            ("ROM", bytes),
+            ("compressed ROM", mem.compressed_bytes(endian)),
+        ] {
+            let loaded = Memory::from_rom_bytes(name, &bytes, options)
+                .map_err(|err| format!("{:?} {} doesn't load: {}", endian, kind, err))?;
+            if loaded.program() != mem.program() || loaded.metadata() != mem.metadata() {
+                return Err(format!(
+                    "{:?} {} loads as a different program",
+                    endian, kind
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The opcodes not in `executed`, in opcode order.
+pub fn missing(executed: &HashSet<OpCode>) -> Vec<OpCode> {
+    (0..64)
+        .filter_map(|code| OpCode::from_int(code).ok())
+        .filter(|op| !executed.contains(op))
+        .collect()
+}
+
+/// Assembles and runs `source` with `input`, returning its exit code and
+/// output, or why it didn't exit. The compilers' tests run their output
+/// with this.
+#[cfg(test)]
+pub(crate) fn run_source(source: &str, input: &str) -> Result<(u16, String), String> {
+    let (mem, _) = assembler::assemble_str("test.tat", source).map_err(|err| err.to_string())?;
+    let mut vm = TeenyAT::new(mem);
+    let output = SharedBuf::default();
+    vm.set_io(
+        Box::new(Cursor::new(input.as_bytes().to_vec())),
+        Box::new(output.clone()),
+    );
+    match vm.run_for(MAX_STEPS).reason {
+        StopReason::Exited(code) => Ok((code, output.text())),
+        reason => Err(format!("stopped with {:?}", reason)),
+    }
+}
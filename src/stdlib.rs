@@ -0,0 +1,16 @@
+//! The standard library of assembly routines bundled into the assembler, so
+//! `.include <std/print.tat>` works without any files installed alongside it.
+
+const FILES: &[(&str, &str)] = &[
+    ("std/print.tat", include_str!("../std/print.tat")),
+    ("std/read.tat", include_str!("../std/read.tat")),
+    ("std/mem.tat", include_str!("../std/mem.tat")),
+    ("std/math.tat", include_str!("../std/math.tat")),
+];
+
+pub fn lookup(name: &str) -> Option<&'static str> {
+    FILES
+        .iter()
+        .find(|(file, _)| *file == name)
+        .map(|(_, text)| *text)
+}
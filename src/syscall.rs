@@ -0,0 +1,80 @@
+//! Host services the guest calls with `int n`.
+//!
+//! Services give a host a way to offer the guest things like printing a
+//! string or reading a line without mapping a device into its address space.
+//! `int n` runs service n from the VM's service table (see
+//! `TeenyAT::set_service`) and continues with the next instruction. A
+//! service sees the registers and the same parts of the machine a device
+//! does; by convention it takes its arguments in `ax`, `bx` and `cx` and
+//! returns its result in `ax`.
+
+use crate::arch::ArchError;
+use crate::devices::{read_line_into, Bus, Rng};
+use std::collections::HashMap;
+
+/// Prints the string at `ax`, one character per word up to a 0 word.
+pub const SYS_PRINT: u16 = 0;
+/// Reads a line of input into the buffer at `ax`, keeping at most `bx`
+/// characters, one per word without the newline, followed by a 0 word. Sets
+/// `ax` to the number of characters kept, or to 0xFFFF once input has run
+/// out.
+pub const SYS_READ_LINE: u16 = 1;
+/// Sets `ax` to a pseudo-random word. A machine config's `seed` makes the
+/// words the same from run to run.
+pub const SYS_RANDOM: u16 = 2;
+
+const AX: usize = 1;
+const BX: usize = 2;
+
+/// Services are `Send` so that a VM can be moved to a worker thread.
+pub trait Service: Send {
+    /// Runs the service. `regs` holds the registers, indexed by number as
+    /// in `REG_NAMES`; changes to them are kept.
+    fn call(&mut self, regs: &mut [u16; 8], bus: &mut Bus) -> Result<(), ArchError>;
+}
+
+impl<F> Service for F
+where
+    F: FnMut(&mut [u16; 8], &mut Bus) -> Result<(), ArchError> + Send,
+{
+    fn call(&mut self, regs: &mut [u16; 8], bus: &mut Bus) -> Result<(), ArchError> {
+        self(regs, bus)
+    }
+}
+
+/// The services every VM starts with.
+pub fn builtins() -> HashMap<u16, Box<dyn Service>> {
+    let mut services: HashMap<u16, Box<dyn Service>> = HashMap::new();
+    services.insert(SYS_PRINT, Box::new(print));
+    services.insert(SYS_READ_LINE, Box::new(read_line));
+    services.insert(SYS_RANDOM, Box::new(Rng::new(None)));
+    services
+}
+
+fn print(regs: &mut [u16; 8], bus: &mut Bus) -> Result<(), ArchError> {
+    let mut addr = regs[AX];
+    let mut text = Vec::new();
+    loop {
+        let chr = bus.mem.read(addr)?;
+        if chr == 0 {
+            break;
+        }
+        text.push(chr as u8);
+        addr = addr.wrapping_add(1);
+    }
+    bus.output.write_all(&text)?;
+    bus.output.flush()?;
+    Ok(())
+}
+
+fn read_line(regs: &mut [u16; 8], bus: &mut Bus) -> Result<(), ArchError> {
+    regs[AX] = read_line_into(bus, regs[AX], regs[BX])?;
+    Ok(())
+}
+
+impl Service for Rng {
+    fn call(&mut self, regs: &mut [u16; 8], _bus: &mut Bus) -> Result<(), ArchError> {
+        regs[AX] = self.next_word();
+        Ok(())
+    }
+}
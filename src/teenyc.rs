@@ -0,0 +1,854 @@
+//! teenyC, a minimal C-like language that compiles to TeenyAT assembly.
+//!
+//! ```text
+//! var count = 0;
+//!
+//! func square(n) {
+//!     return n * n;
+//! }
+//!
+//! func main() {
+//!     var i = 1;
+//!     while (i <= 10) {
+//!         print(square(i));
+//!         putc('\n');
+//!         i = i + 1;
+//!     }
+//!     return 0;
+//! }
+//! ```
+//!
+//! Every value is a 16 bit word. Comparisons are signed, `/` and `%` are
+//! unsigned, and `&&`/`||` short circuit. The builtins are `putc(c)`,
+//! `getc()`, `print(n)` (signed decimal) and `exit(code)`. Execution starts at
+//! `main` and its return value is the program's exit code.
+//!
+//! Globals live just after the program, from `GLOBALS_LABEL`, so that they
+//! never overlap code or the interrupt vector table. Functions use `fx` as a
+//! frame pointer: arguments are pushed left to right by the caller, locals sit
+//! below the saved frame pointer, and results are returned in `ax`.
+
+use std::collections::HashMap;
+use std::io;
+
+const GLOBALS_LABEL: &str = "!tc_globals";
+const KEYWORDS: &[&str] = &["var", "func", "if", "else", "while", "return"];
+const PUNCT: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "!", "=", "(", ")", "{",
+    "}", ",", ";",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Num(u16),
+    Ident(String),
+    Kw(&'static str),
+    Punct(&'static str),
+    Eof,
+}
+
+fn lex(src: &str) -> Result<Vec<(Tok, usize)>, (usize, String)> {
+    let mut toks = Vec::new();
+    let chars: Vec<char> = src.chars().collect();
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let val = match text.strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16),
+                None => text.parse::<u16>(),
+            };
+            match val {
+                Ok(val) => toks.push((Tok::Num(val), line)),
+                Err(_) => return Err((line, format!("invalid number `{}`", text))),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            match KEYWORDS.iter().find(|kw| **kw == word) {
+                Some(kw) => toks.push((Tok::Kw(kw), line)),
+                None => toks.push((Tok::Ident(word), line)),
+            }
+        } else if c == '\'' {
+            let (val, len) = match (chars.get(i + 1), chars.get(i + 2), chars.get(i + 3)) {
+                (Some('\\'), Some(esc), Some('\'')) => (escape(*esc), 4),
+                (Some(chr), Some('\''), _) => (*chr as u16, 3),
+                _ => return Err((line, "malformed character literal".to_string())),
+            };
+            toks.push((Tok::Num(val), line));
+            i += len;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match PUNCT.iter().find(|p| rest.starts_with(**p)) {
+                Some(p) => {
+                    toks.push((Tok::Punct(p), line));
+                    i += p.len();
+                }
+                None => return Err((line, format!("unexpected character `{}`", c))),
+            }
+        }
+    }
+    toks.push((Tok::Eof, line));
+    Ok(toks)
+}
+
+fn escape(chr: char) -> u16 {
+    match chr {
+        'n' => 0x0A,
+        'r' => 0x0D,
+        't' => 0x09,
+        '0' => 0,
+        _ => chr as u16,
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(u16),
+    Var(String),
+    Call(String, Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Var(String, Option<Expr>),
+    Assign(String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Debug)]
+struct Func {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+/// Binary operators from loosest to tightest binding.
+const PRECEDENCE: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser {
+    toks: Vec<(Tok, usize)>,
+    pos: usize,
+}
+
+type ParseResult<T> = Result<T, (usize, String)>;
+type Global = (String, Option<Expr>);
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.toks[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.toks[self.pos].1
+    }
+
+    fn next(&mut self) -> Tok {
+        let tok = self.toks[self.pos].0.clone();
+        if tok != Tok::Eof {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn error<T>(&self, msg: &str) -> ParseResult<T> {
+        Err((self.line(), format!("{}, found {:?}", msg, self.peek())))
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if *self.peek() == Tok::Punct(PUNCT.iter().find(|p| **p == punct).unwrap()) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> ParseResult<()> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.error(&format!("expected `{}`", punct))
+        }
+    }
+
+    fn ident(&mut self) -> ParseResult<String> {
+        match self.peek().clone() {
+            Tok::Ident(name) => {
+                self.next();
+                Ok(name)
+            }
+            _ => self.error("expected a name"),
+        }
+    }
+
+    fn program(&mut self) -> ParseResult<(Vec<Global>, Vec<Func>)> {
+        let mut globals = Vec::new();
+        let mut funcs = Vec::new();
+        loop {
+            match self.next() {
+                Tok::Kw("var") => {
+                    let name = self.ident()?;
+                    let init = if self.eat("=") {
+                        Some(self.expr()?)
+                    } else {
+                        None
+                    };
+                    self.expect(";")?;
+                    globals.push((name, init));
+                }
+                Tok::Kw("func") => {
+                    let name = self.ident()?;
+                    self.expect("(")?;
+                    let mut params = Vec::new();
+                    if !self.eat(")") {
+                        loop {
+                            params.push(self.ident()?);
+                            if self.eat(")") {
+                                break;
+                            }
+                            self.expect(",")?;
+                        }
+                    }
+                    let body = self.block()?;
+                    funcs.push(Func { name, params, body });
+                }
+                Tok::Eof => return Ok((globals, funcs)),
+                _ => {
+                    self.pos -= 1;
+                    return self.error("expected `var` or `func`");
+                }
+            }
+        }
+    }
+
+    fn block(&mut self) -> ParseResult<Vec<Stmt>> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> ParseResult<Stmt> {
+        match self.peek().clone() {
+            Tok::Kw("var") => {
+                self.next();
+                let name = self.ident()?;
+                let init = if self.eat("=") {
+                    Some(self.expr()?)
+                } else {
+                    None
+                };
+                self.expect(";")?;
+                Ok(Stmt::Var(name, init))
+            }
+            Tok::Kw("if") => {
+                self.next();
+                self.expect("(")?;
+                let cond = self.expr()?;
+                self.expect(")")?;
+                let then = self.block()?;
+                let other = if *self.peek() == Tok::Kw("else") {
+                    self.next();
+                    if *self.peek() == Tok::Kw("if") {
+                        vec![self.stmt()?]
+                    } else {
+                        self.block()?
+                    }
+                } else {
+                    Vec::new()
+                };
+                Ok(Stmt::If(cond, then, other))
+            }
+            Tok::Kw("while") => {
+                self.next();
+                self.expect("(")?;
+                let cond = self.expr()?;
+                self.expect(")")?;
+                Ok(Stmt::While(cond, self.block()?))
+            }
+            Tok::Kw("return") => {
+                self.next();
+                if self.eat(";") {
+                    return Ok(Stmt::Return(None));
+                }
+                let val = self.expr()?;
+                self.expect(";")?;
+                Ok(Stmt::Return(Some(val)))
+            }
+            Tok::Ident(name) if self.toks[self.pos + 1].0 == Tok::Punct("=") => {
+                self.pos += 2;
+                let val = self.expr()?;
+                self.expect(";")?;
+                Ok(Stmt::Assign(name, val))
+            }
+            _ => {
+                let expr = self.expr()?;
+                self.expect(";")?;
+                Ok(Stmt::Expr(expr))
+            }
+        }
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> ParseResult<Expr> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Tok::Punct(p) if PRECEDENCE[level].contains(p) => *p,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        if self.eat("-") {
+            return Ok(match self.unary()? {
+                Expr::Num(val) => Expr::Num(val.wrapping_neg()),
+                expr => Expr::Unary("-", Box::new(expr)),
+            });
+        }
+        if self.eat("!") {
+            return Ok(Expr::Unary("!", Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        if *self.peek() == Tok::Eof {
+            return self.error("expected an expression");
+        }
+        match self.next() {
+            Tok::Num(val) => Ok(Expr::Num(val)),
+            Tok::Ident(name) => {
+                if !self.eat("(") {
+                    return Ok(Expr::Var(name));
+                }
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Tok::Punct("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => {
+                self.pos -= 1;
+                self.error("expected an expression")
+            }
+        }
+    }
+}
+
+/// Where a variable lives: below the frame pointer, above it, or this many
+/// words after `GLOBALS_LABEL`.
+#[derive(Clone, Copy)]
+enum Slot {
+    Local(u16),
+    Param(u16),
+    Global(u16),
+}
+
+struct Codegen<'a> {
+    out: Vec<String>,
+    labels: usize,
+    globals: HashMap<String, u16>,
+    funcs: HashMap<&'a str, (usize, usize)>,
+    locals: HashMap<String, Slot>,
+    exit_label: String,
+    uses_print: bool,
+}
+
+type GenResult = Result<(), String>;
+
+impl<'a> Codegen<'a> {
+    fn emit(&mut self, line: &str) {
+        self.out.push(format!("    {}", line));
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("!tc_L{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        self.out.push(label.to_string());
+    }
+
+    /// An unconditional jump that does not depend on any register's value.
+    fn jump(&mut self, label: &str) {
+        self.emit(&format!("je  pc, pc, {}", label));
+    }
+
+    fn slot(&self, name: &str) -> Result<Slot, String> {
+        self.locals
+            .get(name)
+            .copied()
+            .or_else(|| self.globals.get(name).map(|ofs| Slot::Global(*ofs)))
+            .ok_or_else(|| format!("unknown variable `{}`", name))
+    }
+
+    /// Leaves the address of a frame slot in dx.
+    fn frame_addr(&mut self, slot: Slot) {
+        match slot {
+            Slot::Local(ofs) => {
+                self.emit("copy dx, fx");
+                self.emit(&format!("set cx, {}", ofs));
+                self.emit("sub dx, cx");
+            }
+            Slot::Param(ofs) => {
+                self.emit("copy dx, fx");
+                self.emit(&format!("set cx, {}", ofs));
+                self.emit("add dx, cx");
+            }
+            Slot::Global(ofs) => self.emit(&format!("set dx, {}+{}", GLOBALS_LABEL, ofs)),
+        }
+    }
+
+    fn load_var(&mut self, name: &str) -> GenResult {
+        match self.slot(name)? {
+            Slot::Global(ofs) => self.emit(&format!("load ax, {}+{}", GLOBALS_LABEL, ofs)),
+            slot => {
+                self.frame_addr(slot);
+                self.emit("pload ax, dx");
+            }
+        }
+        Ok(())
+    }
+
+    fn store_var(&mut self, name: &str) -> GenResult {
+        match self.slot(name)? {
+            Slot::Global(ofs) => self.emit(&format!("stor {}+{}, ax", GLOBALS_LABEL, ofs)),
+            slot => {
+                self.frame_addr(slot);
+                self.emit("pstor dx, ax");
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates an expression into ax, clobbering bx, cx and dx.
+    fn expr(&mut self, expr: &Expr) -> GenResult {
+        match expr {
+            Expr::Num(val) => self.emit(&format!("set ax, {}", val)),
+            Expr::Var(name) => self.load_var(name)?,
+            Expr::Call(name, args) => self.call(name, args)?,
+            Expr::Unary("-", inner) => {
+                self.expr(inner)?;
+                self.emit("neg ax");
+            }
+            Expr::Unary(_, inner) => {
+                self.expr(inner)?;
+                let done = self.label();
+                self.emit("set bx, 1");
+                self.emit("set cx, 0");
+                self.emit(&format!("je  ax, cx, {}", done));
+                self.emit("set bx, 0");
+                self.place(&done);
+                self.emit("copy ax, bx");
+            }
+            Expr::Binary(op @ ("&&" | "||"), lhs, rhs) => {
+                let done = self.label();
+                let jump = if *op == "&&" { "je " } else { "jne" };
+                for side in [lhs, rhs] {
+                    self.expr(side)?;
+                    self.emit("set bx, 0");
+                    self.emit(&format!("{} ax, bx, {}", jump, done));
+                }
+                self.place(&done);
+                // normalise the result to 0 or 1
+                let zero = self.label();
+                self.emit("set bx, 0");
+                self.emit(&format!("je  ax, bx, {}", zero));
+                self.emit("set ax, 1");
+                self.place(&zero);
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                self.expr(lhs)?;
+                self.emit("push ax");
+                self.expr(rhs)?;
+                self.emit("copy bx, ax");
+                self.emit("pop ax");
+                let arith = match *op {
+                    "+" => Some("add"),
+                    "-" => Some("sub"),
+                    "*" => Some("mult"),
+                    "/" => Some("div"),
+                    "%" => Some("mod"),
+                    _ => None,
+                };
+                if let Some(arith) = arith {
+                    self.emit(&format!("{} ax, bx", arith));
+                    return Ok(());
+                }
+                let jump = match *op {
+                    "==" => "je ",
+                    "!=" => "jne",
+                    "<" => "jl ",
+                    "<=" => "jle",
+                    ">" => "jg ",
+                    _ => "jge",
+                };
+                let done = self.label();
+                self.emit("set cx, 1");
+                self.emit(&format!("{} ax, bx, {}", jump, done));
+                self.emit("set cx, 0");
+                self.place(&done);
+                self.emit("copy ax, cx");
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr]) -> GenResult {
+        let builtin_args = match name {
+            "putc" | "print" | "exit" => Some(1),
+            "getc" => Some(0),
+            _ => None,
+        };
+        if let Some(count) = builtin_args {
+            if args.len() != count {
+                return Err(format!("`{}` takes {} argument(s)", name, count));
+            }
+            if let Some(arg) = args.first() {
+                self.expr(arg)?;
+            }
+            match name {
+                "putc" => self.emit("stor OUT, ax"),
+                "print" => {
+                    self.uses_print = true;
+                    self.emit("call !std_print_num");
+                }
+                "exit" => self.emit("stor END, ax"),
+                _ => self.emit("load ax, IN"),
+            }
+            return Ok(());
+        }
+        let (index, params) = *self
+            .funcs
+            .get(name)
+            .ok_or_else(|| format!("unknown function `{}`", name))?;
+        if args.len() != params {
+            return Err(format!("`{}` takes {} argument(s)", name, params));
+        }
+        for arg in args {
+            self.expr(arg)?;
+            self.emit("push ax");
+        }
+        self.emit(&format!("call !tc_fn_{}", index));
+        if !args.is_empty() {
+            self.emit(&format!("set cx, {}", args.len()));
+            self.emit("add sp, cx");
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> GenResult {
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> GenResult {
+        match stmt {
+            Stmt::Var(name, Some(init)) | Stmt::Assign(name, init) => {
+                self.expr(init)?;
+                self.store_var(name)?;
+            }
+            Stmt::Var(_, None) => {}
+            Stmt::If(cond, then, other) => {
+                let else_label = self.label();
+                let done = self.label();
+                self.expr(cond)?;
+                self.emit("set cx, 0");
+                self.emit(&format!("je  ax, cx, {}", else_label));
+                self.block(then)?;
+                self.jump(&done);
+                self.place(&else_label);
+                self.block(other)?;
+                self.place(&done);
+            }
+            Stmt::While(cond, body) => {
+                let top = self.label();
+                let done = self.label();
+                self.place(&top);
+                self.expr(cond)?;
+                self.emit("set cx, 0");
+                self.emit(&format!("je  ax, cx, {}", done));
+                self.block(body)?;
+                self.jump(&top);
+                self.place(&done);
+            }
+            Stmt::Return(val) => {
+                match val {
+                    Some(val) => self.expr(val)?,
+                    None => self.emit("set ax, 0"),
+                }
+                let exit = self.exit_label.clone();
+                self.jump(&exit);
+            }
+            Stmt::Expr(expr) => self.expr(expr)?,
+        }
+        Ok(())
+    }
+
+    fn func(&mut self, index: usize, func: &Func) -> GenResult {
+        self.locals.clear();
+        let count = func.params.len() as u16;
+        for (i, param) in func.params.iter().enumerate() {
+            // [fx] is the saved frame pointer and [fx + 1] the return address
+            self.locals
+                .insert(param.clone(), Slot::Param(2 + count - 1 - i as u16));
+        }
+        let mut names = Vec::new();
+        collect_locals(&func.body, &mut names);
+        for (i, name) in names.iter().enumerate() {
+            self.locals.insert(name.clone(), Slot::Local(i as u16 + 1));
+        }
+        self.exit_label = format!("!tc_fn_{}_exit", index);
+        self.out.push(String::new());
+        self.out.push(format!("; func {}", func.name));
+        self.place(&format!("!tc_fn_{}", index));
+        self.emit("push fx");
+        self.emit("copy fx, sp");
+        if !names.is_empty() {
+            self.emit(&format!("set cx, {}", names.len()));
+            self.emit("sub sp, cx");
+        }
+        self.block(&func.body)?;
+        self.emit("set ax, 0");
+        let exit = self.exit_label.clone();
+        self.place(&exit);
+        self.emit("copy sp, fx");
+        self.emit("pop fx");
+        self.emit("ret");
+        Ok(())
+    }
+}
+
+fn collect_locals(stmts: &[Stmt], names: &mut Vec<String>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Var(name, _) if !names.contains(name) => names.push(name.clone()),
+            Stmt::If(_, then, other) => {
+                collect_locals(then, names);
+                collect_locals(other, names);
+            }
+            Stmt::While(_, body) => collect_locals(body, names),
+            _ => {}
+        }
+    }
+}
+
+/// Compiles teenyC source to TeenyAT assembly. `name` is only used in the
+/// generated header comment.
+pub fn compile(name: &str, src: &str) -> io::Result<String> {
+    let toks = lex(src).map_err(|(line, msg)| compile_error(line, &msg))?;
+    let mut parser = Parser { toks, pos: 0 };
+    let (globals, funcs) = parser
+        .program()
+        .map_err(|(line, msg)| compile_error(line, &msg))?;
+    let mut gen = Codegen {
+        out: vec![format!("; compiled from {} by teenyat cc", name)],
+        labels: 0,
+        globals: HashMap::new(),
+        funcs: HashMap::new(),
+        locals: HashMap::new(),
+        exit_label: String::new(),
+        uses_print: false,
+    };
+    for (i, func) in funcs.iter().enumerate() {
+        if gen
+            .funcs
+            .insert(&func.name, (i, func.params.len()))
+            .is_some()
+        {
+            return Err(compile_error(0, &format!("`{}` defined twice", func.name)));
+        }
+    }
+    let main = match gen.funcs.get("main") {
+        Some((index, 0)) => *index,
+        _ => return Err(compile_error(0, "missing `func main()`")),
+    };
+    for (i, (name, init)) in globals.iter().enumerate() {
+        gen.globals.insert(name.clone(), i as u16);
+        if let Some(init) = init {
+            gen.expr(init).map_err(|msg| compile_error(0, &msg))?;
+            gen.store_var(name).map_err(|msg| compile_error(0, &msg))?;
+        }
+    }
+    gen.emit(&format!("call !tc_fn_{}", main));
+    gen.emit("stor END, ax");
+    for (i, func) in funcs.iter().enumerate() {
+        gen.func(i, func)
+            .map_err(|msg| compile_error(0, &format!("in `{}`: {}", func.name, msg)))?;
+    }
+    if gen.uses_print {
+        gen.out.push(String::new());
+        gen.out.push(".include <std/print.tat>".to_string());
+    }
+    gen.out.push(GLOBALS_LABEL.to_string());
+    let mut asm = gen.out.join("\n");
+    asm.push('\n');
+    Ok(asm)
+}
+
+fn compile_error(line: usize, msg: &str) -> io::Error {
+    let msg = if line > 0 {
+        format!("teenyC error on line {}: {}", line, msg)
+    } else {
+        format!("teenyC error: {}", msg)
+    };
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selftest::run_source;
+
+    /// Compiles and runs `src`, returning its exit code and output.
+    fn run(src: &str) -> (u16, String) {
+        run_source(&compile("test.tc", src).unwrap(), "").unwrap()
+    }
+
+    fn error(src: &str) -> String {
+        compile("test.tc", src).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn recursion() {
+        let src = "
+            func fact(n) {
+                if (n <= 1) { return 1; }
+                return n * fact(n - 1);
+            }
+            func main() { return fact(6); }";
+        assert_eq!(run(src).0, 720);
+    }
+
+    #[test]
+    fn parameters_and_locals() {
+        let src = "
+            func diff(a, b) {
+                var first = a;
+                var second = b;
+                return first - second;
+            }
+            func main() {
+                var x = 50;
+                var y = diff(x, 8);
+                return diff(y, 2) + x;
+            }";
+        assert_eq!(run(src).0, 90);
+    }
+
+    #[test]
+    fn globals_and_output() {
+        let src = "
+            var total = 5;
+            var count;
+            func add(n) { total = total + n; count = count + 1; return 0; }
+            func main() {
+                add(10);
+                add(-3);
+                print(total);
+                putc('\\n');
+                return count;
+            }";
+        assert_eq!(run(src), (2, "12\n".to_string()));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        // Each call to `mark` prints its argument, so skipped ones don't.
+        let src = "
+            func mark(n) { putc(n); return n - '0'; }
+            func main() {
+                var a = mark('0') && mark('1');
+                var b = mark('2') || mark('3');
+                var c = mark('4') && mark('5');
+                var d = mark('0') || mark('6');
+                return a * 1000 + b * 100 + c * 10 + d;
+            }";
+        assert_eq!(run(src), (111, "024506".to_string()));
+    }
+
+    #[test]
+    fn comparisons_are_signed() {
+        let src = "
+            func main() {
+                return (-1 < 1) * 8 + (-5 <= -5) * 4 + (-32768 < 32767) * 2 + (1 > -1);
+            }";
+        assert_eq!(run(src).0, 15);
+    }
+
+    #[test]
+    fn defined_twice() {
+        let src = "func f() { return 0; } func f() { return 1; } func main() { return 0; }";
+        assert!(error(src).contains("`f` defined twice"));
+    }
+
+    #[test]
+    fn wrong_argument_count() {
+        let src = "func f(a, b) { return a; } func main() { return f(1); }";
+        assert!(error(src).contains("`f` takes 2 argument(s)"));
+        assert!(error("func main() { putc(); return 0; }").contains("`putc` takes 1 argument(s)"));
+    }
+
+    #[test]
+    fn missing_main() {
+        assert!(error("func start() { return 0; }").contains("missing `func main()`"));
+    }
+}
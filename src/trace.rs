@@ -0,0 +1,252 @@
+//! Instruction traces, and finding where two of them part ways.
+//!
+//! A trace has a line for each instruction executed: the step (the number
+//! of instructions executed before it), the instruction's address, the
+//! registers after it, the words it stored to memory or devices, and after
+//! a `;` the instruction itself, which is only there for people to read.
+//!
+//! ```text
+//! 3 0x0006 pc=0x0008 ax=0x0041 bx=0x0000 cx=0x0000 dx=0x0000 ex=0x0000 fx=0x0000 sp=0x8000 [0x8000]=0x0041 ; stor 0x8000, ax
+//! ```
+//!
+//! A JSON trace, for scripts, has an object per line instead, with the
+//! instruction split into its opcode and operands and only the registers it
+//! changed (all of them on the first line, so the starting state is known):
+//!
+//! ```text
+//! {"mem_writes":[{"addr":32768,"val":65}],"opcode":"stor","operands":["0x8000","ax"],"pc":6,"reg_writes":{"pc":8},"step":3}
+//! ```
+
+use crate::arch::REG_NAMES;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Which kind of trace to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    /// A JSON object per line.
+    Json,
+}
+
+impl Format {
+    /// JSON for a `.jsonl` or `.json` file, and text for anything else.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".jsonl") || path.ends_with(".json") {
+            Format::Json
+        } else {
+            Format::Text
+        }
+    }
+}
+
+/// Writes the steps of a trace in either format.
+pub struct Writer {
+    out: Box<dyn Write + Send>,
+    format: Format,
+    /// The registers after the last step written, if any was.
+    regs: Option<[u16; 8]>,
+}
+
+impl Writer {
+    pub fn new(out: Box<dyn Write + Send>, format: Format) -> Self {
+        Self {
+            out,
+            format,
+            regs: None,
+        }
+    }
+
+    pub fn write(&mut self, step: &Step) -> io::Result<()> {
+        match self.format {
+            Format::Text => writeln!(self.out, "{}", step)?,
+            Format::Json => writeln!(self.out, "{}", step.to_json(self.regs.as_ref()))?,
+        }
+        self.regs = Some(step.regs);
+        Ok(())
+    }
+}
+
+/// One instruction's line of a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub step: u64,
+    pub addr: u16,
+    pub regs: [u16; 8],
+    /// The address and value of each word stored, in order.
+    pub writes: Vec<(u16, u16)>,
+    pub ins: String,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} 0x{:04x}", self.step, self.addr)?;
+        for (name, val) in REG_NAMES.iter().zip(self.regs.iter()) {
+            write!(f, " {}=0x{:04x}", name, val)?;
+        }
+        for (addr, val) in self.writes.iter() {
+            write!(f, " [0x{:04x}]=0x{:04x}", addr, val)?;
+        }
+        write!(f, " ; {}", self.ins)
+    }
+}
+
+impl Step {
+    /// The step as a JSON object, with the registers that differ from
+    /// `before`, or all of them if there was no step before.
+    pub fn to_json(&self, before: Option<&[u16; 8]>) -> Value {
+        let (opcode, operands) = self.ins.split_once(' ').unwrap_or((&self.ins, ""));
+        let operands: Vec<&str> = operands
+            .split(", ")
+            .filter(|operand| !operand.is_empty())
+            .collect();
+        let reg_writes: BTreeMap<&str, u16> = REG_NAMES
+            .iter()
+            .zip(self.regs.iter())
+            .enumerate()
+            .filter(|&(i, _)| before.is_none_or(|before| before[i] != self.regs[i]))
+            .map(|(_, (name, val))| (*name, *val))
+            .collect();
+        let mem_writes: Vec<Value> = self
+            .writes
+            .iter()
+            .map(|(addr, val)| json!({ "addr": addr, "val": val }))
+            .collect();
+        json!({
+            "step": self.step,
+            "pc": self.addr,
+            "opcode": opcode,
+            "operands": operands,
+            "reg_writes": reg_writes,
+            "mem_writes": mem_writes,
+        })
+    }
+
+    /// Reads a line of a JSON trace, taking the registers it doesn't
+    /// mention from `regs` and leaving all of them there for the next.
+    fn parse_json(line: &str, regs: &mut [u16; 8]) -> Option<Self> {
+        let obj: Value = serde_json::from_str(line).ok()?;
+        let word = |val: &Value| val.as_u64().and_then(|val| u16::try_from(val).ok());
+        for (name, val) in obj["reg_writes"].as_object()? {
+            let num = REG_NAMES.iter().position(|reg| reg == name)?;
+            regs[num] = word(val)?;
+        }
+        let mut writes = Vec::new();
+        for write in obj["mem_writes"].as_array()? {
+            writes.push((word(&write["addr"])?, word(&write["val"])?));
+        }
+        let mut ins = obj["opcode"].as_str()?.to_string();
+        let operands: Option<Vec<&str>> = obj["operands"]
+            .as_array()?
+            .iter()
+            .map(Value::as_str)
+            .collect();
+        let operands = operands?;
+        if !operands.is_empty() {
+            ins = format!("{} {}", ins, operands.join(", "));
+        }
+        Some(Self {
+            step: obj["step"].as_u64()?,
+            addr: word(&obj["pc"])?,
+            regs: *regs,
+            writes,
+            ins,
+        })
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let (fields, ins) = line.split_once(" ; ").unwrap_or((line, ""));
+        let mut fields = fields.split_whitespace();
+        let step = fields.next()?.parse().ok()?;
+        let addr = hex(fields.next()?)?;
+        let mut regs = [0; 8];
+        for (reg, name) in regs.iter_mut().zip(REG_NAMES.iter()) {
+            let (field_name, val) = fields.next()?.split_once('=')?;
+            if field_name != *name {
+                return None;
+            }
+            *reg = hex(val)?;
+        }
+        let mut writes = Vec::new();
+        for field in fields {
+            let (addr, val) = field.strip_prefix('[')?.split_once("]=")?;
+            writes.push((hex(addr)?, hex(val)?));
+        }
+        Some(Self {
+            step,
+            addr,
+            regs,
+            writes,
+            ins: ins.to_string(),
+        })
+    }
+}
+
+fn hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.strip_prefix("0x")?, 16).ok()
+}
+
+/// Reads the lines of a trace, text or JSON. Blank lines are skipped.
+pub fn parse(text: &str) -> io::Result<Vec<Step>> {
+    let mut regs = [0; 8];
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(num, line)| {
+            let step = if line.trim_start().starts_with('{') {
+                Step::parse_json(line, &mut regs)
+            } else {
+                Step::parse(line)
+            };
+            step.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Bad trace line {}: {}", num + 1, line),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Where two traces first differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The index of the first step that differs.
+    pub index: usize,
+    /// What differs: `address`, register names, `writes` or `length` when
+    /// one trace ends before the other.
+    pub fields: Vec<&'static str>,
+}
+
+/// Compares two traces step by step, returning where they first differ.
+/// The instruction text isn't compared, so traces of the same program
+/// assembled with different labels still match.
+pub fn diverge(a: &[Step], b: &[Step]) -> Option<Divergence> {
+    for (index, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+        let mut fields = Vec::new();
+        if x.addr != y.addr {
+            fields.push("address");
+        }
+        for (i, name) in REG_NAMES.iter().enumerate() {
+            if x.regs[i] != y.regs[i] {
+                fields.push(*name);
+            }
+        }
+        if x.writes != y.writes {
+            fields.push("writes");
+        }
+        if !fields.is_empty() {
+            return Some(Divergence { index, fields });
+        }
+    }
+    if a.len() != b.len() {
+        return Some(Divergence {
+            index: a.len().min(b.len()),
+            fields: vec!["length"],
+        });
+    }
+    None
+}
@@ -0,0 +1,141 @@
+//! Records of the bytes a program reads and writes.
+//!
+//! A transcript has a line for each read from the input and each write to
+//! the output, whether by the console or by a host service: the step it
+//! happened in (the number of instructions executed before it), `in` or
+//! `out`, and the bytes. Bytes from a space to a `~` are written as they
+//! are, except `\`, which is written `\\`; the rest are written as `\xHH`.
+//!
+//! ```text
+//! 12 in hello\x0a
+//! 40 out h
+//! ```
+//!
+//! Replaying a transcript gives a program the input it was recorded with.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Which way bytes went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+/// Writes a transcript as the program runs, to be given to
+/// `TeenyAT::set_transcript`.
+pub struct Transcript {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Transcript {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+
+    /// Creates a transcript file at `path`, replacing any already there.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    /// Adds a line for `bytes` going `dir` during `step`. Lines are flushed
+    /// as they are written, so a transcript survives a program that crashes.
+    pub fn record(&self, step: u64, dir: Direction, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let mut line = format!("{} {} ", step, dir.name());
+        for &byte in bytes {
+            match byte {
+                b'\\' => line.push_str("\\\\"),
+                b' '..=b'~' => line.push(byte as char),
+                _ => line.push_str(&format!("\\x{:02x}", byte)),
+            }
+        }
+        line.push('\n');
+        // A panic elsewhere while holding the lock leaves nothing half done
+        // that matters here.
+        let mut out = self.out.lock().unwrap_or_else(|err| err.into_inner());
+        out.write_all(line.as_bytes())?;
+        out.flush()
+    }
+}
+
+/// One line of a transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub step: u64,
+    pub dir: Direction,
+    pub bytes: Vec<u8>,
+}
+
+/// Reads the lines of a transcript. Blank lines are skipped.
+pub fn parse(text: &str) -> io::Result<Vec<Entry>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(num, line)| {
+            parse_line(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Bad transcript line {}: {}", num + 1, line),
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Entry> {
+    let (step, rest) = line.split_once(' ')?;
+    let (dir, data) = rest.split_once(' ')?;
+    let dir = match dir {
+        "in" => Direction::In,
+        "out" => Direction::Out,
+        _ => return None,
+    };
+    let mut bytes = Vec::new();
+    let mut chars = data.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match chars.next()? {
+            b'\\' => bytes.push(b'\\'),
+            b'x' => {
+                let hex = [chars.next()?, chars.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+    Some(Entry {
+        step: step.parse().ok()?,
+        dir,
+        bytes,
+    })
+}
+
+/// The input recorded in a transcript, in the order the program read it.
+pub fn input(entries: &[Entry]) -> Vec<u8> {
+    entries
+        .iter()
+        .filter(|entry| entry.dir == Direction::In)
+        .flat_map(|entry| entry.bytes.iter().copied())
+        .collect()
+}
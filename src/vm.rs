@@ -1,438 +1,840 @@
-use crate::arch::OpCode::*;
-use crate::arch::RegMnem::*;
 use crate::arch::*;
-use std::io::{self, Write};
-use std::process;
+use crate::channel::{ChannelReader, ChannelWriter, HostLink};
+use crate::clock::{Clock, ClockMode};
+use crate::config::{DeviceSet, MachineConfig};
+use crate::cpu::{Cpu, System};
+use crate::devices::{Bus, Console, Device, Rng};
+use crate::syscall::{self, Service};
+use crate::trace;
+use crate::transcript::{Direction, Transcript};
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub use crate::cpu::{Frame, Overflow, RunStats, WaitStates, VECTORS, VECTOR_TABLE};
+
+const CONSOLE_ADDR: u16 = 0x8000;
+/// How many instructions run between checks against the clock.
+const CLOCK_SLICE: u64 = 256;
+/// How far behind the clock the VM may fall, e.g. while waiting for input,
+/// before it stops trying to catch up and keeps time from where it is.
+const MAX_LAG: Duration = Duration::from_millis(50);
+/// How long to wait before retrying an instruction whose device had no input.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A load or store that reached a device rather than memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortAccess {
+    pub addr: u16,
+    pub val: u16,
+    pub write: bool,
+}
 
-const SP_INIT: u16 = 0x8000;
-const CHAR_OUT_ADDR: u16 = 0x8000;
-const CHAR_IN_ADDR: u16 = 0x8001;
-const END_PROG_ADDR: u16 = 0xFFFF;
+/// Called before or after each instruction with the instruction's address,
+/// the instruction and the registers, indexed by number as in `REG_NAMES`;
+/// see `TeenyAT::set_pre_hook`.
+pub type Hook = Box<dyn FnMut(u16, Instruction, &[u16; 8]) + Send>;
 
-#[derive(Debug)]
-pub struct TeenyAT {
-    mem: Memory,
-    ins: Instruction,
-    pc: Register,
-    r1: Register,
-    r2: Register,
-    r3: Register,
-    r4: Register,
-    r5: Register,
-    r6: Register,
-    sp: Register,
-    op_code: OpCode,
-    ra: RegMnem,
-    rb: RegMnem,
-    imm: u16,
-    addr: u16,
-    pub debug_mode: bool,
+/// Keeps a running VM in step with the wall clock.
+struct Pacer {
+    start: Instant,
+    ticks: u64,
 }
 
-impl TeenyAT {
-    pub fn new(program: Memory) -> Self {
-        let ins = Instruction::new(0, 0);
-        let pc = Register::new(RegMnem::Pc);
-        let r1 = Register::new(RegMnem::R1);
-        let r2 = Register::new(RegMnem::R2);
-        let r3 = Register::new(RegMnem::R3);
-        let r4 = Register::new(RegMnem::R4);
-        let r5 = Register::new(RegMnem::R5);
-        let r6 = Register::new(RegMnem::R6);
-        let mut sp = Register::new(RegMnem::Sp);
-        sp.val = SP_INIT;
+impl Pacer {
+    fn new(ticks: u64) -> Self {
         Self {
-            mem: program,
-            ins,
-            pc,
-            r1,
-            r2,
-            r3,
-            r4,
-            r5,
-            r6,
-            sp,
-            op_code: OpCode::Set,
-            ra: RegMnem::default(),
-            rb: RegMnem::default(),
-            imm: 0,
-            addr: 0,
-            debug_mode: false,
+            start: Instant::now(),
+            ticks,
         }
     }
 
-    pub fn run(&mut self) -> Result<(), ArchError> {
-        if self.debug_mode {
-            self.mem.print_program();
+    /// How long to wait for `ticks` at `rate` per second to have taken the
+    /// time they should. Falling far behind resets the schedule instead of
+    /// running flat out until it has caught up.
+    fn ahead(&mut self, ticks: u64, rate: u64) -> Option<Duration> {
+        let due = Duration::from_secs_f64((ticks - self.ticks) as f64 / rate as f64);
+        let elapsed = self.start.elapsed();
+        if let Some(ahead) = due.checked_sub(elapsed) {
+            return Some(ahead);
         }
-        loop {
-            self.fetch()?;
-            self.decode()?;
-            self.execute()?;
+        if elapsed - due > MAX_LAG {
+            *self = Pacer::new(ticks);
         }
+        None
     }
+}
 
-    fn fetch(&mut self) -> Result<(), ArchError> {
-        let word1 = self.mem.read(self.pc.val)?;
-        let word2 = self.mem.read(self.pc.val + 1)?;
-        self.ins = Instruction::new(word1, word2);
-        self.pc.val += 2;
-        Ok(())
+/// Counts the bytes a device moves through the VM's input or output, and
+/// records them in the transcript, if there is one.
+struct Counted<'a, T: ?Sized> {
+    inner: &'a mut T,
+    bytes: &'a mut u64,
+    /// The transcript and the step being executed.
+    tap: Option<(&'a Transcript, u64)>,
+}
+
+impl<'a, T: ?Sized> Counted<'a, T> {
+    fn new(inner: &'a mut T, bytes: &'a mut u64, tap: Option<(&'a Transcript, u64)>) -> Self {
+        Self { inner, bytes, tap }
     }
 
-    fn decode(&mut self) -> Result<(), ArchError> {
-        self.op_code = self.ins.get_op_code()?;
-        let num_regs = self.op_code.num_regs();
-        if num_regs >= 1 {
-            self.ra = self.ins.get_ra()?;
+    fn record(&self, dir: Direction, bytes: &[u8]) -> io::Result<()> {
+        match self.tap {
+            Some((transcript, step)) => transcript.record(step, dir, bytes),
+            None => Ok(()),
         }
-        if num_regs == 2 {
-            self.rb = self.ins.get_rb()?;
+    }
+}
+
+impl<T: BufRead + ?Sized> Read for Counted<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        *self.bytes += len as u64;
+        self.record(Direction::In, &buf[..len])?;
+        Ok(len)
+    }
+}
+
+impl<T: BufRead + ?Sized> BufRead for Counted<'_, T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        *self.bytes += amt as u64;
+        if let Some((transcript, step)) = self.tap {
+            // The bytes being consumed are still at the front of the buffer,
+            // so this doesn't read any more input.
+            let recorded = match self.inner.fill_buf() {
+                Ok(buf) => transcript.record(step, Direction::In, &buf[..amt.min(buf.len())]),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = recorded {
+                warn!(target: "vm::transcript", "input not recorded: {}", err);
+            }
         }
-        self.imm = self.ins.word_imm;
-        self.addr = self.imm;
-        Ok(())
+        self.inner.consume(amt);
+    }
+}
+
+impl<T: Write + ?Sized> Write for Counted<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        *self.bytes += len as u64;
+        self.record(Direction::Out, &buf[..len])?;
+        Ok(len)
     }
 
-    fn execute(&mut self) -> Result<(), ArchError> {
-        match self.op_code {
-            Set => self.set(),
-            Copy => self.copy(),
-            Load => self.load()?,
-            Stor => self.stor()?,
-            PLoad => self.pload()?,
-            PStor => self.pstor()?,
-            Push => self.push()?,
-            Pop => self.pop()?,
-            Add => self.add(),
-            Sub => self.sub(),
-            Mult => self.mult(),
-            Div => self.div(),
-            Mod => self.divmod(),
-            Neg => self.neg(),
-            Inc => self.inc(),
-            Dec => self.dec(),
-            And => self.and(),
-            Or => self.or(),
-            Xor => self.xor(),
-            Inv => self.inv(),
-            Shl => self.shl(),
-            Shr => self.shr(),
-            Call => self.call()?,
-            Jl => self.jl(),
-            Jle => self.jle(),
-            Je => self.je(),
-            Jne => self.jne(),
-            Jge => self.jge(),
-            Jg => self.jg(),
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Why `TeenyAT::run_for` returned.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program ended with this exit code.
+    Exited(u16),
+    /// The step budget ran out with the program still running.
+    OutOfSteps,
+    /// A device is waiting for input. The instruction runs again next time.
+    Blocked,
+    /// The interrupt flag was raised.
+    Interrupted,
+    /// The fuel given with `TeenyAT::set_fuel` ran out.
+    OutOfFuel,
+    /// An instruction failed. `ins_addr` gives its address.
+    Fault(ArchError),
+}
+
+/// The outcome of `TeenyAT::run_for`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RunFor {
+    /// How many instructions ran, including one that faulted.
+    pub steps: u64,
+    pub reason: StopReason,
+}
+
+/// A `Cpu` with devices, host services, console I/O and a clock: the
+/// machine `teenyat` runs programs on.
+pub struct TeenyAT {
+    cpu: Cpu,
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+    events: Option<Sender<PortAccess>>,
+    devices: Vec<(u16, Box<dyn Device>)>,
+    services: HashMap<u16, Box<dyn Service>>,
+    port_accesses: Vec<PortAccess>,
+    clock_hz: u64,
+    cycle_hz: u64,
+    nonblocking: bool,
+    interrupt: Option<Arc<AtomicBool>>,
+    transcript: Option<Transcript>,
+    trace: Option<trace::Writer>,
+    clock: Clock,
+    pre_hook: Option<Hook>,
+    post_hook: Option<Hook>,
+    fuel: Option<u64>,
+}
+
+impl TeenyAT {
+    pub fn new(program: Memory) -> Self {
+        Self {
+            cpu: Cpu::new(program),
+            input: Box::new(io::BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+            events: None,
+            devices: vec![(CONSOLE_ADDR, Box::new(Console::new()))],
+            services: syscall::builtins(),
+            port_accesses: Vec::new(),
+            clock_hz: 0,
+            cycle_hz: 0,
+            nonblocking: false,
+            interrupt: None,
+            transcript: None,
+            trace: None,
+            clock: Clock::new(ClockMode::Virtual, 0),
+            pre_hook: None,
+            post_hook: None,
+            fuel: None,
         }
-        Ok(())
     }
 
-    fn get_ra(&mut self) -> &mut Register {
-        match self.ra {
-            _R0 | Pc => &mut self.pc,
-            R1 | Ax => &mut self.r1,
-            R2 | Bx => &mut self.r2,
-            R3 | Cx => &mut self.r3,
-            R4 | Dx => &mut self.r4,
-            R5 | Ex => &mut self.r5,
-            R6 | Fx => &mut self.r6,
-            R7 | Sp => &mut self.sp,
+    /// Creates a VM for the machine described by `config`.
+    pub fn with_config(program: Memory, config: &MachineConfig) -> io::Result<Self> {
+        Self::with_devices(program, config, config.build_devices()?)
+    }
+
+    /// Like `with_config`, but attaching `devices`, such as a set from
+    /// `MachineConfig::build_device_sets`, rather than building them.
+    pub fn with_devices(
+        mut program: Memory,
+        config: &MachineConfig,
+        devices: DeviceSet,
+    ) -> io::Result<Self> {
+        program.resize(config.memory_size as usize).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The program is larger than memory_size ({} words)",
+                    config.memory_size
+                ),
+            )
+        })?;
+        if let Some(pattern) = config.poison {
+            program.poison(pattern);
         }
+        let mut vm = Self::new(program);
+        vm.cpu = vm
+            .cpu
+            .with_halt_address(config.halt_address)
+            .with_overflow(config.overflow)
+            .with_shadow_stack(config.shadow_stack)
+            .with_misaligned_trap(config.trap_misaligned)
+            .with_wait_states(config.wait_states)
+            .with_vector_table(config.vector_table);
+        vm.devices = devices;
+        vm.set_service(syscall::SYS_RANDOM, Box::new(Rng::new(config.seed)));
+        vm.clock_hz = config.clock_hz;
+        vm.cycle_hz = config.cycle_hz;
+        vm.clock = Clock::new(config.clock, config.cycle_hz);
+        Ok(vm)
+    }
+
+    /// Chooses what arithmetic does on signed overflow. VMs wrap by default.
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.cpu = self.cpu.with_overflow(overflow);
+        self
+    }
+
+    /// Charges extra cycles for memory accesses. VMs have no wait states by
+    /// default.
+    pub fn with_wait_states(mut self, wait_states: WaitStates) -> Self {
+        self.cpu = self.cpu.with_wait_states(wait_states);
+        self
+    }
+
+    /// Gives the VM a flag that another thread, or a signal handler, can
+    /// raise to stop `run` with `ArchError::Interrupted`.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+
+    /// Whether the interrupt flag has been raised since this was last called.
+    pub fn take_interrupt(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(|flag| flag.swap(false, Ordering::SeqCst))
+    }
+
+    /// Installs `service` as the host service `int number` calls, replacing
+    /// any already there, including the built in ones in `syscall`.
+    pub fn set_service(&mut self, number: u16, service: Box<dyn Service>) {
+        self.services.insert(number, service);
+    }
+
+    /// Chooses whether timers, sleeping and the `rtc` device go by the
+    /// cycles executed or by the host's clock; see `clock`. VMs use a
+    /// virtual clock by default.
+    pub fn set_clock(&mut self, mode: ClockMode) {
+        self.clock = Clock::new(mode, self.cycle_hz);
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Has devices that would wait for input, such as a `mailbox` with
+    /// nothing in it, return `ArchError::WouldBlock` instead, so that `step`
+    /// and `run_for` never hold up the thread. `run_async` does this itself.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Replaces the character input and output ports, which default to the
+    /// host's stdin and stdout.
+    pub fn set_io(&mut self, input: Box<dyn BufRead + Send>, output: Box<dyn Write + Send>) {
+        self.input = input;
+        self.output = output;
+    }
+
+    /// Records everything read from the input and written to the output from
+    /// now on in `transcript`.
+    pub fn set_transcript(&mut self, transcript: Transcript) {
+        self.transcript = Some(transcript);
+    }
+
+    /// Writes a line to `trace` for every instruction executed from now on,
+    /// in the format described in `trace`.
+    pub fn set_trace(&mut self, trace: Box<dyn Write + Send>) {
+        self.set_trace_as(trace, trace::Format::Text);
+    }
+
+    /// Like `set_trace`, writing the trace in `format`.
+    pub fn set_trace_as(&mut self, trace: Box<dyn Write + Send>, format: trace::Format) {
+        self.trace = Some(trace::Writer::new(trace, format));
+    }
+
+    /// Calls `hook` before each instruction runs, with the registers as the
+    /// instruction will find them, replacing any hook already set. An
+    /// instruction retried because a device had no input is seen again.
+    /// Without hooks, stepping costs nothing extra.
+    pub fn set_pre_hook(&mut self, hook: Hook) {
+        self.pre_hook = Some(hook);
+    }
+
+    /// Calls `hook` after each instruction that runs without a fault, with
+    /// the registers as it left them, replacing any hook already set.
+    pub fn set_post_hook(&mut self, hook: Hook) {
+        self.post_hook = Some(hook);
     }
 
-    fn rb_val(&self) -> u16 {
-        match self.rb {
-            _R0 | Pc => self.pc.val,
-            R1 | Ax => self.r1.val,
-            R2 | Bx => self.r2.val,
-            R3 | Cx => self.r3.val,
-            R4 | Dx => self.r4.val,
-            R5 | Ex => self.r5.val,
-            R6 | Fx => self.r6.val,
-            R7 | Sp => self.sp.val,
+    /// Removes the hooks set with `set_pre_hook` and `set_post_hook`.
+    pub fn clear_hooks(&mut self) {
+        self.pre_hook = None;
+        self.post_hook = None;
+    }
+
+    /// Meters the VM: from now on each instruction uses up as much fuel as
+    /// it takes cycles (see `OpCode::cycles`), and once `fuel` is gone
+    /// `step` returns `ArchError::OutOfFuel` without running anything until
+    /// more is given. A host running many VMs can hand each the same amount
+    /// in turn so none gets more than its share. VMs aren't metered by
+    /// default.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    /// The fuel left, or None if the VM isn't metered. The last instruction
+    /// run may have cost more than was left, in which case this is 0.
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Replaces the character input and output ports with channels and
+    /// reports every device access on a third, so the VM can run on a worker
+    /// thread while another thread drives and observes it.
+    pub fn connect_channels(&mut self) -> HostLink {
+        let (input, input_rx) = mpsc::channel();
+        let (output_tx, output) = mpsc::channel();
+        let (events_tx, events) = mpsc::channel();
+        self.set_io(
+            Box::new(ChannelReader::new(input_rx)),
+            Box::new(ChannelWriter::new(output_tx)),
+        );
+        self.events = Some(events_tx);
+        HostLink {
+            input,
+            output,
+            events,
         }
     }
 
-    fn set(&mut self) {
-        let imm = self.imm;
-        let ra = self.get_ra();
-        ra.val = imm;
+    /// Runs until the program stores to or loads from END. The stats returned
+    /// carry the value of the register involved as the exit code.
+    pub fn run(&mut self) -> Result<RunStats, ArchError> {
+        let mut pacer = Pacer::new(self.clock_ticks(0));
+        let mut steps: u64 = 0;
+        loop {
+            if self.take_interrupt() {
+                return Err(ArchError::Interrupted);
+            }
+            match self.step() {
+                Ok(()) => {}
+                Err(ArchError::WouldBlock) => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+            if self.cpu.exit_code().is_some() {
+                return Ok(self.stats());
+            }
+            steps += 1;
+            if steps.is_multiple_of(CLOCK_SLICE) {
+                if let Some(ahead) = self.pace(&mut pacer, steps) {
+                    thread::sleep(ahead);
+                }
+            }
+        }
     }
 
-    fn copy(&mut self) {
-        let rb_val = match self.rb {
-            _R0 | Pc => self.pc.val,
-            R1 | Ax => self.r1.val,
-            R2 | Bx => self.r2.val,
-            R3 | Cx => self.r3.val,
-            R4 | Dx => self.r4.val,
-            R5 | Ex => self.r5.val,
-            R6 | Fx => self.r6.val,
-            R7 | Sp => self.sp.val,
-        };
-        let ra = self.get_ra();
-        ra.val = rb_val;
-    }
-
-    fn load(&mut self) -> Result<(), ArchError> {
-        let addr = self.addr;
-        let ra = match self.ra {
-            _R0 | Pc => &mut self.pc,
-            R1 | Ax => &mut self.r1,
-            R2 | Bx => &mut self.r2,
-            R3 | Cx => &mut self.r3,
-            R4 | Dx => &mut self.r4,
-            R5 | Ex => &mut self.r5,
-            R6 | Fx => &mut self.r6,
-            R7 | Sp => &mut self.sp,
-        };
-        if addr == CHAR_IN_ADDR {
-            ra.val = input_char();
-        } else if addr == END_PROG_ADDR {
-            process::exit(ra.val as i32);
+    /// The count the clock is kept against: cycles, including those slept,
+    /// when pacing by cycles, otherwise the steps taken.
+    fn clock_ticks(&self, steps: u64) -> u64 {
+        if self.cycle_hz > 0 {
+            self.cpu.cycles() + self.clock.slept()
         } else {
-            ra.val = self.mem.read(addr)?;
+            steps
         }
-        Ok(())
     }
 
-    fn stor(&mut self) -> Result<(), ArchError> {
-        let ra = match self.ra {
-            _R0 | Pc => &mut self.pc,
-            R1 | Ax => &mut self.r1,
-            R2 | Bx => &mut self.r2,
-            R3 | Cx => &mut self.r3,
-            R4 | Dx => &mut self.r4,
-            R5 | Ex => &mut self.r5,
-            R6 | Fx => &mut self.r6,
-            R7 | Sp => &mut self.sp,
-        };
-        if self.addr == CHAR_OUT_ADDR {
-            output_char(ra.val);
-        } else if self.addr == END_PROG_ADDR {
-            process::exit(ra.val as i32);
+    /// How long to wait to keep to `cycle_hz` or `clock_hz`, if either is set.
+    fn pace(&self, pacer: &mut Pacer, steps: u64) -> Option<Duration> {
+        let rate = if self.cycle_hz > 0 {
+            self.cycle_hz
         } else {
-            self.mem.write(self.addr, ra.val)?;
+            self.clock_hz
+        };
+        if rate == 0 {
+            return None;
         }
-        Ok(())
+        pacer.ahead(self.clock_ticks(steps), rate)
+    }
+
+    /// Like `run`, but for async hosts running many guests on a few threads.
+    /// Instead of blocking, it awaits the clock, yields every few hundred
+    /// instructions, and while a device is waiting for input (see
+    /// `feed::InputFeed` for character input) polls it every millisecond.
+    /// Needs the `async` feature and a Tokio runtime with timers enabled.
+    #[cfg(feature = "async")]
+    pub async fn run_async(&mut self) -> Result<RunStats, ArchError> {
+        use tokio::time;
+
+        let nonblocking = std::mem::replace(&mut self.nonblocking, true);
+        let mut pacer = Pacer::new(self.clock_ticks(0));
+        let mut steps: u64 = 0;
+        let result = loop {
+            if self.take_interrupt() {
+                break Err(ArchError::Interrupted);
+            }
+            match self.step() {
+                Ok(()) => {}
+                Err(ArchError::WouldBlock) => {
+                    time::sleep(POLL_INTERVAL).await;
+                    continue;
+                }
+                Err(err) => break Err(err),
+            }
+            if self.cpu.exit_code().is_some() {
+                break Ok(self.stats());
+            }
+            steps += 1;
+            if steps.is_multiple_of(CLOCK_SLICE) {
+                match self.pace(&mut pacer, steps) {
+                    Some(ahead) => time::sleep(ahead).await,
+                    None => tokio::task::yield_now().await,
+                }
+            }
+        };
+        self.nonblocking = nonblocking;
+        result
+    }
+
+    /// Runs at most `max_steps` instructions, for hosts that interleave the VM
+    /// with work of their own, such as drawing a frame. Unlike `run` this
+    /// ignores `clock_hz` and `cycle_hz`; pacing is up to the caller.
+    pub fn run_for(&mut self, max_steps: u64) -> RunFor {
+        let mut steps = 0;
+        let reason = loop {
+            if let Some(code) = self.cpu.exit_code() {
+                break StopReason::Exited(code);
+            }
+            if steps == max_steps {
+                break StopReason::OutOfSteps;
+            }
+            if self.take_interrupt() {
+                break StopReason::Interrupted;
+            }
+            steps += 1;
+            match self.step() {
+                Ok(()) => {}
+                Err(ArchError::WouldBlock) => {
+                    steps -= 1;
+                    break StopReason::Blocked;
+                }
+                Err(ArchError::OutOfFuel) => {
+                    steps -= 1;
+                    break StopReason::OutOfFuel;
+                }
+                Err(err) => break StopReason::Fault(err),
+            }
+        };
+        RunFor { steps, reason }
     }
 
-    fn pload(&mut self) -> Result<(), ArchError> {
-        let rb = self.rb_val();
-        let ra = match self.ra {
-            _R0 | Pc => &mut self.pc,
-            R1 | Ax => &mut self.r1,
-            R2 | Bx => &mut self.r2,
-            R3 | Cx => &mut self.r3,
-            R4 | Dx => &mut self.r4,
-            R5 | Ex => &mut self.r5,
-            R6 | Fx => &mut self.r6,
-            R7 | Sp => &mut self.sp,
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Result<(), ArchError> {
+        self.port_accesses.clear();
+        if self.fuel == Some(0) {
+            return Err(ArchError::OutOfFuel);
+        }
+        let mut io_bytes = 0;
+        let step = self.cpu.stats.instructions;
+        if let Some(hook) = &mut self.pre_hook {
+            let pc = self.cpu.pc();
+            if let (Ok(op), Ok(imm)) =
+                (self.cpu.read_mem(pc), self.cpu.read_mem(pc.wrapping_add(1)))
+            {
+                hook(
+                    pc,
+                    Instruction::new(op, imm),
+                    &self.cpu.registers().map(|reg| reg.val),
+                );
+            }
+        }
+        let cycles = self.cpu.cycles();
+        self.clock.set_executed(cycles);
+        let mut system = Attached {
+            devices: &mut self.devices,
+            services: &mut self.services,
+            input: self.input.as_mut(),
+            output: self.output.as_mut(),
+            port_accesses: &mut self.port_accesses,
+            nonblocking: self.nonblocking,
+            clock: &mut self.clock,
+            io_bytes: &mut io_bytes,
+            tap: self
+                .transcript
+                .as_ref()
+                .map(|transcript| (transcript, step)),
         };
-        if rb == CHAR_IN_ADDR {
-            ra.val = input_char();
-        } else if rb == END_PROG_ADDR {
-            process::exit(ra.val as i32);
-        } else {
-            ra.val = self.mem.read(rb)?;
+        let result = self.cpu.step(&mut system);
+        self.cpu.stats.io_bytes += io_bytes;
+        if let Some(fuel) = &mut self.fuel {
+            *fuel = fuel.saturating_sub(self.cpu.cycles() - cycles);
         }
-        Ok(())
+        if result.is_ok() {
+            if let Some(hook) = &mut self.post_hook {
+                if let Some(&(addr, ins)) = self.cpu.history().back() {
+                    hook(addr, ins, &self.cpu.registers().map(|reg| reg.val));
+                }
+            }
+            self.write_trace(step)?;
+        }
+        if let Some(events) = &self.events {
+            for access in &self.port_accesses {
+                let _ = events.send(*access);
+            }
+        }
+        result
     }
 
-    fn pstor(&mut self) -> Result<(), ArchError> {
-        let rb = self.rb_val();
-        let ra = match self.ra {
-            _R0 | Pc => &mut self.pc,
-            R1 | Ax => &mut self.r1,
-            R2 | Bx => &mut self.r2,
-            R3 | Cx => &mut self.r3,
-            R4 | Dx => &mut self.r4,
-            R5 | Ex => &mut self.r5,
-            R6 | Fx => &mut self.r6,
-            R7 | Sp => &mut self.sp,
+    /// Adds the instruction just executed, step `step`, to the trace.
+    fn write_trace(&mut self, step: u64) -> Result<(), ArchError> {
+        if self.trace.is_none() {
+            return Ok(());
+        }
+        let addr = self.cpu.ins_addr();
+        let ins = self
+            .cpu
+            .history()
+            .back()
+            .and_then(|(_, ins)| ins.disassemble())
+            .unwrap_or_default();
+        let mut writes: Vec<(u16, u16)> = self
+            .cpu
+            .written()
+            .iter()
+            .map(|&addr| (addr, self.cpu.read_mem(addr).unwrap_or(0)))
+            .collect();
+        writes.extend(
+            self.port_accesses
+                .iter()
+                .filter(|access| access.write)
+                .map(|access| (access.addr, access.val)),
+        );
+        let line = trace::Step {
+            step,
+            addr,
+            regs: self.cpu.registers().map(|reg| reg.val),
+            writes,
+            ins,
         };
-        if ra.val == CHAR_OUT_ADDR {
-            output_char(rb);
-        } else if ra.val == END_PROG_ADDR {
-            process::exit(ra.val as i32);
-        } else {
-            self.mem.write(ra.val, rb)?;
+        if let Some(trace) = &mut self.trace {
+            trace.write(&line)?;
         }
         Ok(())
     }
 
-    fn push(&mut self) -> Result<(), ArchError> {
-        let ra = self.get_ra();
-        let temp = ra.val;
-        self.sp.val -= 1;
-        self.mem.write(self.sp.val, temp)?;
-        Ok(())
+    /// Keeps what the last `len` steps changed, so that `step_back` can
+    /// undo them; see `Cpu::set_journal`.
+    pub fn set_journal(&mut self, len: usize) {
+        self.cpu.set_journal(len);
     }
 
-    fn pop(&mut self) -> Result<(), ArchError> {
-        let addr = self.sp.val;
-        let ra = match self.ra {
-            _R0 | Pc => &mut self.pc,
-            R1 | Ax => &mut self.r1,
-            R2 | Bx => &mut self.r2,
-            R3 | Cx => &mut self.r3,
-            R4 | Dx => &mut self.r4,
-            R5 | Ex => &mut self.r5,
-            R6 | Fx => &mut self.r6,
-            R7 | Sp => &mut self.sp,
-        };
-        ra.val = self.mem.read(addr)?;
-        self.sp.val += 1;
-        Ok(())
+    /// How many steps `step_back` can undo.
+    pub fn journaled(&self) -> usize {
+        self.cpu.journaled()
     }
 
-    fn add(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        ra.val += rb;
+    /// Undoes the last step, returning false if the journal is empty. Only
+    /// the CPU and memory go back; devices keep their state.
+    pub fn step_back(&mut self) -> bool {
+        self.port_accesses.clear();
+        self.cpu.step_back()
     }
 
-    fn sub(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        ra.val -= rb;
+    /// Raises interrupt `vector` (0 to 7); see `Cpu::raise`. Devices raise
+    /// interrupts through `Device::interrupt`.
+    pub fn raise(&mut self, vector: u16) {
+        self.cpu.raise(vector);
     }
 
-    fn mult(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        ra.val *= rb;
+    /// Whether the guest has interrupts enabled.
+    pub fn interrupts_enabled(&self) -> bool {
+        self.cpu.interrupts_enabled()
     }
 
-    fn div(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        ra.val /= rb;
+    /// The processor, for what the methods here don't cover.
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
     }
 
-    fn divmod(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        ra.val %= rb;
+    /// The estimated clock cycles executed so far; see `OpCode::cycles`.
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
     }
 
-    fn neg(&mut self) {
-        let ra = self.get_ra();
-        ra.val = -(ra.val as i16) as u16;
+    /// What the VM has done so far.
+    pub fn stats(&self) -> RunStats {
+        self.cpu.stats()
     }
 
-    fn inc(&mut self) {
-        let ra = self.get_ra();
-        ra.val += 1;
+    /// The exit code once the program has ended, or None while it can still run.
+    pub fn exit_code(&self) -> Option<u16> {
+        self.cpu.exit_code()
     }
 
-    fn dec(&mut self) {
-        let ra = self.get_ra();
-        ra.val -= 1;
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
     }
 
-    fn and(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        ra.val &= rb;
+    /// The address of the instruction executed most recently, or that was
+    /// being executed when a step failed.
+    pub fn ins_addr(&self) -> u16 {
+        self.cpu.ins_addr()
     }
 
-    fn or(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        ra.val |= rb;
+    /// The calls that haven't returned yet, outermost first. A `pop pc`
+    /// returns from the call whose return address it pops, along with any
+    /// calls made inside it.
+    pub fn call_stack(&self) -> &[Frame] {
+        self.cpu.call_stack()
     }
 
-    fn xor(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        ra.val ^= rb;
+    pub fn registers(&self) -> [&Register; 8] {
+        self.cpu.registers()
     }
 
-    fn inv(&mut self) {
-        let ra = self.get_ra();
-        ra.val = !ra.val;
+    pub fn memory(&self) -> &Memory {
+        self.cpu.memory()
     }
 
-    fn shl(&mut self) {
-        let imm = self.imm;
-        let ra = self.get_ra();
-        ra.val <<= imm;
+    pub fn read_mem(&self, addr: u16) -> Result<u16, ArchError> {
+        self.cpu.read_mem(addr)
     }
 
-    fn shr(&mut self) {
-        let imm = self.imm;
-        let ra = self.get_ra();
-        ra.val >>= imm;
+    /// The addresses and instructions of the last steps, oldest first.
+    pub fn history(&self) -> &VecDeque<(u16, Instruction)> {
+        self.cpu.history()
     }
 
-    fn call(&mut self) -> Result<(), ArchError> {
-        self.push()?;
-        self.pc.val = self.addr;
-        Ok(())
+    /// The device ports the last step read or wrote.
+    pub fn port_accesses(&self) -> &[PortAccess] {
+        &self.port_accesses
     }
 
-    fn jl(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        if (ra.val as i16) < (rb as i16) {
-            self.pc.val = self.addr;
-        }
+    /// The attached devices, each with its base address.
+    pub fn devices(&self) -> impl Iterator<Item = (u16, &dyn Device)> {
+        self.devices
+            .iter()
+            .map(|(base, device)| (*base, device.as_ref()))
     }
 
-    fn jle(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        if (ra.val as i16) <= (rb as i16) {
-            self.pc.val = self.addr;
-        }
+    /// The memory addresses the last step stored to, not counting devices.
+    pub fn written(&self) -> &[u16] {
+        self.cpu.written()
     }
 
-    fn je(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        if (ra.val as i16) == (rb as i16) {
-            self.pc.val = self.addr;
-        }
+    /// Overwrites a register. Setting pc makes execution continue there.
+    pub fn set_register(&mut self, reg: RegMnem, val: u16) {
+        self.cpu.set_register(reg, val);
     }
 
-    fn jne(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        if (ra.val as i16) != (rb as i16) {
-            self.pc.val = self.addr;
-        }
+    /// Overwrites a word of memory, bypassing any device mapped there.
+    pub fn write_mem(&mut self, addr: u16, val: u16) -> Result<(), ArchError> {
+        self.cpu.write_mem(addr, val)
     }
+}
 
-    fn jge(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        if (ra.val as i16) >= (rb as i16) {
-            self.pc.val = self.addr;
-        }
+/// What a `TeenyAT` attaches to its `Cpu` for the length of a step.
+struct Attached<'a> {
+    devices: &'a mut Vec<(u16, Box<dyn Device>)>,
+    services: &'a mut HashMap<u16, Box<dyn Service>>,
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+    port_accesses: &'a mut Vec<PortAccess>,
+    nonblocking: bool,
+    clock: &'a mut Clock,
+    io_bytes: &'a mut u64,
+    tap: Option<(&'a Transcript, u64)>,
+}
+
+/// The device mapped at `addr` and the port `addr` is to it.
+fn device_at(
+    devices: &mut [(u16, Box<dyn Device>)],
+    addr: u16,
+) -> Option<(&mut Box<dyn Device>, u16)> {
+    devices
+        .iter_mut()
+        .find(|(base, device)| addr >= *base && addr - *base < device.ports())
+        .map(|(base, device)| (device, addr - *base))
+}
+
+impl System for Attached<'_> {
+    fn read(
+        &mut self,
+        addr: u16,
+        mem: &mut Memory,
+        regs: &[u16; 8],
+    ) -> Result<Option<u16>, ArchError> {
+        let (device, port) = match device_at(self.devices, addr) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let mut input = Counted::new(&mut *self.input, &mut *self.io_bytes, self.tap);
+        let mut bus = Bus {
+            mem,
+            input: &mut input,
+            output: &mut *self.output,
+            regs: *regs,
+            clock: &mut *self.clock,
+            nonblocking: self.nonblocking,
+        };
+        let val = device.read(port, &mut bus)?;
+        self.port_accesses.push(PortAccess {
+            addr,
+            val,
+            write: false,
+        });
+        Ok(Some(val))
+    }
+
+    fn write(
+        &mut self,
+        addr: u16,
+        val: u16,
+        mem: &mut Memory,
+        regs: &[u16; 8],
+    ) -> Result<bool, ArchError> {
+        let (device, port) = match device_at(self.devices, addr) {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        self.port_accesses.push(PortAccess {
+            addr,
+            val,
+            write: true,
+        });
+        let mut output = Counted::new(&mut *self.output, &mut *self.io_bytes, self.tap);
+        let mut bus = Bus {
+            mem,
+            input: &mut *self.input,
+            output: &mut output,
+            regs: *regs,
+            clock: &mut *self.clock,
+            nonblocking: self.nonblocking,
+        };
+        device.write(port, val, &mut bus)?;
+        Ok(true)
+    }
+
+    fn service(
+        &mut self,
+        number: u16,
+        regs: &mut [u16; 8],
+        mem: &mut Memory,
+    ) -> Result<(), ArchError> {
+        let service = self
+            .services
+            .get_mut(&number)
+            .ok_or(ArchError::NoService(number))?;
+        let mut io_bytes = 0;
+        let mut input = Counted::new(&mut *self.input, &mut io_bytes, self.tap);
+        let mut output = Counted::new(&mut *self.output, &mut *self.io_bytes, self.tap);
+        let mut bus = Bus {
+            mem,
+            input: &mut input,
+            output: &mut output,
+            regs: *regs,
+            clock: &mut *self.clock,
+            nonblocking: self.nonblocking,
+        };
+        service.call(regs, &mut bus)?;
+        *self.io_bytes += io_bytes;
+        Ok(())
     }
 
-    fn jg(&mut self) {
-        let rb = self.rb_val();
-        let ra = self.get_ra();
-        if (ra.val as i16) > (rb as i16) {
-            self.pc.val = self.addr;
+    fn interrupts(&mut self, cycles: u64) -> u16 {
+        self.clock.set_executed(cycles);
+        let now = self.clock.cycles();
+        let mut raised = 0;
+        for (_, device) in self.devices.iter_mut() {
+            if let Some(vector) = device.interrupt(now) {
+                raised |= 1 << (vector % VECTORS);
+            }
         }
+        raised
     }
-}
 
-fn input_char() -> u16 {
-    let input = io::stdin();
-    let mut buf: String = String::new();
-    let _line = input.read_line(&mut buf);
-    buf.bytes().next().unwrap() as u16
+    fn halted(&mut self) -> Result<(), ArchError> {
+        self.devices
+            .iter_mut()
+            .try_for_each(|(_, device)| device.halted())
+    }
 }
 
-fn output_char(chr: u16) {
-    let mut output = io::stdout();
-    let buf: Vec<u8> = vec![chr as u8];
-    output.write(&buf).unwrap();
-    output.flush().unwrap();
+/// The machine state in the fixed layout of `Cpu`'s `Display`.
+impl fmt::Display for TeenyAT {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.cpu.fmt(f)
+    }
 }